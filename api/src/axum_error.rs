@@ -32,18 +32,22 @@ impl AxumError {
         Self::with_status(report, StatusCode::BAD_REQUEST)
     }
 
+    #[allow(dead_code)]
     pub fn unauthorized(report: Report) -> Self {
         Self::with_status(report, StatusCode::UNAUTHORIZED)
     }
 
+    #[allow(dead_code)]
     pub fn forbidden(report: Report) -> Self {
         Self::with_status(report, StatusCode::FORBIDDEN)
     }
 
+    #[allow(dead_code)]
     pub fn not_found(report: Report) -> Self {
         Self::with_status(report, StatusCode::NOT_FOUND)
     }
 
+    #[allow(dead_code)]
     pub fn conflict(report: Report) -> Self {
         Self::with_status(report, StatusCode::CONFLICT)
     }
@@ -1,16 +1,25 @@
 pub mod types;
 
+use std::{
+    io::{self, BufWriter, Write},
+    sync::Arc,
+};
+
 use axum::{
+    body::Body,
     http::{header, StatusCode},
     response::IntoResponse,
     Json,
 };
 use color_eyre::eyre::eyre;
+use futures_util::{stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::warn;
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
-use imagen::{encode_to_png, Canvas};
+use imagen::{registry, Canvas, Compositor, ImageEncoder};
 
 use crate::{
     axum_error::{AxumError, AxumResult},
@@ -29,18 +38,41 @@ pub struct ImageRequest {
     #[serde(default)]
     pub background: Option<Color>,
     pub commands: Vec<DrawCommand>,
+    /// Output format as a file extension or MIME type, such as `webp` or `image/jpeg`.
+    /// PNG when omitted
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Blend translucent colors and anti-aliased text edges in linear light instead of
+    /// directly in sRGB
+    #[serde(default)]
+    pub gamma_correct: bool,
 }
 
 /// Draws an image based on drawing commands
 ///
 /// Creates an image with the specified dimensions and applies a series of drawing commands in order.
-/// Returns the generated image as a PNG.
+/// Returns the generated image as a PNG, or in any registered format given by `format`.
 #[utoipa::path(
     post,
     path = "/",
     request_body = ImageRequest,
     responses(
-        (status = 200, description = "Image generated successfully", content_type = "image/png"),
+        (status = 200, description = "Image generated successfully, in the requested format", content(
+            ("image/png"),
+            ("image/jpeg"),
+            ("image/webp"),
+            ("image/gif"),
+            ("image/qoi"),
+            ("image/bmp"),
+            ("image/x-tga"),
+            ("image/vnd.microsoft.icon"),
+            ("image/x-win-bitmap"),
+            ("image/x-portable-bitmap"),
+            ("image/x-portable-graymap"),
+            ("image/x-portable-pixmap"),
+            ("image/x-portable-arbitrarymap"),
+            ("image/x-farbfeld")
+        )),
         (status = 400, description = "Invalid request"),
         (status = 500, description = "Internal server error")
     ),
@@ -59,8 +91,28 @@ async fn generate_image(Json(request): Json<ImageRequest>) -> AxumResult<impl In
         )));
     }
 
+    let encoder: Arc<dyn ImageEncoder> = match &request.format {
+        Some(format) => {
+            let registry = registry();
+
+            registry
+                .encoder_for_extension(format)
+                .or_else(|| registry.encoder_for_mime(format))
+                .ok_or_else(|| {
+                    AxumError::bad_request(eyre!("Unsupported output format: {}", format))
+                })?
+        }
+        None => registry()
+            .encoder_for_extension("png")
+            .ok_or_else(|| AxumError::new(eyre!("No PNG encoder is registered")))?,
+    };
+
     let mut canvas = Canvas::new(request.width, request.height);
 
+    if request.gamma_correct {
+        canvas = canvas.with_compositor(Compositor::gamma_correct());
+    }
+
     if let Some(bg) = request.background {
         canvas.draw_filled_rect(0, 0, request.width, request.height, bg.into());
     }
@@ -159,18 +211,76 @@ async fn generate_image(Json(request): Json<ImageRequest>) -> AxumResult<impl In
         }
     }
 
-    let png_bytes = canvas_to_png_bytes(&canvas)?;
+    let mime_type = encoder.mime_type();
+    let body = stream_image(canvas, encoder).await?;
 
-    Ok((
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "image/png")],
-        png_bytes,
-    ))
+    Ok((StatusCode::OK, [(header::CONTENT_TYPE, mime_type)], body))
 }
 
-fn canvas_to_png_bytes(canvas: &Canvas) -> AxumResult<Vec<u8>> {
-    let bytes = encode_to_png(&canvas.image)
-        .map_err(|e| AxumError::new(eyre!("Failed to encode PNG: {}", e)))?;
+/// Sends encoded chunks of the response body to the async side as they are produced.
+struct ChannelWriter {
+    sender: mpsc::Sender<io::Result<Vec<u8>>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sender
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Response body was dropped"))?;
+
+        Ok(buf.len())
+    }
 
-    Ok(bytes)
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes the canvas on a blocking thread and streams the result as the response body.
+///
+/// Encoders check the image before writing anything, so waiting for the first chunk turns
+/// errors such as a size the format can't store into an error response instead of a
+/// truncated body.
+async fn stream_image(canvas: Canvas, encoder: Arc<dyn ImageEncoder>) -> AxumResult<Body> {
+    let name = encoder.name();
+    let (sender, mut receiver) = mpsc::channel::<io::Result<Vec<u8>>>(8);
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(
+            64 * 1024,
+            ChannelWriter {
+                sender: sender.clone(),
+            },
+        );
+
+        let result = encoder
+            .write(&mut writer, &canvas.rgba_image())
+            .and_then(|_| writer.flush().map_err(Into::into));
+
+        if let Err(e) = result {
+            warn!(error = ?e, format = encoder.name(), "Failed to stream image");
+            let _ = sender.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+
+    let first = match receiver.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => {
+            return Err(AxumError::new(eyre!(
+                "Failed to encode image as {}: {}",
+                name,
+                e
+            )));
+        }
+        None => {
+            return Err(AxumError::new(eyre!(
+                "{} encoder stopped without writing anything",
+                name
+            )));
+        }
+    };
+
+    Ok(Body::from_stream(
+        stream::once(async { Ok(first) }).chain(stream::poll_fn(move |cx| receiver.poll_recv(cx))),
+    ))
 }
@@ -1,17 +1,18 @@
-use img::rgba::Rgba;
+use img::pixel::Pixel;
 
 use crate::Canvas;
 
-impl Canvas {
+impl<P: Pixel> Canvas<P> {
     pub fn draw_filled_arc(
         &mut self,
         cx: u32,
         cy: u32,
         radius: u32,
-        color: Rgba,
+        color: P,
         start_angle: u32,
         end_angle: u32,
     ) -> &mut Self {
+        let color = color.to_rgba_f32();
         let r_sq = (radius * radius) as i32;
 
         for dy in -(radius as i32)..=(radius as i32) {
@@ -31,7 +32,7 @@ impl Canvas {
                         let py = (cy as i32 + dy) as u32;
 
                         if px < self.image.width && py < self.image.height {
-                            self.image.set_pixel(px, py, color);
+                            self.blend_pixel(px, py, color, 1.0);
                         }
                     }
                 }
@@ -41,16 +42,18 @@ impl Canvas {
         self
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_stroke_arc(
         &mut self,
         cx: u32,
         cy: u32,
         radius: u32,
         thickness: u32,
-        color: Rgba,
+        color: P,
         start_angle: u32,
         end_angle: u32,
     ) -> &mut Self {
+        let color = color.to_rgba_f32();
         let outer_r_sq = (radius * radius) as i32;
         let inner_radius = radius.saturating_sub(thickness);
         let inner_r_sq = (inner_radius * inner_radius) as i32;
@@ -72,7 +75,7 @@ impl Canvas {
                         let py = (cy as i32 + dy) as u32;
 
                         if px < self.image.width && py < self.image.height {
-                            self.image.set_pixel(px, py, color);
+                            self.blend_pixel(px, py, color, 1.0);
                         }
                     }
                 }
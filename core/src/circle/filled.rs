@@ -1,9 +1,10 @@
-use img::rgba::Rgba;
+use img::pixel::Pixel;
 
 use crate::Canvas;
 
-impl Canvas {
-    pub fn draw_filled_circle(&mut self, cx: u32, cy: u32, radius: u32, color: Rgba) -> &mut Self {
+impl<P: Pixel> Canvas<P> {
+    pub fn draw_filled_circle(&mut self, cx: u32, cy: u32, radius: u32, color: P) -> &mut Self {
+        let color = color.to_rgba_f32();
         let r_sq = (radius * radius) as i32;
 
         for dy in -(radius as i32)..=(radius as i32) {
@@ -13,7 +14,7 @@ impl Canvas {
                     let py = (cy as i32 + dy) as u32;
 
                     if px < self.image.width && py < self.image.height {
-                        self.image.set_pixel(px, py, color);
+                        self.blend_pixel(px, py, color, 1.0);
                     }
                 }
             }
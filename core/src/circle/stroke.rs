@@ -1,18 +1,18 @@
 use color_eyre::eyre::{Result, eyre};
-use img::rgba::Rgba;
+use img::{pixel::Pixel, rgba::Rgba};
 
 use crate::Canvas;
 
-pub struct StrokeCircle<'a> {
-    canvas: &'a mut Canvas,
+pub struct StrokeCircle<'a, P: Pixel = Rgba> {
+    canvas: &'a mut Canvas<P>,
     cx: u32,
     cy: u32,
     radius: u32,
     thickness: u32,
 }
 
-impl<'a> StrokeCircle<'a> {
-    pub fn new(canvas: &'a mut Canvas, cx: u32, cy: u32, radius: u32, thickness: u32) -> Self {
+impl<'a, P: Pixel> StrokeCircle<'a, P> {
+    pub fn new(canvas: &'a mut Canvas<P>, cx: u32, cy: u32, radius: u32, thickness: u32) -> Self {
         Self {
             canvas,
             cx,
@@ -22,31 +22,33 @@ impl<'a> StrokeCircle<'a> {
         }
     }
 
-    pub fn fill(&mut self, color: Rgba) -> &mut Self {
+    pub fn fill(&mut self, color: P) -> &mut Self {
         self.canvas
             .draw_filled_circle(self.cx, self.cy, self.radius - self.thickness, color);
 
         self
     }
 
-    pub fn to_canvas(&mut self) -> &mut Canvas {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_canvas(&mut self) -> &mut Canvas<P> {
         self.canvas
     }
 }
 
-impl Canvas {
+impl<P: Pixel> Canvas<P> {
     pub fn draw_stroke_circle(
         &mut self,
         cx: u32,
         cy: u32,
         radius: u32,
         thickness: u32,
-        color: Rgba,
-    ) -> Result<StrokeCircle> {
+        color: P,
+    ) -> Result<StrokeCircle<'_, P>> {
         if thickness > radius {
             return Err(eyre!("Stroke thickness can't be bigger than than radius"));
         }
 
+        let color = color.to_rgba_f32();
         let outer_r_sq = (radius * radius) as i32;
         let inner_radius = radius.saturating_sub(thickness);
         let inner_r_sq = (inner_radius * inner_radius) as i32;
@@ -59,7 +61,7 @@ impl Canvas {
                     let py = (cy as i32 + dy) as u32;
 
                     if px < self.image.width && py < self.image.height {
-                        self.image.set_pixel(px, py, color);
+                        self.blend_pixel(px, py, color, 1.0);
                     }
                 }
             }
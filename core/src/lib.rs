@@ -4,46 +4,274 @@ pub mod rect;
 pub mod text;
 
 use color_eyre::eyre::{Ok, Result, eyre};
+use std::borrow::Cow;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+
+use img::composite::{premultiply, unpremultiply};
 
 pub use img::{
-    codecs::{Codecs, encode_to_png, save_png},
+    codecs::{
+        CodecRegistry, Codecs, FilterStrategy, ImageDecoder, ImageEncoder, ImageFormat, PngCodec,
+        PngEncoderOptions, PngMetadata, PnmFormat, decode, encode, encode_to_bmp, encode_to_png,
+        encode_to_png_with, encode_to_png16, encode_to_qoi, load_image, registry, registry_mut,
+        save_bmp, save_farbfeld, save_gif, save_ico, save_image, save_image_with, save_jpeg,
+        save_png, save_pnm, save_qoi, save_tga, save_webp, write_png,
+    },
+    composite::{AlphaMode, CompositeMode, Compositor},
+    image_buffer::{ImageBuffer, Luma8Image, LumaA8Image, Rgba16Image, RgbaF32Image},
+    image_view::{ImageView, ImageViewMut},
+    pixel::{Luma8, LumaA8, Pixel, Rgba8, Rgba16, RgbaF32},
     rgba::Rgba,
     rgba_image::RgbaImage,
 };
 
+/// Drawing surface over an image of any pixel type, 8-bit RGBA unless stated otherwise.
+///
+/// Every draw call blends its color over the existing pixels with the canvas compositor.
+/// Colors passed to draw calls are always straight alpha, whatever the storage.
+#[derive(Debug, Clone)]
+pub struct Canvas<P: Pixel = Rgba> {
+    pub image: ImageBuffer<P>,
+    compositor: Compositor,
+    shape: Option<ShapeMask>,
+}
+
+/// Pixels the shape being drawn has already blended, so shapes made of overlapping parts
+/// blend every pixel once.
 #[derive(Debug, Clone)]
-pub struct Canvas {
-    pub image: RgbaImage,
+struct ShapeMask {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    painted: Vec<bool>,
 }
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Self {
+        Self::from_image(RgbaImage::new(width, height))
+    }
+
+    /// Loads any image the codec registry can decode, the format is detected from the file
+    /// contents.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        Ok(Self::from_image(load_image(path)?))
+    }
+}
+
+impl<P: Pixel> Canvas<P> {
+    /// A canvas over an existing straight alpha image, e.g.
+    /// `Canvas::from_image(RgbaF32Image::new(w, h))` for float drawing.
+    pub fn from_image(image: ImageBuffer<P>) -> Self {
         Self {
-            image: RgbaImage::new(width, height),
+            image,
+            compositor: Compositor::default(),
+            shape: None,
+        }
+    }
+
+    /// Switches how draw calls blend. Changing the alpha mode converts the stored pixels.
+    pub fn with_compositor(mut self, compositor: Compositor) -> Self {
+        match (self.compositor.alpha, compositor.alpha) {
+            (AlphaMode::Straight, AlphaMode::Premultiplied) => premultiply(&mut self.image),
+            (AlphaMode::Premultiplied, AlphaMode::Straight) => unpremultiply(&mut self.image),
+            _ => {}
         }
+
+        self.compositor = compositor;
+        self
+    }
+
+    pub fn compositor(&self) -> Compositor {
+        self.compositor
     }
 
-    pub fn save(&self, path: PathBuf, codec: Codecs) -> Result<()> {
-        let full_path = Path::new("output").join(path);
+    /// Writes to exactly `path`, with the registered encoder for its extension.
+    pub fn save(&self, path: PathBuf) -> Result<()> {
+        let encoder = registry()
+            .encoder_for_path(&path)
+            .ok_or_else(|| eyre!("Can't tell the image format from the path {:?}", path))?;
 
-        if let Some(parent) = full_path.parent() {
+        self.save_with(path, encoder.as_ref())
+    }
+
+    /// Like `save`, but a built-in codec overrides the extension.
+    pub fn save_as(&self, path: PathBuf, codec: Codecs) -> Result<()> {
+        self.save_with(path, codec.encoder().as_ref())
+    }
+
+    /// Like `save`, with any encoder, e.g. a built-in one with non-default options.
+    pub fn save_with(&self, path: PathBuf, encoder: &dyn ImageEncoder) -> Result<()> {
+        if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        match codec {
-            Codecs::PNG => save_png(full_path, &self.image),
+        save_image_with(path, &self.rgba_image(), encoder)
+    }
+
+    /// The image as straight alpha 8-bit RGBA, what the encoders take. Only copied when
+    /// stored as something else.
+    pub fn rgba_image(&self) -> Cow<'_, RgbaImage> {
+        if self.compositor.alpha == AlphaMode::Premultiplied {
+            return Cow::Owned(RgbaImage {
+                width: self.image.width,
+                height: self.image.height,
+                data: self
+                    .image
+                    .data
+                    .iter()
+                    .map(|pixel| Rgba::from_rgba_f32(pixel.to_rgba_f32().unpremultiplied()))
+                    .collect(),
+            });
         }
+
+        self.image.to_rgba8()
     }
 
-    pub fn draw_pixel(&mut self, x: u32, y: u32, color: Rgba) -> Result<&mut Self> {
+    pub fn draw_pixel(&mut self, x: u32, y: u32, color: P) -> Result<&mut Self> {
         if x >= self.image.width || y >= self.image.height {
             return Err(eyre!("X or Y is not in the image bounds"));
         }
 
-        self.image.set_pixel(x, y, color);
+        self.blend_pixel(x, y, color.to_rgba_f32(), 1.0);
 
         Ok(self)
     }
+
+    /// Blends `color` over the pixel, with `coverage` scaling its alpha. The position must be
+    /// in bounds.
+    pub(crate) fn blend_pixel(&mut self, x: u32, y: u32, color: RgbaF32, coverage: f32) {
+        if let Some(mask) = &mut self.shape
+            && (mask.x..mask.x + mask.width).contains(&x)
+            && (mask.y..mask.y + mask.height).contains(&y)
+        {
+            let index = ((y - mask.y) * mask.width + x - mask.x) as usize;
+
+            if mask.painted[index] {
+                return;
+            }
+
+            mask.painted[index] = true;
+        }
+
+        let existing = self.image.get_pixel(x, y);
+        let blended = self.compositor.blend(existing, color, coverage);
+        self.image.set_pixel(x, y, blended);
+    }
+
+    /// Runs `draw` with every pixel in the bounds blended at most once.
+    pub(crate) fn draw_shape(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        draw: impl FnOnce(&mut Self),
+    ) {
+        if self.shape.is_some() {
+            draw(self);
+            return;
+        }
+
+        let width = width.min(self.image.width.saturating_sub(x));
+        let height = height.min(self.image.height.saturating_sub(y));
+
+        self.shape = Some(ShapeMask {
+            x,
+            y,
+            width,
+            height,
+            painted: vec![false; width as usize * height as usize],
+        });
+
+        draw(self);
+
+        self.shape = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgba = Rgba::new(255, 255, 255, 255);
+    const HALF_RED: Rgba = Rgba::new(255, 0, 0, 128);
+
+    fn white(width: u32, height: u32) -> Canvas {
+        let mut canvas = Canvas::new(width, height);
+        canvas.image.fill(WHITE);
+        canvas
+    }
+
+    #[test]
+    fn draw_pixel_blends_over_the_existing_pixel() {
+        let mut canvas = white(1, 1);
+        canvas.draw_pixel(0, 0, HALF_RED).unwrap();
+
+        let pixel = canvas.image.get_pixel(0, 0);
+        assert_eq!((pixel.r, pixel.a), (255, 255));
+        assert!((126..=128).contains(&pixel.g), "{pixel:?}");
+        assert_eq!(pixel.g, pixel.b);
+    }
+
+    #[test]
+    fn draw_pixel_rejects_out_of_bounds() {
+        assert!(white(2, 2).draw_pixel(2, 0, HALF_RED).is_err());
+    }
+
+    #[test]
+    fn stroke_rect_blends_overlapping_sides_once() {
+        let mut once = white(1, 1);
+        once.draw_pixel(0, 0, HALF_RED).unwrap();
+        let expected = once.image.get_pixel(0, 0);
+
+        // Thick enough that the sides cover the whole rect and overlap everywhere
+        let mut canvas = white(6, 6);
+        canvas.draw_stroke_rect(0, 0, 6, 6, 3, HALF_RED).unwrap();
+
+        assert!(canvas.image.data.iter().all(|&pixel| pixel == expected));
+    }
+
+    #[test]
+    fn separate_draws_still_blend_twice() {
+        let mut canvas = white(1, 1);
+        canvas.draw_pixel(0, 0, HALF_RED).unwrap();
+        let once = canvas.image.get_pixel(0, 0);
+        canvas.draw_pixel(0, 0, HALF_RED).unwrap();
+
+        assert!(canvas.image.get_pixel(0, 0).g < once.g);
+    }
+
+    #[test]
+    fn premultiplied_canvas_round_trips_through_rgba_image() {
+        let mut image = RgbaImage::new(3, 1);
+        image.set_pixel(0, 0, Rgba::new(200, 100, 50, 255));
+        // 20% alpha, so the 8-bit premultiplied values are exact
+        image.set_pixel(1, 0, Rgba::new(200, 100, 50, 51));
+        image.set_pixel(2, 0, Rgba::new(0, 0, 0, 0));
+
+        let premultiplied = Compositor::new(CompositeMode::FastSrgb, AlphaMode::Premultiplied);
+        let canvas = Canvas::from_image(image.clone()).with_compositor(premultiplied);
+
+        assert_eq!(canvas.image.get_pixel(1, 0), Rgba::new(40, 20, 10, 51));
+        assert_eq!(canvas.rgba_image().as_ref(), &image);
+        assert_eq!(
+            canvas.with_compositor(Compositor::fast()).image.data,
+            image.data
+        );
+    }
+
+    #[test]
+    fn premultiplied_canvas_draws_like_a_straight_one() {
+        let premultiplied = Compositor::new(CompositeMode::FastSrgb, AlphaMode::Premultiplied);
+        let mut straight = white(4, 4);
+        let mut canvas = white(4, 4).with_compositor(premultiplied);
+
+        for canvas in [&mut straight, &mut canvas] {
+            canvas.draw_filled_rect(1, 1, 2, 2, HALF_RED);
+        }
+
+        assert_eq!(canvas.rgba_image().as_ref(), straight.rgba_image().as_ref());
+    }
 }
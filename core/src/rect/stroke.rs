@@ -1,10 +1,10 @@
 use crate::Canvas;
 
 use color_eyre::eyre::{Result, eyre};
-use img::rgba::Rgba;
+use img::{pixel::Pixel, rgba::Rgba};
 
-pub struct StrokeRect<'a> {
-    canvas: &'a mut Canvas,
+pub struct StrokeRect<'a, P: Pixel = Rgba> {
+    canvas: &'a mut Canvas<P>,
     thickness: u32,
     x: u32,
     y: u32,
@@ -12,9 +12,9 @@ pub struct StrokeRect<'a> {
     height: u32,
 }
 
-impl<'a> StrokeRect<'a> {
+impl<'a, P: Pixel> StrokeRect<'a, P> {
     pub fn new(
-        canvas: &'a mut Canvas,
+        canvas: &'a mut Canvas<P>,
         thickness: u32,
         x: u32,
         y: u32,
@@ -31,7 +31,7 @@ impl<'a> StrokeRect<'a> {
         }
     }
 
-    pub fn fill(&mut self, color: Rgba) {
+    pub fn fill(&mut self, color: P) {
         self.canvas.draw_filled_rect(
             self.x + self.thickness,
             self.y + self.thickness,
@@ -41,12 +41,13 @@ impl<'a> StrokeRect<'a> {
         );
     }
 
-    pub fn to_canvas(&mut self) -> &mut Canvas {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_canvas(&mut self) -> &mut Canvas<P> {
         self.canvas
     }
 }
 
-impl Canvas {
+impl<P: Pixel> Canvas<P> {
     pub fn draw_stroke_rect(
         &mut self,
         x: u32,
@@ -54,20 +55,23 @@ impl Canvas {
         width: u32,
         height: u32,
         thickness: u32,
-        color: Rgba,
-    ) -> Result<StrokeRect> {
+        color: P,
+    ) -> Result<StrokeRect<'_, P>> {
         if thickness > y + height || thickness > x + width {
             return Err(eyre!("Thinkness can't be bigger than the rect itself"));
         }
 
-        self.draw_filled_rect(x, y, width, thickness, color);
-        self.draw_filled_rect(x, y + height - thickness, width, thickness, color);
-        self.draw_filled_rect(x, y, thickness, height, color);
-        self.draw_filled_rect(x + width - thickness, y, thickness, height, color);
+        self.draw_shape(x, y, width, height, |canvas| {
+            canvas.draw_filled_rect(x, y, width, thickness, color);
+            canvas.draw_filled_rect(x, y + height - thickness, width, thickness, color);
+            canvas.draw_filled_rect(x, y, thickness, height, color);
+            canvas.draw_filled_rect(x + width - thickness, y, thickness, height, color);
+        });
 
         Ok(StrokeRect::new(self, thickness, x, y, width, height))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_rounded_stroke_rect(
         &mut self,
         x: u32,
@@ -76,60 +80,62 @@ impl Canvas {
         height: u32,
         thickness: u32,
         radius: u32,
-        color: Rgba,
+        color: P,
     ) -> &mut Self {
         let radius = radius.min(width / 2).min(height / 2);
 
-        if radius * 2 < width {
-            self.draw_filled_rect(x + radius, y, width - 2 * radius, thickness, color);
-            self.draw_filled_rect(
+        self.draw_shape(x, y, width, height, |canvas| {
+            if radius * 2 < width {
+                canvas.draw_filled_rect(x + radius, y, width - 2 * radius, thickness, color);
+                canvas.draw_filled_rect(
+                    x + radius,
+                    y + height - thickness,
+                    width - 2 * radius,
+                    thickness,
+                    color,
+                );
+            }
+
+            if radius * 2 < height {
+                canvas.draw_filled_rect(x, y + radius, thickness, height - 2 * radius, color);
+                canvas.draw_filled_rect(
+                    x + width - thickness,
+                    y + radius,
+                    thickness,
+                    height - 2 * radius,
+                    color,
+                );
+            }
+
+            canvas.draw_stroke_arc(x + radius, y + radius, radius, thickness, color, 180, 270);
+            canvas.draw_stroke_arc(
+                x + width - radius - 1,
+                y + radius,
+                radius,
+                thickness,
+                color,
+                270,
+                360,
+            );
+            canvas.draw_stroke_arc(
                 x + radius,
-                y + height - thickness,
-                width - 2 * radius,
+                y + height - radius - 1,
+                radius,
                 thickness,
                 color,
+                90,
+                180,
             );
-        }
-
-        if radius * 2 < height {
-            self.draw_filled_rect(x, y + radius, thickness, height - 2 * radius, color);
-            self.draw_filled_rect(
-                x + width - thickness,
-                y + radius,
+            canvas.draw_stroke_arc(
+                x + width - radius - 1,
+                y + height - radius - 1,
+                radius,
                 thickness,
-                height - 2 * radius,
                 color,
+                0,
+                90,
             );
-        }
-
-        self.draw_stroke_arc(x + radius, y + radius, radius, thickness, color, 180, 270);
-        self.draw_stroke_arc(
-            x + width - radius - 1,
-            y + radius,
-            radius,
-            thickness,
-            color,
-            270,
-            360,
-        );
-        self.draw_stroke_arc(
-            x + radius,
-            y + height - radius - 1,
-            radius,
-            thickness,
-            color,
-            90,
-            180,
-        );
-        self.draw_stroke_arc(
-            x + width - radius - 1,
-            y + height - radius - 1,
-            radius,
-            thickness,
-            color,
-            0,
-            90,
-        );
+        });
 
         self
     }
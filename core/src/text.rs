@@ -1,10 +1,10 @@
 use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
 use color_eyre::eyre::{Result, eyre};
-use img::rgba::Rgba;
+use img::pixel::Pixel;
 
 use crate::Canvas;
 
-impl Canvas {
+impl<P: Pixel> Canvas<P> {
     pub fn draw_text(
         &mut self,
         text: &str,
@@ -12,10 +12,11 @@ impl Canvas {
         y: u32,
         font_data: &[u8],
         font_size: f32,
-        color: Rgba,
+        color: P,
     ) -> Result<&mut Self> {
         let font = FontRef::try_from_slice(font_data).map_err(|e| eyre!("Font is invalid: {e}"))?;
 
+        let color = color.to_rgba_f32();
         let scale = PxScale::from(font_size);
         let scaled_font = font.as_scaled(scale);
 
@@ -39,13 +40,7 @@ impl Canvas {
                             && (pixel_x as u32) < self.image.width
                             && (pixel_y as u32) < self.image.height
                         {
-                            let a = (coverage * color.a as f32) as u8;
-                            let text_color = Rgba { a, ..color };
-
-                            let existing = self.image.get_pixel(pixel_x as u32, pixel_y as u32);
-                            let blended = blend_colors(existing, text_color);
-                            self.image
-                                .set_pixel(pixel_x as u32, pixel_y as u32, blended);
+                            self.blend_pixel(pixel_x as u32, pixel_y as u32, color, coverage);
                         }
                     }
                 });
@@ -57,34 +52,3 @@ impl Canvas {
         Ok(self)
     }
 }
-
-fn blend_colors(bg: Rgba, fg: Rgba) -> Rgba {
-    if bg.a == 0 {
-        return fg;
-    }
-
-    if fg.a == 0 {
-        return bg;
-    }
-
-    let fg_alpha = fg.a as f32 / 255.0;
-    let bg_alpha = bg.a as f32 / 255.0;
-
-    let alpha = fg_alpha + bg_alpha * (1.0 - fg_alpha);
-
-    if alpha == 0.0 {
-        return Rgba {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        };
-    }
-
-    Rgba {
-        r: ((fg.r as f32 * fg_alpha + bg.r as f32 * bg_alpha * (1.0 - fg_alpha)) / alpha) as u8,
-        g: ((fg.g as f32 * fg_alpha + bg.g as f32 * bg_alpha * (1.0 - fg_alpha)) / alpha) as u8,
-        b: ((fg.b as f32 * fg_alpha + bg.b as f32 * bg_alpha * (1.0 - fg_alpha)) / alpha) as u8,
-        a: (alpha * 255.0) as u8,
-    }
-}
@@ -37,7 +37,7 @@ fn generate_test_image() -> Result<()> {
         },
     )?;
 
-    canvas.save(Path::new("test.png").to_path_buf(), imagen::Codecs::PNG)?;
+    canvas.save(Path::new("output/test.png").to_path_buf())?;
 
     Ok(())
 }
@@ -47,7 +47,7 @@ fn generate_rect() -> Result<()> {
 
     canvas.draw_filled_rect(20, 10, 50, 50, (200, 200, 30).into());
 
-    canvas.save(Path::new("rect.png").to_path_buf(), imagen::Codecs::PNG)?;
+    canvas.save(Path::new("output/rect.png").to_path_buf())?;
 
     Ok(())
 }
@@ -57,10 +57,7 @@ fn generate_stroke_rect() -> Result<()> {
 
     canvas.draw_stroke_rect(20, 10, 50, 50, 10, (200, 200, 30).into())?;
 
-    canvas.save(
-        Path::new("stroke_rect.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/stroke_rect.png").to_path_buf())?;
 
     Ok(())
 }
@@ -72,10 +69,7 @@ fn generate_filled_rect() -> Result<()> {
         .draw_stroke_rect(20, 10, 50, 50, 10, (200, 200, 30).into())?
         .fill((200, 30, 200).into());
 
-    canvas.save(
-        Path::new("filled_rect.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/filled_rect.png").to_path_buf())?;
 
     Ok(())
 }
@@ -85,10 +79,7 @@ fn generate_rounded_rect() -> Result<()> {
 
     canvas.draw_rounded_filled_rect(10, 10, 150, 150, 30, (200, 200, 30).into());
 
-    canvas.save(
-        Path::new("rounded_rect.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/rounded_rect.png").to_path_buf())?;
 
     Ok(())
 }
@@ -98,10 +89,7 @@ fn generate_rounded_stroke_rect() -> Result<()> {
 
     canvas.draw_rounded_stroke_rect(10, 10, 150, 150, 20, 30, (200, 200, 30).into());
 
-    canvas.save(
-        Path::new("rounded_stroke_rect.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/rounded_stroke_rect.png").to_path_buf())?;
 
     Ok(())
 }
@@ -114,7 +102,7 @@ fn generate_circle() -> Result<()> {
         .draw_rounded_stroke_rect(10, 10, 30, 30, 5, 5, (200, 200, 30).into())
         .draw_text("TEST", 50, 50, FONT, 96.0, (255, 255, 255).into())?;
 
-    canvas.save(Path::new("circle.png").to_path_buf(), imagen::Codecs::PNG)?;
+    canvas.save(Path::new("output/circle.png").to_path_buf())?;
 
     Ok(())
 }
@@ -124,10 +112,7 @@ fn generate_stroke_circle() -> Result<()> {
 
     canvas.draw_stroke_circle(150, 100, 80, 10, (200, 200, 30).into())?;
 
-    canvas.save(
-        Path::new("stroke_circle.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/stroke_circle.png").to_path_buf())?;
 
     Ok(())
 }
@@ -141,10 +126,7 @@ fn generate_filled_stroke_circle() -> Result<()> {
         .to_canvas()
         .draw_text("testasfdasfasf", 50, 50, FONT, 48.0, (0, 0, 0).into())?;
 
-    canvas.save(
-        Path::new("filled_circle.png").to_path_buf(),
-        imagen::Codecs::PNG,
-    )?;
+    canvas.save(Path::new("output/filled_circle.png").to_path_buf())?;
 
     Ok(())
 }
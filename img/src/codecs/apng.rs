@@ -0,0 +1,546 @@
+use color_eyre::eyre::{Result, eyre};
+use std::borrow::Cow;
+
+use crate::{
+    codecs::png::{
+        ColorType, EncodedFormat, PNG_HEADER, PngEncoderOptions, chunk_wrapper, idat_chunk,
+        ihdr_chunk,
+    },
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+/// What happens to the frame's region before the next frame is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisposeOp {
+    /// Leave the canvas as it is
+    #[default]
+    None = 0,
+    /// Clear the frame's region to transparent black
+    Background = 1,
+    /// Restore the region to what it was before this frame
+    Previous = 2,
+}
+
+/// How the frame is combined with the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendOp {
+    /// Overwrite the region, including alpha
+    #[default]
+    Source = 0,
+    /// Alpha composite the frame over the canvas
+    Over = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApngFrame {
+    /// Full canvas sized image for this frame
+    pub image: RgbaImage,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl ApngFrame {
+    pub fn new(image: RgbaImage, delay_ms: u16) -> Self {
+        Self {
+            image,
+            delay_num: delay_ms,
+            delay_den: 1000,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    pub fn with_dispose_op(mut self, dispose_op: DisposeOp) -> Self {
+        self.dispose_op = dispose_op;
+        self
+    }
+
+    pub fn with_blend_op(mut self, blend_op: BlendOp) -> Self {
+        self.blend_op = blend_op;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApngEncoderOptions {
+    pub png: PngEncoderOptions,
+    /// Number of times to play the animation, 0 means forever
+    pub num_plays: u32,
+    /// Store only the region that changed compared to the previous frame
+    pub crop_frames: bool,
+}
+
+impl Default for ApngEncoderOptions {
+    fn default() -> Self {
+        Self {
+            png: PngEncoderOptions::default(),
+            num_plays: 0,
+            crop_frames: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// A frame as it is going to be written: its region, pixels and control fields.
+struct EncodedFrame<'a> {
+    region: Region,
+    image: Cow<'a, RgbaImage>,
+    delay_num: u16,
+    delay_den: u16,
+    dispose_op: DisposeOp,
+    blend_op: BlendOp,
+}
+
+pub fn encode_to_apng(frames: &[ApngFrame], options: &ApngEncoderOptions) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| eyre!("APNG needs at least one frame"))?;
+    let (width, height) = (first.image.width, first.image.height);
+
+    if width == 0 || height == 0 {
+        return Err(eyre!("APNG width and height must be greater than 0"));
+    }
+
+    if let Some(frame) = frames
+        .iter()
+        .find(|frame| frame.image.width != width || frame.image.height != height)
+    {
+        return Err(eyre!(
+            "All APNG frames must be {}x{}, got a {}x{} frame",
+            width,
+            height,
+            frame.image.width,
+            frame.image.height
+        ));
+    }
+
+    let encoded_frames = if options.crop_frames {
+        cropped_frames(frames)
+    } else {
+        frames
+            .iter()
+            .map(|frame| EncodedFrame {
+                region: full_region(&frame.image),
+                image: Cow::Borrowed(&frame.image),
+                delay_num: frame.delay_num,
+                delay_den: frame.delay_den,
+                dispose_op: frame.dispose_op,
+                blend_op: frame.blend_op,
+            })
+            .collect()
+    };
+
+    let format = if options.png.optimize_color_type {
+        EncodedFormat::analyze_all(encoded_frames.iter().map(|frame| frame.image.as_ref()))
+    } else {
+        EncodedFormat::rgba()
+    };
+
+    let mut output: Vec<u8> = vec![];
+
+    output.extend_from_slice(&PNG_HEADER);
+
+    let ihdr = chunk_wrapper(
+        b"IHDR",
+        &ihdr_chunk(width, height, format.bit_depth, format.color_type),
+    );
+    output.extend_from_slice(&ihdr);
+
+    let actl = chunk_wrapper(b"acTL", &actl_chunk(frames.len() as u32, options.num_plays));
+    output.extend_from_slice(&actl);
+
+    if format.color_type == ColorType::Indexed {
+        let plte = chunk_wrapper(b"PLTE", &format.plte_chunk());
+        output.extend_from_slice(&plte);
+
+        let trns_data = format.trns_chunk();
+        if !trns_data.is_empty() {
+            let trns = chunk_wrapper(b"tRNS", &trns_data);
+            output.extend_from_slice(&trns);
+        }
+    }
+
+    let mut sequence_number = 0u32;
+
+    for (index, frame) in encoded_frames.iter().enumerate() {
+        let fctl = chunk_wrapper(b"fcTL", &fctl_chunk(sequence_number, frame));
+        output.extend_from_slice(&fctl);
+        sequence_number += 1;
+
+        let data = idat_chunk(&frame.image, &format, &options.png)?;
+
+        // The first frame doubles as the default image shown by decoders without APNG support.
+        if index == 0 {
+            output.extend_from_slice(&chunk_wrapper(b"IDAT", &data));
+        } else {
+            let mut fdat: Vec<u8> = Vec::with_capacity(data.len() + 4);
+            fdat.extend_from_slice(&sequence_number.to_be_bytes());
+            fdat.extend_from_slice(&data);
+            output.extend_from_slice(&chunk_wrapper(b"fdAT", &fdat));
+            sequence_number += 1;
+        }
+    }
+
+    let iend = chunk_wrapper(b"IEND", &[]);
+    output.extend_from_slice(&iend);
+
+    Ok(output)
+}
+
+/// Renders every frame with its own dispose and blend ops, then stores each one as the
+/// region that differs from the previously displayed image.
+///
+/// The stored frames always use `DisposeOp::None` and `BlendOp::Source`, so the canvas a
+/// decoder holds before frame N is exactly what frame N - 1 displayed.
+fn cropped_frames(frames: &[ApngFrame]) -> Vec<EncodedFrame<'_>> {
+    let first = &frames[0].image;
+    let mut canvas = RgbaImage::new(first.width, first.height);
+    let mut previous: Option<RgbaImage> = None;
+    let mut encoded_frames: Vec<EncodedFrame> = Vec::with_capacity(frames.len());
+
+    for (index, frame) in frames.iter().enumerate() {
+        let before = canvas.clone();
+
+        for (current, &new) in canvas.data.iter_mut().zip(&frame.image.data) {
+            *current = blend(*current, new, frame.blend_op);
+        }
+
+        let displayed = canvas.clone();
+
+        let region = match &previous {
+            Some(previous) => changed_region(previous, &displayed),
+            None => full_region(&displayed),
+        };
+
+        encoded_frames.push(EncodedFrame {
+            region,
+            image: Cow::Owned(crop(&displayed, region)),
+            delay_num: frame.delay_num,
+            delay_den: frame.delay_den,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        });
+
+        match frame.dispose_op {
+            DisposeOp::None => {}
+            // A first frame disposed to "previous" is treated as "background".
+            DisposeOp::Previous if index > 0 => canvas = before,
+            DisposeOp::Background | DisposeOp::Previous => {
+                canvas.data.fill(Rgba::default());
+            }
+        }
+
+        previous = Some(displayed);
+    }
+
+    encoded_frames
+}
+
+fn actl_chunk(num_frames: u32, num_plays: u32) -> [u8; 8] {
+    let mut data = [0u8; 8];
+
+    data[0..4].copy_from_slice(&num_frames.to_be_bytes());
+    data[4..8].copy_from_slice(&num_plays.to_be_bytes());
+
+    data
+}
+
+fn fctl_chunk(sequence_number: u32, frame: &EncodedFrame) -> [u8; 26] {
+    let mut data = [0u8; 26];
+
+    data[0..4].copy_from_slice(&sequence_number.to_be_bytes());
+    data[4..8].copy_from_slice(&frame.region.width.to_be_bytes());
+    data[8..12].copy_from_slice(&frame.region.height.to_be_bytes());
+    data[12..16].copy_from_slice(&frame.region.x.to_be_bytes());
+    data[16..20].copy_from_slice(&frame.region.y.to_be_bytes());
+    data[20..22].copy_from_slice(&frame.delay_num.to_be_bytes());
+    data[22..24].copy_from_slice(&frame.delay_den.to_be_bytes());
+    data[24] = frame.dispose_op as u8;
+    data[25] = frame.blend_op as u8;
+
+    data
+}
+
+fn full_region(image: &RgbaImage) -> Region {
+    Region {
+        x: 0,
+        y: 0,
+        width: image.width,
+        height: image.height,
+    }
+}
+
+/// Bounding box of the pixels that differ between two images of the same size.
+fn changed_region(previous: &RgbaImage, current: &RgbaImage) -> Region {
+    let width = current.width;
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+
+    for (index, (old, new)) in previous.data.iter().zip(&current.data).enumerate() {
+        if old == new {
+            continue;
+        }
+
+        let x = index as u32 % width;
+        let y = index as u32 / width;
+
+        bounds = Some(match bounds {
+            Some((min_x, min_y, max_x, max_y)) => {
+                (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+            }
+            None => (x, y, x, y),
+        });
+    }
+
+    match bounds {
+        Some((min_x, min_y, max_x, max_y)) => Region {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+        },
+        // Frames can't be empty, so an unchanged frame still stores a single pixel.
+        None => Region {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        },
+    }
+}
+
+fn crop(image: &RgbaImage, region: Region) -> RgbaImage {
+    let mut cropped = RgbaImage::new(region.width, region.height);
+
+    for y in 0..region.height {
+        let start = ((region.y + y) * image.width + region.x) as usize;
+        let row = &image.data[start..start + region.width as usize];
+
+        let out_start = (y * region.width) as usize;
+        cropped.data[out_start..out_start + region.width as usize].copy_from_slice(row);
+    }
+
+    cropped
+}
+
+/// Integer blending as described by the APNG specification.
+fn blend(dst: Rgba, src: Rgba, blend_op: BlendOp) -> Rgba {
+    if blend_op == BlendOp::Source || src.a == 255 {
+        return src;
+    }
+
+    if src.a == 0 {
+        return dst;
+    }
+
+    if dst.a == 0 {
+        return src;
+    }
+
+    let u = src.a as u32 * 255;
+    let v = (255 - src.a as u32) * dst.a as u32;
+    let alpha = u + v;
+    let mix = |s: u8, d: u8| ((s as u32 * u + d as u32 * v) / alpha) as u8;
+
+    Rgba::new(
+        mix(src.r, dst.r),
+        mix(src.g, dst.g),
+        mix(src.b, dst.b),
+        (alpha / 255) as u8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::png::{decode_png, decoder::read_chunks};
+
+    const RED: Rgba = Rgba::new(255, 0, 0, 255);
+    const BLUE: Rgba = Rgba::new(0, 0, 255, 255);
+    const GREEN: Rgba = Rgba::new(0, 255, 0, 255);
+
+    /// Plays the animation back, decoding every frame as a standalone PNG.
+    fn render(data: &[u8]) -> Vec<RgbaImage> {
+        let chunks = read_chunks(data).unwrap();
+        let ihdr = chunks[0].data;
+        let width = u32::from_be_bytes(ihdr[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(ihdr[4..8].try_into().unwrap());
+
+        let palette_chunks: Vec<u8> = chunks
+            .iter()
+            .filter(|chunk| matches!(&chunk.chunk_type, b"PLTE" | b"tRNS"))
+            .flat_map(|chunk| chunk_wrapper(&chunk.chunk_type, chunk.data))
+            .collect();
+
+        let mut canvas = RgbaImage::new(width, height);
+        let mut displayed = vec![];
+        let mut region = full_region(&canvas);
+
+        for chunk in &chunks {
+            let data = match &chunk.chunk_type {
+                b"fcTL" => {
+                    let field = |start: usize| {
+                        u32::from_be_bytes(chunk.data[start..start + 4].try_into().unwrap())
+                    };
+                    region = Region {
+                        width: field(4),
+                        height: field(8),
+                        x: field(12),
+                        y: field(16),
+                    };
+                    assert_eq!(&chunk.data[24..26], [0, 0]);
+                    continue;
+                }
+                b"IDAT" => chunk.data,
+                b"fdAT" => &chunk.data[4..],
+                _ => continue,
+            };
+
+            let frame_ihdr = ihdr_chunk(
+                region.width,
+                region.height,
+                ihdr[8],
+                ColorType::try_from(ihdr[9]).unwrap(),
+            );
+            let frame = decode_png(
+                &[
+                    PNG_HEADER.to_vec(),
+                    chunk_wrapper(b"IHDR", &frame_ihdr),
+                    palette_chunks.clone(),
+                    chunk_wrapper(b"IDAT", data),
+                    chunk_wrapper(b"IEND", &[]),
+                ]
+                .concat(),
+            )
+            .unwrap();
+
+            for y in 0..region.height {
+                for x in 0..region.width {
+                    canvas.set_pixel(region.x + x, region.y + y, frame.get_pixel(x, y));
+                }
+            }
+
+            displayed.push(canvas.clone());
+        }
+
+        displayed
+    }
+
+    fn frames() -> Vec<ApngFrame> {
+        let red = RgbaImage::from_pixel(6, 5, RED);
+
+        let mut blue_square = RgbaImage::new(6, 5);
+        blue_square.crop_to_view_mut(1, 1, 2, 2).unwrap().fill(BLUE);
+
+        let mut green_dot = RgbaImage::new(6, 5);
+        green_dot.set_pixel(4, 3, GREEN);
+
+        vec![
+            ApngFrame::new(red, 100),
+            ApngFrame::new(blue_square, 100)
+                .with_blend_op(BlendOp::Over)
+                .with_dispose_op(DisposeOp::Previous),
+            ApngFrame::new(green_dot, 100).with_blend_op(BlendOp::Over),
+        ]
+    }
+
+    fn expected() -> Vec<RgbaImage> {
+        let first = RgbaImage::from_pixel(6, 5, RED);
+
+        let mut second = first.clone();
+        second.crop_to_view_mut(1, 1, 2, 2).unwrap().fill(BLUE);
+
+        let mut third = first.clone();
+        third.set_pixel(4, 3, GREEN);
+
+        vec![first, second, third]
+    }
+
+    #[test]
+    fn plays_back_the_rendered_frames() {
+        for crop_frames in [true, false] {
+            let options = ApngEncoderOptions {
+                crop_frames,
+                ..Default::default()
+            };
+            let frames = if crop_frames {
+                frames()
+            } else {
+                // Uncropped frames keep their own ops, so give them fully rendered images
+                expected()
+                    .into_iter()
+                    .map(|image| ApngFrame::new(image, 100))
+                    .collect()
+            };
+
+            assert_eq!(
+                render(&encode_to_apng(&frames, &options).unwrap()),
+                expected()
+            );
+        }
+    }
+
+    #[test]
+    fn first_frame_is_the_default_image() {
+        let data = encode_to_apng(&frames(), &ApngEncoderOptions::default()).unwrap();
+
+        assert_eq!(decode_png(&data).unwrap(), expected()[0]);
+    }
+
+    #[test]
+    fn numbers_chunks_in_sequence() {
+        let data = encode_to_apng(&frames(), &ApngEncoderOptions::default()).unwrap();
+        let chunks = read_chunks(&data).unwrap();
+
+        let actl = chunks
+            .iter()
+            .find(|chunk| &chunk.chunk_type == b"acTL")
+            .unwrap();
+        assert_eq!(actl.data, actl_chunk(3, 0));
+
+        let sequence: Vec<u32> = chunks
+            .iter()
+            .filter(|chunk| matches!(&chunk.chunk_type, b"fcTL" | b"fdAT"))
+            .map(|chunk| u32::from_be_bytes(chunk.data[0..4].try_into().unwrap()))
+            .collect();
+        assert_eq!(sequence, (0..5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stores_only_changed_regions() {
+        let mut previous = RgbaImage::new(5, 5);
+        let mut current = previous.clone();
+        current.set_pixel(1, 2, RED);
+        current.set_pixel(3, 4, RED);
+
+        let region = changed_region(&previous, &current);
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (1, 2, 3, 3)
+        );
+
+        previous = current.clone();
+        let region = changed_region(&previous, &current);
+        assert_eq!((region.width, region.height), (1, 1));
+    }
+
+    #[test]
+    fn rejects_mismatched_frames() {
+        let frames = [
+            ApngFrame::new(RgbaImage::new(2, 2), 10),
+            ApngFrame::new(RgbaImage::new(3, 2), 10),
+        ];
+
+        assert!(encode_to_apng(&frames, &ApngEncoderOptions::default()).is_err());
+        assert!(encode_to_apng(&[], &ApngEncoderOptions::default()).is_err());
+    }
+}
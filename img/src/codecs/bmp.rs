@@ -0,0 +1,794 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+const V5_HEADER_SIZE: usize = 124;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// `LCS_sRGB`, written as the color space type of V5 headers.
+const LCS_SRGB: u32 = 0x7352_4742;
+const LCS_GM_IMAGES: u32 = 4;
+/// 72 DPI
+const PIXELS_PER_METER: i32 = 2835;
+const BMP_PIXELS_MAX: u64 = 400_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BmpFormat {
+    /// 24-bit BGR with a BITMAPINFOHEADER, alpha is dropped
+    Bgr24,
+    /// 32-bit BGRA with a BITMAPV5HEADER and an alpha mask
+    Bgra32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BmpEncoderOptions {
+    /// Picked from the image alpha when `None`
+    pub format: Option<BmpFormat>,
+    /// Store rows top to bottom (negative height) instead of the usual bottom-up order
+    pub top_down: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Masks {
+    red: u32,
+    green: u32,
+    blue: u32,
+    alpha: u32,
+}
+
+#[derive(Debug)]
+struct Header {
+    width: u32,
+    height: u32,
+    top_down: bool,
+    bits_per_pixel: u16,
+    compression: u32,
+    masks: Option<Masks>,
+    palette: Vec<Rgba>,
+    /// Where pixel data starts when it directly follows the header and palette, as in a DIB
+    pixels_start: usize,
+}
+
+pub fn encode_to_bmp(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_to_bmp_with(image, &BmpEncoderOptions::default())
+}
+
+pub fn encode_to_bmp_with(image: &RgbaImage, options: &BmpEncoderOptions) -> Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("BMP width and height must be greater than 0"));
+    }
+
+    if image.width > i32::MAX as u32 || image.height > i32::MAX as u32 {
+        return Err(eyre!(
+            "BMP width and height must fit in a signed 32-bit value"
+        ));
+    }
+
+    let format = options.format.unwrap_or_else(|| {
+        if image.data.iter().all(|pixel| pixel.a == 255) {
+            BmpFormat::Bgr24
+        } else {
+            BmpFormat::Bgra32
+        }
+    });
+
+    let (header_size, bits_per_pixel) = match format {
+        BmpFormat::Bgr24 => (INFO_HEADER_SIZE, 24),
+        BmpFormat::Bgra32 => (V5_HEADER_SIZE, 32),
+    };
+
+    let row_size = row_size(image.width, bits_per_pixel);
+    let image_size = row_size * image.height as usize;
+    let pixel_offset = FILE_HEADER_SIZE + header_size;
+    let file_size = pixel_offset + image_size;
+
+    if file_size > u32::MAX as usize {
+        return Err(eyre!("BMP files can't be larger than 4 GiB"));
+    }
+
+    let height = if options.top_down {
+        -(image.height as i32)
+    } else {
+        image.height as i32
+    };
+
+    let mut output: Vec<u8> = Vec::with_capacity(file_size);
+
+    output.extend_from_slice(b"BM");
+    output.extend_from_slice(&(file_size as u32).to_le_bytes());
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+
+    output.extend_from_slice(&(header_size as u32).to_le_bytes());
+    output.extend_from_slice(&(image.width as i32).to_le_bytes());
+    output.extend_from_slice(&height.to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&bits_per_pixel.to_le_bytes());
+
+    let compression = match format {
+        BmpFormat::Bgr24 => BI_RGB,
+        BmpFormat::Bgra32 => BI_BITFIELDS,
+    };
+    output.extend_from_slice(&compression.to_le_bytes());
+    output.extend_from_slice(&(image_size as u32).to_le_bytes());
+    output.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    output.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes());
+
+    if format == BmpFormat::Bgra32 {
+        for mask in [0x00FF_0000u32, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000] {
+            output.extend_from_slice(&mask.to_le_bytes());
+        }
+        output.extend_from_slice(&LCS_SRGB.to_le_bytes());
+        // Endpoints and gamma are unused for sRGB.
+        output.extend_from_slice(&[0; 36 + 12]);
+        output.extend_from_slice(&LCS_GM_IMAGES.to_le_bytes());
+        // Profile data, profile size and reserved.
+        output.extend_from_slice(&[0; 12]);
+    }
+
+    let padding = row_size - image.width as usize * (bits_per_pixel as usize / 8);
+
+    for y in 0..image.height {
+        let y = if options.top_down {
+            y
+        } else {
+            image.height - 1 - y
+        };
+        let start = (y * image.width) as usize;
+
+        for pixel in &image.data[start..start + image.width as usize] {
+            match format {
+                BmpFormat::Bgr24 => output.extend_from_slice(&[pixel.b, pixel.g, pixel.r]),
+                BmpFormat::Bgra32 => {
+                    output.extend_from_slice(&[pixel.b, pixel.g, pixel.r, pixel.a])
+                }
+            }
+        }
+
+        output.extend(std::iter::repeat_n(0, padding));
+    }
+
+    Ok(output)
+}
+
+pub fn decode_bmp(data: &[u8]) -> Result<RgbaImage> {
+    if data.len() < FILE_HEADER_SIZE || &data[0..2] != b"BM" {
+        return Err(eyre!("Data is not a BMP file"));
+    }
+
+    let pixel_offset = read_u32(data, 10)? as usize;
+    let header = parse_header(data, FILE_HEADER_SIZE)?;
+
+    let pixels = data
+        .get(pixel_offset..)
+        .ok_or_else(|| eyre!("BMP pixel data offset is past the end of the file"))?;
+
+    decode_pixels(&header, pixels)
+}
+
+/// Encodes a headerless 32-bit DIB the way ICO and CUR entries store it: the height covers
+/// the BGRA rows plus a 1-bit AND mask, which marks fully transparent pixels for readers
+/// that ignore alpha.
+pub fn encode_dib(image: &RgbaImage) -> Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("BMP width and height must be greater than 0"));
+    }
+
+    if image.width > i32::MAX as u32 || image.height > i32::MAX as u32 / 2 {
+        return Err(eyre!(
+            "BMP width and height must fit in a signed 32-bit value"
+        ));
+    }
+
+    let width = image.width as usize;
+    let mask_row_size = row_size(image.width, 1);
+    let image_size = (width * 4 + mask_row_size) * image.height as usize;
+
+    let mut output: Vec<u8> = Vec::with_capacity(INFO_HEADER_SIZE + image_size);
+
+    output.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    output.extend_from_slice(&(image.width as i32).to_le_bytes());
+    output.extend_from_slice(&(image.height as i32 * 2).to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&32u16.to_le_bytes());
+    output.extend_from_slice(&BI_RGB.to_le_bytes());
+    output.extend_from_slice(&(image_size as u32).to_le_bytes());
+    // Resolution and palette counts are unused in icons.
+    output.extend_from_slice(&[0; 16]);
+
+    for row in image.data.chunks_exact(width).rev() {
+        for pixel in row {
+            output.extend_from_slice(&[pixel.b, pixel.g, pixel.r, pixel.a]);
+        }
+    }
+
+    for row in image.data.chunks_exact(width).rev() {
+        let mut mask = vec![0u8; mask_row_size];
+
+        for (x, pixel) in row.iter().enumerate() {
+            if pixel.a == 0 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+
+        output.extend_from_slice(&mask);
+    }
+
+    Ok(output)
+}
+
+/// Decodes a headerless DIB from an ICO or CUR entry. The AND mask after the color rows
+/// provides transparency, unless 32-bit pixels carry their own alpha.
+pub fn decode_dib(data: &[u8]) -> Result<RgbaImage> {
+    let mut header = parse_header(data, 0)?;
+
+    if header.height % 2 != 0 {
+        return Err(eyre!(
+            "Icon bitmap height must cover the image and its mask, got {}",
+            header.height
+        ));
+    }
+    header.height /= 2;
+
+    // The fourth byte of 32-bit icon pixels is alpha, even without an alpha mask
+    if header.bits_per_pixel == 32 && header.compression == BI_RGB {
+        header.masks = header.masks.map(|masks| Masks {
+            alpha: 0xFF00_0000,
+            ..masks
+        });
+    }
+
+    let pixels = data
+        .get(header.pixels_start..)
+        .ok_or_else(|| eyre!("BMP pixel data is truncated"))?;
+    let mut image = decode_pixels(&header, pixels)?;
+
+    let has_alpha = header.masks.is_some_and(|masks| masks.alpha != 0)
+        && image.data.iter().any(|pixel| pixel.a != 0);
+
+    if has_alpha || matches!(header.compression, BI_RLE8 | BI_RLE4) {
+        return Ok(image);
+    }
+
+    for pixel in image.data.iter_mut() {
+        pixel.a = 255;
+    }
+
+    // Some writers leave the mask out, the image is then fully opaque
+    let width = header.width as usize;
+    let mask_row_size = row_size(header.width, 1);
+    let mask_start = row_size(header.width, header.bits_per_pixel) * header.height as usize;
+
+    if let Some(mask) = pixels.get(mask_start..mask_start + mask_row_size * header.height as usize)
+    {
+        for (row_index, row) in mask.chunks_exact(mask_row_size).enumerate() {
+            let y = if header.top_down {
+                row_index
+            } else {
+                header.height as usize - 1 - row_index
+            };
+
+            for (x, pixel) in image.data[y * width..(y + 1) * width]
+                .iter_mut()
+                .enumerate()
+            {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    pixel.a = 0;
+                }
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+fn parse_header(data: &[u8], start: usize) -> Result<Header> {
+    let header_size = read_u32(data, start)? as usize;
+
+    let (width, height, bits_per_pixel, compression, colors_used) =
+        if header_size == CORE_HEADER_SIZE {
+            (
+                read_u16(data, start + 4)? as i32,
+                read_u16(data, start + 6)? as i16 as i32,
+                read_u16(data, start + 10)?,
+                BI_RGB,
+                0,
+            )
+        } else if header_size >= INFO_HEADER_SIZE {
+            (
+                read_u32(data, start + 4)? as i32,
+                read_u32(data, start + 8)? as i32,
+                read_u16(data, start + 14)?,
+                read_u32(data, start + 16)?,
+                read_u32(data, start + 32)?,
+            )
+        } else {
+            return Err(eyre!("Unsupported BMP header size: {}", header_size));
+        };
+
+    if width <= 0 || height == 0 {
+        return Err(eyre!("Invalid BMP dimensions: {}x{}", width, height));
+    }
+
+    let top_down = height < 0;
+    let (width, height) = (width as u32, height.unsigned_abs());
+
+    if width as u64 * height as u64 > BMP_PIXELS_MAX {
+        return Err(eyre!("BMP image is too large: {}x{}", width, height));
+    }
+
+    if !matches!(bits_per_pixel, 1 | 4 | 8 | 16 | 24 | 32) {
+        return Err(eyre!("Unsupported BMP bit depth: {}", bits_per_pixel));
+    }
+
+    let valid_compression = match compression {
+        BI_RGB => true,
+        BI_RLE8 => bits_per_pixel == 8,
+        BI_RLE4 => bits_per_pixel == 4,
+        BI_BITFIELDS | BI_ALPHABITFIELDS => matches!(bits_per_pixel, 16 | 32),
+        _ => false,
+    };
+
+    if !valid_compression {
+        return Err(eyre!(
+            "Unsupported BMP compression {} for {}-bit images",
+            compression,
+            bits_per_pixel
+        ));
+    }
+
+    if top_down && matches!(compression, BI_RLE8 | BI_RLE4) {
+        return Err(eyre!("RLE compressed BMP images can't be top-down"));
+    }
+
+    let masks = match compression {
+        BI_BITFIELDS | BI_ALPHABITFIELDS => {
+            // Masks are part of V2+ headers, but follow a plain BITMAPINFOHEADER.
+            let masks_start = start + 40;
+            let has_alpha =
+                compression == BI_ALPHABITFIELDS || header_size >= INFO_HEADER_SIZE + 16;
+
+            Some(Masks {
+                red: read_u32(data, masks_start)?,
+                green: read_u32(data, masks_start + 4)?,
+                blue: read_u32(data, masks_start + 8)?,
+                alpha: if has_alpha {
+                    read_u32(data, masks_start + 12)?
+                } else {
+                    0
+                },
+            })
+        }
+        _ => match bits_per_pixel {
+            16 => Some(Masks {
+                red: 0x7C00,
+                green: 0x03E0,
+                blue: 0x001F,
+                alpha: 0,
+            }),
+            32 => Some(Masks {
+                red: 0x00FF_0000,
+                green: 0x0000_FF00,
+                blue: 0x0000_00FF,
+                alpha: 0,
+            }),
+            _ => None,
+        },
+    };
+
+    let masks_size = match (header_size, compression) {
+        (INFO_HEADER_SIZE, BI_BITFIELDS) => 12,
+        (INFO_HEADER_SIZE, BI_ALPHABITFIELDS) => 16,
+        _ => 0,
+    };
+    let palette_start = start + header_size + masks_size;
+    let mut pixels_start = palette_start;
+
+    let palette = if bits_per_pixel <= 8 {
+        let max_colors = 1usize << bits_per_pixel;
+        let count = match colors_used as usize {
+            0 => max_colors,
+            count => count.min(max_colors),
+        };
+        let entry_size = if header_size == CORE_HEADER_SIZE {
+            3
+        } else {
+            4
+        };
+        pixels_start += count * entry_size;
+
+        let palette_data = data
+            .get(palette_start..pixels_start)
+            .ok_or_else(|| eyre!("BMP palette is truncated"))?;
+
+        palette_data
+            .chunks_exact(entry_size)
+            .map(|entry| Rgba::new(entry[2], entry[1], entry[0], 255))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    Ok(Header {
+        width,
+        height,
+        top_down,
+        bits_per_pixel,
+        compression,
+        masks,
+        palette,
+        pixels_start,
+    })
+}
+
+fn decode_pixels(header: &Header, pixels: &[u8]) -> Result<RgbaImage> {
+    let pixel_count = header.width as u64 * header.height as u64;
+
+    let fits = match header.compression {
+        // A run of at most 255 pixels is the most two bytes of RLE data decode to
+        BI_RLE8 | BI_RLE4 => pixel_count <= pixels.len() as u64 / 2 * 255,
+        _ => row_size(header.width, header.bits_per_pixel) * header.height as usize <= pixels.len(),
+    };
+
+    if !fits {
+        return Err(eyre!(
+            "BMP pixel data is too short for a {}x{} image",
+            header.width,
+            header.height
+        ));
+    }
+
+    let mut image = RgbaImage::new(header.width, header.height);
+
+    match header.compression {
+        BI_RLE8 | BI_RLE4 => decode_rle(header, pixels, &mut image)?,
+        _ => decode_uncompressed(header, pixels, &mut image)?,
+    }
+
+    Ok(image)
+}
+
+fn decode_uncompressed(header: &Header, pixels: &[u8], image: &mut RgbaImage) -> Result<()> {
+    let row_size = row_size(header.width, header.bits_per_pixel);
+    let width = header.width as usize;
+
+    for (row_index, row) in pixels
+        .chunks_exact(row_size)
+        .take(header.height as usize)
+        .enumerate()
+    {
+        let y = if header.top_down {
+            row_index
+        } else {
+            header.height as usize - 1 - row_index
+        };
+        let out = &mut image.data[y * width..(y + 1) * width];
+
+        match header.bits_per_pixel {
+            1 | 4 | 8 => {
+                let depth = header.bits_per_pixel as usize;
+                let mask = ((1u16 << depth) - 1) as u8;
+
+                for (x, pixel) in out.iter_mut().enumerate() {
+                    let bit = x * depth;
+                    let index = (row[bit / 8] >> (8 - depth - bit % 8)) & mask;
+                    *pixel = palette_color(&header.palette, index as usize);
+                }
+            }
+            24 => {
+                for (pixel, bgr) in out.iter_mut().zip(row.chunks_exact(3)) {
+                    *pixel = Rgba::new(bgr[2], bgr[1], bgr[0], 255);
+                }
+            }
+            16 | 32 => {
+                let masks = header
+                    .masks
+                    .ok_or_else(|| eyre!("BMP is missing color masks"))?;
+                let bytes = header.bits_per_pixel as usize / 8;
+
+                for (pixel, value) in out.iter_mut().zip(row.chunks_exact(bytes)) {
+                    let value = match bytes {
+                        2 => u16::from_le_bytes([value[0], value[1]]) as u32,
+                        _ => u32::from_le_bytes([value[0], value[1], value[2], value[3]]),
+                    };
+
+                    *pixel = Rgba::new(
+                        apply_mask(value, masks.red),
+                        apply_mask(value, masks.green),
+                        apply_mask(value, masks.blue),
+                        if masks.alpha == 0 {
+                            255
+                        } else {
+                            apply_mask(value, masks.alpha)
+                        },
+                    );
+                }
+            }
+            bits => return Err(eyre!("Unsupported BMP bit depth: {}", bits)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes RLE8 and RLE4 data. Pixels skipped with deltas or early line ends stay transparent.
+fn decode_rle(header: &Header, pixels: &[u8], image: &mut RgbaImage) -> Result<()> {
+    let is_rle4 = header.compression == BI_RLE4;
+    let (width, height) = (header.width as usize, header.height as usize);
+
+    let mut x = 0usize;
+    let mut row = 0usize;
+    let mut pos = 0usize;
+
+    let mut put = |x: usize, row: usize, index: u8| {
+        if x < width && row < height {
+            let y = height - 1 - row;
+            image.data[y * width + x] = palette_color(&header.palette, index as usize);
+        }
+    };
+
+    while pos + 1 < pixels.len() && row < height {
+        let (count, value) = (pixels[pos], pixels[pos + 1]);
+        pos += 2;
+
+        if count > 0 {
+            for i in 0..count as usize {
+                let index = if is_rle4 {
+                    if i % 2 == 0 { value >> 4 } else { value & 0x0F }
+                } else {
+                    value
+                };
+                put(x, row, index);
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                x = 0;
+                row += 1;
+            }
+            1 => return Ok(()),
+            2 => {
+                let delta = pixels
+                    .get(pos..pos + 2)
+                    .ok_or_else(|| eyre!("BMP RLE delta is truncated"))?;
+                x += delta[0] as usize;
+                row += delta[1] as usize;
+                pos += 2;
+            }
+            count => {
+                let count = count as usize;
+                let byte_count = if is_rle4 { count.div_ceil(2) } else { count };
+                let run = pixels
+                    .get(pos..pos + byte_count)
+                    .ok_or_else(|| eyre!("BMP RLE absolute run is truncated"))?;
+
+                for i in 0..count {
+                    let index = if is_rle4 {
+                        if i % 2 == 0 {
+                            run[i / 2] >> 4
+                        } else {
+                            run[i / 2] & 0x0F
+                        }
+                    } else {
+                        run[i]
+                    };
+                    put(x, row, index);
+                    x += 1;
+                }
+
+                // Absolute runs are padded to a 16-bit boundary.
+                pos += byte_count + byte_count % 2;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn row_size(width: u32, bits_per_pixel: u16) -> usize {
+    (width as usize * bits_per_pixel as usize).div_ceil(32) * 4
+}
+
+fn palette_color(palette: &[Rgba], index: usize) -> Rgba {
+    palette
+        .get(index)
+        .copied()
+        .unwrap_or(Rgba::new(0, 0, 0, 255))
+}
+
+/// Extracts the masked bits of `value` and scales them to 0-255.
+fn apply_mask(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max = mask >> shift;
+    let component = (value & mask) >> shift;
+
+    ((component as u64 * 255 + max as u64 / 2) / max as u64) as u8
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| eyre!("BMP header is truncated"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| eyre!("BMP header is truncated"))
+}
+
+/// BMP for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BmpCodec {
+    pub options: BmpEncoderOptions,
+}
+
+impl ImageFormat for BmpCodec {
+    fn name(&self) -> &'static str {
+        "BMP"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/bmp"
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &["image/x-bmp", "image/x-ms-bmp"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["bmp", "dib"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(b"BM")
+    }
+}
+
+impl ImageEncoder for BmpCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_bmp_with(image, &self.options)
+    }
+}
+
+impl ImageDecoder for BmpCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_bmp(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(width: u32, height: u32, alpha: bool) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = (index * 29) as u8;
+            let a = if alpha { value / 2 } else { 255 };
+            *pixel = Rgba::new(value, 255 - value, 9, a);
+        }
+
+        image
+    }
+
+    /// A bottom-up BITMAPINFOHEADER file with a red and blue palette
+    fn paletted_bmp(
+        width: i32,
+        height: i32,
+        bits: u16,
+        compression: u32,
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let palette = [0, 0, 255, 0, 255, 0, 0, 0];
+        let pixel_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette.len();
+
+        let mut data = b"BM".to_vec();
+        data.extend_from_slice(&((pixel_offset + pixels.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+        data.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.extend_from_slice(&compression.to_le_bytes());
+        data.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&palette);
+        data.extend_from_slice(pixels);
+
+        data
+    }
+
+    const RED: Rgba = Rgba::new(255, 0, 0, 255);
+    const BLUE: Rgba = Rgba::new(0, 0, 255, 255);
+
+    #[test]
+    fn round_trips_both_formats() {
+        for top_down in [false, true] {
+            // Odd widths need row padding in 24-bit files
+            for (image, format) in [
+                (sample(5, 3, true), BmpFormat::Bgra32),
+                (sample(5, 3, false), BmpFormat::Bgr24),
+            ] {
+                let options = BmpEncoderOptions {
+                    format: Some(format),
+                    top_down,
+                };
+
+                assert_eq!(
+                    decode_bmp(&encode_to_bmp_with(&image, &options).unwrap()).unwrap(),
+                    image
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_icon_dibs() {
+        let mut image = sample(6, 4, true);
+        image.set_pixel(1, 1, Rgba::new(0, 0, 0, 0));
+
+        assert_eq!(decode_dib(&encode_dib(&image).unwrap()).unwrap(), image);
+    }
+
+    #[test]
+    fn decodes_paletted_images() {
+        let one_bit = paletted_bmp(3, 2, 1, BI_RGB, &[0xE0, 0, 0, 0, 0x60, 0, 0, 0]);
+        let rle8 = paletted_bmp(3, 2, 8, BI_RLE8, &[3, 1, 0, 0, 1, 0, 2, 1, 0, 1]);
+
+        for data in [one_bit, rle8] {
+            let image = decode_bmp(&data).unwrap();
+
+            assert_eq!(image.rows().next().unwrap(), [RED, BLUE, BLUE]);
+            assert_eq!(image.rows().nth(1).unwrap(), [BLUE, BLUE, BLUE]);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let data = encode_to_bmp(&sample(4, 4, false)).unwrap();
+        assert!(decode_bmp(&data[..data.len() - 1]).is_err());
+        assert!(decode_bmp(&data[..FILE_HEADER_SIZE + 8]).is_err());
+
+        assert!(decode_bmp(&paletted_bmp(100_000, 100_000, 1, BI_RGB, &[])).is_err());
+        assert!(decode_bmp(&paletted_bmp(0, 2, 1, BI_RGB, &[])).is_err());
+        assert!(decode_bmp(&paletted_bmp(3, -2, 8, BI_RLE8, &[0, 1])).is_err());
+        assert!(decode_bmp(&paletted_bmp(3, 2, 1, BI_RLE8, &[0, 1])).is_err());
+    }
+
+    #[test]
+    fn rejects_images_larger_than_their_data() {
+        for compression in [BI_RGB, BI_RLE8] {
+            let data = paletted_bmp(20000, 20000, 8, compression, &[0, 1]);
+            let error = decode_bmp(&data).unwrap_err();
+
+            assert!(error.to_string().contains("too short"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const FARBFELD_MAGIC: [u8; 8] = *b"farbfeld";
+const FARBFELD_HEADER_SIZE: usize = 16;
+const FARBFELD_PIXELS_MAX: u64 = 400_000_000;
+
+/// Writes 16-bit big endian RGBA, each 8-bit channel is widened with `value * 257`.
+pub fn encode_to_farbfeld(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(FARBFELD_HEADER_SIZE + image.data.len() * 8);
+
+    output.extend_from_slice(&FARBFELD_MAGIC);
+    output.extend_from_slice(&image.width.to_be_bytes());
+    output.extend_from_slice(&image.height.to_be_bytes());
+
+    for pixel in &image.data {
+        for value in pixel.to_array() {
+            output.extend_from_slice(&(value as u16 * 257).to_be_bytes());
+        }
+    }
+
+    Ok(output)
+}
+
+pub fn decode_farbfeld(data: &[u8]) -> Result<RgbaImage> {
+    if data.len() < FARBFELD_HEADER_SIZE || data[0..8] != FARBFELD_MAGIC {
+        return Err(eyre!("Data is not a farbfeld file"));
+    }
+
+    let width = u32::from_be_bytes(data[8..12].try_into()?);
+    let height = u32::from_be_bytes(data[12..16].try_into()?);
+
+    if width as u64 * height as u64 > FARBFELD_PIXELS_MAX {
+        return Err(eyre!("Farbfeld image is too large: {}x{}", width, height));
+    }
+
+    let pixel_count = width as usize * height as usize;
+    let raster = data
+        .get(FARBFELD_HEADER_SIZE..FARBFELD_HEADER_SIZE + pixel_count * 8)
+        .ok_or_else(|| eyre!("Farbfeld pixel data is truncated"))?;
+
+    let mut image = RgbaImage::new(width, height);
+
+    for (pixel, bytes) in image.data.iter_mut().zip(raster.chunks_exact(8)) {
+        let channel = |index: usize| {
+            let value = u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+            ((value as u32 * 255 + 32767) / 65535) as u8
+        };
+
+        *pixel = Rgba::new(channel(0), channel(2), channel(4), channel(6));
+    }
+
+    Ok(image)
+}
+
+/// Farbfeld for the codec registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FarbfeldCodec;
+
+impl ImageFormat for FarbfeldCodec {
+    fn name(&self) -> &'static str {
+        "farbfeld"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/x-farbfeld"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ff", "farbfeld"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(&FARBFELD_MAGIC)
+    }
+}
+
+impl ImageEncoder for FarbfeldCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_farbfeld(image)
+    }
+}
+
+impl ImageDecoder for FarbfeldCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_farbfeld(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let mut image = RgbaImage::new(5, 4);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = (index * 13) as u8;
+            *pixel = Rgba::new(value, 255 - value, 7, value / 2);
+        }
+
+        assert_eq!(
+            decode_farbfeld(&encode_to_farbfeld(&image).unwrap()).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let data = encode_to_farbfeld(&RgbaImage::new(2, 2)).unwrap();
+
+        assert!(decode_farbfeld(&data[..data.len() - 1]).is_err());
+        assert!(decode_farbfeld(&data[..FARBFELD_HEADER_SIZE - 1]).is_err());
+
+        let mut huge = data.clone();
+        huge[8..16].fill(0xFF);
+        assert!(decode_farbfeld(&huge).is_err());
+    }
+}
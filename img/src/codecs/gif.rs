@@ -0,0 +1,734 @@
+use color_eyre::eyre::{Result, eyre};
+use std::collections::HashMap;
+
+use crate::{
+    codecs::registry::{ImageEncoder, ImageFormat},
+    quantize,
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const GIF_HEADER: [u8; 6] = *b"GIF89a";
+const GIF_TRAILER: u8 = 0x3B;
+const EXTENSION_INTRODUCER: u8 = 0x21;
+const GRAPHIC_CONTROL_LABEL: u8 = 0xF9;
+const APPLICATION_LABEL: u8 = 0xFF;
+const IMAGE_SEPARATOR: u8 = 0x2C;
+
+const MAX_COLORS: usize = 256;
+const MAX_LZW_CODE_SIZE: u8 = 12;
+const MAX_LZW_CODES: u16 = 1 << MAX_LZW_CODE_SIZE;
+/// Pixels with less alpha than this are written as the transparent index.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// What happens to the frame before the next one is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GifDisposal {
+    /// Let the decoder decide, which in practice means `Keep`
+    Unspecified = 0,
+    /// Leave the frame in place
+    #[default]
+    Keep = 1,
+    /// Clear the frame's area to the background
+    Background = 2,
+    /// Restore the area to what it was before this frame
+    Previous = 3,
+}
+
+/// Where frame palettes are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GifColorTables {
+    /// One global table when all frames fit in 256 colors together, local tables otherwise
+    #[default]
+    Auto,
+    /// A single global table shared by every frame
+    Global,
+    /// A local table per frame
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct GifFrame {
+    /// Full canvas sized image for this frame
+    pub image: RgbaImage,
+    /// Delay in hundredths of a second
+    pub delay: u16,
+    pub disposal: GifDisposal,
+}
+
+impl GifFrame {
+    pub fn new(image: RgbaImage, delay_ms: u16) -> Self {
+        Self {
+            image,
+            delay: delay_ms.div_ceil(10),
+            disposal: GifDisposal::Keep,
+        }
+    }
+
+    pub fn with_disposal(mut self, disposal: GifDisposal) -> Self {
+        self.disposal = disposal;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GifEncoderOptions {
+    /// Number of times to play the animation, 0 means forever
+    pub num_plays: u16,
+    pub color_tables: GifColorTables,
+}
+
+/// Palette of one color table, with the transparent entry (if any) last.
+struct Palette {
+    colors: Vec<[u8; 3]>,
+    transparent_index: Option<u8>,
+    lookup: HashMap<[u8; 3], u8>,
+}
+
+impl Palette {
+    /// Exact palette when the histogram fits, median cut otherwise.
+    fn build(histogram: &HashMap<[u8; 3], u32>, has_transparency: bool) -> Self {
+        let max_colors = MAX_COLORS - has_transparency as usize;
+
+        let mut colors: Vec<[u8; 3]> = if histogram.len() <= max_colors {
+            let mut colors: Vec<[u8; 3]> = histogram.keys().copied().collect();
+            colors.sort_unstable();
+            colors
+        } else {
+            median_cut(histogram, max_colors)
+        };
+
+        let exact = histogram.len() <= max_colors;
+        let lookup = if exact {
+            colors
+                .iter()
+                .enumerate()
+                .map(|(index, &color)| (color, index as u8))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        let transparent_index = has_transparency.then(|| {
+            colors.push([0, 0, 0]);
+            (colors.len() - 1) as u8
+        });
+
+        Self {
+            colors,
+            transparent_index,
+            lookup,
+        }
+    }
+
+    /// Number of bits needed to index the table, at least 1.
+    fn bits(&self) -> u8 {
+        let mut bits = 1;
+        while (1usize << bits) < self.colors.len() {
+            bits += 1;
+        }
+        bits
+    }
+
+    fn index_of(&mut self, pixel: Rgba) -> u8 {
+        if pixel.a < ALPHA_THRESHOLD
+            && let Some(index) = self.transparent_index
+        {
+            return index;
+        }
+
+        let color = [pixel.r, pixel.g, pixel.b];
+
+        if let Some(&index) = self.lookup.get(&color) {
+            return index;
+        }
+
+        let opaque_colors = self.colors.len() - self.transparent_index.is_some() as usize;
+        let index = self.colors[..opaque_colors]
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| color_distance(color, **entry))
+            .map(|(index, _)| index as u8)
+            .unwrap_or(0);
+
+        self.lookup.insert(color, index);
+        index
+    }
+
+    /// Color table bytes, padded with black to a power of two entries.
+    fn table(&self) -> Vec<u8> {
+        let mut table: Vec<u8> = self.colors.iter().flatten().copied().collect();
+        table.resize(3 << self.bits(), 0);
+        table
+    }
+}
+
+pub fn encode_to_gif(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_frames_to_gif(
+        &[GifFrame::new(image.clone(), 0)],
+        &GifEncoderOptions {
+            num_plays: 1,
+            color_tables: GifColorTables::Global,
+        },
+    )
+}
+
+pub fn encode_frames_to_gif(frames: &[GifFrame], options: &GifEncoderOptions) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| eyre!("GIF needs at least one frame"))?;
+    let (width, height) = (first.image.width, first.image.height);
+
+    if width == 0 || height == 0 {
+        return Err(eyre!("GIF width and height must be greater than 0"));
+    }
+
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(eyre!(
+            "GIF width and height can be at most {}, got {}x{}",
+            u16::MAX,
+            width,
+            height
+        ));
+    }
+
+    if let Some(frame) = frames
+        .iter()
+        .find(|frame| frame.image.width != width || frame.image.height != height)
+    {
+        return Err(eyre!(
+            "All GIF frames must be {}x{}, got a {}x{} frame",
+            width,
+            height,
+            frame.image.width,
+            frame.image.height
+        ));
+    }
+
+    let histograms: Vec<(HashMap<[u8; 3], u32>, bool)> =
+        frames.iter().map(|frame| histogram(&frame.image)).collect();
+
+    let mut combined: HashMap<[u8; 3], u32> = HashMap::new();
+    let mut any_transparency = false;
+
+    for (frame_histogram, has_transparency) in &histograms {
+        for (&color, &count) in frame_histogram {
+            *combined.entry(color).or_default() += count;
+        }
+        any_transparency |= has_transparency;
+    }
+
+    let use_global = match options.color_tables {
+        GifColorTables::Global => true,
+        GifColorTables::Local => false,
+        GifColorTables::Auto => {
+            frames.len() == 1 || combined.len() + any_transparency as usize <= MAX_COLORS
+        }
+    };
+
+    let mut global_palette = use_global.then(|| Palette::build(&combined, any_transparency));
+
+    let mut output: Vec<u8> = vec![];
+
+    output.extend_from_slice(&GIF_HEADER);
+    output.extend_from_slice(&(width as u16).to_le_bytes());
+    output.extend_from_slice(&(height as u16).to_le_bytes());
+
+    match &global_palette {
+        Some(palette) => {
+            // Global table flag, 8 bits of color resolution and the table size.
+            output.push(0x80 | 0x70 | (palette.bits() - 1));
+            output.push(palette.transparent_index.unwrap_or(0));
+            output.push(0);
+            output.extend_from_slice(&palette.table());
+        }
+        None => output.extend_from_slice(&[0x70, 0, 0]),
+    }
+
+    if options.num_plays != 1 {
+        output.extend_from_slice(&netscape_extension(options.num_plays.saturating_sub(1)));
+    }
+
+    for (frame, (frame_histogram, has_transparency)) in frames.iter().zip(&histograms) {
+        let mut local_palette = match global_palette {
+            Some(_) => None,
+            None => Some(Palette::build(frame_histogram, *has_transparency)),
+        };
+
+        let is_local = local_palette.is_some();
+        let palette = local_palette
+            .as_mut()
+            .or(global_palette.as_mut())
+            .ok_or_else(|| eyre!("GIF frame has no color table"))?;
+
+        output.extend_from_slice(&graphic_control_extension(
+            frame.disposal,
+            frame.delay,
+            palette.transparent_index,
+        ));
+
+        output.push(IMAGE_SEPARATOR);
+        output.extend_from_slice(&0u16.to_le_bytes());
+        output.extend_from_slice(&0u16.to_le_bytes());
+        output.extend_from_slice(&(width as u16).to_le_bytes());
+        output.extend_from_slice(&(height as u16).to_le_bytes());
+
+        if is_local {
+            output.push(0x80 | (palette.bits() - 1));
+            output.extend_from_slice(&palette.table());
+        } else {
+            output.push(0);
+        }
+
+        let indices: Vec<u8> = frame
+            .image
+            .data
+            .iter()
+            .map(|&pixel| palette.index_of(pixel))
+            .collect();
+
+        // LZW needs at least 2 bits, even for 2 color palettes.
+        let min_code_size = palette.bits().max(2);
+        output.push(min_code_size);
+
+        for block in lzw_encode(&indices, min_code_size).chunks(255) {
+            output.push(block.len() as u8);
+            output.extend_from_slice(block);
+        }
+        output.push(0);
+    }
+
+    output.push(GIF_TRAILER);
+
+    Ok(output)
+}
+
+/// Histogram of the opaque colors, plus whether any pixel is transparent.
+fn histogram(image: &RgbaImage) -> (HashMap<[u8; 3], u32>, bool) {
+    let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+    let mut has_transparency = false;
+
+    for pixel in &image.data {
+        if pixel.a < ALPHA_THRESHOLD {
+            has_transparency = true;
+        } else {
+            *histogram.entry([pixel.r, pixel.g, pixel.b]).or_default() += 1;
+        }
+    }
+
+    (histogram, has_transparency)
+}
+
+/// Median cut over the opaque colors of the histogram.
+fn median_cut(histogram: &HashMap<[u8; 3], u32>, max_colors: usize) -> Vec<[u8; 3]> {
+    let colors: Vec<([u8; 4], u32)> = histogram
+        .iter()
+        .map(|(&[r, g, b], &count)| ([r, g, b, 255], count))
+        .collect();
+
+    quantize::median_cut(colors, max_colors)
+        .into_iter()
+        .map(|[r, g, b, _]| [r, g, b])
+        .collect()
+}
+
+fn color_distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(&b)
+        .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+fn netscape_extension(loop_count: u16) -> [u8; 19] {
+    let mut data = [0u8; 19];
+
+    data[0] = EXTENSION_INTRODUCER;
+    data[1] = APPLICATION_LABEL;
+    data[2] = 11;
+    data[3..14].copy_from_slice(b"NETSCAPE2.0");
+    data[14] = 3;
+    data[15] = 1;
+    data[16..18].copy_from_slice(&loop_count.to_le_bytes());
+    data[18] = 0;
+
+    data
+}
+
+fn graphic_control_extension(
+    disposal: GifDisposal,
+    delay: u16,
+    transparent_index: Option<u8>,
+) -> [u8; 8] {
+    let mut data = [0u8; 8];
+
+    data[0] = EXTENSION_INTRODUCER;
+    data[1] = GRAPHIC_CONTROL_LABEL;
+    data[2] = 4;
+    data[3] = ((disposal as u8) << 2) | transparent_index.is_some() as u8;
+    data[4..6].copy_from_slice(&delay.to_le_bytes());
+    data[6] = transparent_index.unwrap_or(0);
+    data[7] = 0;
+
+    data
+}
+
+/// Packs variable width codes least significant bit first.
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+
+        while self.bits >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+/// Variable width LZW as used by GIF, restarting with a clear code once the table is full.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear_code: u16 = 1 << min_code_size;
+    let end_code = clear_code + 1;
+
+    let mut writer = BitWriter {
+        output: Vec::with_capacity(indices.len() / 2),
+        buffer: 0,
+        bits: 0,
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+
+    writer.write(clear_code, code_size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(end_code, code_size);
+        return writer.finish();
+    };
+
+    let mut prefix = first as u16;
+
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+
+        if next_code < MAX_LZW_CODES {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+
+            // Decoders add their entries one code later, so they widen when the next code
+            // is past the current size.
+            if next_code > (1 << code_size) && code_size < MAX_LZW_CODE_SIZE {
+                code_size += 1;
+            }
+        } else {
+            writer.write(clear_code, code_size);
+            table.clear();
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+        }
+
+        prefix = index as u16;
+    }
+
+    writer.write(prefix, code_size);
+
+    // The decoder adds an entry for the last code too, which can widen the end code.
+    if next_code == (1 << code_size) && code_size < MAX_LZW_CODE_SIZE {
+        code_size += 1;
+    }
+
+    writer.write(end_code, code_size);
+
+    writer.finish()
+}
+
+/// GIF for the codec registry, writing single frame images with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GifCodec {
+    pub options: GifEncoderOptions,
+}
+
+impl Default for GifCodec {
+    fn default() -> Self {
+        Self {
+            options: GifEncoderOptions {
+                num_plays: 1,
+                color_tables: GifColorTables::Global,
+            },
+        }
+    }
+}
+
+impl ImageFormat for GifCodec {
+    fn name(&self) -> &'static str {
+        "GIF"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/gif"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["gif"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        matches!(data, [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..])
+    }
+}
+
+impl ImageEncoder for GifCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_frames_to_gif(&[GifFrame::new(image.clone(), 0)], &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads LSB-first codes the way GIF decoders do.
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear_code = 1usize << min_code_size;
+        let end_code = clear_code + 1;
+        let initial: Vec<Vec<u8>> = (0..=end_code).map(|code| vec![code as u8]).collect();
+
+        let mut table = initial.clone();
+        let mut code_size = min_code_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut output = vec![];
+        let mut bit = 0;
+
+        loop {
+            let code = (0..code_size as usize).fold(0, |code, offset| {
+                let position = bit + offset;
+                code | (((data[position / 8] >> (position % 8)) & 1) as usize) << offset
+            });
+            bit += code_size as usize;
+
+            if code == clear_code {
+                table = initial.clone();
+                code_size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+
+            if code == end_code {
+                return output;
+            }
+
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                None => {
+                    let mut entry = table[previous.unwrap()].clone();
+                    entry.push(entry[0]);
+                    entry
+                }
+            };
+
+            if let Some(previous) = previous
+                && table.len() < MAX_LZW_CODES as usize
+            {
+                let mut new_entry = table[previous].clone();
+                new_entry.push(entry[0]);
+                table.push(new_entry);
+            }
+
+            if table.len() == 1 << code_size && code_size < MAX_LZW_CODE_SIZE {
+                code_size += 1;
+            }
+
+            output.extend_from_slice(&entry);
+            previous = Some(code);
+        }
+    }
+
+    /// Every frame of a GIF written by this encoder, frames always cover the whole canvas.
+    fn decode_frames(data: &[u8]) -> Vec<RgbaImage> {
+        assert_eq!(data[0..6], GIF_HEADER);
+
+        let width = u16::from_le_bytes([data[6], data[7]]) as u32;
+        let height = u16::from_le_bytes([data[8], data[9]]) as u32;
+        let table_size = |packed: u8| 3 << ((packed & 0x07) + 1);
+
+        let mut pos = 13;
+        let mut global_table: &[u8] = &[];
+        if data[10] & 0x80 != 0 {
+            global_table = &data[pos..pos + table_size(data[10])];
+            pos += global_table.len();
+        }
+
+        let read_blocks = |pos: &mut usize| {
+            let mut blocks = vec![];
+            while data[*pos] != 0 {
+                let length = data[*pos] as usize;
+                blocks.extend_from_slice(&data[*pos + 1..*pos + 1 + length]);
+                *pos += length + 1;
+            }
+            *pos += 1;
+            blocks
+        };
+
+        let mut frames = vec![];
+        let mut transparent_index = None;
+
+        loop {
+            match data[pos] {
+                EXTENSION_INTRODUCER => {
+                    let label = data[pos + 1];
+                    pos += 2;
+                    let blocks = read_blocks(&mut pos);
+
+                    if label == GRAPHIC_CONTROL_LABEL {
+                        transparent_index = (blocks[0] & 1 != 0).then_some(blocks[3]);
+                    }
+                }
+                IMAGE_SEPARATOR => {
+                    assert_eq!(
+                        data[pos + 5..pos + 9],
+                        [
+                            width as u8,
+                            (width >> 8) as u8,
+                            height as u8,
+                            (height >> 8) as u8
+                        ]
+                    );
+                    let packed = data[pos + 9];
+                    pos += 10;
+
+                    let table = if packed & 0x80 != 0 {
+                        let table = &data[pos..pos + table_size(packed)];
+                        pos += table.len();
+                        table
+                    } else {
+                        global_table
+                    };
+
+                    let min_code_size = data[pos];
+                    pos += 1;
+                    let indices = lzw_decode(&read_blocks(&mut pos), min_code_size);
+                    assert_eq!(indices.len(), (width * height) as usize);
+
+                    let pixels = indices
+                        .iter()
+                        .map(|&index| match transparent_index {
+                            Some(transparent) if transparent == index => Rgba::default(),
+                            _ => {
+                                let color = &table[index as usize * 3..index as usize * 3 + 3];
+                                Rgba::new(color[0], color[1], color[2], 255)
+                            }
+                        })
+                        .collect();
+
+                    frames.push(RgbaImage::from_data(width, height, pixels).unwrap());
+                }
+                GIF_TRAILER => return frames,
+                byte => panic!("Unexpected GIF block {:#x}", byte),
+            }
+        }
+    }
+
+    /// Noise over the given number of colors, long enough to fill the LZW table many times.
+    fn noise(width: u32, height: u32, colors: u32, offset: u32) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        let mut state = 12345u32;
+
+        for pixel in image.pixels_mut() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let color = (state >> 16) % colors + offset;
+            *pixel = Rgba::new(color as u8, (color * 7) as u8, (color / 2) as u8, 255);
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips_palette_images() {
+        let mut transparent = noise(40, 30, 100, 0);
+        for x in 0..40 {
+            transparent.set_pixel(x, 3, Rgba::default());
+        }
+
+        let images = [
+            RgbaImage::from_pixel(3, 3, Rgba::new(1, 2, 3, 255)),
+            noise(7, 5, 2, 0),
+            noise(200, 200, 256, 0),
+            transparent,
+        ];
+
+        for image in images {
+            assert_eq!(decode_frames(&encode_to_gif(&image).unwrap()), [image]);
+        }
+    }
+
+    #[test]
+    fn uses_local_tables_when_frames_need_more_colors() {
+        let frames = [noise(20, 20, 200, 0), noise(20, 20, 200, 200)];
+        let gif_frames: Vec<GifFrame> = frames
+            .iter()
+            .map(|image| GifFrame::new(image.clone(), 50))
+            .collect();
+
+        let data = encode_frames_to_gif(&gif_frames, &GifEncoderOptions::default()).unwrap();
+
+        assert_eq!(data[10] & 0x80, 0);
+        assert_eq!(decode_frames(&data), frames);
+    }
+
+    #[test]
+    fn quantizes_images_with_too_many_colors() {
+        let mut image = RgbaImage::new(64, 64);
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            *pixel = Rgba::new(index as u8, (index / 64 * 4) as u8, 128, 255);
+        }
+
+        let decoded = &decode_frames(&encode_to_gif(&image).unwrap())[0];
+
+        for (original, decoded) in image.pixels().zip(decoded.pixels()) {
+            assert!(
+                color_distance(
+                    [original.r, original.g, original.b],
+                    [decoded.r, decoded.g, decoded.b]
+                ) < 300
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let options = GifEncoderOptions::default();
+
+        assert!(encode_frames_to_gif(&[], &options).is_err());
+        assert!(encode_to_gif(&RgbaImage::new(65536, 1)).is_err());
+        assert!(
+            encode_frames_to_gif(
+                &[
+                    GifFrame::new(RgbaImage::new(2, 2), 10),
+                    GifFrame::new(RgbaImage::new(2, 3), 10),
+                ],
+                &options
+            )
+            .is_err()
+        );
+    }
+}
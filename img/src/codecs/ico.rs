@@ -0,0 +1,355 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::{
+        bmp::{decode_dib, encode_dib},
+        png::{PNG_HEADER, PngEncoderOptions, decode_png, encode_to_png_with},
+        registry::{ImageDecoder, ImageEncoder, ImageFormat},
+        tga::TGA_SIGNATURE,
+    },
+    rgba_image::RgbaImage,
+};
+
+const ICO_HEADER_SIZE: usize = 6;
+const ICO_ENTRY_SIZE: usize = 16;
+/// Directory entries store sizes in a byte, 0 meaning 256
+const ICO_MAX_DIMENSION: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IcoKind {
+    #[default]
+    Icon = 1,
+    Cursor = 2,
+}
+
+#[derive(Debug, Clone)]
+pub struct IcoEntry {
+    pub image: RgbaImage,
+    /// Cursor hotspot measured from the top-left corner, ignored for icons
+    pub hotspot: (u16, u16),
+}
+
+impl IcoEntry {
+    pub fn new(image: RgbaImage) -> Self {
+        Self {
+            image,
+            hotspot: (0, 0),
+        }
+    }
+
+    pub fn with_hotspot(mut self, x: u16, y: u16) -> Self {
+        self.hotspot = (x, y);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcoEncoderOptions {
+    pub kind: IcoKind,
+    /// Entries at least this wide or tall are stored as PNG, smaller ones as BMP which
+    /// every reader supports
+    pub png_min_size: u32,
+}
+
+impl Default for IcoEncoderOptions {
+    fn default() -> Self {
+        Self {
+            kind: IcoKind::Icon,
+            png_min_size: ICO_MAX_DIMENSION,
+        }
+    }
+}
+
+impl IcoEncoderOptions {
+    pub fn cursor() -> Self {
+        Self {
+            kind: IcoKind::Cursor,
+            ..Default::default()
+        }
+    }
+}
+
+/// Packs every image into one icon, typically 16, 32, 48 and 256 pixels square.
+pub fn encode_to_ico(images: &[RgbaImage]) -> Result<Vec<u8>> {
+    let entries: Vec<IcoEntry> = images.iter().cloned().map(IcoEntry::new).collect();
+
+    encode_to_ico_with(&entries, &IcoEncoderOptions::default())
+}
+
+pub fn encode_to_cur(entries: &[IcoEntry]) -> Result<Vec<u8>> {
+    encode_to_ico_with(entries, &IcoEncoderOptions::cursor())
+}
+
+pub fn encode_to_ico_with(entries: &[IcoEntry], options: &IcoEncoderOptions) -> Result<Vec<u8>> {
+    if entries.is_empty() {
+        return Err(eyre!("ICO files need at least one image"));
+    }
+
+    if entries.len() > u16::MAX as usize {
+        return Err(eyre!("ICO files can hold at most {} images", u16::MAX));
+    }
+
+    let mut images: Vec<Vec<u8>> = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let (width, height) = (entry.image.width, entry.image.height);
+
+        if width == 0 || height == 0 || width > ICO_MAX_DIMENSION || height > ICO_MAX_DIMENSION {
+            return Err(eyre!(
+                "ICO images must be between 1 and {} pixels wide and tall, got {}x{}",
+                ICO_MAX_DIMENSION,
+                width,
+                height
+            ));
+        }
+
+        if width.max(height) >= options.png_min_size {
+            // Readers expect 32-bit RGBA PNG entries
+            let png_options = PngEncoderOptions {
+                optimize_color_type: false,
+                ..PngEncoderOptions::best()
+            };
+            images.push(encode_to_png_with(&entry.image, &png_options)?);
+        } else {
+            images.push(encode_dib(&entry.image)?);
+        }
+    }
+
+    let directory_size = ICO_HEADER_SIZE + ICO_ENTRY_SIZE * entries.len();
+    let total_size = directory_size + images.iter().map(Vec::len).sum::<usize>();
+
+    if total_size > u32::MAX as usize {
+        return Err(eyre!("ICO files can't be larger than 4 GiB"));
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(total_size);
+
+    output.extend_from_slice(&0u16.to_le_bytes());
+    output.extend_from_slice(&(options.kind as u16).to_le_bytes());
+    output.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+
+    let mut offset = directory_size;
+
+    for (entry, image) in entries.iter().zip(&images) {
+        // Color count and reserved byte
+        output.extend_from_slice(&[entry.image.width as u8, entry.image.height as u8, 0, 0]);
+
+        // Icons store color planes and bit depth where cursors store the hotspot
+        let (first, second) = match options.kind {
+            IcoKind::Icon => (1, 32),
+            IcoKind::Cursor => entry.hotspot,
+        };
+        output.extend_from_slice(&first.to_le_bytes());
+        output.extend_from_slice(&second.to_le_bytes());
+
+        output.extend_from_slice(&(image.len() as u32).to_le_bytes());
+        output.extend_from_slice(&(offset as u32).to_le_bytes());
+        offset += image.len();
+    }
+
+    for image in &images {
+        output.extend_from_slice(image);
+    }
+
+    Ok(output)
+}
+
+/// Every image in an ICO or CUR file, in directory order.
+pub fn decode_ico(data: &[u8]) -> Result<Vec<RgbaImage>> {
+    let (_, entries) = decode_ico_entries(data)?;
+
+    Ok(entries.into_iter().map(|entry| entry.image).collect())
+}
+
+/// Like `decode_ico`, but also reports whether the file is a cursor along with the hotspots.
+pub fn decode_ico_entries(data: &[u8]) -> Result<(IcoKind, Vec<IcoEntry>)> {
+    if data.len() < ICO_HEADER_SIZE || data[0..2] != [0, 0] {
+        return Err(eyre!("Data is not an ICO or CUR file"));
+    }
+
+    let kind = match u16::from_le_bytes([data[2], data[3]]) {
+        1 => IcoKind::Icon,
+        2 => IcoKind::Cursor,
+        other => return Err(eyre!("Unknown ICO resource type: {}", other)),
+    };
+
+    let count = u16::from_le_bytes([data[4], data[5]]) as usize;
+    if count == 0 {
+        return Err(eyre!("ICO file contains no images"));
+    }
+
+    let directory = data
+        .get(ICO_HEADER_SIZE..ICO_HEADER_SIZE + count * ICO_ENTRY_SIZE)
+        .ok_or_else(|| eyre!("ICO directory is truncated"))?;
+
+    let mut entries: Vec<IcoEntry> = Vec::with_capacity(count);
+
+    for record in directory.chunks_exact(ICO_ENTRY_SIZE) {
+        let read_u16 = |offset: usize| u16::from_le_bytes([record[offset], record[offset + 1]]);
+        let read_u32 = |offset: usize| {
+            u32::from_le_bytes([
+                record[offset],
+                record[offset + 1],
+                record[offset + 2],
+                record[offset + 3],
+            ]) as usize
+        };
+
+        let (size, offset) = (read_u32(8), read_u32(12));
+        let image_data = offset
+            .checked_add(size)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(|| eyre!("ICO image data is past the end of the file"))?;
+
+        let image = if image_data.starts_with(&PNG_HEADER) {
+            decode_png(image_data)?
+        } else {
+            decode_dib(image_data)?
+        };
+
+        let hotspot = match kind {
+            IcoKind::Icon => (0, 0),
+            IcoKind::Cursor => (read_u16(4), read_u16(6)),
+        };
+
+        entries.push(IcoEntry { image, hotspot });
+    }
+
+    Ok((kind, entries))
+}
+
+/// ICO or CUR for the codec registry, depending on the kind in its options. Encodes a single
+/// image, and decodes the largest image in either kind of file, but only probes its own kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IcoCodec {
+    pub options: IcoEncoderOptions,
+}
+
+impl IcoCodec {
+    pub fn cursor() -> Self {
+        Self {
+            options: IcoEncoderOptions::cursor(),
+        }
+    }
+}
+
+impl ImageFormat for IcoCodec {
+    fn name(&self) -> &'static str {
+        match self.options.kind {
+            IcoKind::Icon => "ICO",
+            IcoKind::Cursor => "CUR",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self.options.kind {
+            IcoKind::Icon => "image/vnd.microsoft.icon",
+            IcoKind::Cursor => "image/x-win-bitmap",
+        }
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        match self.options.kind {
+            IcoKind::Icon => &["image/x-icon"],
+            IcoKind::Cursor => &[],
+        }
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match self.options.kind {
+            IcoKind::Icon => &["ico"],
+            IcoKind::Cursor => &["cur"],
+        }
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        let kind = match self.options.kind {
+            IcoKind::Icon => 1,
+            IcoKind::Cursor => 2,
+        };
+
+        // An uncompressed TGA can start with the same bytes, but never has images to count
+        matches!(data, [0, 0, found, 0, low, high, ..] if *found == kind && [*low, *high] != [0, 0])
+            && !data.ends_with(&TGA_SIGNATURE)
+    }
+}
+
+impl ImageEncoder for IcoCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_ico_with(&[IcoEntry::new(image.clone())], &self.options)
+    }
+}
+
+impl ImageDecoder for IcoCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_ico(data)?
+            .into_iter()
+            .max_by_key(|image| image.width as u64 * image.height as u64)
+            .ok_or_else(|| eyre!("ICO file contains no images"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{codecs::registry::registry, rgba::Rgba};
+
+    fn gradient(size: u32) -> RgbaImage {
+        let mut image = RgbaImage::new(size, size);
+
+        for y in 0..size {
+            for x in 0..size {
+                image.set_pixel(x, y, Rgba::new(x as u8, y as u8, 128, (x + y) as u8));
+            }
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips_bmp_and_png_entries() {
+        let images = [gradient(16), gradient(256)];
+
+        assert_eq!(
+            decode_ico(&encode_to_ico(&images).unwrap()).unwrap(),
+            images
+        );
+    }
+
+    #[test]
+    fn round_trips_cursor_hotspots() {
+        let entries = [IcoEntry::new(gradient(32)).with_hotspot(3, 7)];
+        let (kind, decoded) = decode_ico_entries(&encode_to_cur(&entries).unwrap()).unwrap();
+
+        assert_eq!(kind, IcoKind::Cursor);
+        assert_eq!(decoded[0].hotspot, (3, 7));
+        assert_eq!(decoded[0].image, entries[0].image);
+    }
+
+    #[test]
+    fn cur_extension_encodes_a_cursor() {
+        let encoder = registry().encoder_for_extension("cur").unwrap();
+        let (kind, _) = decode_ico_entries(&encoder.encode(&gradient(16)).unwrap()).unwrap();
+
+        assert_eq!(kind, IcoKind::Cursor);
+    }
+
+    #[test]
+    fn rejects_oversized_images() {
+        assert!(encode_to_ico(&[gradient(257)]).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let ico = encode_to_ico(&[gradient(16)]).unwrap();
+
+        assert!(decode_ico(&ico[..ico.len() - 1]).is_err());
+        assert!(decode_ico(&ico[..ICO_HEADER_SIZE + 4]).is_err());
+        assert!(decode_ico(&[0, 0, 1, 0, 0, 0]).is_err());
+        assert!(decode_ico(&[0, 0, 3, 0, 1, 0]).is_err());
+
+        let mut past_end = ico.clone();
+        past_end[ICO_HEADER_SIZE + 12..ICO_HEADER_SIZE + 16].fill(0xFF);
+        assert!(decode_ico(&past_end).is_err());
+    }
+}
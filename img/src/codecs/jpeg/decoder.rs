@@ -0,0 +1,1362 @@
+use color_eyre::eyre::{Result, eyre};
+use std::f32::consts::PI;
+
+use crate::{
+    codecs::jpeg::{DHT, DQT, EOI, SOF0, SOI, SOS, ZIGZAG},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const SOF1: u8 = 0xC1;
+const SOF2: u8 = 0xC2;
+const DRI: u8 = 0xDD;
+const APP1: u8 = 0xE1;
+const APP14: u8 = 0xEE;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+
+const JPEG_PIXELS_MAX: u64 = 400_000_000;
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+/// Codes up to this many bits are decoded with a single table lookup.
+const HUFFMAN_LOOKUP_BITS: u32 = 9;
+/// Largest coefficient magnitude categories 8-bit JPEG allows
+const DC_SIZE_MAX: u32 = 11;
+const AC_SIZE_MAX: u32 = 10;
+/// Zero bytes a scan may read past its data, the bit buffer alone looks 8 bytes ahead
+const SCAN_PADDING_MAX: usize = 16;
+
+struct HuffmanTable {
+    /// `(symbol, length)` indexed by the next `HUFFMAN_LOOKUP_BITS` bits, length 0 means the
+    /// code is longer
+    lookup: Vec<(u8, u8)>,
+    /// Largest code of each length, -1 when there are none
+    max_code: [i32; 17],
+    /// Index into `values` of the first code of each length, minus that code
+    offsets: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8; 16], values: &[u8]) -> Result<Self> {
+        if counts.iter().map(|&count| count as usize).sum::<usize>() != values.len() {
+            return Err(eyre!("JPEG Huffman table has mismatched counts"));
+        }
+
+        let mut lookup = vec![(0u8, 0u8); 1 << HUFFMAN_LOOKUP_BITS];
+        let mut max_code = [-1i32; 17];
+        let mut offsets = [0i32; 17];
+        let mut code = 0i32;
+        let mut index = 0usize;
+
+        for length in 1..=16 {
+            let count = counts[length - 1] as usize;
+            offsets[length] = index as i32 - code;
+
+            for _ in 0..count {
+                if code >= 1 << length {
+                    return Err(eyre!("JPEG Huffman table has too many codes"));
+                }
+
+                if length as u32 <= HUFFMAN_LOOKUP_BITS {
+                    let shift = HUFFMAN_LOOKUP_BITS - length as u32;
+                    let start = (code as usize) << shift;
+
+                    for entry in &mut lookup[start..start + (1 << shift)] {
+                        *entry = (values[index], length as u8);
+                    }
+                }
+
+                code += 1;
+                index += 1;
+            }
+
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+
+            code <<= 1;
+        }
+
+        Ok(Self {
+            lookup,
+            max_code,
+            offsets,
+            values: values.to_vec(),
+        })
+    }
+}
+
+/// Reads entropy coded data, removing stuffed zero bytes and stopping at markers.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u64,
+    bits: u32,
+    /// Marker that ended the entropy coded data, once reached
+    marker: Option<u8>,
+    /// Zero bytes handed out past the marker or the end of the data
+    padding: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self {
+            data,
+            pos,
+            buffer: 0,
+            bits: 0,
+            marker: None,
+            padding: 0,
+        }
+    }
+
+    /// Tops the buffer up to at least 57 bits, padding with zeros past a marker.
+    fn fill(&mut self) {
+        while self.bits <= 56 {
+            let byte = if self.marker.is_some() || self.pos >= self.data.len() {
+                self.padding += 1;
+                0
+            } else if self.data[self.pos] == 0xFF {
+                match self.data.get(self.pos + 1) {
+                    Some(0) => {
+                        self.pos += 2;
+                        0xFF
+                    }
+                    // Fill bytes before a marker.
+                    Some(0xFF) => {
+                        self.pos += 1;
+                        continue;
+                    }
+                    Some(&marker) => {
+                        self.marker = Some(marker);
+                        self.pos += 2;
+                        self.padding += 1;
+                        0
+                    }
+                    None => {
+                        self.pos += 1;
+                        self.padding += 1;
+                        0
+                    }
+                }
+            } else {
+                self.pos += 1;
+                self.data[self.pos - 1]
+            };
+
+            self.buffer |= (byte as u64) << (56 - self.bits);
+            self.bits += 8;
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+
+        self.fill();
+        let value = (self.buffer >> (64 - count)) as u32;
+        self.buffer <<= count;
+        self.bits -= count;
+
+        value
+    }
+
+    fn bit(&mut self) -> bool {
+        self.bits(1) == 1
+    }
+
+    /// Reads `count` bits as a signed coefficient value.
+    fn receive_extend(&mut self, count: u32) -> i32 {
+        if count == 0 {
+            return 0;
+        }
+
+        let value = self.bits(count) as i32;
+
+        if value < 1 << (count - 1) {
+            value - (1 << count) + 1
+        } else {
+            value
+        }
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8> {
+        self.fill();
+
+        let peek = (self.buffer >> (64 - HUFFMAN_LOOKUP_BITS)) as usize;
+        let (value, length) = table.lookup[peek];
+
+        if length > 0 {
+            self.buffer <<= length;
+            self.bits -= length as u32;
+            return Ok(value);
+        }
+
+        let peek = (self.buffer >> 48) as i32;
+
+        for length in HUFFMAN_LOOKUP_BITS as usize + 1..=16 {
+            let code = peek >> (16 - length);
+
+            if code <= table.max_code[length] {
+                self.buffer <<= length;
+                self.bits -= length as u32;
+
+                return table
+                    .values
+                    .get((table.offsets[length] + code) as usize)
+                    .copied()
+                    .ok_or_else(|| eyre!("Invalid JPEG Huffman code"));
+            }
+        }
+
+        Err(eyre!("Invalid JPEG Huffman code"))
+    }
+
+    /// Drops buffered bits and skips the next restart marker.
+    fn restart(&mut self) {
+        self.buffer = 0;
+        self.bits = 0;
+        self.padding = 0;
+
+        match self.marker {
+            Some(RST0..=RST7) => self.marker = None,
+            Some(_) => {}
+            None => {
+                while self.pos + 1 < self.data.len()
+                    && !(self.data[self.pos] == 0xFF
+                        && (RST0..=RST7).contains(&self.data[self.pos + 1]))
+                {
+                    self.pos += 1;
+                }
+                self.pos = (self.pos + 2).min(self.data.len());
+            }
+        }
+    }
+
+    /// Whether more bits were read than the data holds, past what the lookahead explains.
+    fn truncated(&self) -> bool {
+        self.padding > SCAN_PADDING_MAX
+    }
+
+    /// Position of the marker following the entropy coded data.
+    fn end_position(&self) -> usize {
+        if self.marker.is_some() {
+            return self.pos - 2;
+        }
+
+        let mut pos = self.pos;
+        while pos + 1 < self.data.len() {
+            let next = self.data[pos + 1];
+            if self.data[pos] == 0xFF && next != 0 && next != 0xFF && !(RST0..=RST7).contains(&next)
+            {
+                break;
+            }
+            pos += 1;
+        }
+
+        pos
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quantization_table: usize,
+    /// Blocks per line and column, padded to whole MCUs
+    blocks_width: usize,
+    blocks_height: usize,
+    coefficients: Vec<[i16; 64]>,
+    dc_table: usize,
+    ac_table: usize,
+    previous_dc: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    h_max: usize,
+    v_max: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+    /// Scans decoded so far, without any the file has no pixel data
+    scans: usize,
+}
+
+impl Frame {
+    /// Size of a component in samples, before padding.
+    fn component_size(&self, component: &Component) -> (usize, usize) {
+        (
+            (self.width * component.h).div_ceil(self.h_max),
+            (self.height * component.v).div_ceil(self.v_max),
+        )
+    }
+}
+
+struct Scan {
+    spectral_start: usize,
+    spectral_end: usize,
+    approximation_high: u32,
+    approximation_low: u32,
+    /// Remaining blocks of the current end-of-band run
+    eob_run: u32,
+}
+
+struct Decoder {
+    quantization: [[u16; 64]; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: usize,
+    frame: Option<Frame>,
+    adobe_transform: Option<u8>,
+    orientation: u16,
+}
+
+pub fn decode_jpeg(data: &[u8]) -> Result<RgbaImage> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != SOI {
+        return Err(eyre!("Data is not a JPEG file"));
+    }
+
+    let mut decoder = Decoder {
+        quantization: [[1; 64]; 4],
+        dc_tables: Default::default(),
+        ac_tables: Default::default(),
+        restart_interval: 0,
+        frame: None,
+        adobe_transform: None,
+        orientation: 1,
+    };
+    let mut pos = 2;
+
+    loop {
+        // Skip fill bytes and anything that isn't a marker.
+        while pos < data.len() && data[pos] != 0xFF {
+            pos += 1;
+        }
+        while pos < data.len() && data[pos] == 0xFF {
+            pos += 1;
+        }
+
+        let Some(&marker) = data.get(pos) else {
+            break;
+        };
+        pos += 1;
+
+        match marker {
+            EOI => break,
+            RST0..=RST7 | 0x01 => continue,
+            _ => {}
+        }
+
+        let length = data
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+            .filter(|&length| length >= 2)
+            .ok_or_else(|| eyre!("JPEG segment is truncated"))?;
+        let segment = data
+            .get(pos + 2..pos + length)
+            .ok_or_else(|| eyre!("JPEG segment is truncated"))?;
+        pos += length;
+
+        match marker {
+            SOF0 | SOF1 | SOF2 => decoder.read_frame(segment, marker == SOF2, data.len() - pos)?,
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(eyre!(
+                    "Unsupported JPEG process, only baseline and progressive Huffman are supported"
+                ));
+            }
+            DHT => decoder.read_huffman_tables(segment)?,
+            DQT => decoder.read_quantization_tables(segment)?,
+            DRI => {
+                decoder.restart_interval = segment
+                    .get(0..2)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+                    .ok_or_else(|| eyre!("JPEG DRI segment is truncated"))?;
+            }
+            APP1 => decoder.read_exif(segment),
+            APP14 if segment.len() >= 12 && segment.starts_with(b"Adobe") => {
+                decoder.adobe_transform = Some(segment[11]);
+            }
+            SOS => pos = decoder.decode_scan(data, segment, pos)?,
+            _ => {}
+        }
+    }
+
+    let image = decoder.output()?;
+
+    Ok(apply_orientation(image, decoder.orientation))
+}
+
+impl Decoder {
+    /// `remaining` is the number of bytes after the frame header, which have to be able to
+    /// hold the scans.
+    fn read_frame(&mut self, segment: &[u8], progressive: bool, remaining: usize) -> Result<()> {
+        if self.frame.is_some() {
+            return Err(eyre!("JPEG has more than one frame"));
+        }
+
+        if segment.len() < 6 {
+            return Err(eyre!("JPEG frame header is truncated"));
+        }
+
+        if segment[0] != 8 {
+            return Err(eyre!("Unsupported JPEG sample precision: {}", segment[0]));
+        }
+
+        let height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+        let width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+        let count = segment[5] as usize;
+
+        if width == 0 || height == 0 {
+            return Err(eyre!("JPEG width and height must be greater than 0"));
+        }
+
+        if width as u64 * height as u64 > JPEG_PIXELS_MAX {
+            return Err(eyre!("JPEG image is too large: {}x{}", width, height));
+        }
+
+        if !matches!(count, 1 | 3 | 4) {
+            return Err(eyre!("Unsupported JPEG component count: {}", count));
+        }
+
+        let specs = segment
+            .get(6..6 + count * 3)
+            .ok_or_else(|| eyre!("JPEG frame header is truncated"))?;
+
+        let mut components: Vec<Component> = specs
+            .chunks_exact(3)
+            .map(|spec| Component {
+                id: spec[0],
+                h: (spec[1] >> 4) as usize,
+                v: (spec[1] & 0x0F) as usize,
+                quantization_table: spec[2] as usize,
+                blocks_width: 0,
+                blocks_height: 0,
+                coefficients: vec![],
+                dc_table: 0,
+                ac_table: 0,
+                previous_dc: 0,
+            })
+            .collect();
+
+        if components.iter().any(|component| {
+            !(1..=4).contains(&component.h)
+                || !(1..=4).contains(&component.v)
+                || component.quantization_table > 3
+        }) {
+            return Err(eyre!("Invalid JPEG component sampling factors"));
+        }
+
+        // A single component scan has one block per MCU, whatever its sampling factors say.
+        if count == 1 {
+            components[0].h = 1;
+            components[0].v = 1;
+        }
+
+        let h_max = components
+            .iter()
+            .map(|component| component.h)
+            .max()
+            .unwrap_or(1);
+        let v_max = components
+            .iter()
+            .map(|component| component.v)
+            .max()
+            .unwrap_or(1);
+        let mcus_x = width.div_ceil(8 * h_max);
+        let mcus_y = height.div_ceil(8 * v_max);
+
+        for component in components.iter_mut() {
+            component.blocks_width = mcus_x * component.h;
+            component.blocks_height = mcus_y * component.v;
+        }
+
+        // Every block takes at least a bit, check that before allocating the coefficients
+        let blocks: usize = components
+            .iter()
+            .map(|component| component.blocks_width * component.blocks_height)
+            .sum();
+
+        if blocks / 8 > remaining {
+            return Err(eyre!(
+                "JPEG data is too short for a {}x{} image",
+                width,
+                height
+            ));
+        }
+
+        for component in components.iter_mut() {
+            component.coefficients =
+                vec![[0i16; 64]; component.blocks_width * component.blocks_height];
+        }
+
+        self.frame = Some(Frame {
+            width,
+            height,
+            progressive,
+            components,
+            h_max,
+            v_max,
+            mcus_x,
+            mcus_y,
+            scans: 0,
+        });
+
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self, mut segment: &[u8]) -> Result<()> {
+        while !segment.is_empty() {
+            if segment.len() < 17 {
+                return Err(eyre!("JPEG Huffman table is truncated"));
+            }
+
+            let class = segment[0] >> 4;
+            let index = (segment[0] & 0x0F) as usize;
+            let counts: [u8; 16] = segment[1..17].try_into()?;
+            let total: usize = counts.iter().map(|&count| count as usize).sum();
+
+            let values = segment
+                .get(17..17 + total)
+                .ok_or_else(|| eyre!("JPEG Huffman table is truncated"))?;
+
+            if index > 3 || class > 1 {
+                return Err(eyre!("Invalid JPEG Huffman table {}/{}", class, index));
+            }
+
+            let table = HuffmanTable::new(&counts, values)?;
+
+            if class == 0 {
+                self.dc_tables[index] = Some(table);
+            } else {
+                self.ac_tables[index] = Some(table);
+            }
+
+            segment = &segment[17 + total..];
+        }
+
+        Ok(())
+    }
+
+    fn read_quantization_tables(&mut self, mut segment: &[u8]) -> Result<()> {
+        while !segment.is_empty() {
+            let precision = segment[0] >> 4;
+            let index = (segment[0] & 0x0F) as usize;
+            let size = if precision == 0 { 64 } else { 128 };
+
+            let values = segment
+                .get(1..1 + size)
+                .ok_or_else(|| eyre!("JPEG quantization table is truncated"))?;
+
+            if index > 3 {
+                return Err(eyre!("Invalid JPEG quantization table {}", index));
+            }
+
+            for (zigzag, &natural) in ZIGZAG.iter().enumerate() {
+                self.quantization[index][natural] = if precision == 0 {
+                    values[zigzag] as u16
+                } else {
+                    u16::from_be_bytes([values[zigzag * 2], values[zigzag * 2 + 1]])
+                };
+            }
+
+            segment = &segment[1 + size..];
+        }
+
+        Ok(())
+    }
+
+    /// Picks the orientation tag out of IFD0, anything malformed is ignored.
+    fn read_exif(&mut self, segment: &[u8]) {
+        let Some(tiff) = segment.strip_prefix(b"Exif\0\0") else {
+            return;
+        };
+
+        let little_endian = match tiff.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return,
+        };
+
+        let read_u16 = |offset: usize| {
+            tiff.get(offset..offset + 2).map(|bytes| {
+                if little_endian {
+                    u16::from_le_bytes([bytes[0], bytes[1]])
+                } else {
+                    u16::from_be_bytes([bytes[0], bytes[1]])
+                }
+            })
+        };
+        let read_u32 = |offset: usize| {
+            tiff.get(offset..offset + 4).map(|bytes| {
+                let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                if little_endian {
+                    u32::from_le_bytes(bytes)
+                } else {
+                    u32::from_be_bytes(bytes)
+                }
+            })
+        };
+
+        let Some(ifd) = read_u32(4).map(|offset| offset as usize) else {
+            return;
+        };
+        let Some(entries) = read_u16(ifd) else {
+            return;
+        };
+
+        for entry in 0..entries as usize {
+            let offset = ifd + 2 + entry * 12;
+
+            if read_u16(offset) == Some(EXIF_ORIENTATION_TAG) {
+                if let Some(orientation) =
+                    read_u16(offset + 8).filter(|value| (1..=8).contains(value))
+                {
+                    self.orientation = orientation;
+                }
+                return;
+            }
+        }
+    }
+
+    fn decode_scan(&mut self, data: &[u8], header: &[u8], pos: usize) -> Result<usize> {
+        let Self {
+            frame,
+            dc_tables,
+            ac_tables,
+            restart_interval,
+            ..
+        } = self;
+
+        let frame = frame
+            .as_mut()
+            .ok_or_else(|| eyre!("JPEG scan comes before the frame header"))?;
+
+        let count = *header
+            .first()
+            .ok_or_else(|| eyre!("JPEG scan header is truncated"))? as usize;
+        let specs = header
+            .get(1..1 + count * 2 + 3)
+            .ok_or_else(|| eyre!("JPEG scan header is truncated"))?;
+
+        let mut components: Vec<usize> = Vec::with_capacity(count);
+        let mut scan = Scan {
+            spectral_start: specs[count * 2] as usize,
+            spectral_end: specs[count * 2 + 1] as usize,
+            approximation_high: (specs[count * 2 + 2] >> 4) as u32,
+            approximation_low: (specs[count * 2 + 2] & 0x0F) as u32,
+            eob_run: 0,
+        };
+
+        for spec in specs[..count * 2].chunks_exact(2) {
+            let index = frame
+                .components
+                .iter()
+                .position(|component| component.id == spec[0])
+                .ok_or_else(|| eyre!("JPEG scan references unknown component {}", spec[0]))?;
+
+            let component = &mut frame.components[index];
+            component.dc_table = (spec[1] >> 4) as usize & 3;
+            component.ac_table = (spec[1] & 0x0F) as usize & 3;
+            component.previous_dc = 0;
+
+            components.push(index);
+        }
+
+        if components.is_empty() {
+            return Err(eyre!("JPEG scan has no components"));
+        }
+
+        if frame.progressive {
+            let valid = scan.spectral_start <= scan.spectral_end
+                && scan.spectral_end < 64
+                && (scan.spectral_start == 0) == (scan.spectral_end == 0)
+                && (scan.spectral_start == 0 || components.len() == 1);
+
+            if !valid {
+                return Err(eyre!("Invalid JPEG progressive scan parameters"));
+            }
+        } else {
+            scan.spectral_start = 0;
+            scan.spectral_end = 63;
+            scan.approximation_high = 0;
+            scan.approximation_low = 0;
+        }
+
+        let single = components.len() == 1;
+
+        // Scans with one component go through its blocks in raster order, one block per MCU.
+        let (mcus_x, mcus_y) = if single {
+            let (width, height) = frame.component_size(&frame.components[components[0]]);
+            (width.div_ceil(8), height.div_ceil(8))
+        } else {
+            (frame.mcus_x, frame.mcus_y)
+        };
+
+        let mut reader = BitReader::new(data, pos);
+
+        for mcu in 0..mcus_x * mcus_y {
+            if *restart_interval > 0 && mcu > 0 && mcu % *restart_interval == 0 {
+                reader.restart();
+                scan.eob_run = 0;
+
+                for &index in &components {
+                    frame.components[index].previous_dc = 0;
+                }
+            }
+
+            let (mcu_x, mcu_y) = (mcu % mcus_x, mcu / mcus_x);
+
+            for &index in &components {
+                let component = &mut frame.components[index];
+                let (h, v) = if single {
+                    (1, 1)
+                } else {
+                    (component.h, component.v)
+                };
+
+                for block_y in 0..v {
+                    for block_x in 0..h {
+                        let x = mcu_x * h + block_x;
+                        let y = mcu_y * v + block_y;
+                        let block_index = y * component.blocks_width + x;
+
+                        let tables = (
+                            dc_tables[component.dc_table].as_ref(),
+                            ac_tables[component.ac_table].as_ref(),
+                        );
+
+                        let block = component
+                            .coefficients
+                            .get_mut(block_index)
+                            .ok_or_else(|| eyre!("JPEG block is outside the image"))?;
+
+                        if frame.progressive {
+                            decode_progressive_block(
+                                &mut reader,
+                                &mut scan,
+                                tables,
+                                &mut component.previous_dc,
+                                block,
+                            )?;
+                        } else {
+                            decode_baseline_block(
+                                &mut reader,
+                                tables,
+                                &mut component.previous_dc,
+                                block,
+                            )?;
+                        }
+                    }
+                }
+            }
+
+            if reader.truncated() {
+                return Err(eyre!("JPEG scan data is truncated"));
+            }
+        }
+
+        frame.scans += 1;
+
+        Ok(reader.end_position())
+    }
+
+    fn output(&self) -> Result<RgbaImage> {
+        let frame = self
+            .frame
+            .as_ref()
+            .ok_or_else(|| eyre!("JPEG has no frame header"))?;
+
+        if frame.scans == 0 {
+            return Err(eyre!("JPEG has no image data"));
+        }
+
+        let idct = IdctTable::new();
+
+        let planes: Vec<Vec<u8>> = frame
+            .components
+            .iter()
+            .map(|component| {
+                let table = &self.quantization[component.quantization_table];
+                let plane_width = component.blocks_width * 8;
+                let mut plane = vec![0u8; plane_width * component.blocks_height * 8];
+
+                for (index, block) in component.coefficients.iter().enumerate() {
+                    let x = (index % component.blocks_width) * 8;
+                    let y = (index / component.blocks_width) * 8;
+                    let samples = idct.inverse(block, table);
+
+                    for row in 0..8 {
+                        let start = (y + row) * plane_width + x;
+                        plane[start..start + 8].copy_from_slice(&samples[row * 8..row * 8 + 8]);
+                    }
+                }
+
+                plane
+            })
+            .collect();
+
+        let mut image = RgbaImage::new(frame.width as u32, frame.height as u32);
+        let mut samples = [0u8; 4];
+
+        let transform = match frame.components.len() {
+            1 => ColorTransform::Grayscale,
+            3 => {
+                let ids: Vec<u8> = frame
+                    .components
+                    .iter()
+                    .map(|component| component.id)
+                    .collect();
+                if self.adobe_transform == Some(0) || ids == b"RGB" {
+                    ColorTransform::Rgb
+                } else {
+                    ColorTransform::YCbCr
+                }
+            }
+            _ => match self.adobe_transform {
+                Some(2) => ColorTransform::Ycck,
+                Some(_) => ColorTransform::AdobeCmyk,
+                None => ColorTransform::Cmyk,
+            },
+        };
+
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                for (channel, (component, plane)) in
+                    frame.components.iter().zip(&planes).enumerate()
+                {
+                    samples[channel] = sample(frame, component, plane, x, y);
+                }
+
+                image.data[y * frame.width + x] = transform.apply(samples);
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Only the tables a scan actually reads from have to be defined.
+fn table(table: Option<&HuffmanTable>) -> Result<&HuffmanTable> {
+    table.ok_or_else(|| eyre!("JPEG scan uses an undefined Huffman table"))
+}
+
+/// Magnitude category of a DC difference, which is the number of bits that follow.
+fn dc_size(size: u32) -> Result<u32> {
+    if size > DC_SIZE_MAX {
+        return Err(eyre!("JPEG DC coefficient has invalid size {}", size));
+    }
+
+    Ok(size)
+}
+
+fn ac_size(size: u32) -> Result<u32> {
+    if size > AC_SIZE_MAX {
+        return Err(eyre!("JPEG AC coefficient has invalid size {}", size));
+    }
+
+    Ok(size)
+}
+
+fn decode_baseline_block(
+    reader: &mut BitReader,
+    (dc, ac): (Option<&HuffmanTable>, Option<&HuffmanTable>),
+    previous_dc: &mut i32,
+    block: &mut [i16; 64],
+) -> Result<()> {
+    let size = dc_size(reader.decode(table(dc)?)? as u32)?;
+    *previous_dc += reader.receive_extend(size);
+    block[0] = *previous_dc as i16;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = reader.decode(table(ac)?)?;
+        let (run, size) = ((symbol >> 4) as usize, (symbol & 0x0F) as u32);
+
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+
+        k += run;
+        if k > 63 {
+            return Err(eyre!("JPEG block has too many coefficients"));
+        }
+
+        block[ZIGZAG[k]] = reader.receive_extend(ac_size(size)?) as i16;
+        k += 1;
+    }
+
+    Ok(())
+}
+
+fn decode_progressive_block(
+    reader: &mut BitReader,
+    scan: &mut Scan,
+    (dc, ac): (Option<&HuffmanTable>, Option<&HuffmanTable>),
+    previous_dc: &mut i32,
+    block: &mut [i16; 64],
+) -> Result<()> {
+    let low = scan.approximation_low;
+
+    if scan.spectral_start == 0 {
+        if scan.approximation_high == 0 {
+            let size = dc_size(reader.decode(table(dc)?)? as u32)?;
+            *previous_dc += reader.receive_extend(size);
+            block[0] = (*previous_dc << low) as i16;
+        } else if reader.bit() {
+            block[0] |= 1 << low;
+        }
+
+        return Ok(());
+    }
+
+    let (start, end) = (scan.spectral_start, scan.spectral_end);
+
+    if scan.approximation_high == 0 {
+        if scan.eob_run > 0 {
+            scan.eob_run -= 1;
+            return Ok(());
+        }
+
+        let mut k = start;
+        while k <= end {
+            let symbol = reader.decode(table(ac)?)?;
+            let (run, size) = ((symbol >> 4) as u32, (symbol & 0x0F) as u32);
+
+            if size == 0 {
+                if run < 15 {
+                    scan.eob_run = (1 << run) - 1 + reader.bits(run);
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+
+            k += run as usize;
+            if k > 63 {
+                return Err(eyre!("JPEG block has too many coefficients"));
+            }
+
+            block[ZIGZAG[k]] = (reader.receive_extend(ac_size(size)?) * (1 << low)) as i16;
+            k += 1;
+        }
+
+        return Ok(());
+    }
+
+    // Refinement of AC coefficients, following the structure of libjpeg's decode_mcu_AC_refine.
+    let positive = 1i16 << low;
+    let negative = -1i16 << low;
+
+    let refine = |reader: &mut BitReader, coefficient: &mut i16| {
+        if reader.bit() && *coefficient & positive == 0 {
+            *coefficient += if *coefficient >= 0 {
+                positive
+            } else {
+                negative
+            };
+        }
+    };
+
+    let mut k = start;
+
+    if scan.eob_run == 0 {
+        while k <= end {
+            let symbol = reader.decode(table(ac)?)?;
+            let (mut run, size) = ((symbol >> 4) as i32, symbol & 0x0F);
+            let mut value = 0i16;
+
+            if size == 0 {
+                if run < 15 {
+                    scan.eob_run = (1 << run) + reader.bits(run as u32);
+                    break;
+                }
+            } else {
+                value = if reader.bit() { positive } else { negative };
+            }
+
+            while k <= end {
+                let coefficient = &mut block[ZIGZAG[k]];
+
+                if *coefficient != 0 {
+                    refine(reader, coefficient);
+                } else {
+                    if run == 0 {
+                        break;
+                    }
+                    run -= 1;
+                }
+
+                k += 1;
+            }
+
+            if value != 0 && k <= end {
+                block[ZIGZAG[k]] = value;
+            }
+
+            k += 1;
+        }
+    }
+
+    if scan.eob_run > 0 {
+        while k <= end {
+            let coefficient = &mut block[ZIGZAG[k]];
+            if *coefficient != 0 {
+                refine(reader, coefficient);
+            }
+            k += 1;
+        }
+
+        scan.eob_run -= 1;
+    }
+
+    Ok(())
+}
+
+/// Reads a component sample at full resolution coordinates, interpolating subsampled planes
+/// between sample centers.
+fn sample(frame: &Frame, component: &Component, plane: &[u8], x: usize, y: usize) -> u8 {
+    let plane_width = component.blocks_width * 8;
+
+    if component.h == frame.h_max && component.v == frame.v_max {
+        return plane[y * plane_width + x];
+    }
+
+    let (width, height) = frame.component_size(component);
+    let position = |coordinate: usize, factor: usize, max: usize, size: usize| {
+        let position = (coordinate as f32 + 0.5) * factor as f32 / max as f32 - 0.5;
+        let position = position.clamp(0.0, (size - 1) as f32);
+        let low = position.floor() as usize;
+        (low, (low + 1).min(size - 1), position - low as f32)
+    };
+
+    let (x0, x1, fx) = position(x, component.h, frame.h_max, width);
+    let (y0, y1, fy) = position(y, component.v, frame.v_max, height);
+
+    let at = |x: usize, y: usize| plane[y * plane_width + x] as f32;
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+
+    (top + (bottom - top) * fy).round() as u8
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ColorTransform {
+    Grayscale,
+    Rgb,
+    YCbCr,
+    /// Plain CMYK, 0 means no ink
+    Cmyk,
+    /// CMYK as written by Adobe software, 0 means full ink
+    AdobeCmyk,
+    /// YCbCr encoded inverted CMY plus K, always from Adobe software
+    Ycck,
+}
+
+impl ColorTransform {
+    fn apply(self, samples: [u8; 4]) -> Rgba {
+        let [a, b, c, d] = samples;
+
+        match self {
+            Self::Grayscale => Rgba::new(a, a, a, 255),
+            Self::Rgb => Rgba::new(a, b, c, 255),
+            Self::YCbCr => {
+                let [r, g, b] = ycbcr_to_rgb(a, b, c);
+                Rgba::new(r, g, b, 255)
+            }
+            Self::Cmyk => cmyk_to_rgba([255 - a, 255 - b, 255 - c, 255 - d]),
+            Self::AdobeCmyk => cmyk_to_rgba(samples),
+            Self::Ycck => {
+                let [r, g, b] = ycbcr_to_rgb(a, b, c);
+                cmyk_to_rgba([255 - r, 255 - g, 255 - b, d])
+            }
+        }
+    }
+}
+
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> [u8; 3] {
+    let y = y as f32;
+    let cb = cb as f32 - 128.0;
+    let cr = cr as f32 - 128.0;
+
+    [
+        y + 1.402 * cr,
+        y - 0.344_136 * cb - 0.714_136 * cr,
+        y + 1.772 * cb,
+    ]
+    .map(|value| value.round().clamp(0.0, 255.0) as u8)
+}
+
+/// Converts inverted CMYK, where 255 means no ink.
+fn cmyk_to_rgba([c, m, y, k]: [u8; 4]) -> Rgba {
+    let mix = |value: u8| ((value as u32 * k as u32 + 127) / 255) as u8;
+
+    Rgba::new(mix(c), mix(m), mix(y), 255)
+}
+
+/// Separable inverse DCT using a precomputed cosine table.
+struct IdctTable {
+    /// `cos((2x + 1) * u * pi / 16) * c(u) / 2` at `[u * 8 + x]`
+    cosines: [f32; 64],
+}
+
+impl IdctTable {
+    fn new() -> Self {
+        let mut cosines = [0f32; 64];
+
+        for u in 0..8 {
+            let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+
+            for x in 0..8 {
+                cosines[u * 8 + x] = scale * (((2 * x + 1) as f32 * u as f32 * PI) / 16.0).cos();
+            }
+        }
+
+        Self { cosines }
+    }
+
+    /// Dequantizes and transforms a block in natural order into level shifted samples.
+    fn inverse(&self, block: &[i16; 64], quantization: &[u16; 64]) -> [u8; 64] {
+        let mut coefficients = [0f32; 64];
+        for (index, coefficient) in coefficients.iter_mut().enumerate() {
+            *coefficient = block[index] as f32 * quantization[index] as f32;
+        }
+
+        let mut columns = [0f32; 64];
+
+        for u in 0..8 {
+            for y in 0..8 {
+                columns[y * 8 + u] = (0..8)
+                    .map(|v| coefficients[v * 8 + u] * self.cosines[v * 8 + y])
+                    .sum();
+            }
+        }
+
+        let mut output = [0u8; 64];
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let value: f32 = (0..8)
+                    .map(|u| columns[y * 8 + u] * self.cosines[u * 8 + x])
+                    .sum();
+
+                output[y * 8 + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+
+        output
+    }
+}
+
+/// Transforms the decoded image so it is displayed upright, per the EXIF orientation values.
+fn apply_orientation(image: RgbaImage, orientation: u16) -> RgbaImage {
+    if orientation <= 1 || orientation > 8 {
+        return image;
+    }
+
+    let (width, height) = (image.width as usize, image.height as usize);
+    let swaps_axes = orientation >= 5;
+    let (out_width, out_height) = if swaps_axes {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut output = RgbaImage::new(out_width as u32, out_height as u32);
+
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (source_x, source_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                _ => (width - 1 - y, x),
+            };
+
+            output.data[y * out_width + x] = image.data[source_y * width + source_x];
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::jpeg::{
+        ChromaSubsampling, JpegEncoderOptions, encode_to_jpeg, encode_to_jpeg_with, segment,
+    };
+
+    /// A DHT segment with a single table of `counts` codes per length.
+    fn dht(class_and_id: u8, counts: [u8; 16], values: &[u8]) -> Vec<u8> {
+        let mut data = vec![class_and_id];
+        data.extend_from_slice(&counts);
+        data.extend_from_slice(values);
+        segment(DHT, &data)
+    }
+
+    fn insert_before_scan(jpeg: &[u8], extra: &[u8]) -> Vec<u8> {
+        let sos = jpeg
+            .windows(2)
+            .position(|marker| marker == [0xFF, SOS])
+            .unwrap();
+
+        [&jpeg[..sos], extra, &jpeg[sos..]].concat()
+    }
+
+    #[test]
+    fn rejects_oversubscribed_huffman_table() {
+        let mut counts = [0; 16];
+        counts[0] = 3;
+
+        let jpeg = [&[0xFF, SOI][..], &dht(0x00, counts, &[0, 1, 2])].concat();
+
+        assert!(decode_jpeg(&jpeg).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_dc_size() {
+        let image = RgbaImage::from_pixel(8, 8, Rgba::new(128, 64, 32, 255));
+        let jpeg = encode_to_jpeg(&image, 90).unwrap();
+
+        // Both 1 bit codes decode to a DC size no 8-bit JPEG can have
+        let mut counts = [0; 16];
+        counts[0] = 2;
+        let jpeg = insert_before_scan(&jpeg, &dht(0x00, counts, &[255, 255]));
+
+        assert!(decode_jpeg(&jpeg).is_err());
+    }
+
+    /// A progressive 8x8 grayscale JPEG with a DC scan for level 192 and optionally an AC
+    /// scan adding a horizontal gradient, every table entry quantizing by 1
+    fn progressive_gray(with_ac: bool) -> Vec<u8> {
+        let mut counts = [0; 16];
+        counts[0] = 1;
+
+        let mut jpeg = [
+            &[0xFF, SOI][..],
+            &segment(DQT, &[[0].as_slice(), &[1; 64]].concat()),
+            &segment(SOF2, &[8, 0, 8, 0, 8, 1, 1, 0x11, 0]),
+            // DC difference 512 with 10 bits
+            &dht(0x00, counts, &[10]),
+            &segment(SOS, &[1, 1, 0x00, 0, 0, 0]),
+            &[0b0100_0000, 0b0001_1111],
+        ]
+        .concat();
+
+        if with_ac {
+            // First AC coefficient 64 with 7 bits
+            jpeg.extend_from_slice(&dht(0x10, counts, &[0x07]));
+            jpeg.extend_from_slice(&segment(SOS, &[1, 1, 0x00, 1, 1, 0]));
+            jpeg.push(0b0100_0000);
+        }
+
+        jpeg.extend_from_slice(&[0xFF, EOI]);
+        jpeg
+    }
+
+    /// An APP1 segment holding only an EXIF orientation.
+    fn exif_orientation(orientation: u16) -> Vec<u8> {
+        let mut data = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        data.extend_from_slice(&EXIF_ORIENTATION_TAG.to_be_bytes());
+        data.extend_from_slice(&[0, 3, 0, 0, 0, 1]);
+        data.extend_from_slice(&orientation.to_be_bytes());
+        data.extend_from_slice(&[0; 6]);
+        segment(APP1, &data)
+    }
+
+    #[test]
+    fn decodes_progressive_scans() {
+        let flat = decode_jpeg(&progressive_gray(false)).unwrap();
+        assert!(
+            flat.pixels()
+                .all(|&pixel| pixel == Rgba::new(192, 192, 192, 255))
+        );
+
+        let gradient = decode_jpeg(&progressive_gray(true)).unwrap();
+        let (left, right) = (gradient.get_pixel(0, 0), gradient.get_pixel(7, 0));
+
+        assert!(left.r > 200 && right.r < 184, "{:?} {:?}", left, right);
+        assert_eq!(gradient.get_pixel(0, 7), left);
+    }
+
+    #[test]
+    fn applies_exif_orientation() {
+        let mut image = RgbaImage::from_pixel(16, 8, Rgba::new(255, 255, 255, 255));
+        image
+            .crop_to_view_mut(0, 0, 8, 8)
+            .unwrap()
+            .fill(Rgba::new(0, 0, 0, 255));
+
+        let options = JpegEncoderOptions::new(95).with_subsampling(ChromaSubsampling::Yuv444);
+        let jpeg = encode_to_jpeg_with(&image, &options).unwrap();
+
+        // Rotating a quarter turn clockwise moves the left half to the top
+        let rotated = decode_jpeg(&insert_before_scan(&jpeg, &exif_orientation(6))).unwrap();
+        assert_eq!((rotated.width, rotated.height), (8, 16));
+        assert!(rotated.get_pixel(4, 2).r < 16);
+        assert!(rotated.get_pixel(4, 13).r > 240);
+
+        let unchanged = decode_jpeg(&insert_before_scan(&jpeg, &exif_orientation(9))).unwrap();
+        assert_eq!((unchanged.width, unchanged.height), (16, 8));
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let jpeg = encode_to_jpeg(&RgbaImage::new(16, 16), 90).unwrap();
+        let sos = jpeg
+            .windows(2)
+            .position(|marker| marker == [0xFF, SOS])
+            .unwrap();
+
+        assert!(decode_jpeg(&jpeg[..sos]).is_err());
+        assert!(decode_jpeg(&jpeg[..sos + 3]).is_err());
+        assert!(decode_jpeg(&[0xFF, SOI, 0xFF, SOF2, 0, 9, 8, 0, 0, 0, 0, 1]).is_err());
+        assert!(decode_jpeg(b"not a jpeg").is_err());
+    }
+
+    #[test]
+    fn rejects_frames_larger_than_their_data() {
+        let mut jpeg = encode_to_jpeg(&RgbaImage::new(16, 16), 90).unwrap();
+        let sof = jpeg
+            .windows(2)
+            .position(|marker| marker == [0xFF, SOF0])
+            .unwrap();
+
+        // Height and width follow the length and the sample precision
+        jpeg[sof + 5..sof + 9].copy_from_slice(&[0x4E, 0x20, 0x4E, 0x20]);
+
+        let error = decode_jpeg(&jpeg).unwrap_err();
+        assert!(error.to_string().contains("too short"), "{error}");
+    }
+
+    #[test]
+    fn rejects_truncated_scans() {
+        let mut image = RgbaImage::new(64, 64);
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = (index * 97 % 251) as u8;
+            *pixel = Rgba::new(value, 255 - value, value / 2, 255);
+        }
+
+        let jpeg = encode_to_jpeg(&image, 90).unwrap();
+        let sos = jpeg
+            .windows(2)
+            .position(|marker| marker == [0xFF, SOS])
+            .unwrap();
+        let scan = sos + 2 + u16::from_be_bytes([jpeg[sos + 2], jpeg[sos + 3]]) as usize;
+        let truncated = [&jpeg[..scan + 40], &[0xFF, EOI]].concat();
+
+        let error = decode_jpeg(&truncated).unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{error}");
+    }
+}
@@ -0,0 +1,536 @@
+use color_eyre::eyre::{Result, eyre};
+use std::{f32::consts::PI, io::Write};
+
+use crate::{
+    codecs::jpeg::{
+        APP0, CHROMA_AC_HUFFMAN, CHROMA_DC_HUFFMAN, CHROMA_QUANTIZATION, DHT, DQT, EOI,
+        HuffmanSpec, JpegEncoderOptions, LUMA_AC_HUFFMAN, LUMA_DC_HUFFMAN, LUMA_QUANTIZATION, SOF0,
+        SOI, SOS, ZIGZAG, scaled_quantization, segment,
+    },
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+/// Huffman codes indexed by symbol, as `(code, length)`.
+struct HuffmanCodes {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanCodes {
+    fn new(spec: &HuffmanSpec) -> Self {
+        let mut codes = [(0u16, 0u8); 256];
+        let mut code = 0u16;
+        let mut values = spec.values.iter();
+
+        for (length, &count) in spec.counts.iter().enumerate() {
+            for _ in 0..count {
+                if let Some(&value) = values.next() {
+                    codes[value as usize] = (code, length as u8 + 1);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+
+        Self { codes }
+    }
+}
+
+/// Entropy coded segment writer, stuffs a zero byte after every 0xFF.
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u16, size: u8) {
+        if size == 0 {
+            return;
+        }
+
+        self.buffer = (self.buffer << size) | (value as u32 & ((1 << size) - 1));
+        self.bits += size;
+
+        while self.bits >= 8 {
+            let byte = (self.buffer >> (self.bits - 8)) as u8;
+            self.output.push(byte);
+
+            if byte == 0xFF {
+                self.output.push(0);
+            }
+
+            self.bits -= 8;
+        }
+
+        self.buffer &= (1 << self.bits) - 1;
+    }
+
+    /// Pads the last byte with one bits.
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            let padding = 8 - self.bits;
+            self.write((1 << padding) - 1, padding);
+        }
+
+        self.output
+    }
+}
+
+struct Component {
+    id: u8,
+    /// Horizontal and vertical sampling factors
+    sampling: (usize, usize),
+    quantization: [u16; 64],
+    table_index: u8,
+    dc_codes: HuffmanCodes,
+    ac_codes: HuffmanCodes,
+    /// Samples at the component's own resolution, padded to whole MCUs
+    plane: Vec<f32>,
+    plane_width: usize,
+    previous_dc: i32,
+}
+
+/// Encodes `image` as a baseline JFIF into `writer`, returning the writer once the file is complete.
+pub fn write_jpeg<W: Write>(
+    mut writer: W,
+    image: &RgbaImage,
+    options: &JpegEncoderOptions,
+) -> Result<W> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("JPEG width and height must be greater than 0"));
+    }
+
+    if image.width > u16::MAX as u32 || image.height > u16::MAX as u32 {
+        return Err(eyre!(
+            "JPEG width and height can be at most {}, got {}x{}",
+            u16::MAX,
+            image.width,
+            image.height
+        ));
+    }
+
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (h_max, v_max) = options.subsampling.luma_factors();
+    let mcus_x = width.div_ceil(8 * h_max);
+    let mcus_y = height.div_ceil(8 * v_max);
+    let padded_width = mcus_x * 8 * h_max;
+    let padded_height = mcus_y * 8 * v_max;
+
+    let [luma, cb, cr] = ycbcr_planes(image, options.matte, padded_width, padded_height);
+
+    let luma_quantization = scaled_quantization(&LUMA_QUANTIZATION, options.quality);
+    let chroma_quantization = scaled_quantization(&CHROMA_QUANTIZATION, options.quality);
+
+    let chroma_width = padded_width / h_max;
+    let chroma_height = padded_height / v_max;
+
+    let mut components = [
+        Component {
+            id: 1,
+            sampling: (h_max, v_max),
+            quantization: luma_quantization,
+            table_index: 0,
+            dc_codes: HuffmanCodes::new(&LUMA_DC_HUFFMAN),
+            ac_codes: HuffmanCodes::new(&LUMA_AC_HUFFMAN),
+            plane: luma,
+            plane_width: padded_width,
+            previous_dc: 0,
+        },
+        Component {
+            id: 2,
+            sampling: (1, 1),
+            quantization: chroma_quantization,
+            table_index: 1,
+            dc_codes: HuffmanCodes::new(&CHROMA_DC_HUFFMAN),
+            ac_codes: HuffmanCodes::new(&CHROMA_AC_HUFFMAN),
+            plane: downsample(&cb, padded_width, h_max, v_max, chroma_width, chroma_height),
+            plane_width: chroma_width,
+            previous_dc: 0,
+        },
+        Component {
+            id: 3,
+            sampling: (1, 1),
+            quantization: chroma_quantization,
+            table_index: 1,
+            dc_codes: HuffmanCodes::new(&CHROMA_DC_HUFFMAN),
+            ac_codes: HuffmanCodes::new(&CHROMA_AC_HUFFMAN),
+            plane: downsample(&cr, padded_width, h_max, v_max, chroma_width, chroma_height),
+            plane_width: chroma_width,
+            previous_dc: 0,
+        },
+    ];
+
+    writer.write_all(&[0xFF, SOI])?;
+    writer.write_all(&segment(APP0, &jfif_header()))?;
+
+    for (index, table) in [luma_quantization, chroma_quantization].iter().enumerate() {
+        let mut data = vec![index as u8];
+        data.extend(ZIGZAG.iter().map(|&natural| table[natural] as u8));
+        writer.write_all(&segment(DQT, &data))?;
+    }
+
+    let mut frame_header: Vec<u8> = vec![8];
+    frame_header.extend_from_slice(&(height as u16).to_be_bytes());
+    frame_header.extend_from_slice(&(width as u16).to_be_bytes());
+    frame_header.push(components.len() as u8);
+    for component in &components {
+        frame_header.push(component.id);
+        frame_header.push(((component.sampling.0 as u8) << 4) | component.sampling.1 as u8);
+        frame_header.push(component.table_index);
+    }
+    writer.write_all(&segment(SOF0, &frame_header))?;
+
+    for (class, index, spec) in [
+        (0, 0, &LUMA_DC_HUFFMAN),
+        (1, 0, &LUMA_AC_HUFFMAN),
+        (0, 1, &CHROMA_DC_HUFFMAN),
+        (1, 1, &CHROMA_AC_HUFFMAN),
+    ] {
+        let mut data = vec![(class << 4) | index];
+        data.extend_from_slice(&spec.counts);
+        data.extend_from_slice(spec.values);
+        writer.write_all(&segment(DHT, &data))?;
+    }
+
+    let mut scan_header: Vec<u8> = vec![components.len() as u8];
+    for component in &components {
+        scan_header.push(component.id);
+        scan_header.push((component.table_index << 4) | component.table_index);
+    }
+    // Spectral selection 0-63, no successive approximation.
+    scan_header.extend_from_slice(&[0, 63, 0]);
+    writer.write_all(&segment(SOS, &scan_header))?;
+
+    let dct = DctTable::new();
+    let mut bits = BitWriter {
+        output: Vec::with_capacity(width * height / 4),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut block = [0f32; 64];
+
+    for mcu_y in 0..mcus_y {
+        for mcu_x in 0..mcus_x {
+            for component in components.iter_mut() {
+                let (h, v) = component.sampling;
+
+                for block_y in 0..v {
+                    for block_x in 0..h {
+                        let x = (mcu_x * h + block_x) * 8;
+                        let y = (mcu_y * v + block_y) * 8;
+
+                        for row in 0..8 {
+                            let start = (y + row) * component.plane_width + x;
+                            block[row * 8..row * 8 + 8]
+                                .copy_from_slice(&component.plane[start..start + 8]);
+                        }
+
+                        let coefficients = dct.forward(&block, &component.quantization);
+                        encode_block(&mut bits, component, &coefficients);
+                    }
+                }
+            }
+        }
+    }
+
+    writer.write_all(&bits.finish())?;
+    writer.write_all(&[0xFF, EOI])?;
+    writer.flush()?;
+
+    Ok(writer)
+}
+
+fn jfif_header() -> [u8; 14] {
+    let mut data = [0u8; 14];
+
+    data[0..5].copy_from_slice(b"JFIF\0");
+    // Version 1.01, densities in dots per inch.
+    data[5..8].copy_from_slice(&[1, 1, 1]);
+    data[8..10].copy_from_slice(&72u16.to_be_bytes());
+    data[10..12].copy_from_slice(&72u16.to_be_bytes());
+
+    data
+}
+
+/// Converts to level shifted Y, Cb and Cr planes of the padded size, repeating the last
+/// row and column into the padding.
+fn ycbcr_planes(
+    image: &RgbaImage,
+    matte: Rgba,
+    padded_width: usize,
+    padded_height: usize,
+) -> [Vec<f32>; 3] {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let mut planes = [
+        vec![0f32; padded_width * padded_height],
+        vec![0f32; padded_width * padded_height],
+        vec![0f32; padded_width * padded_height],
+    ];
+
+    for y in 0..padded_height {
+        for x in 0..padded_width {
+            let pixel = image.data[y.min(height - 1) * width + x.min(width - 1)];
+            let [r, g, b] = flatten(pixel, matte).map(|value| value as f32);
+            let index = y * padded_width + x;
+
+            planes[0][index] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+            planes[1][index] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+            planes[2][index] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+        }
+    }
+
+    planes
+}
+
+/// Composites a pixel over the opaque matte color.
+fn flatten(pixel: Rgba, matte: Rgba) -> [u8; 3] {
+    let alpha = pixel.a as u32;
+    let mix = |value: u8, background: u8| {
+        ((value as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8
+    };
+
+    [
+        mix(pixel.r, matte.r),
+        mix(pixel.g, matte.g),
+        mix(pixel.b, matte.b),
+    ]
+}
+
+/// Averages `h` by `v` boxes of a full resolution plane.
+fn downsample(
+    plane: &[f32],
+    plane_width: usize,
+    h: usize,
+    v: usize,
+    width: usize,
+    height: usize,
+) -> Vec<f32> {
+    if h == 1 && v == 1 {
+        return plane.to_vec();
+    }
+
+    let mut output = vec![0f32; width * height];
+    let scale = 1.0 / (h * v) as f32;
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+
+            for dy in 0..v {
+                let row = (y * v + dy) * plane_width;
+                for dx in 0..h {
+                    sum += plane[row + x * h + dx];
+                }
+            }
+
+            output[y * width + x] = sum * scale;
+        }
+    }
+
+    output
+}
+
+/// Separable forward DCT using a precomputed cosine table.
+struct DctTable {
+    /// `cos((2x + 1) * u * pi / 16) * c(u) / 2` at `[u * 8 + x]`
+    cosines: [f32; 64],
+}
+
+impl DctTable {
+    fn new() -> Self {
+        let mut cosines = [0f32; 64];
+
+        for u in 0..8 {
+            let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+
+            for x in 0..8 {
+                cosines[u * 8 + x] = scale * (((2 * x + 1) as f32 * u as f32 * PI) / 16.0).cos();
+            }
+        }
+
+        Self { cosines }
+    }
+
+    /// Transforms and quantizes a block, returning the coefficients in natural order.
+    fn forward(&self, block: &[f32; 64], quantization: &[u16; 64]) -> [i32; 64] {
+        let mut rows = [0f32; 64];
+
+        for y in 0..8 {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..8)
+                    .map(|x| block[y * 8 + x] * self.cosines[u * 8 + x])
+                    .sum();
+            }
+        }
+
+        let mut output = [0i32; 64];
+
+        for v in 0..8 {
+            for u in 0..8 {
+                let value: f32 = (0..8)
+                    .map(|y| rows[y * 8 + u] * self.cosines[v * 8 + y])
+                    .sum();
+
+                output[v * 8 + u] = (value / quantization[v * 8 + u] as f32).round() as i32;
+            }
+        }
+
+        output
+    }
+}
+
+/// Number of bits needed for `value`, and the bits as JPEG stores them.
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value - 1 } else { value };
+
+    (size, bits as u16)
+}
+
+fn encode_block(bits: &mut BitWriter, component: &mut Component, coefficients: &[i32; 64]) {
+    let dc = coefficients[0];
+    let (size, value) = magnitude(dc - component.previous_dc);
+    component.previous_dc = dc;
+
+    let (code, length) = component.dc_codes.codes[size as usize];
+    bits.write(code, length);
+    bits.write(value, size);
+
+    let mut zero_run = 0;
+
+    for &natural in &ZIGZAG[1..] {
+        // Baseline AC coefficients have at most 10 bits.
+        let coefficient = coefficients[natural].clamp(-1023, 1023);
+
+        if coefficient == 0 {
+            zero_run += 1;
+            continue;
+        }
+
+        while zero_run >= 16 {
+            let (code, length) = component.ac_codes.codes[0xF0];
+            bits.write(code, length);
+            zero_run -= 16;
+        }
+
+        let (size, value) = magnitude(coefficient);
+        let (code, length) = component.ac_codes.codes[(zero_run << 4) | size as usize];
+        bits.write(code, length);
+        bits.write(value, size);
+
+        zero_run = 0;
+    }
+
+    if zero_run > 0 {
+        let (code, length) = component.ac_codes.codes[0x00];
+        bits.write(code, length);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::jpeg::{
+        ChromaSubsampling, decoder::decode_jpeg, encode_to_jpeg, encode_to_jpeg_with,
+    };
+
+    /// Smooth gradients, which survive JPEG compression with little error
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g) = (x * 255 / width, y * 255 / height);
+                image.set_pixel(x, y, Rgba::new(r as u8, g as u8, 128, 255));
+            }
+        }
+
+        image
+    }
+
+    fn mean_error(a: &RgbaImage, b: &RgbaImage) -> f64 {
+        let total: u64 = a
+            .pixels()
+            .zip(b.pixels())
+            .flat_map(|(a, b)| [a.r.abs_diff(b.r), a.g.abs_diff(b.g), a.b.abs_diff(b.b)])
+            .map(u64::from)
+            .sum();
+
+        total as f64 / (a.data.len() * 3) as f64
+    }
+
+    #[test]
+    fn round_trips_every_subsampling() {
+        // Sizes that aren't a multiple of the MCU need padding
+        let image = gradient(37, 23);
+
+        for subsampling in [
+            ChromaSubsampling::Yuv444,
+            ChromaSubsampling::Yuv422,
+            ChromaSubsampling::Yuv420,
+        ] {
+            let options = JpegEncoderOptions::new(90).with_subsampling(subsampling);
+            let decoded = decode_jpeg(&encode_to_jpeg_with(&image, &options).unwrap()).unwrap();
+
+            assert_eq!((decoded.width, decoded.height), (37, 23));
+            assert!(decoded.pixels().all(|pixel| pixel.a == 255));
+            assert!(mean_error(&image, &decoded) < 3.0, "{:?}", subsampling);
+        }
+    }
+
+    #[test]
+    fn higher_quality_is_closer_and_larger() {
+        let mut image = gradient(64, 64);
+        image
+            .crop_to_view_mut(20, 20, 9, 9)
+            .unwrap()
+            .fill(Rgba::new(0, 0, 255, 255));
+
+        let low = encode_to_jpeg(&image, 10).unwrap();
+        let high = encode_to_jpeg(&image, 95).unwrap();
+
+        assert!(low.len() < high.len());
+        assert!(
+            mean_error(&image, &decode_jpeg(&high).unwrap())
+                < mean_error(&image, &decode_jpeg(&low).unwrap())
+        );
+    }
+
+    #[test]
+    fn flattens_alpha_against_the_matte() {
+        let image = RgbaImage::from_pixel(8, 8, Rgba::new(0, 0, 0, 0));
+        let options = JpegEncoderOptions::new(100)
+            .with_subsampling(ChromaSubsampling::Yuv444)
+            .with_matte(Rgba::new(200, 40, 40, 255));
+
+        let decoded = decode_jpeg(&encode_to_jpeg_with(&image, &options).unwrap()).unwrap();
+        let pixel = decoded.get_pixel(4, 4);
+
+        assert!(
+            pixel.r.abs_diff(200) <= 2 && pixel.g.abs_diff(40) <= 2,
+            "{:?}",
+            pixel
+        );
+    }
+
+    #[test]
+    fn scales_quantization_like_libjpeg() {
+        assert_eq!(
+            scaled_quantization(&LUMA_QUANTIZATION, 50),
+            LUMA_QUANTIZATION
+        );
+        assert_eq!(scaled_quantization(&LUMA_QUANTIZATION, 100), [1; 64]);
+        assert!(
+            scaled_quantization(&LUMA_QUANTIZATION, 0)
+                .iter()
+                .all(|&value| value <= 255)
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_sizes() {
+        assert!(encode_to_jpeg(&RgbaImage::new(0, 8), 90).is_err());
+        assert!(encode_to_jpeg(&RgbaImage::new(65536, 1), 90).is_err());
+    }
+}
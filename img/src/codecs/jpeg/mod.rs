@@ -0,0 +1,238 @@
+pub mod decoder;
+pub mod encoder;
+
+use color_eyre::eyre::Result;
+use std::io::Write;
+
+use crate::{
+    codecs::registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+pub use decoder::decode_jpeg;
+pub use encoder::write_jpeg;
+
+pub const SOI: u8 = 0xD8;
+pub const EOI: u8 = 0xD9;
+pub const SOF0: u8 = 0xC0;
+pub const DHT: u8 = 0xC4;
+pub const DQT: u8 = 0xDB;
+pub const SOS: u8 = 0xDA;
+pub const APP0: u8 = 0xE0;
+
+/// Natural (row-major) index of each coefficient in zigzag order.
+pub const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Luminance quantization table from Annex K of the JPEG specification, in natural order.
+pub const LUMA_QUANTIZATION: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Chrominance quantization table from Annex K of the JPEG specification, in natural order.
+pub const CHROMA_QUANTIZATION: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// Huffman table as stored in a DHT segment: code counts per length and the symbols.
+#[derive(Debug, Clone, Copy)]
+pub struct HuffmanSpec {
+    pub counts: [u8; 16],
+    pub values: &'static [u8],
+}
+
+pub const LUMA_DC_HUFFMAN: HuffmanSpec = HuffmanSpec {
+    counts: [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    values: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+pub const CHROMA_DC_HUFFMAN: HuffmanSpec = HuffmanSpec {
+    counts: [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    values: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+pub const LUMA_AC_HUFFMAN: HuffmanSpec = HuffmanSpec {
+    counts: [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D],
+    values: &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52,
+        0xD1, 0xF0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6,
+        0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3,
+        0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8,
+        0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+};
+
+pub const CHROMA_AC_HUFFMAN: HuffmanSpec = HuffmanSpec {
+    counts: [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    values: &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33,
+        0x52, 0xF0, 0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18,
+        0x19, 0x1A, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4,
+        0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA,
+        0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7,
+        0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+};
+
+/// How much the Cb and Cr planes are reduced compared to luma.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromaSubsampling {
+    /// Full resolution chroma
+    Yuv444,
+    /// Half horizontal resolution
+    Yuv422,
+    /// Half horizontal and vertical resolution
+    #[default]
+    Yuv420,
+}
+
+impl ChromaSubsampling {
+    /// Horizontal and vertical sampling factors of the luma component.
+    pub const fn luma_factors(&self) -> (usize, usize) {
+        match self {
+            Self::Yuv444 => (1, 1),
+            Self::Yuv422 => (2, 1),
+            Self::Yuv420 => (2, 2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegEncoderOptions {
+    /// From 1 (smallest) to 100 (best), scales the Annex K quantization tables like libjpeg
+    pub quality: u8,
+    pub subsampling: ChromaSubsampling,
+    /// Color translucent pixels are flattened against, JPEG has no alpha channel
+    pub matte: Rgba,
+}
+
+impl Default for JpegEncoderOptions {
+    fn default() -> Self {
+        Self {
+            quality: 90,
+            subsampling: ChromaSubsampling::Yuv420,
+            matte: Rgba::new(255, 255, 255, 255),
+        }
+    }
+}
+
+impl JpegEncoderOptions {
+    pub fn new(quality: u8) -> Self {
+        Self {
+            quality,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_subsampling(mut self, subsampling: ChromaSubsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    pub fn with_matte(mut self, matte: Rgba) -> Self {
+        self.matte = matte;
+        self
+    }
+}
+
+pub fn encode_to_jpeg(image: &RgbaImage, quality: u8) -> Result<Vec<u8>> {
+    encode_to_jpeg_with(image, &JpegEncoderOptions::new(quality))
+}
+
+pub fn encode_to_jpeg_with(image: &RgbaImage, options: &JpegEncoderOptions) -> Result<Vec<u8>> {
+    write_jpeg(Vec::new(), image, options)
+}
+
+/// Scales a base quantization table the same way libjpeg does for its quality setting.
+pub fn scaled_quantization(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+
+    base.map(|value| ((value as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// Writes a marker segment, the length covers itself and `data`.
+pub fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len() + 4);
+
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    output.extend_from_slice(data);
+
+    output
+}
+
+/// JPEG for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JpegCodec {
+    pub options: JpegEncoderOptions,
+}
+
+impl JpegCodec {
+    pub const fn new(options: JpegEncoderOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl ImageFormat for JpegCodec {
+    fn name(&self) -> &'static str {
+        "JPEG"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/jpeg"
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &["image/jpg", "image/pjpeg"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["jpg", "jpeg", "jpe", "jfif"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(&[0xFF, 0xD8, 0xFF])
+    }
+}
+
+impl ImageEncoder for JpegCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_jpeg_with(image, &self.options)
+    }
+
+    fn write(&self, writer: &mut dyn Write, image: &RgbaImage) -> Result<()> {
+        write_jpeg(writer, image, &self.options)?;
+
+        Ok(())
+    }
+}
+
+impl ImageDecoder for JpegCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_jpeg(data)
+    }
+}
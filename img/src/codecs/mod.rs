@@ -1,20 +1,458 @@
+pub mod apng;
+pub mod bmp;
+pub mod farbfeld;
+pub mod gif;
+pub mod ico;
+pub mod jpeg;
 pub mod png;
+pub mod pnm;
+pub mod qoi;
+pub mod registry;
+pub mod tga;
+pub mod webp;
 
-use std::{fs::write, path::PathBuf};
+use std::{
+    fs::{File, read, write},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use color_eyre::eyre::{Result, eyre};
 
-pub use crate::{codecs::png::encode_to_png, rgba_image::RgbaImage};
+pub use crate::{
+    codecs::apng::{ApngEncoderOptions, ApngFrame, encode_to_apng},
+    codecs::bmp::{
+        BmpCodec, BmpEncoderOptions, BmpFormat, decode_bmp, encode_to_bmp, encode_to_bmp_with,
+    },
+    codecs::farbfeld::{FarbfeldCodec, decode_farbfeld, encode_to_farbfeld},
+    codecs::gif::{
+        GifCodec, GifColorTables, GifDisposal, GifEncoderOptions, GifFrame, encode_frames_to_gif,
+        encode_to_gif,
+    },
+    codecs::ico::{
+        IcoCodec, IcoEncoderOptions, IcoEntry, IcoKind, decode_ico, decode_ico_entries,
+        encode_to_cur, encode_to_ico, encode_to_ico_with,
+    },
+    codecs::jpeg::{
+        ChromaSubsampling, JpegCodec, JpegEncoderOptions, decode_jpeg, encode_to_jpeg,
+        encode_to_jpeg_with, write_jpeg,
+    },
+    codecs::png::{
+        FilterStrategy, PngCodec, PngEncoderOptions, PngMetadata, PngWriter, decode_png,
+        decode_png_with_metadata, encode_to_png, encode_to_png_with, encode_to_png_with_metadata,
+        encode_to_png16, write_png, write_png16,
+    },
+    codecs::pnm::{
+        PnmCodec, PnmEncoderOptions, PnmEncoding, PnmFormat, decode_pnm, encode_to_pnm,
+        encode_to_pnm_with,
+    },
+    codecs::qoi::{QoiCodec, QoiEncoderOptions, decode_qoi, encode_to_qoi, encode_to_qoi_with},
+    codecs::registry::{
+        CodecRegistry, ImageDecoder, ImageEncoder, ImageFormat, matches_mime, registry,
+        registry_mut,
+    },
+    codecs::tga::{
+        TgaCodec, TgaEncoderOptions, TgaFormat, decode_tga, encode_to_tga, encode_to_tga_with,
+        is_tga,
+    },
+    codecs::webp::{WebpCodec, WebpEncoderOptions, encode_to_webp, encode_to_webp_with},
+    rgba_image::RgbaImage,
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Codecs {
     PNG,
+    QOI,
+    BMP,
+    PBM,
+    PGM,
+    PPM,
+    PAM,
+    Farbfeld,
+    GIF,
+    JPEG { quality: u8 },
+    WEBP,
+    ICO,
+    CUR,
+    TGA,
+}
+
+impl Codecs {
+    /// Every built-in format, JPEG at its default quality. TGA has no magic number, so it
+    /// comes last for `detect`.
+    fn all() -> [Self; 14] {
+        [
+            Self::PNG,
+            Self::QOI,
+            Self::BMP,
+            Self::PBM,
+            Self::PGM,
+            Self::PPM,
+            Self::PAM,
+            Self::Farbfeld,
+            Self::GIF,
+            Self::JPEG {
+                quality: JpegEncoderOptions::default().quality,
+            },
+            Self::WEBP,
+            Self::ICO,
+            Self::CUR,
+            Self::TGA,
+        ]
+    }
+
+    /// Case-insensitive file extension without the leading dot, e.g. `"png"` or `"JPG"`.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+
+        Self::all()
+            .into_iter()
+            .find(|codec| codec.encoder().extensions().contains(&extension.as_str()))
+    }
+
+    /// Ignores parameters such as `; charset=binary`.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|codec| matches_mime(codec.encoder().as_ref(), mime))
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// Recognizes the format from the magic bytes at the start of the data, with the same
+    /// probes the codec registry uses.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|codec| codec.encoder().probe(data))
+    }
+
+    /// The built-in encoder for this format, with default options apart from the JPEG quality.
+    pub fn encoder(&self) -> Arc<dyn ImageEncoder> {
+        match self {
+            Self::PNG => Arc::new(PngCodec::default()),
+            Self::QOI => Arc::new(QoiCodec::default()),
+            Self::BMP => Arc::new(BmpCodec::default()),
+            Self::PBM => Arc::new(PnmCodec::pbm()),
+            Self::PGM => Arc::new(PnmCodec::pgm()),
+            Self::PPM => Arc::new(PnmCodec::ppm()),
+            Self::PAM => Arc::new(PnmCodec::pam()),
+            Self::Farbfeld => Arc::new(FarbfeldCodec),
+            Self::GIF => Arc::new(GifCodec::default()),
+            Self::JPEG { quality } => Arc::new(JpegCodec::new(JpegEncoderOptions::new(*quality))),
+            Self::WEBP => Arc::new(WebpCodec::default()),
+            Self::ICO => Arc::new(IcoCodec::default()),
+            Self::CUR => Arc::new(IcoCodec::cursor()),
+            Self::TGA => Arc::new(TgaCodec::default()),
+        }
+    }
+
+    /// The preferred extension of the format.
+    pub fn extension(&self) -> &'static str {
+        self.encoder().extensions()[0]
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        self.encoder().mime_type()
+    }
+}
+
+/// Decodes any format in the registry, detected from the data itself. ICO files give
+/// their largest image.
+pub fn decode(data: &[u8]) -> Result<RgbaImage> {
+    let decoder = registry().decoder_for(data);
+
+    match decoder {
+        Some(decoder) => decoder.decode(data),
+        None => Err(match Codecs::detect(data) {
+            Some(codec) => eyre!(
+                "Decoding {} images is not supported",
+                codec.encoder().name()
+            ),
+            None => eyre!("Unrecognized image format"),
+        }),
+    }
+}
+
+/// Encodes with the built-in codec and its default options.
+pub fn encode(image: &RgbaImage, codec: Codecs) -> Result<Vec<u8>> {
+    codec.encoder().encode(image)
+}
+
+/// Writes the image to exactly `path`, with the codec looked up in the registry by its
+/// extension when `None`.
+pub fn save_image(path: PathBuf, image: &RgbaImage, codec: Option<Codecs>) -> Result<()> {
+    let encoder = match codec {
+        Some(codec) => codec.encoder(),
+        None => registry()
+            .encoder_for_path(&path)
+            .ok_or_else(|| eyre!("Can't tell the image format from the path {:?}", path))?,
+    };
+
+    save_image_with(path, image, encoder.as_ref())
+}
+
+pub fn save_image_with(path: PathBuf, image: &RgbaImage, encoder: &dyn ImageEncoder) -> Result<()> {
+    let file = File::create(path.clone())
+        .map_err(|e| eyre!("Failed to create {} file {:?}: {}", encoder.name(), path, e))?;
+    let mut writer = BufWriter::new(file);
+
+    encoder
+        .write(&mut writer, image)
+        .and_then(|_| writer.flush().map_err(Into::into))
+        .map_err(|e| {
+            eyre!(
+                "Failed to write {} file to {:?}: {}",
+                encoder.name(),
+                path,
+                e
+            )
+        })?;
+
+    Ok(())
+}
+
+pub fn load_image(path: PathBuf) -> Result<RgbaImage> {
+    let data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read image file {:?}: {}", path, e))?;
+
+    decode(&data)
 }
 
 pub fn save_png(path: PathBuf, image: &RgbaImage) -> Result<()> {
-    let png_data = encode_to_png(image)?;
+    let file = File::create(path.clone())
+        .map_err(|e| eyre!("Failed to create PNG file {:?}: {}", path, e))?;
+
+    write_png(
+        BufWriter::new(file),
+        image,
+        &PngEncoderOptions::default(),
+        &PngMetadata::default(),
+    )
+    .map_err(|e| eyre!("Failed to write PNG file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
 
-    write(path.clone(), png_data)
-        .map_err(|e| eyre!("Failed to write PNG file to {:?}: {}", path, e))?;
+pub fn load_png(path: PathBuf) -> Result<RgbaImage> {
+    let png_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read PNG file {:?}: {}", path, e))?;
+
+    decode_png(&png_data)
+}
+
+pub fn save_apng(path: PathBuf, frames: &[ApngFrame]) -> Result<()> {
+    let apng_data = encode_to_apng(frames, &ApngEncoderOptions::default())?;
+
+    write(path.clone(), apng_data)
+        .map_err(|e| eyre!("Failed to write APNG file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn save_qoi(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let qoi_data = encode_to_qoi(image)?;
+
+    write(path.clone(), qoi_data)
+        .map_err(|e| eyre!("Failed to write QOI file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_qoi(path: PathBuf) -> Result<RgbaImage> {
+    let qoi_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read QOI file {:?}: {}", path, e))?;
+
+    decode_qoi(&qoi_data)
+}
+
+pub fn save_bmp(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let bmp_data = encode_to_bmp(image)?;
+
+    write(path.clone(), bmp_data)
+        .map_err(|e| eyre!("Failed to write BMP file to {:?}: {}", path, e))?;
 
     Ok(())
 }
+
+pub fn load_bmp(path: PathBuf) -> Result<RgbaImage> {
+    let bmp_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read BMP file {:?}: {}", path, e))?;
+
+    decode_bmp(&bmp_data)
+}
+
+pub fn save_pnm(path: PathBuf, image: &RgbaImage, format: PnmFormat) -> Result<()> {
+    let pnm_data = encode_to_pnm(image, format)?;
+
+    write(path.clone(), pnm_data)
+        .map_err(|e| eyre!("Failed to write PNM file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_pnm(path: PathBuf) -> Result<RgbaImage> {
+    let pnm_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read PNM file {:?}: {}", path, e))?;
+
+    decode_pnm(&pnm_data)
+}
+
+pub fn save_farbfeld(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let farbfeld_data = encode_to_farbfeld(image)?;
+
+    write(path.clone(), farbfeld_data)
+        .map_err(|e| eyre!("Failed to write farbfeld file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_farbfeld(path: PathBuf) -> Result<RgbaImage> {
+    let farbfeld_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read farbfeld file {:?}: {}", path, e))?;
+
+    decode_farbfeld(&farbfeld_data)
+}
+
+pub fn save_gif(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let gif_data = encode_to_gif(image)?;
+
+    write(path.clone(), gif_data)
+        .map_err(|e| eyre!("Failed to write GIF file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn save_animated_gif(path: PathBuf, frames: &[GifFrame]) -> Result<()> {
+    let gif_data = encode_frames_to_gif(frames, &GifEncoderOptions::default())?;
+
+    write(path.clone(), gif_data)
+        .map_err(|e| eyre!("Failed to write GIF file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn save_jpeg(path: PathBuf, image: &RgbaImage, quality: u8) -> Result<()> {
+    let file = File::create(path.clone())
+        .map_err(|e| eyre!("Failed to create JPEG file {:?}: {}", path, e))?;
+
+    write_jpeg(
+        BufWriter::new(file),
+        image,
+        &JpegEncoderOptions::new(quality),
+    )
+    .map_err(|e| eyre!("Failed to write JPEG file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_jpeg(path: PathBuf) -> Result<RgbaImage> {
+    let jpeg_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read JPEG file {:?}: {}", path, e))?;
+
+    decode_jpeg(&jpeg_data)
+}
+
+pub fn save_webp(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let webp_data = encode_to_webp(image)?;
+
+    write(path.clone(), webp_data)
+        .map_err(|e| eyre!("Failed to write WebP file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn save_ico(path: PathBuf, images: &[RgbaImage]) -> Result<()> {
+    let ico_data = encode_to_ico(images)?;
+
+    write(path.clone(), ico_data)
+        .map_err(|e| eyre!("Failed to write ICO file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn save_cur(path: PathBuf, entries: &[IcoEntry]) -> Result<()> {
+    let cur_data = encode_to_cur(entries)?;
+
+    write(path.clone(), cur_data)
+        .map_err(|e| eyre!("Failed to write CUR file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_ico(path: PathBuf) -> Result<Vec<RgbaImage>> {
+    let ico_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read ICO file {:?}: {}", path, e))?;
+
+    decode_ico(&ico_data)
+}
+
+pub fn save_tga(path: PathBuf, image: &RgbaImage) -> Result<()> {
+    let tga_data = encode_to_tga(image)?;
+
+    write(path.clone(), tga_data)
+        .map_err(|e| eyre!("Failed to write TGA file to {:?}: {}", path, e))?;
+
+    Ok(())
+}
+
+pub fn load_tga(path: PathBuf) -> Result<RgbaImage> {
+    let tga_data =
+        read(path.clone()).map_err(|e| eyre!("Failed to read TGA file {:?}: {}", path, e))?;
+
+    decode_tga(&tga_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba::Rgba;
+
+    #[test]
+    fn lookups_agree_with_the_encoders() {
+        for codec in Codecs::all() {
+            let encoder = codec.encoder();
+
+            for extension in encoder.extensions() {
+                assert_eq!(Codecs::from_extension(extension), Some(codec));
+            }
+
+            for mime in std::iter::once(&encoder.mime_type()).chain(encoder.mime_aliases()) {
+                assert_eq!(Codecs::from_mime(mime), Some(codec));
+            }
+        }
+    }
+
+    #[test]
+    fn lookups_ignore_case_and_parameters() {
+        assert_eq!(
+            Codecs::from_extension("JPG").map(|codec| codec.extension()),
+            Some("jpg")
+        );
+        assert_eq!(
+            Codecs::from_mime("Image/PNG; charset=binary"),
+            Some(Codecs::PNG)
+        );
+        assert_eq!(Codecs::from_mime("image/x-icon"), Some(Codecs::ICO));
+        assert_eq!(Codecs::from_extension("txt"), None);
+    }
+
+    #[test]
+    fn detects_what_it_encodes() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba::new(200, 100, 50, 255));
+
+        for codec in Codecs::all() {
+            let data = encode(&image, codec).unwrap();
+
+            assert_eq!(
+                Codecs::detect(&data).map(|codec| codec.extension()),
+                Some(codec.extension())
+            );
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use color_eyre::eyre::{Result, eyre};
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+use tracing::warn;
+
+use crate::{
+    codecs::png::{
+        ColorType, PNG_HEADER, crc,
+        filter::{FilterType, unfilter_row},
+        metadata::PngMetadata,
+    },
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const PNG_PIXELS_MAX: u64 = 400_000_000;
+
+/// Adam7 passes as (x offset, y offset, x step, y step).
+const ADAM7_PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+pub struct Chunk<'a> {
+    pub chunk_type: [u8; 4],
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlaced: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transparency {
+    None,
+    Gray(u16),
+    Rgb(u16, u16, u16),
+}
+
+struct RowDecoder {
+    header: Header,
+    palette: Vec<Rgba>,
+    transparency: Transparency,
+}
+
+pub fn decode_png(data: &[u8]) -> Result<RgbaImage> {
+    let (image, _) = decode_png_with_metadata(data)?;
+
+    Ok(image)
+}
+
+pub fn decode_png_with_metadata(data: &[u8]) -> Result<(RgbaImage, PngMetadata)> {
+    let chunks = read_chunks(data)?;
+
+    let first = chunks.first().ok_or_else(|| eyre!("PNG has no chunks"))?;
+    if &first.chunk_type != b"IHDR" {
+        return Err(eyre!("First PNG chunk must be IHDR"));
+    }
+    let header = parse_ihdr(first.data)?;
+
+    let mut palette: Vec<Rgba> = vec![];
+    let mut trns: Option<&[u8]> = None;
+    let mut compressed: Vec<u8> = vec![];
+    let mut seen_idat = false;
+    let mut seen_iend = false;
+    let mut metadata = PngMetadata::new();
+
+    for chunk in &chunks[1..] {
+        if seen_iend {
+            return Err(eyre!("PNG has chunks after IEND"));
+        }
+
+        match &chunk.chunk_type {
+            b"IHDR" => return Err(eyre!("PNG has more than one IHDR chunk")),
+            b"PLTE" => {
+                if seen_idat {
+                    return Err(eyre!("PLTE chunk must come before IDAT"));
+                }
+                palette = parse_plte(chunk.data)?;
+            }
+            b"tRNS" => {
+                if seen_idat {
+                    return Err(eyre!("tRNS chunk must come before IDAT"));
+                }
+                trns = Some(chunk.data);
+            }
+            b"IDAT" => {
+                seen_idat = true;
+                compressed.extend_from_slice(chunk.data);
+            }
+            b"IEND" => seen_iend = true,
+            chunk_type if chunk_type[0].is_ascii_uppercase() => {
+                return Err(eyre!(
+                    "Unknown critical PNG chunk: {}",
+                    String::from_utf8_lossy(chunk_type)
+                ));
+            }
+            chunk_type => {
+                if let Err(e) = metadata.read_chunk(chunk_type, chunk.data) {
+                    warn!(
+                        "Ignoring invalid {} chunk: {}",
+                        String::from_utf8_lossy(chunk_type),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    if !seen_idat {
+        return Err(eyre!("PNG has no IDAT chunk"));
+    }
+
+    if !seen_iend {
+        return Err(eyre!("PNG is missing the IEND chunk"));
+    }
+
+    if header.color_type == ColorType::Indexed && palette.is_empty() {
+        return Err(eyre!("Indexed PNG is missing the PLTE chunk"));
+    }
+
+    let transparency = match trns {
+        Some(trns) => parse_trns(trns, header.color_type, &mut palette)?,
+        None => Transparency::None,
+    };
+
+    let decoder = RowDecoder {
+        header,
+        palette,
+        transparency,
+    };
+
+    // One byte past what the image needs is enough to tell that there's too much data
+    let expected = decoder.raw_len();
+    let mut raw_data: Vec<u8> = vec![];
+    ZlibDecoder::new(compressed.as_slice())
+        .take(expected as u64 + 1)
+        .read_to_end(&mut raw_data)
+        .map_err(|e| eyre!("Failed to decompress PNG image data: {}", e))?;
+
+    if raw_data.len() < expected {
+        return Err(eyre!(
+            "PNG image data is too short: expected {} bytes, got {}",
+            expected,
+            raw_data.len()
+        ));
+    }
+
+    if raw_data.len() > expected {
+        warn!("Ignoring extra PNG image data past {} bytes", expected);
+    }
+
+    Ok((decoder.decode(&raw_data)?, metadata))
+}
+
+pub fn read_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    if data.len() < PNG_HEADER.len() || data[..PNG_HEADER.len()] != PNG_HEADER {
+        return Err(eyre!("Data is not a PNG file"));
+    }
+
+    let mut chunks: Vec<Chunk> = vec![];
+    let mut pos = PNG_HEADER.len();
+
+    while pos < data.len() {
+        if data.len() - pos < 12 {
+            return Err(eyre!("Truncated PNG chunk at offset {}", pos));
+        }
+
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let chunk_type: [u8; 4] = data[pos + 4..pos + 8].try_into()?;
+
+        let data_start = pos + 8;
+        let data_end = data_start
+            .checked_add(length)
+            .filter(|end| end + 4 <= data.len())
+            .ok_or_else(|| {
+                eyre!(
+                    "PNG chunk {} is longer than the file",
+                    String::from_utf8_lossy(&chunk_type)
+                )
+            })?;
+
+        let chunk_data = &data[data_start..data_end];
+        let expected_crc: [u8; 4] = data[data_end..data_end + 4].try_into()?;
+
+        if crc(&chunk_type, chunk_data) != expected_crc {
+            return Err(eyre!(
+                "CRC mismatch in PNG chunk {}",
+                String::from_utf8_lossy(&chunk_type)
+            ));
+        }
+
+        chunks.push(Chunk {
+            chunk_type,
+            data: chunk_data,
+        });
+
+        pos = data_end + 4;
+    }
+
+    Ok(chunks)
+}
+
+fn parse_ihdr(data: &[u8]) -> Result<Header> {
+    if data.len() != 13 {
+        return Err(eyre!("IHDR chunk must be 13 bytes long"));
+    }
+
+    let width = u32::from_be_bytes(data[0..4].try_into()?);
+    let height = u32::from_be_bytes(data[4..8].try_into()?);
+    let bit_depth = data[8];
+    let color_type = ColorType::try_from(data[9])?;
+
+    if width == 0 || height == 0 {
+        return Err(eyre!("PNG width and height must be greater than 0"));
+    }
+
+    if width as u64 * height as u64 > PNG_PIXELS_MAX {
+        return Err(eyre!("PNG image is too large: {}x{}", width, height));
+    }
+
+    if !color_type.allowed_bit_depths().contains(&bit_depth) {
+        return Err(eyre!(
+            "Bit depth {} is not allowed for color type {:?}",
+            bit_depth,
+            color_type
+        ));
+    }
+
+    if data[10] != 0 {
+        return Err(eyre!("Unknown PNG compression method: {}", data[10]));
+    }
+
+    if data[11] != 0 {
+        return Err(eyre!("Unknown PNG filter method: {}", data[11]));
+    }
+
+    let interlaced = match data[12] {
+        0 => false,
+        1 => true,
+        method => return Err(eyre!("Unknown PNG interlace method: {}", method)),
+    };
+
+    Ok(Header {
+        width,
+        height,
+        bit_depth,
+        color_type,
+        interlaced,
+    })
+}
+
+fn parse_plte(data: &[u8]) -> Result<Vec<Rgba>> {
+    if data.is_empty() || !data.len().is_multiple_of(3) || data.len() / 3 > 256 {
+        return Err(eyre!("Invalid PLTE chunk length: {}", data.len()));
+    }
+
+    Ok(data
+        .chunks_exact(3)
+        .map(|c| Rgba::new(c[0], c[1], c[2], 255))
+        .collect())
+}
+
+fn parse_trns(data: &[u8], color_type: ColorType, palette: &mut [Rgba]) -> Result<Transparency> {
+    match color_type {
+        ColorType::Grayscale if data.len() == 2 => {
+            Ok(Transparency::Gray(u16::from_be_bytes([data[0], data[1]])))
+        }
+        ColorType::Rgb if data.len() == 6 => Ok(Transparency::Rgb(
+            u16::from_be_bytes([data[0], data[1]]),
+            u16::from_be_bytes([data[2], data[3]]),
+            u16::from_be_bytes([data[4], data[5]]),
+        )),
+        ColorType::Indexed if data.len() <= palette.len() => {
+            for (entry, alpha) in palette.iter_mut().zip(data) {
+                entry.a = *alpha;
+            }
+            Ok(Transparency::None)
+        }
+        ColorType::GrayscaleAlpha | ColorType::Rgba => {
+            Err(eyre!("tRNS chunk is not allowed for images with alpha"))
+        }
+        _ => Err(eyre!("Invalid tRNS chunk length: {}", data.len())),
+    }
+}
+
+impl RowDecoder {
+    fn bits_per_pixel(&self) -> usize {
+        self.header.color_type.channels() * self.header.bit_depth as usize
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    /// Size of the inflated image data, a filter byte and the filtered bytes per row of
+    /// every pass.
+    fn raw_len(&self) -> usize {
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+
+        if !self.header.interlaced {
+            return (self.row_bytes(width) + 1) * height;
+        }
+
+        ADAM7_PASSES
+            .iter()
+            .map(|&(x_start, y_start, x_step, y_step)| {
+                let (pass_width, pass_height) =
+                    pass_dimensions(width, height, (x_start, y_start, x_step, y_step));
+
+                if pass_width == 0 {
+                    0
+                } else {
+                    (self.row_bytes(pass_width) + 1) * pass_height
+                }
+            })
+            .sum()
+    }
+
+    fn decode(&self, raw_data: &[u8]) -> Result<RgbaImage> {
+        let width = self.header.width as usize;
+        let height = self.header.height as usize;
+
+        let mut image = RgbaImage::new(self.header.width, self.header.height);
+
+        if !self.header.interlaced {
+            self.decode_pass(raw_data, width, height, |x, y, color| {
+                image.data[y * width + x] = color;
+            })?;
+
+            return Ok(image);
+        }
+
+        let mut offset = 0;
+        for (x_start, y_start, x_step, y_step) in ADAM7_PASSES {
+            let (pass_width, pass_height) =
+                pass_dimensions(width, height, (x_start, y_start, x_step, y_step));
+
+            if pass_width == 0 || pass_height == 0 {
+                continue;
+            }
+
+            offset += self.decode_pass(
+                &raw_data[offset.min(raw_data.len())..],
+                pass_width,
+                pass_height,
+                |x, y, color| {
+                    let px = x_start + x * x_step;
+                    let py = y_start + y * y_step;
+                    image.data[py * width + px] = color;
+                },
+            )?;
+        }
+
+        Ok(image)
+    }
+
+    /// Unfilters and converts one (sub)image, returning the number of raw bytes consumed.
+    fn decode_pass(
+        &self,
+        raw_data: &[u8],
+        width: usize,
+        height: usize,
+        mut put_pixel: impl FnMut(usize, usize, Rgba),
+    ) -> Result<usize> {
+        let row_bytes = self.row_bytes(width);
+        let bpp = self.bits_per_pixel().div_ceil(8);
+        let needed = (row_bytes + 1) * height;
+
+        if raw_data.len() < needed {
+            return Err(eyre!(
+                "PNG image data is too short: expected {} bytes, got {}",
+                needed,
+                raw_data.len()
+            ));
+        }
+
+        let mut prev = vec![0u8; row_bytes];
+        let mut row = vec![0u8; row_bytes];
+
+        for y in 0..height {
+            let start = y * (row_bytes + 1);
+            let filter = FilterType::try_from(raw_data[start])?;
+            row.copy_from_slice(&raw_data[start + 1..start + 1 + row_bytes]);
+
+            unfilter_row(filter, &mut row, &prev, bpp);
+
+            for x in 0..width {
+                put_pixel(x, y, self.pixel(&row, x));
+            }
+
+            std::mem::swap(&mut row, &mut prev);
+        }
+
+        Ok(needed)
+    }
+
+    fn pixel(&self, row: &[u8], x: usize) -> Rgba {
+        let depth = self.header.bit_depth;
+        let channels = self.header.color_type.channels();
+        let sample = |channel: usize| read_sample(row, x * channels + channel, depth);
+        let scale = |value: u16| scale_to_u8(value, depth);
+
+        match self.header.color_type {
+            ColorType::Grayscale => {
+                let value = sample(0);
+                let gray = scale(value);
+                let a = match self.transparency {
+                    Transparency::Gray(key) if key == value => 0,
+                    _ => 255,
+                };
+                Rgba::new(gray, gray, gray, a)
+            }
+            ColorType::Rgb => {
+                let (r, g, b) = (sample(0), sample(1), sample(2));
+                let a = match self.transparency {
+                    Transparency::Rgb(kr, kg, kb) if (kr, kg, kb) == (r, g, b) => 0,
+                    _ => 255,
+                };
+                Rgba::new(scale(r), scale(g), scale(b), a)
+            }
+            ColorType::Indexed => self
+                .palette
+                .get(sample(0) as usize)
+                .copied()
+                .unwrap_or_default(),
+            ColorType::GrayscaleAlpha => {
+                let gray = scale(sample(0));
+                Rgba::new(gray, gray, gray, scale(sample(1)))
+            }
+            ColorType::Rgba => Rgba::new(
+                scale(sample(0)),
+                scale(sample(1)),
+                scale(sample(2)),
+                scale(sample(3)),
+            ),
+        }
+    }
+}
+
+/// Size of the subimage an Adam7 pass covers, 0 when it has no pixels.
+fn pass_dimensions(
+    width: usize,
+    height: usize,
+    (x_start, y_start, x_step, y_step): (usize, usize, usize, usize),
+) -> (usize, usize) {
+    (
+        (width + x_step - 1).saturating_sub(x_start) / x_step,
+        (height + y_step - 1).saturating_sub(y_start) / y_step,
+    )
+}
+
+fn read_sample(row: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            let mask = (1u16 << depth) - 1;
+            (row[bit / 8] as u16 >> shift) & mask
+        }
+    }
+}
+
+fn scale_to_u8(value: u16, depth: u8) -> u8 {
+    match depth {
+        16 => ((value as u32 * 255 + 32767) / 65535) as u8,
+        8 => value as u8,
+        _ => (value as u32 * 255 / ((1u32 << depth) - 1)) as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::png::{chunk_wrapper, ihdr_chunk};
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+
+    fn png(width: u32, height: u32, raw_data: &[u8]) -> Vec<u8> {
+        png_with_header(ihdr_chunk(width, height, 8, ColorType::Rgba), raw_data)
+    }
+
+    fn png_with_header(ihdr: [u8; 13], raw_data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw_data).unwrap();
+
+        [
+            PNG_HEADER.to_vec(),
+            chunk_wrapper(b"IHDR", &ihdr),
+            chunk_wrapper(b"IDAT", &encoder.finish().unwrap()),
+            chunk_wrapper(b"IEND", &[]),
+        ]
+        .concat()
+    }
+
+    fn gray(value: u8) -> Rgba {
+        Rgba::new(value, value, value, 255)
+    }
+
+    #[test]
+    fn rejects_huge_dimensions() {
+        assert!(decode_png(&png(65536, 65536, &[0; 16])).is_err());
+        assert!(decode_png(&png(2147483647, 2147483647, &[0; 16])).is_err());
+    }
+
+    #[test]
+    fn rejects_short_image_data() {
+        // Small enough for the pixel cap, but the data only covers a single row
+        assert!(decode_png(&png(4096, 4096, &[0; 4097 * 4])).is_err());
+    }
+
+    #[test]
+    fn ignores_extra_image_data() {
+        let mut raw_data = vec![0, 1, 2, 3, 4, 0, 5, 6, 7, 8];
+        raw_data.extend_from_slice(&[0xAA; 100]);
+
+        let image = decode_png(&png(1, 2, &raw_data)).unwrap();
+
+        assert_eq!(image.data, [Rgba::new(1, 2, 3, 4), Rgba::new(5, 6, 7, 8)]);
+    }
+
+    #[test]
+    fn decodes_interlaced_images() {
+        let mut ihdr = ihdr_chunk(3, 3, 8, ColorType::Grayscale);
+        ihdr[12] = 1;
+
+        // Adam7 passes of a 3x3 image, numbering pixels in raster order
+        let raw_data = [
+            0, 0, // pass 1: (0, 0)
+            0, 2, // pass 4: (2, 0)
+            0, 6, 8, // pass 5: (0, 2), (2, 2)
+            0, 1, 0, 7, // pass 6: (1, 0), (1, 2)
+            0, 3, 4, 5, // pass 7: row 1
+        ];
+
+        let image = decode_png(&png_with_header(ihdr, &raw_data)).unwrap();
+
+        assert_eq!(image.data, (0..9).map(gray).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scales_low_bit_depths() {
+        let ihdr = ihdr_chunk(4, 1, 2, ColorType::Grayscale);
+        let image = decode_png(&png_with_header(ihdr, &[0, 0b00_01_10_11])).unwrap();
+
+        assert_eq!(image.data, [gray(0), gray(85), gray(170), gray(255)]);
+    }
+
+    #[test]
+    fn reads_the_high_byte_of_16_bit_samples() {
+        let ihdr = ihdr_chunk(1, 1, 16, ColorType::Rgb);
+        let image = decode_png(&png_with_header(
+            ihdr,
+            &[0, 0x12, 0x34, 0x56, 0x78, 0xFF, 0xFF],
+        ))
+        .unwrap();
+
+        assert_eq!(image.data[0].r, 0x12);
+        assert_eq!(image.data[0].g, 0x56);
+        assert_eq!(image.data[0].b, 0xFF);
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        let data = png(1, 1, &[0, 1, 2, 3, 4]);
+
+        let mut bad_crc = data.clone();
+        let last = bad_crc.len() - 1;
+        bad_crc[last] ^= 1;
+        assert!(decode_png(&bad_crc).is_err());
+
+        assert!(decode_png(&data[..data.len() - 12]).is_err());
+        assert!(decode_png(&data[..data.len() - 3]).is_err());
+
+        let mut bad_depth = ihdr_chunk(1, 1, 8, ColorType::Rgba);
+        bad_depth[8] = 4;
+        assert!(decode_png(&png_with_header(bad_depth, &[0; 5])).is_err());
+
+        let ihdr = ihdr_chunk(1, 1, 8, ColorType::Indexed);
+        assert!(decode_png(&png_with_header(ihdr, &[0, 0])).is_err());
+    }
+}
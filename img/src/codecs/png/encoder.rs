@@ -0,0 +1,430 @@
+use color_eyre::eyre::{Result, eyre};
+use flate2::write::ZlibEncoder;
+use std::io::{self, Write};
+
+use crate::{
+    codecs::png::{
+        ColorType, EncodedFormat, FilterStrategy, PNG_HEADER, PngEncoderOptions, PngMetadata,
+        chunk_wrapper, filter::filter_row_with_strategy, ihdr_chunk,
+    },
+    image_buffer::Rgba16Image,
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+/// Packs, filters and compresses scanlines one at a time into `W`.
+pub struct ScanlineEncoder<W: Write> {
+    encoder: ZlibEncoder<W>,
+    format: EncodedFormat,
+    options: PngEncoderOptions,
+    filter: FilterStrategy,
+    prev: Vec<u8>,
+    row: Vec<u8>,
+    filtered: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl<W: Write> ScanlineEncoder<W> {
+    pub fn new(writer: W, width: u32, format: EncodedFormat, options: &PngEncoderOptions) -> Self {
+        let row_bytes = format.row_bytes(width);
+
+        // Filtering rarely helps palette and sub-byte images, so the heuristic leaves them alone.
+        let filter = match options.filter {
+            FilterStrategy::Adaptive if format.color_type == ColorType::Indexed => {
+                FilterStrategy::None
+            }
+            FilterStrategy::Adaptive if format.bit_depth < 8 => FilterStrategy::None,
+            filter => filter,
+        };
+
+        Self {
+            encoder: ZlibEncoder::new(writer, options.compression()),
+            format,
+            options: *options,
+            filter,
+            prev: vec![0u8; row_bytes],
+            row: Vec::with_capacity(row_bytes),
+            filtered: Vec::with_capacity(row_bytes + 1),
+            scratch: Vec::with_capacity(row_bytes + 1),
+        }
+    }
+
+    pub fn write_row(&mut self, pixels: &[Rgba]) -> Result<()> {
+        self.row.clear();
+        self.format.pack_row(pixels, &mut self.row);
+
+        self.write_packed_row()
+    }
+
+    /// Writes a scanline that is already in the encoded format, e.g. 16-bit samples.
+    pub fn write_raw_row(&mut self, row: &[u8]) -> Result<()> {
+        self.row.clear();
+        self.row.extend_from_slice(row);
+
+        self.write_packed_row()
+    }
+
+    fn write_packed_row(&mut self) -> Result<()> {
+        if self.row.len() != self.prev.len() {
+            return Err(eyre!(
+                "PNG row has {} bytes, expected {}",
+                self.row.len(),
+                self.prev.len()
+            ));
+        }
+
+        self.filtered.clear();
+        filter_row_with_strategy(
+            self.filter,
+            self.options.compression(),
+            &self.row,
+            &self.prev,
+            self.format.bytes_per_pixel(),
+            &mut self.scratch,
+            &mut self.filtered,
+        )?;
+
+        self.encoder.write_all(&self.filtered)?;
+        std::mem::swap(&mut self.row, &mut self.prev);
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W> {
+        Ok(self.encoder.finish()?)
+    }
+}
+
+/// Splits everything written into it into IDAT chunks of at most `max_size` bytes.
+pub struct IdatWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+    max_size: usize,
+}
+
+impl<W: Write> IdatWriter<W> {
+    pub fn new(inner: W, max_size: usize) -> Self {
+        let max_size = max_size.clamp(1, i32::MAX as usize);
+
+        Self {
+            inner,
+            buffer: Vec::with_capacity(max_size.min(1 << 20)),
+            max_size,
+        }
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.inner
+                .write_all(&chunk_wrapper(b"IDAT", &self.buffer))?;
+            self.buffer.clear();
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_chunk()?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.max_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+
+        if self.buffer.len() == self.max_size {
+            self.flush_chunk()?;
+        }
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Row oriented PNG encoder that writes the file into `W` as rows come in.
+///
+/// Header and palette chunks are written by `new`, the compressed rows go out as soon as
+/// an IDAT chunk fills up and `finish` writes the trailing chunks.
+pub struct PngWriter<W: Write> {
+    scanlines: ScanlineEncoder<IdatWriter<W>>,
+    width: u32,
+    height: u32,
+    rows_written: u32,
+    trailing_chunks: Vec<u8>,
+}
+
+impl<W: Write> PngWriter<W> {
+    pub fn new(
+        mut writer: W,
+        width: u32,
+        height: u32,
+        format: EncodedFormat,
+        options: &PngEncoderOptions,
+        metadata: &PngMetadata,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(eyre!("PNG width and height must be greater than 0"));
+        }
+
+        // Validates the text keywords, before anything is written
+        let trailing_chunks = metadata.chunks_after_idat()?;
+
+        writer.write_all(&PNG_HEADER)?;
+
+        let ihdr = ihdr_chunk(width, height, format.bit_depth, format.color_type);
+        writer.write_all(&chunk_wrapper(b"IHDR", &ihdr))?;
+
+        writer.write_all(&metadata.chunks_before_plte())?;
+
+        if format.color_type == ColorType::Indexed {
+            writer.write_all(&chunk_wrapper(b"PLTE", &format.plte_chunk()))?;
+
+            let trns_data = format.trns_chunk();
+            if !trns_data.is_empty() {
+                writer.write_all(&chunk_wrapper(b"tRNS", &trns_data))?;
+            }
+        }
+
+        writer.write_all(&metadata.chunks_before_idat())?;
+
+        let idat_writer = IdatWriter::new(writer, options.max_idat_size);
+
+        Ok(Self {
+            scanlines: ScanlineEncoder::new(idat_writer, width, format, options),
+            width,
+            height,
+            rows_written: 0,
+            trailing_chunks,
+        })
+    }
+
+    pub fn write_row(&mut self, pixels: &[Rgba]) -> Result<()> {
+        if self.rows_written == self.height {
+            return Err(eyre!("PNG already has all {} rows", self.height));
+        }
+
+        if pixels.len() != self.width as usize {
+            return Err(eyre!(
+                "PNG row has {} pixels, expected {}",
+                pixels.len(),
+                self.width
+            ));
+        }
+
+        self.scanlines.write_row(pixels)?;
+        self.rows_written += 1;
+
+        Ok(())
+    }
+
+    /// Like `write_row`, with the scanline already packed in the format given to `new`.
+    pub fn write_raw_row(&mut self, row: &[u8]) -> Result<()> {
+        if self.rows_written == self.height {
+            return Err(eyre!("PNG already has all {} rows", self.height));
+        }
+
+        self.scanlines.write_raw_row(row)?;
+        self.rows_written += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<W> {
+        if self.rows_written != self.height {
+            return Err(eyre!(
+                "PNG has {} rows, expected {}",
+                self.rows_written,
+                self.height
+            ));
+        }
+
+        let mut writer = self.scanlines.finish()?.finish()?;
+
+        writer.write_all(&self.trailing_chunks)?;
+        writer.write_all(&chunk_wrapper(b"IEND", &[]))?;
+        writer.flush()?;
+
+        Ok(writer)
+    }
+}
+
+/// Encodes `image` straight into `writer`, returning the writer once the file is complete.
+pub fn write_png<W: Write>(
+    writer: W,
+    image: &RgbaImage,
+    options: &PngEncoderOptions,
+    metadata: &PngMetadata,
+) -> Result<W> {
+    let format = if options.optimize_color_type {
+        EncodedFormat::analyze(image)
+    } else {
+        EncodedFormat::rgba()
+    };
+
+    let mut png_writer =
+        PngWriter::new(writer, image.width, image.height, format, options, metadata)?;
+
+    for row in image.rows() {
+        png_writer.write_row(row)?;
+    }
+
+    png_writer.finish()
+}
+
+/// Encodes a 16-bit image as 16-bit RGBA. `optimize_color_type` doesn't apply, every sample
+/// keeps its full precision.
+pub fn write_png16<W: Write>(
+    writer: W,
+    image: &Rgba16Image,
+    options: &PngEncoderOptions,
+    metadata: &PngMetadata,
+) -> Result<W> {
+    let mut png_writer = PngWriter::new(
+        writer,
+        image.width,
+        image.height,
+        EncodedFormat::rgba16(),
+        options,
+        metadata,
+    )?;
+
+    let mut row = Vec::with_capacity(image.width as usize * 8);
+
+    for pixels in image.rows() {
+        row.clear();
+        row.extend(
+            pixels
+                .iter()
+                .flat_map(|pixel| pixel.to_array())
+                .flat_map(u16::to_be_bytes),
+        );
+
+        png_writer.write_raw_row(&row)?;
+    }
+
+    png_writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::png::{
+            decode_png,
+            decoder::{decode_png_with_metadata, read_chunks},
+        },
+        image_buffer::Rgba16Image,
+        pixel::Rgba16,
+        rgba::Rgba,
+    };
+
+    #[test]
+    fn streams_rows_and_metadata() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba::new(10, 20, 30, 40));
+        let metadata = PngMetadata::new().with_text("Title", "Rows");
+
+        let png = write_png(Vec::new(), &image, &PngEncoderOptions::default(), &metadata).unwrap();
+        let (decoded, decoded_metadata) = decode_png_with_metadata(&png).unwrap();
+
+        assert_eq!(decoded, image);
+        assert_eq!(decoded_metadata.get_text("Title"), Some("Rows"));
+    }
+
+    #[test]
+    fn writes_nothing_for_an_invalid_keyword() {
+        let metadata = PngMetadata::new().with_text("", "No keyword");
+        let mut output: Vec<u8> = vec![];
+
+        let failed = PngWriter::new(
+            &mut output,
+            1,
+            1,
+            EncodedFormat::rgba(),
+            &PngEncoderOptions::default(),
+            &metadata,
+        )
+        .is_err();
+
+        assert!(failed);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn splits_image_data_into_idat_chunks() {
+        let mut image = RgbaImage::new(32, 32);
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            *pixel = Rgba::new((index * 7) as u8, (index * 13) as u8, index as u8, 255);
+        }
+
+        let options = PngEncoderOptions {
+            max_idat_size: 100,
+            ..PngEncoderOptions::fast()
+        };
+        let png = write_png(Vec::new(), &image, &options, &PngMetadata::new()).unwrap();
+
+        let idat_sizes: Vec<usize> = read_chunks(&png)
+            .unwrap()
+            .iter()
+            .filter(|chunk| &chunk.chunk_type == b"IDAT")
+            .map(|chunk| chunk.data.len())
+            .collect();
+
+        assert!(idat_sizes.len() > 1);
+        assert!(idat_sizes.iter().all(|&size| size <= 100));
+        assert_eq!(decode_png(&png).unwrap(), image);
+    }
+
+    #[test]
+    fn checks_the_row_count() {
+        let new_writer = || {
+            PngWriter::new(
+                Vec::new(),
+                2,
+                2,
+                EncodedFormat::rgba(),
+                &PngEncoderOptions::default(),
+                &PngMetadata::new(),
+            )
+            .unwrap()
+        };
+        let row = [Rgba::default(); 2];
+
+        let mut writer = new_writer();
+        writer.write_row(&row).unwrap();
+        assert!(writer.finish().is_err());
+
+        let mut writer = new_writer();
+        assert!(writer.write_row(&row[..1]).is_err());
+        writer.write_row(&row).unwrap();
+        writer.write_row(&row).unwrap();
+        assert!(writer.write_row(&row).is_err());
+        assert!(writer.finish().is_ok());
+    }
+
+    #[test]
+    fn writes_16_bit_samples() {
+        let image = Rgba16Image::from_pixel(2, 1, Rgba16::new(0x1234, 0x5678, 0x9ABC, 0xFFFF));
+        let png = write_png16(
+            Vec::new(),
+            &image,
+            &PngEncoderOptions::default(),
+            &PngMetadata::new(),
+        )
+        .unwrap();
+
+        let chunks = read_chunks(&png).unwrap();
+        assert_eq!(chunks[0].data[8..10], [16, ColorType::Rgba as u8]);
+        assert!(
+            decode_png(&png)
+                .unwrap()
+                .pixels()
+                .all(|&pixel| pixel == Rgba::new(0x12, 0x56, 0x9A, 0xFF))
+        );
+    }
+}
@@ -0,0 +1,252 @@
+use color_eyre::eyre::{Result, eyre};
+use flate2::{Compression, write::ZlibEncoder};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Sub),
+            2 => Ok(Self::Up),
+            3 => Ok(Self::Average),
+            4 => Ok(Self::Paeth),
+            _ => Err(eyre!("Unknown PNG filter type: {}", value)),
+        }
+    }
+}
+
+pub fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filter applied to a single scanline in place.
+///
+/// `prev` is the already unfiltered previous scanline (all zeros for the first one)
+/// and `bpp` is the number of bytes per complete pixel, rounded up to 1.
+pub fn unfilter_row(filter: FilterType, row: &mut [u8], prev: &[u8], bpp: usize) {
+    match filter {
+        FilterType::None => {}
+        FilterType::Sub => {
+            for i in bpp..row.len() {
+                row[i] = row[i].wrapping_add(row[i - bpp]);
+            }
+        }
+        FilterType::Up => {
+            for i in 0..row.len() {
+                row[i] = row[i].wrapping_add(prev[i]);
+            }
+        }
+        FilterType::Average => {
+            for i in 0..row.len() {
+                let left = if i >= bpp { row[i - bpp] } else { 0 };
+                let avg = ((left as u16 + prev[i] as u16) / 2) as u8;
+                row[i] = row[i].wrapping_add(avg);
+            }
+        }
+        FilterType::Paeth => {
+            for i in 0..row.len() {
+                let (left, upper_left) = if i >= bpp {
+                    (row[i - bpp], prev[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                row[i] = row[i].wrapping_add(paeth_predictor(left, prev[i], upper_left));
+            }
+        }
+    }
+}
+
+/// How the encoder picks a filter type for each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterStrategy {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    /// Pick the filter whose output has the smallest sum of absolute values (signed bytes).
+    #[default]
+    Adaptive,
+    /// Compress every candidate and keep the one that deflates best. Slow but smallest.
+    BruteForce,
+}
+
+pub const ALL_FILTERS: [FilterType; 5] = [
+    FilterType::None,
+    FilterType::Sub,
+    FilterType::Up,
+    FilterType::Average,
+    FilterType::Paeth,
+];
+
+/// Applies `filter` to `row` and appends the filter type byte followed by the filtered bytes to `out`.
+pub fn filter_row(filter: FilterType, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(filter as u8);
+
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let up = prev[i];
+
+        let predicted = match filter {
+            FilterType::None => 0,
+            FilterType::Sub => left,
+            FilterType::Up => up,
+            FilterType::Average => ((left as u16 + up as u16) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(left, up, upper_left),
+        };
+
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// Filters one scanline according to `strategy`, appending the result to `out`.
+///
+/// `scratch` is reused between calls to avoid allocating candidate rows.
+pub fn filter_row_with_strategy(
+    strategy: FilterStrategy,
+    compression: Compression,
+    row: &[u8],
+    prev: &[u8],
+    bpp: usize,
+    scratch: &mut Vec<u8>,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    let filter = match strategy {
+        FilterStrategy::None => FilterType::None,
+        FilterStrategy::Sub => FilterType::Sub,
+        FilterStrategy::Up => FilterType::Up,
+        FilterStrategy::Average => FilterType::Average,
+        FilterStrategy::Paeth => FilterType::Paeth,
+        FilterStrategy::Adaptive => {
+            let mut best = (FilterType::None, u64::MAX);
+
+            for filter in ALL_FILTERS {
+                scratch.clear();
+                filter_row(filter, row, prev, bpp, scratch);
+
+                let sum = scratch[1..]
+                    .iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum::<u64>();
+
+                if sum < best.1 {
+                    best = (filter, sum);
+                }
+            }
+
+            best.0
+        }
+        FilterStrategy::BruteForce => {
+            let mut best = (FilterType::None, usize::MAX);
+
+            for filter in ALL_FILTERS {
+                scratch.clear();
+                filter_row(filter, row, prev, bpp, scratch);
+
+                let mut encoder = ZlibEncoder::new(Vec::new(), compression);
+                encoder.write_all(scratch)?;
+                let size = encoder.finish()?.len();
+
+                if size < best.1 {
+                    best = (filter, size);
+                }
+            }
+
+            best.0
+        }
+    };
+
+    filter_row(filter, row, prev, bpp, out);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::png::{PngEncoderOptions, decode_png, encode_to_png_with},
+        rgba::Rgba,
+        rgba_image::RgbaImage,
+    };
+
+    #[test]
+    fn unfilter_reverses_every_filter() {
+        let prev: Vec<u8> = (0..24).map(|i| (i * 41 % 256) as u8).collect();
+        let row: Vec<u8> = (0..24).map(|i| (i * 97 % 256) as u8).collect();
+
+        for filter in ALL_FILTERS {
+            let mut filtered = vec![];
+            filter_row(filter, &row, &prev, 3, &mut filtered);
+
+            assert_eq!(filtered[0], filter as u8);
+
+            let mut unfiltered = filtered[1..].to_vec();
+            unfilter_row(filter, &mut unfiltered, &prev, 3);
+            assert_eq!(unfiltered, row);
+        }
+    }
+
+    #[test]
+    fn paeth_picks_the_closest_neighbor() {
+        assert_eq!(paeth_predictor(10, 20, 10), 20);
+        assert_eq!(paeth_predictor(20, 10, 10), 20);
+        assert_eq!(paeth_predictor(10, 10, 20), 10);
+        assert_eq!(paeth_predictor(0, 255, 255), 0);
+    }
+
+    #[test]
+    fn every_strategy_round_trips() {
+        let mut image = RgbaImage::new(13, 7);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = (index * 11) as u8;
+            *pixel = Rgba::new(value, value.wrapping_mul(3), 255 - value, value | 1);
+        }
+
+        let strategies = [
+            FilterStrategy::None,
+            FilterStrategy::Sub,
+            FilterStrategy::Up,
+            FilterStrategy::Average,
+            FilterStrategy::Paeth,
+            FilterStrategy::Adaptive,
+            FilterStrategy::BruteForce,
+        ];
+
+        for filter in strategies {
+            for compression_level in [0, 6, 9] {
+                let options = PngEncoderOptions {
+                    optimize_color_type: false,
+                    ..PngEncoderOptions::new(compression_level, filter)
+                };
+
+                let png = encode_to_png_with(&image, &options).unwrap();
+                assert_eq!(decode_png(&png).unwrap(), image, "{:?}", filter);
+            }
+        }
+    }
+}
@@ -0,0 +1,553 @@
+use color_eyre::eyre::{Result, eyre};
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use std::io::{Read, Write};
+
+use crate::codecs::png::chunk_wrapper;
+
+const INCHES_PER_METER: f64 = 39.3701;
+/// Compressed text inflating past this is rejected, a small chunk could otherwise expand to
+/// gigabytes. Same default as libpng's chunk allocation limit
+const TEXT_BYTES_MAX: usize = 8 * 1024 * 1024;
+
+/// Ancillary information stored next to the pixels of a PNG.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PngMetadata {
+    pub text: Vec<TextChunk>,
+    pub physical_size: Option<PhysicalSize>,
+    /// Image gamma times 100000, as stored in gAMA
+    pub gamma: Option<u32>,
+    pub srgb: Option<RenderingIntent>,
+    pub chromaticities: Option<Chromaticities>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub keyword: String,
+    pub text: String,
+    pub kind: TextKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextKind {
+    /// Uncompressed Latin-1 text (tEXt)
+    Plain,
+    /// zlib compressed Latin-1 text (zTXt)
+    Compressed,
+    /// UTF-8 text with optional language information (iTXt)
+    International {
+        compressed: bool,
+        language_tag: String,
+        translated_keyword: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    Unknown = 0,
+    Meter = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicalSize {
+    pub x_pixels_per_unit: u32,
+    pub y_pixels_per_unit: u32,
+    pub unit: PhysicalUnit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderingIntent {
+    Perceptual = 0,
+    RelativeColorimetric = 1,
+    Saturation = 2,
+    AbsoluteColorimetric = 3,
+}
+
+/// CIE x,y chromaticities of the white point and primaries, times 100000 (cHRM).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chromaticities {
+    pub white: (u32, u32),
+    pub red: (u32, u32),
+    pub green: (u32, u32),
+    pub blue: (u32, u32),
+}
+
+impl Chromaticities {
+    pub const SRGB: Self = Self {
+        white: (31270, 32900),
+        red: (64000, 33000),
+        green: (30000, 60000),
+        blue: (15000, 6000),
+    };
+}
+
+impl PhysicalSize {
+    pub fn from_dpi(dpi: f64) -> Self {
+        let pixels_per_meter = (dpi * INCHES_PER_METER).round() as u32;
+
+        Self {
+            x_pixels_per_unit: pixels_per_meter,
+            y_pixels_per_unit: pixels_per_meter,
+            unit: PhysicalUnit::Meter,
+        }
+    }
+
+    /// Horizontal and vertical DPI, if the unit is known.
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit {
+            PhysicalUnit::Meter => Some((
+                self.x_pixels_per_unit as f64 / INCHES_PER_METER,
+                self.y_pixels_per_unit as f64 / INCHES_PER_METER,
+            )),
+            PhysicalUnit::Unknown => None,
+        }
+    }
+}
+
+impl TextChunk {
+    /// Creates a tEXt chunk, or an iTXt one when the text doesn't fit in Latin-1.
+    pub fn new(keyword: &str, text: &str) -> Self {
+        let kind = if is_latin1(text) {
+            TextKind::Plain
+        } else {
+            TextKind::International {
+                compressed: false,
+                language_tag: String::new(),
+                translated_keyword: String::new(),
+            }
+        };
+
+        Self {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+            kind,
+        }
+    }
+
+    /// Creates a zTXt chunk, or a compressed iTXt one when the text doesn't fit in Latin-1.
+    pub fn compressed(keyword: &str, text: &str) -> Self {
+        let kind = if is_latin1(text) {
+            TextKind::Compressed
+        } else {
+            TextKind::International {
+                compressed: true,
+                language_tag: String::new(),
+                translated_keyword: String::new(),
+            }
+        };
+
+        Self {
+            keyword: keyword.to_string(),
+            text: text.to_string(),
+            kind,
+        }
+    }
+
+    fn to_chunk(&self) -> Result<Vec<u8>> {
+        if !is_valid_keyword(&self.keyword) {
+            return Err(eyre!(
+                "PNG text keyword must be 1-79 printable Latin-1 characters without extra spaces: {:?}",
+                self.keyword
+            ));
+        }
+
+        if !matches!(self.kind, TextKind::International { .. }) && self.text.contains('\0') {
+            return Err(eyre!(
+                "PNG text for keyword {:?} must not contain NUL characters",
+                self.keyword
+            ));
+        }
+
+        let mut data = to_latin1(&self.keyword)?;
+        data.push(0);
+
+        match &self.kind {
+            TextKind::Plain => {
+                data.extend_from_slice(&to_latin1(&self.text)?);
+                Ok(chunk_wrapper(b"tEXt", &data))
+            }
+            TextKind::Compressed => {
+                data.push(0);
+                data.extend_from_slice(&compress(&to_latin1(&self.text)?)?);
+                Ok(chunk_wrapper(b"zTXt", &data))
+            }
+            TextKind::International {
+                compressed,
+                language_tag,
+                translated_keyword,
+            } => {
+                data.push(*compressed as u8);
+                data.push(0);
+                data.extend_from_slice(language_tag.as_bytes());
+                data.push(0);
+                data.extend_from_slice(translated_keyword.as_bytes());
+                data.push(0);
+
+                if *compressed {
+                    data.extend_from_slice(&compress(self.text.as_bytes())?);
+                } else {
+                    data.extend_from_slice(self.text.as_bytes());
+                }
+
+                Ok(chunk_wrapper(b"iTXt", &data))
+            }
+        }
+    }
+}
+
+impl PngMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_text(mut self, keyword: &str, text: &str) -> Self {
+        self.text.push(TextChunk::new(keyword, text));
+        self
+    }
+
+    pub fn with_compressed_text(mut self, keyword: &str, text: &str) -> Self {
+        self.text.push(TextChunk::compressed(keyword, text));
+        self
+    }
+
+    pub fn with_dpi(mut self, dpi: f64) -> Self {
+        self.physical_size = Some(PhysicalSize::from_dpi(dpi));
+        self
+    }
+
+    /// Marks the image as sRGB, together with the gAMA and cHRM fallbacks the spec recommends.
+    pub fn with_srgb(mut self, intent: RenderingIntent) -> Self {
+        self.srgb = Some(intent);
+        self.gamma = Some(45455);
+        self.chromaticities = Some(Chromaticities::SRGB);
+        self
+    }
+
+    pub fn get_text(&self, keyword: &str) -> Option<&str> {
+        self.text
+            .iter()
+            .find(|chunk| chunk.keyword == keyword)
+            .map(|chunk| chunk.text.as_str())
+    }
+
+    /// Chunks that must come before PLTE and IDAT.
+    pub fn chunks_before_plte(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+
+        if let Some(chromaticities) = self.chromaticities {
+            let mut data: Vec<u8> = vec![];
+            for (x, y) in [
+                chromaticities.white,
+                chromaticities.red,
+                chromaticities.green,
+                chromaticities.blue,
+            ] {
+                data.extend_from_slice(&x.to_be_bytes());
+                data.extend_from_slice(&y.to_be_bytes());
+            }
+            output.extend_from_slice(&chunk_wrapper(b"cHRM", &data));
+        }
+
+        if let Some(gamma) = self.gamma {
+            output.extend_from_slice(&chunk_wrapper(b"gAMA", &gamma.to_be_bytes()));
+        }
+
+        if let Some(intent) = self.srgb {
+            output.extend_from_slice(&chunk_wrapper(b"sRGB", &[intent as u8]));
+        }
+
+        output
+    }
+
+    /// Chunks that must come before IDAT but may follow PLTE.
+    pub fn chunks_before_idat(&self) -> Vec<u8> {
+        let mut output: Vec<u8> = vec![];
+
+        if let Some(size) = self.physical_size {
+            let mut data: Vec<u8> = vec![];
+            data.extend_from_slice(&size.x_pixels_per_unit.to_be_bytes());
+            data.extend_from_slice(&size.y_pixels_per_unit.to_be_bytes());
+            data.push(size.unit as u8);
+            output.extend_from_slice(&chunk_wrapper(b"pHYs", &data));
+        }
+
+        output
+    }
+
+    /// Chunks written between the last IDAT and IEND.
+    pub fn chunks_after_idat(&self) -> Result<Vec<u8>> {
+        let mut output: Vec<u8> = vec![];
+
+        for text in &self.text {
+            output.extend_from_slice(&text.to_chunk()?);
+        }
+
+        Ok(output)
+    }
+
+    /// Reads a known ancillary chunk into the metadata, returning false for anything else.
+    pub fn read_chunk(&mut self, chunk_type: &[u8; 4], data: &[u8]) -> Result<bool> {
+        match chunk_type {
+            b"tEXt" => {
+                let (keyword, text) = split_keyword(data)?;
+                self.text.push(TextChunk {
+                    keyword,
+                    text: from_latin1(text),
+                    kind: TextKind::Plain,
+                });
+            }
+            b"zTXt" => {
+                let (keyword, rest) = split_keyword(data)?;
+                let (&method, compressed) = rest
+                    .split_first()
+                    .ok_or_else(|| eyre!("zTXt chunk is missing the compression method"))?;
+
+                if method != 0 {
+                    return Err(eyre!("Unknown zTXt compression method: {}", method));
+                }
+
+                self.text.push(TextChunk {
+                    keyword,
+                    text: from_latin1(&decompress(compressed)?),
+                    kind: TextKind::Compressed,
+                });
+            }
+            b"iTXt" => {
+                let (keyword, rest) = split_keyword(data)?;
+                if rest.len() < 2 {
+                    return Err(eyre!("iTXt chunk is too short"));
+                }
+
+                let compressed = rest[0] == 1;
+                let (language_tag, rest) = split_null(&rest[2..])?;
+                let (translated_keyword, text) = split_null(rest)?;
+
+                let text = if compressed {
+                    decompress(text)?
+                } else {
+                    text.to_vec()
+                };
+
+                self.text.push(TextChunk {
+                    keyword,
+                    text: String::from_utf8(text)
+                        .map_err(|e| eyre!("iTXt text is not valid UTF-8: {}", e))?,
+                    kind: TextKind::International {
+                        compressed,
+                        language_tag: String::from_utf8_lossy(language_tag).into_owned(),
+                        translated_keyword: String::from_utf8_lossy(translated_keyword)
+                            .into_owned(),
+                    },
+                });
+            }
+            b"pHYs" => {
+                if data.len() != 9 {
+                    return Err(eyre!("pHYs chunk must be 9 bytes long"));
+                }
+
+                self.physical_size = Some(PhysicalSize {
+                    x_pixels_per_unit: u32::from_be_bytes(data[0..4].try_into()?),
+                    y_pixels_per_unit: u32::from_be_bytes(data[4..8].try_into()?),
+                    unit: match data[8] {
+                        1 => PhysicalUnit::Meter,
+                        _ => PhysicalUnit::Unknown,
+                    },
+                });
+            }
+            b"gAMA" => {
+                if data.len() != 4 {
+                    return Err(eyre!("gAMA chunk must be 4 bytes long"));
+                }
+
+                self.gamma = Some(u32::from_be_bytes(data.try_into()?));
+            }
+            b"sRGB" => {
+                self.srgb = Some(match data {
+                    [0] => RenderingIntent::Perceptual,
+                    [1] => RenderingIntent::RelativeColorimetric,
+                    [2] => RenderingIntent::Saturation,
+                    [3] => RenderingIntent::AbsoluteColorimetric,
+                    _ => return Err(eyre!("Invalid sRGB chunk")),
+                });
+            }
+            b"cHRM" => {
+                if data.len() != 32 {
+                    return Err(eyre!("cHRM chunk must be 32 bytes long"));
+                }
+
+                let value =
+                    |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+                self.chromaticities = Some(Chromaticities {
+                    white: (value(0), value(4)),
+                    red: (value(8), value(12)),
+                    green: (value(16), value(20)),
+                    blue: (value(24), value(28)),
+                });
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+}
+
+fn is_latin1(text: &str) -> bool {
+    text.chars().all(|c| (c as u32) <= 0xFF)
+}
+
+/// Printable Latin-1 without leading, trailing or consecutive spaces, as the spec requires.
+fn is_valid_keyword(keyword: &str) -> bool {
+    (1..=79).contains(&keyword.chars().count())
+        && keyword
+            .chars()
+            .all(|c| matches!(c as u32, 32..=126 | 161..=255))
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ")
+}
+
+fn to_latin1(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(c).map_err(|_| eyre!("Character {:?} is not Latin-1", c)))
+        .collect()
+}
+
+fn from_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn split_null(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let end = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| eyre!("Missing null separator in PNG text chunk"))?;
+
+    Ok((&data[..end], &data[end + 1..]))
+}
+
+fn split_keyword(data: &[u8]) -> Result<(String, &[u8])> {
+    let (keyword, rest) = split_null(data)?;
+
+    Ok((from_latin1(keyword), rest))
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+
+    Ok(encoder.finish()?)
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut output: Vec<u8> = vec![];
+    ZlibDecoder::new(data)
+        .take(TEXT_BYTES_MAX as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|e| eyre!("Failed to decompress PNG text: {}", e))?;
+
+    if output.len() > TEXT_BYTES_MAX {
+        return Err(eyre!(
+            "PNG text is longer than {} bytes once decompressed",
+            TEXT_BYTES_MAX
+        ));
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codecs::png::{PngEncoderOptions, decode_png_with_metadata, encode_to_png_with_metadata},
+        rgba_image::RgbaImage,
+    };
+
+    #[test]
+    fn reads_compressed_text() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend_from_slice(&compress(b"Hello").unwrap());
+
+        let mut metadata = PngMetadata::new();
+        metadata.read_chunk(b"zTXt", &data).unwrap();
+
+        assert_eq!(metadata.get_text("Comment"), Some("Hello"));
+    }
+
+    #[test]
+    fn rejects_text_inflating_past_limit() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend_from_slice(&compress(&vec![b'a'; TEXT_BYTES_MAX + 1]).unwrap());
+
+        // Highly repetitive text compresses to a tiny chunk
+        assert!(data.len() < 64 * 1024);
+        assert!(PngMetadata::new().read_chunk(b"zTXt", &data).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_an_image() {
+        let metadata = PngMetadata::new()
+            .with_text("Title", "Plain")
+            .with_compressed_text("Comment", "Squeezed")
+            .with_text("Author", "Łukasz")
+            .with_compressed_text("Description", "Żółw")
+            .with_dpi(300.0)
+            .with_srgb(RenderingIntent::Perceptual);
+
+        let png = encode_to_png_with_metadata(
+            &RgbaImage::new(1, 1),
+            &PngEncoderOptions::default(),
+            &metadata,
+        )
+        .unwrap();
+        let (_, decoded) = decode_png_with_metadata(&png).unwrap();
+
+        assert_eq!(decoded, metadata);
+
+        let (dpi, _) = decoded.physical_size.unwrap().dpi().unwrap();
+        assert!((dpi - 300.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn rejects_invalid_keywords() {
+        let too_long = "k".repeat(80);
+        let invalid = [
+            "",
+            &too_long,
+            "Ключ",
+            " Title",
+            "Title ",
+            "Long  title",
+            "Tab\tbed",
+            "No\u{A0}break",
+        ];
+
+        for keyword in invalid {
+            assert!(
+                PngMetadata::new()
+                    .with_text(keyword, "text")
+                    .chunks_after_idat()
+                    .is_err(),
+                "{:?}",
+                keyword
+            );
+        }
+
+        // 79 characters, even though the é takes two bytes in UTF-8
+        let longest = format!("é{}", "k".repeat(78));
+        assert!(
+            PngMetadata::new()
+                .with_text(&longest, "text")
+                .chunks_after_idat()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_nul_in_latin1_text() {
+        let plain = PngMetadata::new().with_text("Comment", "a\0b");
+        let compressed = PngMetadata::new().with_compressed_text("Comment", "a\0b");
+
+        assert!(plain.chunks_after_idat().is_err());
+        assert!(compressed.chunks_after_idat().is_err());
+    }
+}
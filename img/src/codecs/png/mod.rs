@@ -0,0 +1,248 @@
+pub mod decoder;
+pub mod encoder;
+pub mod filter;
+pub mod metadata;
+pub mod reduce;
+
+use color_eyre::eyre::{Result, eyre};
+use crc32fast::Hasher;
+use flate2::Compression;
+use std::{io::Write, vec};
+
+use crate::{
+    codecs::{
+        png::encoder::ScanlineEncoder,
+        registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    },
+    image_buffer::Rgba16Image,
+    rgba_image::RgbaImage,
+};
+
+pub use decoder::{decode_png, decode_png_with_metadata};
+pub use encoder::{PngWriter, write_png, write_png16};
+pub use filter::FilterStrategy;
+pub use metadata::{PngMetadata, RenderingIntent, TextChunk};
+pub use reduce::EncodedFormat;
+
+pub const PNG_HEADER: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+const DEFAULT_MAX_IDAT_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType {
+    pub const fn channels(&self) -> usize {
+        match self {
+            Self::Grayscale | Self::Indexed => 1,
+            Self::GrayscaleAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    pub const fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            Self::Grayscale => &[1, 2, 4, 8, 16],
+            Self::Indexed => &[1, 2, 4, 8],
+            Self::Rgb | Self::GrayscaleAlpha | Self::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Grayscale),
+            2 => Ok(Self::Rgb),
+            3 => Ok(Self::Indexed),
+            4 => Ok(Self::GrayscaleAlpha),
+            6 => Ok(Self::Rgba),
+            _ => Err(eyre!("Unknown PNG color type: {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PngEncoderOptions {
+    /// zlib compression level, from 0 (store only) to 9 (best)
+    pub compression_level: u32,
+    pub filter: FilterStrategy,
+    /// Store the image as grayscale, RGB or indexed when that is lossless and smaller
+    pub optimize_color_type: bool,
+    /// Compressed data is split into IDAT chunks of at most this many bytes
+    pub max_idat_size: usize,
+}
+
+impl Default for PngEncoderOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: 6,
+            filter: FilterStrategy::Adaptive,
+            optimize_color_type: true,
+            max_idat_size: DEFAULT_MAX_IDAT_SIZE,
+        }
+    }
+}
+
+impl PngEncoderOptions {
+    pub const fn new(compression_level: u32, filter: FilterStrategy) -> Self {
+        Self {
+            compression_level,
+            filter,
+            optimize_color_type: true,
+            max_idat_size: DEFAULT_MAX_IDAT_SIZE,
+        }
+    }
+
+    pub const fn fast() -> Self {
+        Self {
+            optimize_color_type: false,
+            ..Self::new(1, FilterStrategy::None)
+        }
+    }
+
+    pub const fn best() -> Self {
+        Self::new(9, FilterStrategy::BruteForce)
+    }
+
+    pub(crate) fn compression(&self) -> Compression {
+        Compression::new(self.compression_level.min(9))
+    }
+}
+
+pub fn encode_to_png(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_to_png_with(image, &PngEncoderOptions::default())
+}
+
+pub fn encode_to_png_with(image: &RgbaImage, options: &PngEncoderOptions) -> Result<Vec<u8>> {
+    encode_to_png_with_metadata(image, options, &PngMetadata::default())
+}
+
+pub fn encode_to_png_with_metadata(
+    image: &RgbaImage,
+    options: &PngEncoderOptions,
+    metadata: &PngMetadata,
+) -> Result<Vec<u8>> {
+    write_png(Vec::new(), image, options, metadata)
+}
+
+pub fn encode_to_png16(image: &Rgba16Image, options: &PngEncoderOptions) -> Result<Vec<u8>> {
+    write_png16(Vec::new(), image, options, &PngMetadata::default())
+}
+
+pub fn chunk_wrapper(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut output: Vec<u8> = vec![];
+
+    let length = data.len() as u32;
+    output.extend_from_slice(&length.to_be_bytes());
+
+    output.extend_from_slice(chunk_type);
+
+    output.extend_from_slice(data);
+
+    let crc = crc(chunk_type, data);
+    output.extend_from_slice(&crc);
+
+    output
+}
+
+pub fn crc(chunk_type: &[u8; 4], data: &[u8]) -> [u8; 4] {
+    let mut hasher = Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    hasher.finalize().to_be_bytes()
+}
+
+pub fn ihdr_chunk(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> [u8; 13] {
+    let mut data = [0u8; 13];
+
+    let width_bytes = width.to_be_bytes();
+    data[0..4].copy_from_slice(&width_bytes);
+
+    let height = height.to_be_bytes();
+    data[4..8].copy_from_slice(&height);
+
+    data[8] = bit_depth;
+    data[9] = color_type as u8;
+    data[10] = 0;
+    data[11] = 0;
+    data[12] = 0;
+
+    data
+}
+
+/// Filtered and zlib compressed image data, ready to be wrapped in IDAT or fdAT chunks.
+pub fn idat_chunk(
+    image: &RgbaImage,
+    format: &EncodedFormat,
+    options: &PngEncoderOptions,
+) -> Result<Vec<u8>> {
+    let mut encoder = ScanlineEncoder::new(Vec::new(), image.width, format.clone(), options);
+
+    for row in image.rows() {
+        encoder.write_row(row)?;
+    }
+
+    encoder.finish()
+}
+
+/// PNG for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PngCodec {
+    pub options: PngEncoderOptions,
+}
+
+impl PngCodec {
+    pub const fn new(options: PngEncoderOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl ImageFormat for PngCodec {
+    fn name(&self) -> &'static str {
+        "PNG"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/png"
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &["image/x-png"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["png"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(&PNG_HEADER)
+    }
+}
+
+impl ImageEncoder for PngCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_png_with(image, &self.options)
+    }
+
+    fn write(&self, writer: &mut dyn Write, image: &RgbaImage) -> Result<()> {
+        write_png(writer, image, &self.options, &PngMetadata::default())?;
+
+        Ok(())
+    }
+}
+
+impl ImageDecoder for PngCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_png(data)
+    }
+}
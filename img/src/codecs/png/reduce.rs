@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use crate::{codecs::png::ColorType, rgba::Rgba, rgba_image::RgbaImage};
+
+const MAX_PALETTE_SIZE: usize = 256;
+
+/// Color type, bit depth and palette chosen to store an image losslessly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedFormat {
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    pub palette: Vec<Rgba>,
+    palette_lookup: HashMap<Rgba, u8>,
+}
+
+impl EncodedFormat {
+    pub fn rgba() -> Self {
+        Self::new(ColorType::Rgba, 8)
+    }
+
+    /// Rows have to be packed by the caller, `pack_row` only produces 8-bit samples.
+    pub fn rgba16() -> Self {
+        Self::new(ColorType::Rgba, 16)
+    }
+
+    fn new(color_type: ColorType, bit_depth: u8) -> Self {
+        Self {
+            color_type,
+            bit_depth,
+            palette: vec![],
+            palette_lookup: HashMap::new(),
+        }
+    }
+
+    /// Picks the smallest lossless representation of `image`.
+    ///
+    /// Alpha is dropped when every pixel is opaque, grayscale is used when r = g = b everywhere
+    /// and images with at most 256 distinct colors are stored as indexed with PLTE and tRNS.
+    pub fn analyze(image: &RgbaImage) -> Self {
+        Self::analyze_all([image])
+    }
+
+    /// Picks one representation that fits every image, e.g. all frames of an animation.
+    pub fn analyze_all<'a>(images: impl IntoIterator<Item = &'a RgbaImage>) -> Self {
+        let mut opaque = true;
+        let mut gray = true;
+        let mut gray_levels = [false; 256];
+        let mut palette: Vec<Rgba> = vec![];
+        let mut palette_lookup: HashMap<Rgba, u8> = HashMap::new();
+        let mut palette_fits = true;
+
+        for &pixel in images.into_iter().flat_map(|image| &image.data) {
+            opaque &= pixel.a == 255;
+
+            if pixel.r == pixel.g && pixel.g == pixel.b {
+                gray_levels[pixel.r as usize] = true;
+            } else {
+                gray = false;
+            }
+
+            if palette_fits && !palette_lookup.contains_key(&pixel) {
+                if palette.len() == MAX_PALETTE_SIZE {
+                    palette_fits = false;
+                } else {
+                    palette_lookup.insert(pixel, palette.len() as u8);
+                    palette.push(pixel);
+                }
+            }
+        }
+
+        let mut candidates: Vec<(usize, Self)> = vec![];
+
+        if gray {
+            if opaque {
+                let depth = gray_bit_depth(&gray_levels);
+                candidates.push((depth as usize, Self::new(ColorType::Grayscale, depth)));
+            } else {
+                candidates.push((16, Self::new(ColorType::GrayscaleAlpha, 8)));
+            }
+        }
+
+        if palette_fits {
+            let depth = palette_bit_depth(palette.len());
+            candidates.push((depth as usize, Self::indexed(depth, palette)));
+        }
+
+        if opaque {
+            candidates.push((24, Self::new(ColorType::Rgb, 8)));
+        }
+
+        candidates.push((32, Self::rgba()));
+
+        // Stable ordering keeps grayscale ahead of indexed when both need the same bits per
+        // pixel, since grayscale doesn't need the PLTE chunk.
+        candidates.sort_by_key(|(bits, _)| *bits);
+        candidates.swap_remove(0).1
+    }
+
+    fn indexed(bit_depth: u8, mut palette: Vec<Rgba>) -> Self {
+        // Translucent entries go first so the tRNS chunk can stop at the last one.
+        palette.sort_by_key(|color| color.a == 255);
+
+        let palette_lookup = palette
+            .iter()
+            .enumerate()
+            .map(|(index, color)| (*color, index as u8))
+            .collect();
+
+        Self {
+            color_type: ColorType::Indexed,
+            bit_depth,
+            palette,
+            palette_lookup,
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    pub fn row_bytes(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn plte_chunk(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect()
+    }
+
+    /// Alpha values of the palette up to the last translucent entry, empty when fully opaque.
+    pub fn trns_chunk(&self) -> Vec<u8> {
+        self.palette
+            .iter()
+            .take_while(|color| color.a != 255)
+            .map(|color| color.a)
+            .collect()
+    }
+
+    /// Converts a row of pixels into scanline bytes (without the filter type byte).
+    pub fn pack_row(&self, pixels: &[Rgba], out: &mut Vec<u8>) {
+        match self.color_type {
+            ColorType::Rgba => out.extend(pixels.iter().flat_map(|p| p.to_array())),
+            ColorType::Rgb => out.extend(pixels.iter().flat_map(|p| [p.r, p.g, p.b])),
+            ColorType::GrayscaleAlpha => out.extend(pixels.iter().flat_map(|p| [p.r, p.a])),
+            ColorType::Grayscale => {
+                let step = 255 / ((1u16 << self.bit_depth) - 1) as u8;
+                self.pack_samples(pixels.iter().map(|p| p.r / step), out);
+            }
+            ColorType::Indexed => {
+                self.pack_samples(pixels.iter().map(|p| self.palette_lookup[p]), out);
+            }
+        }
+    }
+
+    fn pack_samples(&self, samples: impl Iterator<Item = u8>, out: &mut Vec<u8>) {
+        let depth = self.bit_depth as usize;
+
+        if depth == 8 {
+            out.extend(samples);
+            return;
+        }
+
+        let mut byte = 0u8;
+        let mut used_bits = 0;
+
+        for sample in samples {
+            byte |= sample << (8 - depth - used_bits);
+            used_bits += depth;
+
+            if used_bits == 8 {
+                out.push(byte);
+                byte = 0;
+                used_bits = 0;
+            }
+        }
+
+        if used_bits > 0 {
+            out.push(byte);
+        }
+    }
+}
+
+fn gray_bit_depth(levels: &[bool; 256]) -> u8 {
+    for depth in [1u8, 2, 4] {
+        let step = 255 / ((1usize << depth) - 1);
+
+        if levels
+            .iter()
+            .enumerate()
+            .all(|(level, used)| !used || level.is_multiple_of(step))
+        {
+            return depth;
+        }
+    }
+
+    8
+}
+
+fn palette_bit_depth(colors: usize) -> u8 {
+    match colors {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::png::{decode_png, encode_to_png};
+
+    fn image_of(colors: &[Rgba]) -> RgbaImage {
+        let mut image = RgbaImage::new(colors.len() as u32, 3);
+
+        for row in image.rows_mut() {
+            row.copy_from_slice(colors);
+        }
+
+        image
+    }
+
+    fn gray(value: u8) -> Rgba {
+        Rgba::new(value, value, value, 255)
+    }
+
+    #[test]
+    fn picks_the_smallest_lossless_format() {
+        let many_colors: Vec<Rgba> = (0..300)
+            .map(|i| Rgba::new(i as u8, (i / 2) as u8, 7, 255))
+            .collect();
+        let mut many_translucent = many_colors.clone();
+        many_translucent[0].a = 3;
+
+        let cases = [
+            (vec![gray(0), gray(255)], ColorType::Grayscale, 1),
+            (vec![gray(0), gray(85), gray(170)], ColorType::Grayscale, 2),
+            (
+                vec![gray(0), gray(17), gray(34), gray(51), gray(68)],
+                ColorType::Grayscale,
+                4,
+            ),
+            ((0..=255).map(gray).collect(), ColorType::Grayscale, 8),
+            (
+                vec![Rgba::new(9, 9, 9, 10), gray(10)],
+                ColorType::Indexed,
+                1,
+            ),
+            (
+                vec![Rgba::new(255, 0, 0, 255), Rgba::new(0, 0, 255, 255)],
+                ColorType::Indexed,
+                1,
+            ),
+            (many_colors, ColorType::Rgb, 8),
+            (many_translucent, ColorType::Rgba, 8),
+        ];
+
+        for (colors, color_type, bit_depth) in cases {
+            let format = EncodedFormat::analyze(&image_of(&colors));
+
+            assert_eq!(
+                (format.color_type, format.bit_depth),
+                (color_type, bit_depth)
+            );
+        }
+    }
+
+    #[test]
+    fn reduced_images_round_trip() {
+        let palettes = [
+            vec![gray(0), gray(255), gray(0)],
+            vec![gray(0), gray(85), gray(170), gray(255), gray(85)],
+            vec![gray(17), gray(238)],
+            vec![gray(3), gray(200), gray(201)],
+            vec![
+                Rgba::new(255, 0, 0, 255),
+                Rgba::new(0, 0, 255, 128),
+                Rgba::new(0, 0, 0, 0),
+            ],
+            (0..16)
+                .map(|i| Rgba::new(i * 16, 0, 255 - i, 255))
+                .collect(),
+            vec![Rgba::new(40, 40, 40, 100), Rgba::new(41, 41, 41, 200)],
+            (0..=255)
+                .map(|i| Rgba::new(i, 255 - i, i / 2, 255))
+                .collect(),
+        ];
+
+        for colors in palettes {
+            let image = image_of(&colors);
+
+            assert_eq!(decode_png(&encode_to_png(&image).unwrap()).unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn translucent_palette_entries_come_first() {
+        let format = EncodedFormat::analyze(&image_of(&[
+            Rgba::new(1, 2, 3, 255),
+            Rgba::new(4, 5, 6, 0),
+            Rgba::new(7, 8, 9, 255),
+        ]));
+
+        assert_eq!(format.color_type, ColorType::Indexed);
+        assert_eq!(format.trns_chunk(), [0]);
+        assert_eq!(format.plte_chunk().len(), 9);
+    }
+}
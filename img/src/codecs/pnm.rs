@@ -0,0 +1,677 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const PNM_PIXELS_MAX: u64 = 400_000_000;
+/// Plain formats should keep lines at most this long.
+const ASCII_LINE_LENGTH: usize = 70;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PnmFormat {
+    /// Black and white, pixels darker than mid gray are black
+    Pbm,
+    /// Grayscale, alpha is dropped
+    Pgm,
+    /// RGB, alpha is dropped
+    Ppm,
+    /// RGBA with the RGB_ALPHA tuple type, binary only
+    Pam,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PnmEncoding {
+    #[default]
+    Binary,
+    /// The "plain" variant with samples written as decimal text
+    Ascii,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PnmEncoderOptions {
+    pub format: PnmFormat,
+    pub encoding: PnmEncoding,
+    /// Largest sample value, values above 255 store two bytes per sample. Ignored for PBM.
+    pub maxval: u16,
+}
+
+impl Default for PnmEncoderOptions {
+    fn default() -> Self {
+        Self {
+            format: PnmFormat::Ppm,
+            encoding: PnmEncoding::Binary,
+            maxval: 255,
+        }
+    }
+}
+
+impl PnmEncoderOptions {
+    pub const fn new(format: PnmFormat) -> Self {
+        Self {
+            format,
+            encoding: PnmEncoding::Binary,
+            maxval: 255,
+        }
+    }
+
+    pub const fn ascii(mut self) -> Self {
+        self.encoding = PnmEncoding::Ascii;
+        self
+    }
+
+    pub const fn with_maxval(mut self, maxval: u16) -> Self {
+        self.maxval = maxval;
+        self
+    }
+}
+
+pub fn encode_to_pnm(image: &RgbaImage, format: PnmFormat) -> Result<Vec<u8>> {
+    encode_to_pnm_with(image, &PnmEncoderOptions::new(format))
+}
+
+pub fn encode_to_pnm_with(image: &RgbaImage, options: &PnmEncoderOptions) -> Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("PNM width and height must be greater than 0"));
+    }
+
+    if options.maxval == 0 {
+        return Err(eyre!("PNM maxval must be greater than 0"));
+    }
+
+    let ascii = options.encoding == PnmEncoding::Ascii;
+
+    let magic = match (options.format, ascii) {
+        (PnmFormat::Pbm, true) => "P1",
+        (PnmFormat::Pgm, true) => "P2",
+        (PnmFormat::Ppm, true) => "P3",
+        (PnmFormat::Pbm, false) => "P4",
+        (PnmFormat::Pgm, false) => "P5",
+        (PnmFormat::Ppm, false) => "P6",
+        (PnmFormat::Pam, false) => "P7",
+        (PnmFormat::Pam, true) => return Err(eyre!("PAM has no ASCII variant")),
+    };
+
+    let (width, height) = (image.width, image.height);
+
+    let mut output = match options.format {
+        PnmFormat::Pbm => format!("{}\n{} {}\n", magic, width, height),
+        PnmFormat::Pgm | PnmFormat::Ppm => {
+            format!("{}\n{} {}\n{}\n", magic, width, height, options.maxval)
+        }
+        PnmFormat::Pam => format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL {}\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            width, height, options.maxval
+        ),
+    }
+    .into_bytes();
+
+    if options.format == PnmFormat::Pbm {
+        for row in image.data.chunks_exact(width as usize) {
+            let bits = row.iter().map(|&pixel| luma(pixel) < 128);
+
+            if ascii {
+                let mut line_length = 0;
+
+                for black in bits {
+                    if line_length + 2 > ASCII_LINE_LENGTH {
+                        output.push(b'\n');
+                        line_length = 0;
+                    }
+
+                    output.extend_from_slice(if black { b"1 " } else { b"0 " });
+                    line_length += 2;
+                }
+
+                output.push(b'\n');
+            } else {
+                let mut packed = vec![0u8; (width as usize).div_ceil(8)];
+
+                for (x, black) in bits.enumerate() {
+                    if black {
+                        packed[x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+
+                output.extend_from_slice(&packed);
+            }
+        }
+
+        return Ok(output);
+    }
+
+    let maxval = options.maxval;
+    let mut samples: Vec<u16> = Vec::with_capacity(width as usize * 4);
+    let mut line_length = 0;
+
+    for row in image.data.chunks_exact(width as usize) {
+        samples.clear();
+
+        for &pixel in row {
+            match options.format {
+                PnmFormat::Pgm => samples.push(scale_from_u8(luma(pixel), maxval)),
+                PnmFormat::Ppm => samples
+                    .extend([pixel.r, pixel.g, pixel.b].map(|value| scale_from_u8(value, maxval))),
+                _ => samples.extend(pixel.to_array().map(|value| scale_from_u8(value, maxval))),
+            }
+        }
+
+        for &sample in &samples {
+            if ascii {
+                let text = sample.to_string();
+
+                if line_length > 0 && line_length + text.len() + 1 > ASCII_LINE_LENGTH {
+                    output.push(b'\n');
+                    line_length = 0;
+                } else if line_length > 0 {
+                    output.push(b' ');
+                    line_length += 1;
+                }
+
+                output.extend_from_slice(text.as_bytes());
+                line_length += text.len();
+            } else if maxval > 255 {
+                output.extend_from_slice(&sample.to_be_bytes());
+            } else {
+                output.push(sample as u8);
+            }
+        }
+    }
+
+    if ascii {
+        output.push(b'\n');
+    }
+
+    Ok(output)
+}
+
+pub fn decode_pnm(data: &[u8]) -> Result<RgbaImage> {
+    if data.len() < 2 || data[0] != b'P' {
+        return Err(eyre!("Data is not a PNM file"));
+    }
+
+    let mut reader = HeaderReader { data, pos: 2 };
+
+    match data[1] {
+        b'1'..=b'6' => {
+            let kind = data[1] - b'0';
+            let width = reader.number()?;
+            let height = reader.number()?;
+            let maxval = if kind == 1 || kind == 4 {
+                1
+            } else {
+                reader.number()?
+            };
+
+            let (channels, ascii) = match kind {
+                1 => (0, true),
+                2 => (1, true),
+                3 => (3, true),
+                4 => (0, false),
+                5 => (1, false),
+                _ => (3, false),
+            };
+
+            check_header(width, height, maxval)?;
+
+            if !ascii {
+                // A single whitespace character separates the header from the raster.
+                reader.pos += 1;
+            }
+
+            reader.check_raster_size(width, height, channels, maxval, ascii)?;
+            let mut image = RgbaImage::new(width, height);
+
+            if channels == 0 {
+                decode_pbm(&mut reader, &mut image, ascii)?;
+            } else {
+                let samples = reader.samples(width, height, channels, maxval, ascii)?;
+                fill_pixels(&mut image, &samples, channels, maxval);
+            }
+
+            Ok(image)
+        }
+        b'7' => {
+            let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 0);
+
+            loop {
+                match reader.token()? {
+                    b"WIDTH" => width = reader.number()?,
+                    b"HEIGHT" => height = reader.number()?,
+                    b"DEPTH" => depth = reader.number()?,
+                    b"MAXVAL" => maxval = reader.number()?,
+                    // The depth alone decides how samples are read.
+                    b"TUPLTYPE" => {
+                        reader.token()?;
+                    }
+                    b"ENDHDR" => break,
+                    token => {
+                        return Err(eyre!(
+                            "Unknown PAM header field: {}",
+                            String::from_utf8_lossy(token)
+                        ));
+                    }
+                }
+            }
+
+            check_header(width, height, maxval)?;
+
+            if !(1..=4).contains(&depth) {
+                return Err(eyre!("Unsupported PAM depth: {}", depth));
+            }
+
+            reader.pos += 1;
+
+            reader.check_raster_size(width, height, depth as usize, maxval, false)?;
+            let mut image = RgbaImage::new(width, height);
+            let samples = reader.samples(width, height, depth as usize, maxval, false)?;
+            fill_pixels(&mut image, &samples, depth as usize, maxval);
+
+            Ok(image)
+        }
+        _ => Err(eyre!("Data is not a PNM file")),
+    }
+}
+
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    /// Skips whitespace and `#` comments.
+    fn skip_whitespace(&mut self) {
+        while let Some(&byte) = self.data.get(self.pos) {
+            if byte == b'#' {
+                while self.data.get(self.pos).is_some_and(|&byte| byte != b'\n') {
+                    self.pos += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<&'a [u8]> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return Err(eyre!("PNM data ends unexpectedly"));
+        }
+
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        let token = self.token()?;
+
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .ok_or_else(|| eyre!("Invalid PNM number: {}", String::from_utf8_lossy(token)))
+    }
+
+    /// Fails before anything is allocated for the image when the rest of the data can't hold
+    /// its raster. Plain samples take at least a byte each, PBM packs 8 pixels in a byte.
+    fn check_raster_size(
+        &self,
+        width: u32,
+        height: u32,
+        channels: usize,
+        maxval: u32,
+        ascii: bool,
+    ) -> Result<()> {
+        let (width, height) = (width as usize, height as usize);
+
+        let size = if ascii {
+            width * height * channels.max(1)
+        } else if channels == 0 {
+            width.div_ceil(8) * height
+        } else {
+            width * height * channels * if maxval > 255 { 2 } else { 1 }
+        };
+
+        if self.data.len().saturating_sub(self.pos) < size {
+            return Err(eyre!("PNM pixel data is truncated"));
+        }
+
+        Ok(())
+    }
+
+    /// Reads `width * height * channels` samples, checking them against `maxval`.
+    fn samples(
+        &mut self,
+        width: u32,
+        height: u32,
+        channels: usize,
+        maxval: u32,
+        ascii: bool,
+    ) -> Result<Vec<u16>> {
+        let count = width as usize * height as usize * channels;
+
+        let samples: Vec<u16> = if ascii {
+            (0..count)
+                .map(|_| self.number().map(|value| value.min(u16::MAX as u32) as u16))
+                .collect::<Result<_>>()?
+        } else {
+            let sample_size = if maxval > 255 { 2 } else { 1 };
+            let raster = self
+                .data
+                .get(self.pos..self.pos + count * sample_size)
+                .ok_or_else(|| eyre!("PNM pixel data is truncated"))?;
+
+            if sample_size == 2 {
+                raster
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                    .collect()
+            } else {
+                raster.iter().map(|&byte| byte as u16).collect()
+            }
+        };
+
+        if samples.iter().any(|&sample| sample as u32 > maxval) {
+            return Err(eyre!("PNM sample is larger than maxval {}", maxval));
+        }
+
+        Ok(samples)
+    }
+}
+
+fn check_header(width: u32, height: u32, maxval: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(eyre!("PNM width and height must be greater than 0"));
+    }
+
+    if width as u64 * height as u64 > PNM_PIXELS_MAX {
+        return Err(eyre!("PNM image is too large: {}x{}", width, height));
+    }
+
+    if !(1..=65535).contains(&maxval) {
+        return Err(eyre!("Invalid PNM maxval: {}", maxval));
+    }
+
+    Ok(())
+}
+
+fn decode_pbm(reader: &mut HeaderReader, image: &mut RgbaImage, ascii: bool) -> Result<()> {
+    let width = image.width as usize;
+    let black = Rgba::new(0, 0, 0, 255);
+    let white = Rgba::new(255, 255, 255, 255);
+
+    if ascii {
+        // Plain PBM digits don't need to be separated by whitespace.
+        for pixel in image.data.iter_mut() {
+            reader.skip_whitespace();
+
+            *pixel = match reader.data.get(reader.pos) {
+                Some(b'0') => white,
+                Some(b'1') => black,
+                Some(_) => return Err(eyre!("Invalid PBM pixel value")),
+                None => return Err(eyre!("PNM pixel data is truncated")),
+            };
+            reader.pos += 1;
+        }
+
+        return Ok(());
+    }
+
+    let row_bytes = width.div_ceil(8);
+    let raster = reader
+        .data
+        .get(reader.pos..reader.pos + row_bytes * image.height as usize)
+        .ok_or_else(|| eyre!("PNM pixel data is truncated"))?;
+
+    for (row, packed) in image
+        .data
+        .chunks_exact_mut(width)
+        .zip(raster.chunks_exact(row_bytes))
+    {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = if packed[x / 8] & (0x80 >> (x % 8)) != 0 {
+                black
+            } else {
+                white
+            };
+        }
+    }
+
+    Ok(())
+}
+
+fn fill_pixels(image: &mut RgbaImage, samples: &[u16], channels: usize, maxval: u32) {
+    let scale = |sample: u16| scale_to_u8(sample, maxval);
+
+    for (pixel, tuple) in image.data.iter_mut().zip(samples.chunks_exact(channels)) {
+        *pixel = match tuple {
+            [gray] => Rgba::new(scale(*gray), scale(*gray), scale(*gray), 255),
+            [gray, alpha] => Rgba::new(scale(*gray), scale(*gray), scale(*gray), scale(*alpha)),
+            [r, g, b] => Rgba::new(scale(*r), scale(*g), scale(*b), 255),
+            [r, g, b, a, ..] => Rgba::new(scale(*r), scale(*g), scale(*b), scale(*a)),
+            [] => Rgba::default(),
+        };
+    }
+}
+
+/// Rec. 601 luma, exact for pixels that are already gray.
+pub(crate) fn luma(pixel: Rgba) -> u8 {
+    ((pixel.r as u32 * 299 + pixel.g as u32 * 587 + pixel.b as u32 * 114 + 500) / 1000) as u8
+}
+
+fn scale_from_u8(value: u8, maxval: u16) -> u16 {
+    ((value as u32 * maxval as u32 + 127) / 255) as u16
+}
+
+fn scale_to_u8(value: u16, maxval: u32) -> u8 {
+    ((value as u32 * 255 + maxval / 2) / maxval) as u8
+}
+
+/// Netpbm for the codec registry. Decodes every variant, but probes and encodes only the one
+/// its options name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PnmCodec {
+    pub options: PnmEncoderOptions,
+}
+
+impl PnmCodec {
+    pub const fn new(options: PnmEncoderOptions) -> Self {
+        Self { options }
+    }
+
+    pub const fn pbm() -> Self {
+        Self::new(PnmEncoderOptions::new(PnmFormat::Pbm))
+    }
+
+    pub const fn pgm() -> Self {
+        Self::new(PnmEncoderOptions::new(PnmFormat::Pgm))
+    }
+
+    pub const fn ppm() -> Self {
+        Self::new(PnmEncoderOptions::new(PnmFormat::Ppm))
+    }
+
+    pub const fn pam() -> Self {
+        Self::new(PnmEncoderOptions::new(PnmFormat::Pam))
+    }
+}
+
+impl ImageFormat for PnmCodec {
+    fn name(&self) -> &'static str {
+        match self.options.format {
+            PnmFormat::Pbm => "PBM",
+            PnmFormat::Pgm => "PGM",
+            PnmFormat::Ppm => "PPM",
+            PnmFormat::Pam => "PAM",
+        }
+    }
+
+    fn mime_type(&self) -> &'static str {
+        match self.options.format {
+            PnmFormat::Pbm => "image/x-portable-bitmap",
+            PnmFormat::Pgm => "image/x-portable-graymap",
+            PnmFormat::Ppm => "image/x-portable-pixmap",
+            PnmFormat::Pam => "image/x-portable-arbitrarymap",
+        }
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        match self.options.format {
+            PnmFormat::Ppm => &["image/x-portable-anymap"],
+            _ => &[],
+        }
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        match self.options.format {
+            PnmFormat::Pbm => &["pbm"],
+            PnmFormat::Pgm => &["pgm"],
+            PnmFormat::Ppm => &["ppm", "pnm"],
+            PnmFormat::Pam => &["pam"],
+        }
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        match self.options.format {
+            PnmFormat::Pbm => matches!(data, [b'P', b'1' | b'4', ..]),
+            PnmFormat::Pgm => matches!(data, [b'P', b'2' | b'5', ..]),
+            PnmFormat::Ppm => matches!(data, [b'P', b'3' | b'6', ..]),
+            PnmFormat::Pam => matches!(data, [b'P', b'7', ..]),
+        }
+    }
+}
+
+impl ImageEncoder for PnmCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_pnm_with(image, &self.options)
+    }
+}
+
+impl ImageDecoder for PnmCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_pnm(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors() -> RgbaImage {
+        let mut image = RgbaImage::new(11, 3);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = (index * 23) as u8;
+            *pixel = Rgba::new(value, 255 - value, value / 3, 255);
+        }
+
+        image
+    }
+
+    fn grays() -> RgbaImage {
+        let mut image = colors();
+
+        for pixel in image.pixels_mut() {
+            *pixel = Rgba::new(pixel.r, pixel.r, pixel.r, 255);
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let mut translucent = colors();
+        translucent.set_pixel(2, 1, Rgba::new(1, 2, 3, 4));
+
+        let mut black_and_white = grays();
+        for pixel in black_and_white.pixels_mut() {
+            *pixel = if pixel.r < 128 {
+                Rgba::new(0, 0, 0, 255)
+            } else {
+                Rgba::new(255, 255, 255, 255)
+            };
+        }
+
+        let cases = [
+            (PnmFormat::Pbm, &black_and_white),
+            (PnmFormat::Pgm, &grays()),
+            (PnmFormat::Ppm, &colors()),
+            (PnmFormat::Pam, &translucent),
+        ];
+
+        for (format, image) in cases {
+            let options = PnmEncoderOptions::new(format);
+            assert_eq!(
+                &decode_pnm(&encode_to_pnm_with(image, &options).unwrap()).unwrap(),
+                image
+            );
+
+            let options = options.with_maxval(65535);
+            assert_eq!(
+                &decode_pnm(&encode_to_pnm_with(image, &options).unwrap()).unwrap(),
+                image
+            );
+
+            if format != PnmFormat::Pam {
+                let options = options.ascii();
+                assert_eq!(
+                    &decode_pnm(&encode_to_pnm_with(image, &options).unwrap()).unwrap(),
+                    image
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_ascii_pam() {
+        let options = PnmEncoderOptions::new(PnmFormat::Pam).ascii();
+
+        assert!(encode_to_pnm_with(&colors(), &options).is_err());
+    }
+
+    #[test]
+    fn reads_comments_and_small_maxval() {
+        let image = decode_pnm(b"P2 # gray\n2 1\n# max\n3\n0 3\n").unwrap();
+
+        assert_eq!(image.get_pixel(0, 0), Rgba::new(0, 0, 0, 255));
+        assert_eq!(image.get_pixel(1, 0), Rgba::new(255, 255, 255, 255));
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        let data = encode_to_pnm(&colors(), PnmFormat::Ppm).unwrap();
+
+        assert!(decode_pnm(&data[..data.len() - 1]).is_err());
+        assert!(decode_pnm(b"P6\n4000000000 4000000000\n255\n").is_err());
+        assert!(decode_pnm(b"P5\n1 1\n0\n\0").is_err());
+        assert!(decode_pnm(b"P2\n1 1\n3\n4\n").is_err());
+        assert!(decode_pnm(b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n").is_err());
+    }
+
+    #[test]
+    fn rejects_rasters_larger_than_the_data() {
+        let headers: [&[u8]; 4] = [
+            b"P6 20000 20000 255\n",
+            b"P4 20000 20000\n",
+            b"P3 20000 20000 255\n",
+            b"P7\nWIDTH 20000\nHEIGHT 20000\nDEPTH 4\nMAXVAL 255\nENDHDR\n",
+        ];
+
+        for header in headers {
+            let error = decode_pnm(&[header, &[0; 64]].concat()).unwrap_err();
+            assert!(error.to_string().contains("truncated"), "{}", error);
+        }
+    }
+}
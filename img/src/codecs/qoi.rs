@@ -0,0 +1,405 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const QOI_MAGIC: [u8; 4] = *b"qoif";
+const QOI_END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+const QOI_HEADER_SIZE: usize = 14;
+/// Same limit as the reference implementation, keeps corrupted headers from allocating gigabytes.
+const QOI_PIXELS_MAX: u64 = 400_000_000;
+/// Most pixels a single chunk can decode to, a run covers up to 62.
+const QOI_RUN_MAX: u8 = 62;
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xC0;
+const QOI_OP_RGB: u8 = 0xFE;
+const QOI_OP_RGBA: u8 = 0xFF;
+const QOI_MASK_2: u8 = 0xC0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QoiChannels {
+    Rgb = 3,
+    Rgba = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QoiColorspace {
+    /// sRGB color channels with linear alpha
+    #[default]
+    Srgb = 0,
+    /// All channels linear
+    Linear = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QoiHeader {
+    pub width: u32,
+    pub height: u32,
+    pub channels: QoiChannels,
+    pub colorspace: QoiColorspace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QoiEncoderOptions {
+    /// Channel count written to the header, picked from the image alpha when `None`.
+    /// With `Rgb` every pixel is stored as opaque.
+    pub channels: Option<QoiChannels>,
+    pub colorspace: QoiColorspace,
+}
+
+pub fn encode_to_qoi(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_to_qoi_with(image, &QoiEncoderOptions::default())
+}
+
+pub fn encode_to_qoi_with(image: &RgbaImage, options: &QoiEncoderOptions) -> Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("QOI width and height must be greater than 0"));
+    }
+
+    if image.width as u64 * image.height as u64 > QOI_PIXELS_MAX {
+        return Err(eyre!(
+            "QOI images can have at most {} pixels",
+            QOI_PIXELS_MAX
+        ));
+    }
+
+    let channels = options.channels.unwrap_or_else(|| {
+        if image.data.iter().all(|pixel| pixel.a == 255) {
+            QoiChannels::Rgb
+        } else {
+            QoiChannels::Rgba
+        }
+    });
+
+    let mut output: Vec<u8> = Vec::with_capacity(QOI_HEADER_SIZE + image.data.len() * 2);
+
+    output.extend_from_slice(&QOI_MAGIC);
+    output.extend_from_slice(&image.width.to_be_bytes());
+    output.extend_from_slice(&image.height.to_be_bytes());
+    output.push(channels as u8);
+    output.push(options.colorspace as u8);
+
+    let mut index = [Rgba::default(); 64];
+    let mut prev = Rgba::new(0, 0, 0, 255);
+    let mut run = 0u8;
+
+    for (i, &pixel) in image.data.iter().enumerate() {
+        let pixel = match channels {
+            QoiChannels::Rgb => Rgba { a: 255, ..pixel },
+            QoiChannels::Rgba => pixel,
+        };
+
+        if pixel == prev {
+            run += 1;
+
+            if run == QOI_RUN_MAX || i == image.data.len() - 1 {
+                output.push(QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+
+            continue;
+        }
+
+        if run > 0 {
+            output.push(QOI_OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let hash = qoi_hash(pixel);
+
+        if index[hash] == pixel {
+            output.push(QOI_OP_INDEX | hash as u8);
+        } else {
+            index[hash] = pixel;
+
+            if pixel.a == prev.a {
+                let vr = pixel.r.wrapping_sub(prev.r) as i8;
+                let vg = pixel.g.wrapping_sub(prev.g) as i8;
+                let vb = pixel.b.wrapping_sub(prev.b) as i8;
+
+                let vg_r = vr.wrapping_sub(vg);
+                let vg_b = vb.wrapping_sub(vg);
+
+                if (-2..=1).contains(&vr) && (-2..=1).contains(&vg) && (-2..=1).contains(&vb) {
+                    output.push(
+                        QOI_OP_DIFF
+                            | (((vr + 2) as u8) << 4)
+                            | (((vg + 2) as u8) << 2)
+                            | (vb + 2) as u8,
+                    );
+                } else if (-32..=31).contains(&vg)
+                    && (-8..=7).contains(&vg_r)
+                    && (-8..=7).contains(&vg_b)
+                {
+                    output.push(QOI_OP_LUMA | (vg + 32) as u8);
+                    output.push((((vg_r + 8) as u8) << 4) | (vg_b + 8) as u8);
+                } else {
+                    output.extend_from_slice(&[QOI_OP_RGB, pixel.r, pixel.g, pixel.b]);
+                }
+            } else {
+                output.extend_from_slice(&[QOI_OP_RGBA, pixel.r, pixel.g, pixel.b, pixel.a]);
+            }
+        }
+
+        prev = pixel;
+    }
+
+    output.extend_from_slice(&QOI_END_MARKER);
+
+    Ok(output)
+}
+
+pub fn decode_qoi(data: &[u8]) -> Result<RgbaImage> {
+    let (image, _) = decode_qoi_with_header(data)?;
+
+    Ok(image)
+}
+
+pub fn decode_qoi_with_header(data: &[u8]) -> Result<(RgbaImage, QoiHeader)> {
+    let header = parse_header(data)?;
+
+    let chunks_size = data.len() - QOI_HEADER_SIZE - QOI_END_MARKER.len();
+    if header.width as u64 * header.height as u64 > chunks_size as u64 * QOI_RUN_MAX as u64 {
+        return Err(eyre!(
+            "QOI data is too short for a {}x{} image",
+            header.width,
+            header.height
+        ));
+    }
+
+    let mut image = RgbaImage::new(header.width, header.height);
+
+    let chunks_end = data.len().saturating_sub(QOI_END_MARKER.len());
+    let mut pos = QOI_HEADER_SIZE;
+
+    let mut index = [Rgba::default(); 64];
+    let mut pixel = Rgba::new(0, 0, 0, 255);
+    let mut run = 0usize;
+
+    let read = |pos: &mut usize, count: usize| -> Result<&[u8]> {
+        let bytes = data
+            .get(*pos..*pos + count)
+            .filter(|_| *pos + count <= chunks_end)
+            .ok_or_else(|| eyre!("QOI data ends before all pixels are decoded"))?;
+        *pos += count;
+        Ok(bytes)
+    };
+
+    for out in image.data.iter_mut() {
+        if run > 0 {
+            run -= 1;
+            *out = pixel;
+            continue;
+        }
+
+        let op = read(&mut pos, 1)?[0];
+
+        match op {
+            QOI_OP_RGB => {
+                let rgb = read(&mut pos, 3)?;
+                pixel = Rgba {
+                    r: rgb[0],
+                    g: rgb[1],
+                    b: rgb[2],
+                    ..pixel
+                };
+            }
+            QOI_OP_RGBA => {
+                let rgba = read(&mut pos, 4)?;
+                pixel = Rgba::new(rgba[0], rgba[1], rgba[2], rgba[3]);
+            }
+            _ => match op & QOI_MASK_2 {
+                QOI_OP_INDEX => pixel = index[op as usize],
+                QOI_OP_DIFF => {
+                    pixel.r = pixel.r.wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                    pixel.g = pixel.g.wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                    pixel.b = pixel.b.wrapping_add(op & 0x03).wrapping_sub(2);
+                }
+                QOI_OP_LUMA => {
+                    let second = read(&mut pos, 1)?[0];
+                    let vg = (op & 0x3F).wrapping_sub(32);
+                    pixel.r = pixel
+                        .r
+                        .wrapping_add(vg.wrapping_sub(8).wrapping_add(second >> 4));
+                    pixel.g = pixel.g.wrapping_add(vg);
+                    pixel.b = pixel
+                        .b
+                        .wrapping_add(vg.wrapping_sub(8).wrapping_add(second & 0x0F));
+                }
+                _ => run = (op & 0x3F) as usize,
+            },
+        }
+
+        index[qoi_hash(pixel)] = pixel;
+        *out = pixel;
+    }
+
+    Ok((image, header))
+}
+
+fn parse_header(data: &[u8]) -> Result<QoiHeader> {
+    if data.len() < QOI_HEADER_SIZE + QOI_END_MARKER.len() || data[0..4] != QOI_MAGIC {
+        return Err(eyre!("Data is not a QOI file"));
+    }
+
+    let width = u32::from_be_bytes(data[4..8].try_into()?);
+    let height = u32::from_be_bytes(data[8..12].try_into()?);
+
+    if width == 0 || height == 0 {
+        return Err(eyre!("QOI width and height must be greater than 0"));
+    }
+
+    if width as u64 * height as u64 > QOI_PIXELS_MAX {
+        return Err(eyre!("QOI image is too large: {}x{}", width, height));
+    }
+
+    let channels = match data[12] {
+        3 => QoiChannels::Rgb,
+        4 => QoiChannels::Rgba,
+        channels => return Err(eyre!("Invalid QOI channel count: {}", channels)),
+    };
+
+    let colorspace = match data[13] {
+        0 => QoiColorspace::Srgb,
+        1 => QoiColorspace::Linear,
+        colorspace => return Err(eyre!("Invalid QOI colorspace: {}", colorspace)),
+    };
+
+    Ok(QoiHeader {
+        width,
+        height,
+        channels,
+        colorspace,
+    })
+}
+
+fn qoi_hash(pixel: Rgba) -> usize {
+    (pixel.r as usize * 3 + pixel.g as usize * 5 + pixel.b as usize * 7 + pixel.a as usize * 11)
+        % 64
+}
+
+/// QOI for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QoiCodec {
+    pub options: QoiEncoderOptions,
+}
+
+impl ImageFormat for QoiCodec {
+    fn name(&self) -> &'static str {
+        "QOI"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/qoi"
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &["image/x-qoi"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["qoi"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(&QOI_MAGIC)
+    }
+}
+
+impl ImageEncoder for QoiCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_qoi_with(image, &self.options)
+    }
+}
+
+impl ImageDecoder for QoiCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_qoi(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs, small and large differences, repeats from the index and alpha changes
+    fn sample() -> RgbaImage {
+        let mut image = RgbaImage::new(17, 9);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            *pixel = match index % 7 {
+                0 | 1 => Rgba::new(10, 20, 30, 255),
+                2 => Rgba::new(11, 19, 31, 255),
+                3 => Rgba::new(40, 50, 45, 255),
+                4 => Rgba::new((index * 31) as u8, (index * 7) as u8, 3, 255),
+                5 => Rgba::new(10, 20, 30, 255),
+                _ => Rgba::new(200, 100, 50, (index * 13) as u8),
+            };
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips() {
+        let image = sample();
+        let (decoded, header) = decode_qoi_with_header(&encode_to_qoi(&image).unwrap()).unwrap();
+
+        assert_eq!(decoded, image);
+        assert_eq!(header.channels, QoiChannels::Rgba);
+        assert_eq!(header.colorspace, QoiColorspace::Srgb);
+    }
+
+    #[test]
+    fn rgb_channels_store_opaque_pixels() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba::new(1, 2, 3, 100));
+        let options = QoiEncoderOptions {
+            channels: Some(QoiChannels::Rgb),
+            colorspace: QoiColorspace::Linear,
+        };
+
+        let (decoded, header) =
+            decode_qoi_with_header(&encode_to_qoi_with(&image, &options).unwrap()).unwrap();
+
+        assert_eq!(header.channels, QoiChannels::Rgb);
+        assert_eq!(header.colorspace, QoiColorspace::Linear);
+        assert!(
+            decoded
+                .pixels()
+                .all(|&pixel| pixel == Rgba::new(1, 2, 3, 255))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = encode_to_qoi(&sample()).unwrap();
+
+        assert!(decode_qoi(&data[..data.len() - 12]).is_err());
+        assert!(decode_qoi(&data[..QOI_HEADER_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_dimensions_larger_than_the_data() {
+        let mut data = encode_to_qoi(&sample()).unwrap();
+        data[4..8].copy_from_slice(&20000u32.to_be_bytes());
+        data[8..12].copy_from_slice(&20000u32.to_be_bytes());
+
+        let error = decode_qoi(&data).unwrap_err();
+        assert!(error.to_string().contains("too short"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_huge_dimensions() {
+        let mut data = encode_to_qoi(&sample()).unwrap();
+        data[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        assert!(decode_qoi(&data).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    io::Write,
+    path::Path,
+    sync::{Arc, OnceLock, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::{
+        bmp::BmpCodec, farbfeld::FarbfeldCodec, gif::GifCodec, ico::IcoCodec, jpeg::JpegCodec,
+        png::PngCodec, pnm::PnmCodec, qoi::QoiCodec, tga::TgaCodec, webp::WebpCodec,
+    },
+    rgba_image::RgbaImage,
+};
+
+/// What the registry knows about a format, shared by its encoder and decoder.
+pub trait ImageFormat: Send + Sync {
+    /// Short display name such as `"PNG"`
+    fn name(&self) -> &'static str;
+
+    fn mime_type(&self) -> &'static str;
+
+    /// Other MIME types seen in the wild for the format, such as `image/x-png`
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Lowercase extensions without the dot, the first one is the preferred one
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Whether the data looks like this format, usually by checking its magic bytes.
+    fn probe(&self, data: &[u8]) -> bool;
+}
+
+pub trait ImageEncoder: ImageFormat {
+    /// Encodes with the options this encoder was created with.
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>>;
+
+    /// Encoders that can produce output incrementally override this to stream it.
+    fn write(&self, writer: &mut dyn Write, image: &RgbaImage) -> Result<()> {
+        let data = self.encode(image)?;
+
+        writer
+            .write_all(&data)
+            .map_err(|e| eyre!("Failed to write {} data: {}", self.name(), e))
+    }
+}
+
+pub trait ImageDecoder: ImageFormat {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage>;
+}
+
+/// Encoders and decoders looked up by extension, MIME type or content. Codecs registered
+/// later take precedence, so an in-house codec can replace a built-in one.
+#[derive(Clone, Default)]
+pub struct CodecRegistry {
+    encoders: Vec<Arc<dyn ImageEncoder>>,
+    decoders: Vec<Arc<dyn ImageDecoder>>,
+}
+
+impl CodecRegistry {
+    /// An empty registry, see `with_builtin` for one with every format this crate implements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::new();
+
+        // TGA has no magic number, so it is probed last
+        registry
+            .register(TgaCodec::default())
+            .register(PnmCodec::default())
+            .register(PnmCodec::pbm())
+            .register(PnmCodec::pgm())
+            .register(PnmCodec::pam())
+            .register(BmpCodec::default())
+            .register(IcoCodec::default())
+            .register(IcoCodec::cursor())
+            .register(FarbfeldCodec)
+            .register(QoiCodec::default())
+            .register_encoder(GifCodec::default())
+            .register_encoder(WebpCodec::default())
+            .register(JpegCodec::default())
+            .register(PngCodec::default());
+
+        registry
+    }
+
+    pub fn register_encoder(&mut self, encoder: impl ImageEncoder + 'static) -> &mut Self {
+        self.encoders.push(Arc::new(encoder));
+        self
+    }
+
+    pub fn register_decoder(&mut self, decoder: impl ImageDecoder + 'static) -> &mut Self {
+        self.decoders.push(Arc::new(decoder));
+        self
+    }
+
+    /// Registers a type that both encodes and decodes its format.
+    pub fn register<C>(&mut self, codec: C) -> &mut Self
+    where
+        C: ImageEncoder + ImageDecoder + 'static,
+    {
+        let codec = Arc::new(codec);
+
+        self.encoders.push(codec.clone());
+        self.decoders.push(codec);
+        self
+    }
+
+    /// Encoders from the most recently registered.
+    pub fn encoders(&self) -> impl Iterator<Item = &Arc<dyn ImageEncoder>> {
+        self.encoders.iter().rev()
+    }
+
+    /// Decoders from the most recently registered.
+    pub fn decoders(&self) -> impl Iterator<Item = &Arc<dyn ImageDecoder>> {
+        self.decoders.iter().rev()
+    }
+
+    /// Case-insensitive, with or without the leading dot.
+    pub fn encoder_for_extension(&self, extension: &str) -> Option<Arc<dyn ImageEncoder>> {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+
+        self.encoders()
+            .find(|encoder| encoder.extensions().contains(&extension.as_str()))
+            .cloned()
+    }
+
+    /// Ignores parameters such as `; charset=binary`.
+    pub fn encoder_for_mime(&self, mime: &str) -> Option<Arc<dyn ImageEncoder>> {
+        self.encoders()
+            .find(|encoder| matches_mime(encoder.as_ref(), mime))
+            .cloned()
+    }
+
+    pub fn encoder_for_path(&self, path: &Path) -> Option<Arc<dyn ImageEncoder>> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| self.encoder_for_extension(extension))
+    }
+
+    /// The first decoder whose probe accepts the data.
+    pub fn decoder_for(&self, data: &[u8]) -> Option<Arc<dyn ImageDecoder>> {
+        self.decoders().find(|decoder| decoder.probe(data)).cloned()
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        self.decoder_for(data)
+            .ok_or_else(|| eyre!("Unrecognized image format"))?
+            .decode(data)
+    }
+}
+
+/// Whether `mime` is the format's MIME type or one of its aliases, ignoring parameters such
+/// as `; charset=binary`.
+pub fn matches_mime(format: &dyn ImageFormat, mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+
+    std::iter::once(format.mime_type())
+        .chain(format.mime_aliases().iter().copied())
+        .any(|known| known.eq_ignore_ascii_case(essence))
+}
+
+fn global_registry() -> &'static RwLock<CodecRegistry> {
+    static REGISTRY: OnceLock<RwLock<CodecRegistry>> = OnceLock::new();
+
+    REGISTRY.get_or_init(|| RwLock::new(CodecRegistry::with_builtin()))
+}
+
+/// The registry `decode`, `load_image` and `save_image` consult, starting out with the
+/// built-in codecs.
+pub fn registry() -> RwLockReadGuard<'static, CodecRegistry> {
+    global_registry()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Lets applications add their own formats to the shared registry.
+pub fn registry_mut() -> RwLockWriteGuard<'static, CodecRegistry> {
+    global_registry()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba::Rgba;
+
+    /// Claims PNG files and always decodes to a single red pixel
+    struct FakePng;
+
+    impl ImageFormat for FakePng {
+        fn name(&self) -> &'static str {
+            "Fake PNG"
+        }
+
+        fn mime_type(&self) -> &'static str {
+            "image/png"
+        }
+
+        fn extensions(&self) -> &'static [&'static str] {
+            &["png"]
+        }
+
+        fn probe(&self, data: &[u8]) -> bool {
+            data.starts_with(b"\x89PNG")
+        }
+    }
+
+    impl ImageEncoder for FakePng {
+        fn encode(&self, _image: &RgbaImage) -> Result<Vec<u8>> {
+            Ok(vec![])
+        }
+    }
+
+    impl ImageDecoder for FakePng {
+        fn decode(&self, _data: &[u8]) -> Result<RgbaImage> {
+            Ok(RgbaImage::from_pixel(1, 1, Rgba::new(255, 0, 0, 255)))
+        }
+    }
+
+    #[test]
+    fn later_codecs_take_precedence() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba::new(1, 2, 3, 255));
+        let png = PngCodec::default().encode(&image).unwrap();
+
+        let mut registry = CodecRegistry::with_builtin();
+        assert_eq!(registry.decode(&png).unwrap(), image);
+
+        registry.register(FakePng);
+        assert_eq!(
+            registry.encoder_for_extension(".PNG").unwrap().name(),
+            "Fake PNG"
+        );
+        assert_eq!(
+            registry.encoder_for_mime("image/png").unwrap().name(),
+            "Fake PNG"
+        );
+        assert_eq!(registry.decode(&png).unwrap().width, 1);
+    }
+
+    #[test]
+    fn finds_encoders_by_alias_and_path() {
+        let registry = CodecRegistry::with_builtin();
+        let name = |encoder: Option<Arc<dyn ImageEncoder>>| encoder.map(|encoder| encoder.name());
+
+        assert_eq!(
+            name(registry.encoder_for_mime("image/x-ms-bmp")),
+            Some("BMP")
+        );
+        assert_eq!(
+            name(registry.encoder_for_mime(" image/JPG ; q=1")),
+            Some("JPEG")
+        );
+        assert_eq!(
+            name(registry.encoder_for_path(Path::new("out/icon.cur"))),
+            Some("CUR")
+        );
+        assert_eq!(name(registry.encoder_for_path(Path::new("Makefile"))), None);
+        assert_eq!(name(registry.encoder_for_mime("text/plain")), None);
+    }
+
+    #[test]
+    fn every_decoder_reads_its_own_format() {
+        let registry = CodecRegistry::with_builtin();
+        let image = RgbaImage::from_pixel(3, 2, Rgba::new(10, 20, 30, 255));
+
+        for decoder in registry.decoders() {
+            let encoder = registry
+                .encoder_for_extension(decoder.extensions()[0])
+                .unwrap();
+            let data = encoder.encode(&image).unwrap();
+
+            assert_eq!(registry.decoder_for(&data).unwrap().name(), decoder.name());
+            assert_eq!(
+                registry.decode(&data).unwrap().width,
+                3,
+                "{}",
+                decoder.name()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_data() {
+        assert!(CodecRegistry::new().decode(b"\x89PNG").is_err());
+        assert!(CodecRegistry::with_builtin().decode(b"plain text").is_err());
+    }
+}
@@ -0,0 +1,654 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::{
+        pnm::luma,
+        registry::{ImageDecoder, ImageEncoder, ImageFormat},
+    },
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+const TGA_HEADER_SIZE: usize = 18;
+const TGA_FOOTER_SIZE: usize = 26;
+pub const TGA_SIGNATURE: [u8; 18] = *b"TRUEVISION-XFILE.\0";
+const TGA_EXTENSION_SIZE: usize = 495;
+/// Offset of the attributes type, which says what the alpha channel means
+const TGA_ATTRIBUTES_TYPE: usize = 494;
+const TGA_PIXELS_MAX: u64 = 400_000_000;
+
+const COLOR_MAPPED: u8 = 1;
+const TRUE_COLOR: u8 = 2;
+const GRAYSCALE: u8 = 3;
+const RLE_FLAG: u8 = 8;
+
+/// Image descriptor bits
+const ALPHA_BITS_MASK: u8 = 0x0F;
+const RIGHT_TO_LEFT: u8 = 0x10;
+const TOP_TO_BOTTOM: u8 = 0x20;
+
+const ATTRIBUTES_NO_ALPHA: u8 = 0;
+const ATTRIBUTES_UNDEFINED_IGNORE: u8 = 1;
+const ATTRIBUTES_UNDEFINED_RETAIN: u8 = 2;
+const ATTRIBUTES_ALPHA: u8 = 3;
+const ATTRIBUTES_PREMULTIPLIED: u8 = 4;
+
+/// Packets hold at most this many pixels
+const RLE_MAX_PACKET: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TgaFormat {
+    /// 8-bit grayscale, alpha is dropped
+    Gray8,
+    /// 8-bit grayscale followed by 8-bit alpha
+    GrayAlpha16,
+    /// 24-bit BGR, alpha is dropped
+    Bgr24,
+    /// 32-bit BGRA
+    Bgra32,
+}
+
+impl TgaFormat {
+    const fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Gray8 => 1,
+            Self::GrayAlpha16 => 2,
+            Self::Bgr24 => 3,
+            Self::Bgra32 => 4,
+        }
+    }
+
+    const fn has_alpha(&self) -> bool {
+        matches!(self, Self::GrayAlpha16 | Self::Bgra32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TgaEncoderOptions {
+    /// Picked from the image when `None`, grayscale when every pixel is gray
+    pub format: Option<TgaFormat>,
+    /// Compress with run-length packets, which never cross a row
+    pub rle: bool,
+    /// Store rows top to bottom instead of the usual bottom-up order
+    pub top_down: bool,
+    /// Append a TGA 2.0 extension area and footer, which mark whether alpha is meaningful
+    pub footer: bool,
+}
+
+impl Default for TgaEncoderOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            rle: true,
+            top_down: false,
+            footer: true,
+        }
+    }
+}
+
+pub fn encode_to_tga(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_to_tga_with(image, &TgaEncoderOptions::default())
+}
+
+pub fn encode_to_tga_with(image: &RgbaImage, options: &TgaEncoderOptions) -> Result<Vec<u8>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("TGA width and height must be greater than 0"));
+    }
+
+    if image.width > u16::MAX as u32 || image.height > u16::MAX as u32 {
+        return Err(eyre!("TGA width and height must be at most {}", u16::MAX));
+    }
+
+    let format = options.format.unwrap_or_else(|| {
+        let opaque = image.data.iter().all(|pixel| pixel.a == 255);
+        let gray = image
+            .data
+            .iter()
+            .all(|pixel| pixel.r == pixel.g && pixel.g == pixel.b);
+
+        match (gray, opaque) {
+            (true, true) => TgaFormat::Gray8,
+            (true, false) => TgaFormat::GrayAlpha16,
+            (false, true) => TgaFormat::Bgr24,
+            (false, false) => TgaFormat::Bgra32,
+        }
+    });
+
+    let image_type = match format {
+        TgaFormat::Gray8 | TgaFormat::GrayAlpha16 => GRAYSCALE,
+        TgaFormat::Bgr24 | TgaFormat::Bgra32 => TRUE_COLOR,
+    } | if options.rle { RLE_FLAG } else { 0 };
+
+    let alpha_bits = if format.has_alpha() { 8 } else { 0 };
+    let descriptor = alpha_bits | if options.top_down { TOP_TO_BOTTOM } else { 0 };
+
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let mut output: Vec<u8> =
+        Vec::with_capacity(TGA_HEADER_SIZE + image.data.len() * bytes_per_pixel);
+
+    // No image ID and no color map
+    output.extend_from_slice(&[0, 0, image_type]);
+    output.extend_from_slice(&[0; 5]);
+    // X and Y origin
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(&(image.width as u16).to_le_bytes());
+    output.extend_from_slice(&(image.height as u16).to_le_bytes());
+    output.push((bytes_per_pixel * 8) as u8);
+    output.push(descriptor);
+
+    let width = image.width as usize;
+    let mut row_bytes: Vec<u8> = Vec::with_capacity(width * bytes_per_pixel);
+
+    for y in 0..image.height as usize {
+        let y = if options.top_down {
+            y
+        } else {
+            image.height as usize - 1 - y
+        };
+
+        row_bytes.clear();
+        for &pixel in &image.data[y * width..(y + 1) * width] {
+            match format {
+                TgaFormat::Gray8 => row_bytes.push(luma(pixel)),
+                TgaFormat::GrayAlpha16 => row_bytes.extend_from_slice(&[luma(pixel), pixel.a]),
+                TgaFormat::Bgr24 => row_bytes.extend_from_slice(&[pixel.b, pixel.g, pixel.r]),
+                TgaFormat::Bgra32 => {
+                    row_bytes.extend_from_slice(&[pixel.b, pixel.g, pixel.r, pixel.a])
+                }
+            }
+        }
+
+        if options.rle {
+            encode_rle_row(&mut output, &row_bytes, bytes_per_pixel);
+        } else {
+            output.extend_from_slice(&row_bytes);
+        }
+    }
+
+    if options.footer {
+        let extension_offset = output.len();
+        let attributes_type = if format.has_alpha() {
+            ATTRIBUTES_ALPHA
+        } else {
+            ATTRIBUTES_NO_ALPHA
+        };
+
+        let mut extension = [0u8; TGA_EXTENSION_SIZE];
+        extension[0..2].copy_from_slice(&(TGA_EXTENSION_SIZE as u16).to_le_bytes());
+        extension[TGA_ATTRIBUTES_TYPE] = attributes_type;
+        output.extend_from_slice(&extension);
+
+        if output.len() > u32::MAX as usize {
+            return Err(eyre!("TGA files with a footer can't be larger than 4 GiB"));
+        }
+
+        output.extend_from_slice(&(extension_offset as u32).to_le_bytes());
+        // No developer directory
+        output.extend_from_slice(&0u32.to_le_bytes());
+        output.extend_from_slice(&TGA_SIGNATURE);
+    }
+
+    Ok(output)
+}
+
+/// Splits one row into run packets for repeated pixels and raw packets for the rest.
+fn encode_rle_row(output: &mut Vec<u8>, row: &[u8], bytes_per_pixel: usize) {
+    let pixels: Vec<&[u8]> = row.chunks_exact(bytes_per_pixel).collect();
+    // A run of two single byte pixels saves nothing over staying in a raw packet
+    let min_run = if bytes_per_pixel == 1 { 3 } else { 2 };
+    let run_length = |start: usize| {
+        pixels[start..]
+            .iter()
+            .take(RLE_MAX_PACKET)
+            .take_while(|&&pixel| pixel == pixels[start])
+            .count()
+    };
+
+    let mut index = 0;
+
+    while index < pixels.len() {
+        let run = run_length(index);
+
+        if run >= min_run {
+            output.push(0x80 | (run - 1) as u8);
+            output.extend_from_slice(pixels[index]);
+            index += run;
+            continue;
+        }
+
+        let start = index;
+        while index < pixels.len()
+            && index - start < RLE_MAX_PACKET
+            && (index == start || run_length(index) < min_run)
+        {
+            index += 1;
+        }
+
+        output.push((index - start - 1) as u8);
+        for pixel in &pixels[start..index] {
+            output.extend_from_slice(pixel);
+        }
+    }
+}
+
+/// TGA has no magic number, so this accepts a TGA 2.0 footer or a plausible header.
+pub fn is_tga(data: &[u8]) -> bool {
+    if data.len() < TGA_HEADER_SIZE {
+        return false;
+    }
+
+    if data.ends_with(&TGA_SIGNATURE) {
+        return true;
+    }
+
+    let width = u16::from_le_bytes([data[12], data[13]]);
+    let height = u16::from_le_bytes([data[14], data[15]]);
+
+    data[1] <= 1
+        && matches!(data[2] & !RLE_FLAG, COLOR_MAPPED | TRUE_COLOR | GRAYSCALE)
+        && matches!(data[16], 8 | 15 | 16 | 24 | 32)
+        && width > 0
+        && height > 0
+}
+
+pub fn decode_tga(data: &[u8]) -> Result<RgbaImage> {
+    if data.len() < TGA_HEADER_SIZE {
+        return Err(eyre!("Data is not a TGA file"));
+    }
+
+    let id_length = data[0] as usize;
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let color_map_first = u16::from_le_bytes([data[3], data[4]]) as usize;
+    let color_map_length = u16::from_le_bytes([data[5], data[6]]) as usize;
+    let color_map_bits = data[7];
+    let width = u16::from_le_bytes([data[12], data[13]]) as u32;
+    let height = u16::from_le_bytes([data[14], data[15]]) as u32;
+    let bits_per_pixel = data[16];
+    let descriptor = data[17];
+
+    if color_map_type > 1 {
+        return Err(eyre!("Data is not a TGA file"));
+    }
+
+    let kind = image_type & !RLE_FLAG;
+    let rle = image_type & RLE_FLAG != 0;
+
+    if !matches!(kind, COLOR_MAPPED | TRUE_COLOR | GRAYSCALE) {
+        return Err(eyre!("Unsupported TGA image type: {}", image_type));
+    }
+
+    if width == 0 || height == 0 {
+        return Err(eyre!("TGA width and height must be greater than 0"));
+    }
+
+    if width as u64 * height as u64 > TGA_PIXELS_MAX {
+        return Err(eyre!("TGA image is too large: {}x{}", width, height));
+    }
+
+    if color_map_type == 1 && !matches!(color_map_bits, 15 | 16 | 24 | 32) {
+        return Err(eyre!("Unsupported TGA color map depth: {}", color_map_bits));
+    }
+
+    let attributes_type = attributes_type(data);
+    let alpha_bits = descriptor & ALPHA_BITS_MASK;
+    // Only trust alpha that the file says is there
+    let use_alpha = !matches!(
+        attributes_type,
+        Some(ATTRIBUTES_NO_ALPHA | ATTRIBUTES_UNDEFINED_IGNORE | ATTRIBUTES_UNDEFINED_RETAIN)
+    );
+
+    let color_map_start = TGA_HEADER_SIZE + id_length;
+    let color_map_entry_size = (color_map_bits as usize).div_ceil(8);
+    let pixels_start = color_map_start
+        + if color_map_type == 1 {
+            color_map_length * color_map_entry_size
+        } else {
+            0
+        };
+
+    let palette: Vec<Rgba> = if kind == COLOR_MAPPED {
+        if color_map_type != 1 {
+            return Err(eyre!("Color-mapped TGA image has no color map"));
+        }
+
+        data.get(color_map_start..pixels_start)
+            .ok_or_else(|| eyre!("TGA color map is truncated"))?
+            .chunks_exact(color_map_entry_size)
+            .map(|entry| read_color(entry, color_map_bits, alpha_bits, use_alpha))
+            .collect::<Result<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    let bytes_per_pixel = match (kind, bits_per_pixel) {
+        (COLOR_MAPPED, 8 | 16) | (GRAYSCALE, 8 | 16) | (TRUE_COLOR, 15 | 16 | 24 | 32) => {
+            (bits_per_pixel as usize).div_ceil(8)
+        }
+        _ => {
+            return Err(eyre!(
+                "Unsupported TGA bit depth {} for image type {}",
+                bits_per_pixel,
+                image_type
+            ));
+        }
+    };
+
+    let pixel_data = data
+        .get(pixels_start..)
+        .ok_or_else(|| eyre!("TGA pixel data is truncated"))?;
+    let pixel_count = width as usize * height as usize;
+
+    let raw = if rle {
+        decode_rle(pixel_data, pixel_count, bytes_per_pixel)?
+    } else {
+        pixel_data
+            .get(..pixel_count * bytes_per_pixel)
+            .ok_or_else(|| eyre!("TGA pixel data is truncated"))?
+            .to_vec()
+    };
+
+    let mut image = RgbaImage::new(width, height);
+    let (width, height) = (width as usize, height as usize);
+
+    for (index, bytes) in raw.chunks_exact(bytes_per_pixel).enumerate() {
+        let pixel = match kind {
+            COLOR_MAPPED => {
+                let index = if bytes_per_pixel == 1 {
+                    bytes[0] as usize
+                } else {
+                    u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+                };
+
+                *index
+                    .checked_sub(color_map_first)
+                    .and_then(|index| palette.get(index))
+                    .ok_or_else(|| eyre!("TGA color index {} is out of range", index))?
+            }
+            GRAYSCALE => {
+                let alpha = match bytes.get(1) {
+                    Some(&alpha) if use_alpha => alpha,
+                    _ => 255,
+                };
+                Rgba::new(bytes[0], bytes[0], bytes[0], alpha)
+            }
+            _ => read_color(bytes, bits_per_pixel, alpha_bits, use_alpha)?,
+        };
+
+        let (row, column) = (index / width, index % width);
+        let x = if descriptor & RIGHT_TO_LEFT != 0 {
+            width - 1 - column
+        } else {
+            column
+        };
+        let y = if descriptor & TOP_TO_BOTTOM != 0 {
+            row
+        } else {
+            height - 1 - row
+        };
+
+        image.data[y * width + x] = pixel;
+    }
+
+    if attributes_type == Some(ATTRIBUTES_PREMULTIPLIED) {
+        for pixel in &mut image.data {
+            if pixel.a > 0 {
+                let unpremultiply = |value: u8| {
+                    ((value as u32 * 255 + pixel.a as u32 / 2) / pixel.a as u32).min(255) as u8
+                };
+                *pixel = Rgba::new(
+                    unpremultiply(pixel.r),
+                    unpremultiply(pixel.g),
+                    unpremultiply(pixel.b),
+                    pixel.a,
+                );
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+/// A true-color pixel or color map entry, stored as little endian BGR(A).
+fn read_color(bytes: &[u8], bits: u8, alpha_bits: u8, use_alpha: bool) -> Result<Rgba> {
+    Ok(match bits {
+        15 | 16 => {
+            let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let channel = |shift: u16| {
+                let value = (value >> shift & 0x1F) as u8;
+                value << 3 | value >> 2
+            };
+            // The top bit is only alpha when the descriptor says so, many writers leave it unset
+            let alpha = if bits == 16 && alpha_bits == 1 && use_alpha && value & 0x8000 == 0 {
+                0
+            } else {
+                255
+            };
+
+            Rgba::new(channel(10), channel(5), channel(0), alpha)
+        }
+        24 => Rgba::new(bytes[2], bytes[1], bytes[0], 255),
+        32 => Rgba::new(
+            bytes[2],
+            bytes[1],
+            bytes[0],
+            if use_alpha { bytes[3] } else { 255 },
+        ),
+        _ => return Err(eyre!("Unsupported TGA color depth: {}", bits)),
+    })
+}
+
+/// Expands run and raw packets, which older writers let run across rows.
+fn decode_rle(data: &[u8], pixel_count: usize, bytes_per_pixel: usize) -> Result<Vec<u8>> {
+    let total = pixel_count * bytes_per_pixel;
+    let mut output: Vec<u8> = Vec::with_capacity(total);
+    let mut position = 0;
+
+    while output.len() < total {
+        let header = *data
+            .get(position)
+            .ok_or_else(|| eyre!("TGA RLE data is truncated"))?;
+        let count = (header & 0x7F) as usize + 1;
+        position += 1;
+
+        if header & 0x80 != 0 {
+            let pixel = data
+                .get(position..position + bytes_per_pixel)
+                .ok_or_else(|| eyre!("TGA RLE data is truncated"))?;
+            position += bytes_per_pixel;
+
+            for _ in 0..count {
+                output.extend_from_slice(pixel);
+            }
+        } else {
+            let pixels = data
+                .get(position..position + count * bytes_per_pixel)
+                .ok_or_else(|| eyre!("TGA RLE data is truncated"))?;
+            position += count * bytes_per_pixel;
+
+            output.extend_from_slice(pixels);
+        }
+    }
+
+    // The last packet may run past the image
+    output.truncate(total);
+
+    Ok(output)
+}
+
+/// Attributes type from the TGA 2.0 extension area, when the file has one.
+fn attributes_type(data: &[u8]) -> Option<u8> {
+    let footer = data.get(data.len().checked_sub(TGA_FOOTER_SIZE)?..)?;
+
+    if footer[8..] != TGA_SIGNATURE {
+        return None;
+    }
+
+    let extension_offset = u32::from_le_bytes(footer[0..4].try_into().ok()?) as usize;
+    if extension_offset == 0 {
+        return None;
+    }
+
+    let extension = data.get(extension_offset..extension_offset + TGA_EXTENSION_SIZE)?;
+    if (u16::from_le_bytes([extension[0], extension[1]]) as usize) < TGA_EXTENSION_SIZE {
+        return None;
+    }
+
+    Some(extension[TGA_ATTRIBUTES_TYPE])
+}
+
+/// TGA for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TgaCodec {
+    pub options: TgaEncoderOptions,
+}
+
+impl ImageFormat for TgaCodec {
+    fn name(&self) -> &'static str {
+        "TGA"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/x-tga"
+    }
+
+    fn mime_aliases(&self) -> &'static [&'static str] {
+        &["image/x-targa", "image/tga"]
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["tga", "icb", "vda", "vst"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        is_tga(data)
+    }
+}
+
+impl ImageEncoder for TgaCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_tga_with(image, &self.options)
+    }
+}
+
+impl ImageDecoder for TgaCodec {
+    fn decode(&self, data: &[u8]) -> Result<RgbaImage> {
+        decode_tga(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codecs::registry::registry;
+
+    fn sample() -> RgbaImage {
+        let mut image = RgbaImage::new(5, 3);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            let value = index as u8 * 16;
+            *pixel = Rgba::new(value, 255 - value, value / 2, 255 - value / 3);
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips_with_and_without_rle() {
+        for rle in [true, false] {
+            for top_down in [true, false] {
+                let options = TgaEncoderOptions {
+                    rle,
+                    top_down,
+                    ..Default::default()
+                };
+                let tga = encode_to_tga_with(&sample(), &options).unwrap();
+
+                assert_eq!(decode_tga(&tga).unwrap(), sample());
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_grayscale() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba::new(90, 90, 90, 255));
+
+        assert_eq!(decode_tga(&encode_to_tga(&image).unwrap()).unwrap(), image);
+    }
+
+    #[test]
+    fn rejects_zero_color_map_depth() {
+        let mut header = [0u8; TGA_HEADER_SIZE];
+        header[1] = 1;
+        header[2] = COLOR_MAPPED;
+        header[5] = 2;
+        header[12] = 1;
+        header[14] = 1;
+        header[16] = 8;
+
+        let tga = [&header[..], &[0, 0, 0]].concat();
+
+        assert!(decode_tga(&tga).is_err());
+        assert!(registry().decode(&tga).is_err());
+    }
+
+    fn color_mapped(image_type: u8, pixels: &[u8]) -> Vec<u8> {
+        let header = [
+            0,
+            1,
+            image_type,
+            0,
+            0,
+            2,
+            0,
+            24,
+            0,
+            0,
+            0,
+            0,
+            2,
+            0,
+            1,
+            0,
+            8,
+            TOP_TO_BOTTOM,
+        ];
+        // Blue and red entries, stored as BGR
+        let color_map = [255, 0, 0, 0, 0, 255];
+
+        [&header[..], &color_map, pixels].concat()
+    }
+
+    #[test]
+    fn decodes_color_mapped_images() {
+        let blue = Rgba::new(0, 0, 255, 255);
+        let red = Rgba::new(255, 0, 0, 255);
+
+        let raw = decode_tga(&color_mapped(COLOR_MAPPED, &[1, 0])).unwrap();
+        assert_eq!(raw.data, [red, blue]);
+
+        let rle = decode_tga(&color_mapped(COLOR_MAPPED | RLE_FLAG, &[0x81, 1])).unwrap();
+        assert_eq!(rle.data, [red, red]);
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        for rle in [true, false] {
+            let options = TgaEncoderOptions {
+                rle,
+                ..Default::default()
+            };
+            let tga = encode_to_tga_with(&sample(), &options).unwrap();
+            let pixels_end = tga.len() - TGA_FOOTER_SIZE - TGA_EXTENSION_SIZE;
+
+            assert!(decode_tga(&tga[..pixels_end - 1]).is_err());
+        }
+
+        assert!(decode_tga(&color_mapped(COLOR_MAPPED, &[1])).is_err());
+        assert!(decode_tga(&color_mapped(COLOR_MAPPED | RLE_FLAG, &[0x81])).is_err());
+
+        let mut huge = color_mapped(COLOR_MAPPED, &[]);
+        huge[12..16].fill(0xFF);
+        assert!(decode_tga(&huge).is_err());
+    }
+}
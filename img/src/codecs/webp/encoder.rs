@@ -0,0 +1,208 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::webp::{
+        VP8L_MAX_DIMENSION, VP8L_SIGNATURE, WebpEncoderOptions,
+        huffman::{BitWriter, PrefixCode},
+        lz77::{Histograms, Token, apply_color_cache, backward_references},
+        prefix_encode,
+        transform::{
+            COLOR_INDEXING_TRANSFORM, PREDICTOR_TRANSFORM, SUBTRACT_GREEN_TRANSFORM, bundle_bits,
+            find_palette, index_pixels, palette_deltas, predict, subtract_green,
+        },
+    },
+    rgba_image::RgbaImage,
+};
+
+const MAX_CACHE_BITS: u8 = 11;
+
+/// Encodes the image as a VP8L bitstream, without the RIFF container.
+pub fn encode_vp8l(image: &RgbaImage, options: &WebpEncoderOptions) -> Result<Vec<u8>> {
+    if image.width == 0
+        || image.height == 0
+        || image.width > VP8L_MAX_DIMENSION
+        || image.height > VP8L_MAX_DIMENSION
+    {
+        return Err(eyre!(
+            "WebP width and height must be between 1 and {}",
+            VP8L_MAX_DIMENSION
+        ));
+    }
+
+    if image.data.len() != image.width as usize * image.height as usize {
+        return Err(eyre!("WebP image data does not match its dimensions"));
+    }
+
+    let pixels: Vec<u32> = image
+        .data
+        .iter()
+        .map(|pixel| u32::from_be_bytes([pixel.a, pixel.r, pixel.g, pixel.b]))
+        .collect();
+
+    let palette = options.use_palette.then(|| find_palette(&pixels)).flatten();
+
+    Ok(match palette {
+        // Without bundling, indices lose the predictor and can end up larger than the colors
+        Some(palette) if bundle_bits(palette.len()) == 0 && options.effort >= 3 => {
+            let indexed = write_bitstream(image, pixels.clone(), Some(&palette), options);
+            let predicted = write_bitstream(image, pixels, None, options);
+
+            if indexed.len() <= predicted.len() {
+                indexed
+            } else {
+                predicted
+            }
+        }
+        palette => write_bitstream(image, pixels, palette.as_deref(), options),
+    })
+}
+
+fn write_bitstream(
+    image: &RgbaImage,
+    mut pixels: Vec<u32>,
+    palette: Option<&[u32]>,
+    options: &WebpEncoderOptions,
+) -> Vec<u8> {
+    let mut width = image.width as usize;
+    let has_alpha = image.data.iter().any(|pixel| pixel.a != 255);
+
+    let mut writer = BitWriter::new();
+    writer.write(VP8L_SIGNATURE as u32, 8);
+    writer.write(image.width - 1, 14);
+    writer.write(image.height - 1, 14);
+    writer.write(has_alpha as u32, 1);
+    // Version
+    writer.write(0, 3);
+
+    if let Some(palette) = palette {
+        writer.write(1, 1);
+        writer.write(COLOR_INDEXING_TRANSFORM, 2);
+        writer.write(palette.len() as u32 - 1, 8);
+        write_image(
+            &mut writer,
+            &palette_deltas(palette),
+            palette.len(),
+            options,
+        );
+
+        (pixels, width) = index_pixels(&pixels, width, palette);
+    } else {
+        writer.write(1, 1);
+        writer.write(SUBTRACT_GREEN_TRANSFORM, 2);
+        subtract_green(&mut pixels);
+
+        let tile_bits = options.predictor_tile_bits();
+        let (residuals, modes) = predict(&pixels, width, tile_bits);
+
+        writer.write(1, 1);
+        writer.write(PREDICTOR_TRANSFORM, 2);
+        writer.write(tile_bits - 2, 3);
+        write_image(&mut writer, &modes, width.div_ceil(1 << tile_bits), options);
+
+        pixels = residuals;
+    }
+
+    // No more transforms
+    writer.write(0, 1);
+
+    let tokens = backward_references(&pixels, width, options.chain_depth());
+    let cache_bits = options
+        .color_cache_bits
+        .map(|bits| bits.min(MAX_CACHE_BITS))
+        .unwrap_or_else(|| best_cache_bits(&tokens, &pixels, options.effort));
+    write_tokens(&mut writer, &tokens, &pixels, cache_bits, true);
+
+    writer.finish()
+}
+
+/// Entropy codes a transform image such as the predictor modes or the palette.
+fn write_image(writer: &mut BitWriter, pixels: &[u32], width: usize, options: &WebpEncoderOptions) {
+    let tokens = backward_references(pixels, width, options.chain_depth());
+    write_tokens(writer, &tokens, pixels, 0, false);
+}
+
+/// Tries a few color cache sizes against the entropy of the symbols they produce.
+fn best_cache_bits(tokens: &[Token], pixels: &[u32], effort: u8) -> u8 {
+    let candidates: &[u8] = match effort {
+        0..=2 => &[0, 8],
+        3..=6 => &[0, 4, 6, 8, 10],
+        _ => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+    };
+
+    let cost = |cache_bits: u8| {
+        let tokens = if cache_bits > 0 {
+            apply_color_cache(tokens, pixels, cache_bits)
+        } else {
+            tokens.to_vec()
+        };
+
+        // Each extra cache bit roughly doubles the green alphabet that has to be described
+        Histograms::new(&tokens, cache_bits).estimate_bits() + (1u32 << cache_bits) as f64
+    };
+
+    candidates
+        .iter()
+        .copied()
+        .map(|cache_bits| (cache_bits, cost(cache_bits)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(cache_bits, _)| cache_bits)
+}
+
+fn write_tokens(
+    writer: &mut BitWriter,
+    tokens: &[Token],
+    pixels: &[u32],
+    cache_bits: u8,
+    is_main_image: bool,
+) {
+    let cached;
+    let tokens = if cache_bits > 0 {
+        cached = apply_color_cache(tokens, pixels, cache_bits);
+        writer.write(1, 1);
+        writer.write(cache_bits as u32, 4);
+        &cached
+    } else {
+        writer.write(0, 1);
+        tokens
+    };
+
+    if is_main_image {
+        // A single group of prefix codes for the whole image
+        writer.write(0, 1);
+    }
+
+    let histograms = Histograms::new(tokens, cache_bits);
+    let green = PrefixCode::from_histogram(&histograms.green);
+    let red = PrefixCode::from_histogram(&histograms.red);
+    let blue = PrefixCode::from_histogram(&histograms.blue);
+    let alpha = PrefixCode::from_histogram(&histograms.alpha);
+    let distance = PrefixCode::from_histogram(&histograms.distance);
+
+    for code in [&green, &red, &blue, &alpha, &distance] {
+        code.write(writer);
+    }
+
+    for &token in tokens {
+        match token {
+            Token::Literal(argb) => {
+                green.write_symbol(writer, (argb >> 8 & 0xFF) as usize);
+                red.write_symbol(writer, (argb >> 16 & 0xFF) as usize);
+                blue.write_symbol(writer, (argb & 0xFF) as usize);
+                alpha.write_symbol(writer, (argb >> 24) as usize);
+            }
+            Token::CacheIndex(key) => green.write_symbol(writer, 256 + 24 + key as usize),
+            Token::Copy {
+                length,
+                distance_code,
+            } => {
+                let (symbol, extra_bits, extra) = prefix_encode(length);
+                green.write_symbol(writer, 256 + symbol as usize);
+                writer.write(extra, extra_bits);
+
+                let (symbol, extra_bits, extra) = prefix_encode(distance_code);
+                distance.write_symbol(writer, symbol as usize);
+                writer.write(extra, extra_bits);
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Longest code VP8L allows for the image alphabets.
+pub const MAX_CODE_LENGTH: u8 = 15;
+/// Code lengths are themselves prefix coded, with lengths stored in 3 bits.
+const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+
+const CODE_LENGTH_CODES: usize = 19;
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_CODES] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+/// Length `repeat previous` refers to before any non-zero length was written.
+const INITIAL_REPEAT_LENGTH: u8 = 8;
+const REPEAT_PREVIOUS: u8 = 16;
+const REPEAT_ZERO_SHORT: u8 = 17;
+const REPEAT_ZERO_LONG: u8 = 18;
+
+/// Packs values least significant bit first, the order VP8L reads them in.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    output: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, value: u32, count: u32) {
+        debug_assert!(count <= 32);
+
+        self.buffer |= (value as u64 & ((1 << count) - 1)) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+
+        self.output
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CodeKind {
+    /// One or two symbols below 256, stored directly in the header
+    Simple(Vec<u16>),
+    Normal,
+}
+
+/// Canonical prefix code for one alphabet, built from symbol counts.
+#[derive(Debug, Clone)]
+pub struct PrefixCode {
+    kind: CodeKind,
+    lengths: Vec<u8>,
+    /// Codes are bit reversed so they can go straight into the LSB first writer
+    codes: Vec<u16>,
+}
+
+impl PrefixCode {
+    pub fn from_histogram(histogram: &[u32]) -> Self {
+        let used: Vec<u16> = (0..histogram.len() as u16)
+            .filter(|&symbol| histogram[symbol as usize] > 0)
+            .collect();
+
+        let mut lengths = vec![0u8; histogram.len()];
+
+        let kind = match used.as_slice() {
+            // A lone symbol takes no bits at all
+            [] => CodeKind::Simple(vec![0]),
+            [symbol] if *symbol < 256 => CodeKind::Simple(used),
+            [first, second] if *second < 256 => {
+                lengths[*first as usize] = 1;
+                lengths[*second as usize] = 1;
+                CodeKind::Simple(used)
+            }
+            _ => {
+                lengths = code_lengths(histogram, MAX_CODE_LENGTH);
+                CodeKind::Normal
+            }
+        };
+
+        let codes = canonical_codes(&lengths);
+
+        Self {
+            kind,
+            lengths,
+            codes,
+        }
+    }
+
+    pub fn write(&self, writer: &mut BitWriter) {
+        match &self.kind {
+            CodeKind::Simple(symbols) => {
+                writer.write(1, 1);
+                writer.write(symbols.len() as u32 - 1, 1);
+
+                if symbols[0] < 2 {
+                    writer.write(0, 1);
+                    writer.write(symbols[0] as u32, 1);
+                } else {
+                    writer.write(1, 1);
+                    writer.write(symbols[0] as u32, 8);
+                }
+
+                if let Some(&second) = symbols.get(1) {
+                    writer.write(second as u32, 8);
+                }
+            }
+            CodeKind::Normal => {
+                writer.write(0, 1);
+                write_code_lengths(writer, &self.lengths);
+            }
+        }
+    }
+
+    pub fn write_symbol(&self, writer: &mut BitWriter, symbol: usize) {
+        writer.write(self.codes[symbol] as u32, self.lengths[symbol] as u32);
+    }
+}
+
+/// Huffman code lengths no longer than `max_length`. Small counts get raised until the
+/// tree is shallow enough. Always uses at least two symbols, decoders treat a code with a
+/// single length specially.
+pub fn code_lengths(histogram: &[u32], max_length: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; histogram.len()];
+    let used: Vec<usize> = (0..histogram.len())
+        .filter(|&symbol| histogram[symbol] > 0)
+        .collect();
+
+    match used.as_slice() {
+        [] => return lengths,
+        [symbol] => {
+            lengths[*symbol] = 1;
+            lengths[if *symbol == 0 { 1 } else { 0 }] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    let mut minimum_count = 1u32;
+
+    loop {
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        let mut parents: Vec<usize> = vec![usize::MAX; used.len()];
+
+        for (node, &symbol) in used.iter().enumerate() {
+            heap.push(Reverse((histogram[symbol].max(minimum_count) as u64, node)));
+        }
+
+        while heap.len() > 1 {
+            let Reverse((first_weight, first)) = heap.pop().unwrap_or_default();
+            let Reverse((second_weight, second)) = heap.pop().unwrap_or_default();
+            let node = parents.len();
+
+            parents.push(usize::MAX);
+            parents[first] = node;
+            parents[second] = node;
+            heap.push(Reverse((first_weight + second_weight, node)));
+        }
+
+        // Parents are always created after their children, so walking backwards sees them first
+        let mut depths = vec![0u8; parents.len()];
+        for node in (0..parents.len()).rev() {
+            if parents[node] != usize::MAX {
+                depths[node] = depths[parents[node]] + 1;
+            }
+        }
+
+        if depths[..used.len()]
+            .iter()
+            .all(|&depth| depth <= max_length)
+        {
+            for (node, &symbol) in used.iter().enumerate() {
+                lengths[symbol] = depths[node];
+            }
+
+            return lengths;
+        }
+
+        minimum_count *= 2;
+    }
+}
+
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut length_counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+    for &length in lengths.iter().filter(|&&length| length > 0) {
+        length_counts[length as usize] += 1;
+    }
+
+    let mut next_code = [0u16; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0u16;
+    for length in 1..next_code.len() {
+        code = (code + length_counts[length - 1]) << 1;
+        next_code[length] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+
+            code.reverse_bits() >> (16 - length)
+        })
+        .collect()
+}
+
+/// Stores the code lengths run-length encoded, using a second prefix code for the tokens.
+fn write_code_lengths(writer: &mut BitWriter, lengths: &[u8]) {
+    // (symbol, extra bits value, extra bits count)
+    let mut tokens: Vec<(u8, u32, u32)> = Vec::new();
+    let mut previous = INITIAL_REPEAT_LENGTH;
+    let mut index = 0;
+
+    while index < lengths.len() {
+        let value = lengths[index];
+        let mut run = lengths[index..]
+            .iter()
+            .take_while(|&&length| length == value)
+            .count();
+        index += run;
+
+        if value == 0 {
+            while run >= 3 {
+                let count = if run >= 11 { run.min(138) } else { run.min(10) };
+                if count >= 11 {
+                    tokens.push((REPEAT_ZERO_LONG, count as u32 - 11, 7));
+                } else {
+                    tokens.push((REPEAT_ZERO_SHORT, count as u32 - 3, 3));
+                }
+                run -= count;
+            }
+        } else {
+            if value != previous {
+                tokens.push((value, 0, 0));
+                previous = value;
+                run -= 1;
+            }
+
+            while run >= 3 {
+                let count = run.min(6);
+                tokens.push((REPEAT_PREVIOUS, count as u32 - 3, 2));
+                run -= count;
+            }
+        }
+
+        tokens.extend((0..run).map(|_| (value, 0, 0)));
+    }
+
+    let mut histogram = [0u32; CODE_LENGTH_CODES];
+    for &(symbol, _, _) in &tokens {
+        histogram[symbol as usize] += 1;
+    }
+
+    let token_lengths = code_lengths(&histogram, MAX_CODE_LENGTH_CODE_LENGTH);
+    let token_codes = canonical_codes(&token_lengths);
+
+    let stored = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&symbol| token_lengths[symbol] > 0)
+        .map_or(0, |position| position + 1)
+        .max(4);
+
+    writer.write(stored as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..stored] {
+        writer.write(token_lengths[symbol] as u32, 3);
+    }
+
+    // Lengths are given for the whole alphabet rather than a trimmed prefix of it
+    writer.write(0, 1);
+
+    for (symbol, extra, extra_bits) in tokens {
+        writer.write(
+            token_codes[symbol as usize] as u32,
+            token_lengths[symbol as usize] as u32,
+        );
+        writer.write(extra, extra_bits);
+    }
+}
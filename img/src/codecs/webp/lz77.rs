@@ -0,0 +1,294 @@
+use crate::codecs::webp::{DISTANCE_MAP, prefix_encode};
+
+const HASH_BITS: u32 = 18;
+const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 4096;
+/// Distance codes above 120 are plain distances, the largest prefix code covers up to 2^20.
+const WINDOW_SIZE: usize = (1 << 20) - 120;
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// ARGB pixel
+    Literal(u32),
+    CacheIndex(u32),
+    Copy {
+        length: u32,
+        distance_code: u32,
+    },
+}
+
+/// Rough cost of the length and distance symbols of a copy, on top of their extra bits.
+const COPY_SYMBOL_BITS: f64 = 10.0;
+
+/// Greedy LZ77 over whole pixels using hash chains, `chain_depth` bounds how many earlier
+/// positions are tried for each match. A match is only taken when it is estimated to be
+/// cheaper than storing its pixels as literals.
+pub fn backward_references(pixels: &[u32], width: usize, chain_depth: usize) -> Vec<Token> {
+    let distance_codes = plane_codes(width);
+    let distance_code = |distance: usize| {
+        distance_codes
+            .get(distance)
+            .copied()
+            .filter(|&code| code > 0)
+            .unwrap_or(distance as u32 + 120)
+    };
+    let copy_cost = |length: usize, distance_code: u32| {
+        COPY_SYMBOL_BITS
+            + prefix_encode(length as u32).1 as f64
+            + prefix_encode(distance_code).1 as f64
+    };
+
+    // literal_costs[i] is the estimated size of pixels[..i] stored as literals
+    let mut literal_costs = vec![0.0; pixels.len() + 1];
+    let channel_costs = channel_costs(pixels);
+    for (index, &pixel) in pixels.iter().enumerate() {
+        let cost: f64 = pixel
+            .to_le_bytes()
+            .iter()
+            .zip(&channel_costs)
+            .map(|(&value, costs)| costs[value as usize])
+            .sum();
+        literal_costs[index + 1] = literal_costs[index] + cost;
+    }
+
+    // Hashes the MIN_MATCH pixels starting at `index`
+    let hash = |index: usize| {
+        let value = pixels[index].wrapping_mul(0x1E35_A7BD)
+            ^ pixels[index + 1].rotate_left(11).wrapping_mul(0x9E37_79B1)
+            ^ pixels[index + 2].rotate_left(22);
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut chain = vec![NONE; pixels.len()];
+    let insert = |index: usize, head: &mut [usize], chain: &mut [usize]| {
+        if index + MIN_MATCH <= pixels.len() {
+            let key = hash(index);
+            chain[index] = head[key];
+            head[key] = index;
+        }
+    };
+
+    let mut tokens: Vec<Token> = Vec::with_capacity(pixels.len());
+    let mut index = 0;
+
+    while index < pixels.len() {
+        let max_length = MAX_MATCH.min(pixels.len() - index);
+        // (length, distance code, bits saved over literals)
+        let mut best: Option<(usize, u32, f64)> = None;
+
+        if max_length >= MIN_MATCH {
+            let mut consider = |distance: usize| -> usize {
+                let length = pixels[index - distance..index - distance + max_length]
+                    .iter()
+                    .zip(&pixels[index..index + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length >= MIN_MATCH {
+                    let code = distance_code(distance);
+                    let savings = literal_costs[index + length]
+                        - literal_costs[index]
+                        - copy_cost(length, code);
+
+                    if savings > best.map_or(0.0, |(_, _, savings)| savings) {
+                        best = Some((length, code, savings));
+                    }
+                }
+
+                length
+            };
+
+            // The previous pixel and the one above are the most common matches in flat
+            // graphics, and they can be buried deep in the hash chain
+            let mut longest = 0;
+            for distance in [1, width] {
+                if distance <= index {
+                    longest = longest.max(consider(distance));
+                }
+            }
+
+            let mut candidate = head[hash(index)];
+            let mut depth = chain_depth;
+
+            while candidate != NONE
+                && depth > 0
+                && longest < max_length
+                && index - candidate <= WINDOW_SIZE
+            {
+                // Candidates only get further away, so they are worth a look only if longer
+                if pixels[candidate + longest] == pixels[index + longest] {
+                    longest = longest.max(consider(index - candidate));
+                }
+                candidate = chain[candidate];
+                depth -= 1;
+            }
+        }
+
+        if let Some((length, distance_code, _)) = best {
+            tokens.push(Token::Copy {
+                length: length as u32,
+                distance_code,
+            });
+
+            for position in index..index + length {
+                insert(position, &mut head, &mut chain);
+            }
+            index += length;
+        } else {
+            tokens.push(Token::Literal(pixels[index]));
+            insert(index, &mut head, &mut chain);
+            index += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Bits per value for each channel if every pixel were a literal. A prefix code spends at
+/// least one bit per symbol unless the channel is constant.
+fn channel_costs(pixels: &[u32]) -> [[f64; 256]; 4] {
+    let mut histograms = [[0u32; 256]; 4];
+    for &pixel in pixels {
+        for (channel, value) in pixel.to_le_bytes().into_iter().enumerate() {
+            histograms[channel][value as usize] += 1;
+        }
+    }
+
+    histograms.map(|histogram| {
+        let used = histogram.iter().filter(|&&count| count > 0).count();
+        let total = pixels.len() as f64;
+
+        histogram.map(|count| match (used, count) {
+            (_, 0) | (1, _) => 0.0,
+            _ => (total / count as f64).log2().max(1.0),
+        })
+    })
+}
+
+/// Short distance code for each distance reachable through the 2D neighbourhood map,
+/// 0 where there is none.
+fn plane_codes(width: usize) -> Vec<u32> {
+    let mut codes = vec![0u32; 8 * width + 9];
+
+    for (code, &(x, y)) in DISTANCE_MAP.iter().enumerate() {
+        let distance = x as isize + y as isize * width as isize;
+
+        if distance >= 1 && codes[distance as usize] == 0 {
+            codes[distance as usize] = code as u32 + 1;
+        }
+    }
+
+    codes
+}
+
+pub fn cache_hash(argb: u32, cache_bits: u8) -> usize {
+    (argb.wrapping_mul(0x1E35_A7BD) >> (32 - cache_bits as u32)) as usize
+}
+
+/// Replaces literals that the decoder's color cache would already hold with cache indices.
+pub fn apply_color_cache(tokens: &[Token], pixels: &[u32], cache_bits: u8) -> Vec<Token> {
+    let mut cache = vec![0u32; 1 << cache_bits];
+    let mut position = 0;
+
+    tokens
+        .iter()
+        .map(|&token| match token {
+            Token::Literal(argb) => {
+                let key = cache_hash(argb, cache_bits);
+                position += 1;
+
+                if cache[key] == argb {
+                    Token::CacheIndex(key as u32)
+                } else {
+                    cache[key] = argb;
+                    token
+                }
+            }
+            Token::Copy { length, .. } => {
+                for &argb in &pixels[position..position + length as usize] {
+                    cache[cache_hash(argb, cache_bits)] = argb;
+                }
+                position += length as usize;
+                token
+            }
+            Token::CacheIndex(_) => token,
+        })
+        .collect()
+}
+
+/// Counts symbol usage for the five prefix codes of one image.
+#[derive(Debug, Clone)]
+pub struct Histograms {
+    pub green: Vec<u32>,
+    pub red: Vec<u32>,
+    pub blue: Vec<u32>,
+    pub alpha: Vec<u32>,
+    pub distance: Vec<u32>,
+}
+
+impl Histograms {
+    pub fn new(tokens: &[Token], cache_bits: u8) -> Self {
+        let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+        let mut histograms = Self {
+            green: vec![0; 256 + 24 + cache_size],
+            red: vec![0; 256],
+            blue: vec![0; 256],
+            alpha: vec![0; 256],
+            distance: vec![0; 40],
+        };
+
+        for &token in tokens {
+            match token {
+                Token::Literal(argb) => {
+                    histograms.green[(argb >> 8 & 0xFF) as usize] += 1;
+                    histograms.red[(argb >> 16 & 0xFF) as usize] += 1;
+                    histograms.blue[(argb & 0xFF) as usize] += 1;
+                    histograms.alpha[(argb >> 24) as usize] += 1;
+                }
+                Token::CacheIndex(key) => histograms.green[256 + 24 + key as usize] += 1,
+                Token::Copy {
+                    length,
+                    distance_code,
+                } => {
+                    histograms.green[256 + prefix_encode(length).0 as usize] += 1;
+                    histograms.distance[prefix_encode(distance_code).0 as usize] += 1;
+                }
+            }
+        }
+
+        histograms
+    }
+
+    /// Shannon estimate of the coded size, ignoring the code headers.
+    pub fn estimate_bits(&self) -> f64 {
+        [
+            &self.green,
+            &self.red,
+            &self.blue,
+            &self.alpha,
+            &self.distance,
+        ]
+        .iter()
+        .map(|histogram| entropy(histogram))
+        .sum()
+    }
+}
+
+pub fn entropy(histogram: &[u32]) -> f64 {
+    let total: u32 = histogram.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let total = total as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let count = count as f64;
+            count * (total / count).log2()
+        })
+        .sum()
+}
@@ -0,0 +1,754 @@
+pub mod encoder;
+pub mod huffman;
+pub mod lz77;
+pub mod transform;
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    codecs::registry::{ImageEncoder, ImageFormat},
+    rgba_image::RgbaImage,
+};
+
+pub use encoder::encode_vp8l;
+
+pub const RIFF_SIGNATURE: [u8; 4] = *b"RIFF";
+pub const WEBP_SIGNATURE: [u8; 4] = *b"WEBP";
+pub const VP8L_CHUNK: [u8; 4] = *b"VP8L";
+pub const VP8L_SIGNATURE: u8 = 0x2F;
+/// Width and height are stored in 14 bits
+pub const VP8L_MAX_DIMENSION: u32 = 1 << 14;
+
+/// (x, y) offsets of the 120 short distance codes, ordered roughly by how close they are.
+pub const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1),
+    (1, 0),
+    (1, 1),
+    (-1, 1),
+    (0, 2),
+    (2, 0),
+    (1, 2),
+    (-1, 2),
+    (2, 1),
+    (-2, 1),
+    (2, 2),
+    (-2, 2),
+    (0, 3),
+    (3, 0),
+    (1, 3),
+    (-1, 3),
+    (3, 1),
+    (-3, 1),
+    (2, 3),
+    (-2, 3),
+    (3, 2),
+    (-3, 2),
+    (0, 4),
+    (4, 0),
+    (1, 4),
+    (-1, 4),
+    (4, 1),
+    (-4, 1),
+    (3, 3),
+    (-3, 3),
+    (2, 4),
+    (-2, 4),
+    (4, 2),
+    (-4, 2),
+    (0, 5),
+    (3, 4),
+    (-3, 4),
+    (4, 3),
+    (-4, 3),
+    (5, 0),
+    (1, 5),
+    (-1, 5),
+    (5, 1),
+    (-5, 1),
+    (2, 5),
+    (-2, 5),
+    (5, 2),
+    (-5, 2),
+    (4, 4),
+    (-4, 4),
+    (3, 5),
+    (-3, 5),
+    (5, 3),
+    (-5, 3),
+    (0, 6),
+    (6, 0),
+    (1, 6),
+    (-1, 6),
+    (6, 1),
+    (-6, 1),
+    (2, 6),
+    (-2, 6),
+    (6, 2),
+    (-6, 2),
+    (4, 5),
+    (-4, 5),
+    (5, 4),
+    (-5, 4),
+    (3, 6),
+    (-3, 6),
+    (6, 3),
+    (-6, 3),
+    (0, 7),
+    (7, 0),
+    (1, 7),
+    (-1, 7),
+    (5, 5),
+    (-5, 5),
+    (7, 1),
+    (-7, 1),
+    (4, 6),
+    (-4, 6),
+    (6, 4),
+    (-6, 4),
+    (2, 7),
+    (-2, 7),
+    (7, 2),
+    (-7, 2),
+    (3, 7),
+    (-3, 7),
+    (7, 3),
+    (-7, 3),
+    (5, 6),
+    (-5, 6),
+    (6, 5),
+    (-6, 5),
+    (8, 0),
+    (4, 7),
+    (-4, 7),
+    (7, 4),
+    (-7, 4),
+    (8, 1),
+    (8, 2),
+    (6, 6),
+    (-6, 6),
+    (8, 3),
+    (5, 7),
+    (-5, 7),
+    (7, 5),
+    (-7, 5),
+    (8, 4),
+    (6, 7),
+    (-6, 7),
+    (7, 6),
+    (-7, 6),
+    (8, 5),
+    (7, 7),
+    (-7, 7),
+    (8, 6),
+    (8, 7),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebpEncoderOptions {
+    /// From 0 (fastest) to 9 (smallest), how hard LZ77 searches and how finely predictors adapt
+    pub effort: u8,
+    /// Color cache size as a power of two up to 11, picked per image when `None`.
+    /// `Some(0)` turns the cache off
+    pub color_cache_bits: Option<u8>,
+    /// Store images with at most 256 colors as palette indices
+    pub use_palette: bool,
+}
+
+impl Default for WebpEncoderOptions {
+    fn default() -> Self {
+        Self {
+            effort: 5,
+            color_cache_bits: None,
+            use_palette: true,
+        }
+    }
+}
+
+impl WebpEncoderOptions {
+    pub const fn new(effort: u8) -> Self {
+        Self {
+            effort,
+            color_cache_bits: None,
+            use_palette: true,
+        }
+    }
+
+    pub const fn fast() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn best() -> Self {
+        Self::new(9)
+    }
+
+    pub(crate) fn chain_depth(&self) -> usize {
+        1 << self.effort.min(9)
+    }
+
+    pub(crate) fn predictor_tile_bits(&self) -> u32 {
+        match self.effort {
+            0..=2 => 5,
+            3..=7 => 4,
+            _ => 3,
+        }
+    }
+}
+
+pub fn encode_to_webp(image: &RgbaImage) -> Result<Vec<u8>> {
+    encode_to_webp_with(image, &WebpEncoderOptions::default())
+}
+
+/// Lossless WebP in a simple (non extended) RIFF container.
+pub fn encode_to_webp_with(image: &RgbaImage, options: &WebpEncoderOptions) -> Result<Vec<u8>> {
+    let vp8l_data = encode_vp8l(image, options)?;
+    let padded_size = vp8l_data.len() + vp8l_data.len() % 2;
+    let riff_size = u32::try_from(WEBP_SIGNATURE.len() + 8 + padded_size)
+        .map_err(|_| eyre!("WebP data is too large for a RIFF container"))?;
+
+    let mut output: Vec<u8> = Vec::with_capacity(padded_size + 20);
+
+    output.extend_from_slice(&RIFF_SIGNATURE);
+    output.extend_from_slice(&riff_size.to_le_bytes());
+    output.extend_from_slice(&WEBP_SIGNATURE);
+    output.extend_from_slice(&VP8L_CHUNK);
+    output.extend_from_slice(&(vp8l_data.len() as u32).to_le_bytes());
+    output.extend_from_slice(&vp8l_data);
+
+    // Chunks are padded to an even size
+    if vp8l_data.len() % 2 == 1 {
+        output.push(0);
+    }
+
+    Ok(output)
+}
+
+/// Splits an LZ77 length or distance code into its prefix symbol, the number of extra
+/// bits and their value.
+pub fn prefix_encode(value: u32) -> (u32, u32, u32) {
+    let value = value - 1;
+
+    if value < 4 {
+        return (value, 0, 0);
+    }
+
+    let highest_bit = 31 - value.leading_zeros();
+    let second_bit = value >> (highest_bit - 1) & 1;
+    let extra_bits = highest_bit - 1;
+
+    (
+        2 * highest_bit + second_bit,
+        extra_bits,
+        value & ((1 << extra_bits) - 1),
+    )
+}
+
+/// Lossless WebP for the codec registry, encoding with its options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WebpCodec {
+    pub options: WebpEncoderOptions,
+}
+
+impl ImageFormat for WebpCodec {
+    fn name(&self) -> &'static str {
+        "WebP"
+    }
+
+    fn mime_type(&self) -> &'static str {
+        "image/webp"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["webp"]
+    }
+
+    fn probe(&self, data: &[u8]) -> bool {
+        data.starts_with(&RIFF_SIGNATURE) && data.get(8..12) == Some(&WEBP_SIGNATURE[..])
+    }
+}
+
+impl ImageEncoder for WebpCodec {
+    fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>> {
+        encode_to_webp_with(image, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba::Rgba;
+
+    const CODE_LENGTH_ORDER: [usize; 19] = [
+        17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    ];
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, offset| {
+                let byte = self.data[self.bit / 8];
+                let bit = (byte >> (self.bit % 8)) & 1;
+                self.bit += 1;
+                value | (bit as u32) << offset
+            })
+        }
+    }
+
+    /// Canonical code as (length, code, symbol), read most significant bit first.
+    struct Code(Vec<(u8, u32, usize)>);
+
+    impl Code {
+        fn from_lengths(lengths: &[u8]) -> Self {
+            let mut symbols: Vec<(u8, usize)> = lengths
+                .iter()
+                .enumerate()
+                .filter(|(_, length)| **length > 0)
+                .map(|(symbol, &length)| (length, symbol))
+                .collect();
+            symbols.sort();
+
+            if let [(_, symbol)] = symbols[..] {
+                return Self(vec![(0, 0, symbol)]);
+            }
+
+            let mut code = 0u32;
+            let mut previous_length = symbols.first().map_or(0, |(length, _)| *length);
+            let mut entries = vec![];
+
+            for (length, symbol) in symbols {
+                code <<= length - previous_length;
+                previous_length = length;
+                entries.push((length, code, symbol));
+                code += 1;
+            }
+
+            Self(entries)
+        }
+
+        fn read(&self, reader: &mut BitReader) -> usize {
+            let (mut length, mut code) = (0, 0);
+
+            loop {
+                if let Some(&(_, _, symbol)) = self
+                    .0
+                    .iter()
+                    .find(|entry| entry.0 == length && entry.1 == code)
+                {
+                    return symbol;
+                }
+
+                assert!(length < 15, "Invalid prefix code");
+                code = code << 1 | reader.read(1);
+                length += 1;
+            }
+        }
+    }
+
+    fn read_code(reader: &mut BitReader, alphabet_size: usize) -> Code {
+        let mut lengths = vec![0u8; alphabet_size];
+
+        if reader.read(1) == 1 {
+            let count = reader.read(1) + 1;
+            let first_bits = if reader.read(1) == 1 { 8 } else { 1 };
+            lengths[reader.read(first_bits) as usize] = 1;
+
+            if count == 2 {
+                lengths[reader.read(8) as usize] = 1;
+            }
+
+            return Code::from_lengths(&lengths);
+        }
+
+        let mut length_code_lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..4 + reader.read(4) as usize] {
+            length_code_lengths[symbol] = reader.read(3) as u8;
+        }
+        let length_code = Code::from_lengths(&length_code_lengths);
+
+        let mut max_symbol = if reader.read(1) == 1 {
+            let bits = 2 + 2 * reader.read(3);
+            2 + reader.read(bits) as usize
+        } else {
+            alphabet_size
+        };
+
+        let (mut symbol, mut previous) = (0, 8);
+
+        while symbol < alphabet_size && max_symbol > 0 {
+            max_symbol -= 1;
+
+            let (value, repeat) = match length_code.read(reader) {
+                length @ 0..=15 => (length as u8, 1),
+                16 => (previous, 3 + reader.read(2)),
+                17 => (0, 3 + reader.read(3)),
+                _ => (0, 11 + reader.read(7)),
+            };
+
+            if repeat == 1 && value != 0 {
+                previous = value;
+            }
+
+            for _ in 0..repeat {
+                lengths[symbol] = value;
+                symbol += 1;
+            }
+        }
+
+        Code::from_lengths(&lengths)
+    }
+
+    fn prefix_decode(reader: &mut BitReader, prefix: usize) -> usize {
+        if prefix < 4 {
+            return prefix + 1;
+        }
+
+        let extra_bits = (prefix as u32 - 2) >> 1;
+        let offset = (2 + (prefix & 1)) << extra_bits;
+
+        offset + reader.read(extra_bits) as usize + 1
+    }
+
+    fn read_image(reader: &mut BitReader, width: usize, height: usize, is_main: bool) -> Vec<u32> {
+        let cache_bits = if reader.read(1) == 1 {
+            reader.read(4) as u8
+        } else {
+            0
+        };
+
+        if is_main {
+            assert_eq!(reader.read(1), 0, "Meta prefix codes aren't written");
+        }
+
+        let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+        let green = read_code(reader, 256 + 24 + cache_size);
+        let red = read_code(reader, 256);
+        let blue = read_code(reader, 256);
+        let alpha = read_code(reader, 256);
+        let distance = read_code(reader, 40);
+
+        let mut cache = vec![0u32; cache_size];
+        let mut pixels: Vec<u32> = Vec::with_capacity(width * height);
+        let mut cached = 0;
+
+        while pixels.len() < width * height {
+            match green.read(reader) {
+                symbol @ 0..256 => {
+                    let (r, b, a) = (red.read(reader), blue.read(reader), alpha.read(reader));
+                    pixels.push(u32::from_be_bytes([
+                        a as u8,
+                        r as u8,
+                        symbol as u8,
+                        b as u8,
+                    ]));
+                }
+                symbol @ 256..280 => {
+                    let length = prefix_decode(reader, symbol - 256);
+                    let symbol = distance.read(reader);
+                    let code = prefix_decode(reader, symbol);
+
+                    let distance = if code > 120 {
+                        code - 120
+                    } else {
+                        let (x, y) = DISTANCE_MAP[code - 1];
+                        (x as isize + y as isize * width as isize).max(1) as usize
+                    };
+
+                    for _ in 0..length {
+                        pixels.push(pixels[pixels.len() - distance]);
+                    }
+                }
+                symbol => pixels.push(cache[symbol - 280]),
+            }
+
+            if cache_bits > 0 {
+                for &pixel in &pixels[cached..] {
+                    cache[(pixel.wrapping_mul(0x1E35_A7BD) >> (32 - cache_bits)) as usize] = pixel;
+                }
+            }
+            cached = pixels.len();
+        }
+
+        assert_eq!(pixels.len(), width * height, "Copy runs past the image");
+        pixels
+    }
+
+    fn channels(pixel: u32) -> [i32; 4] {
+        pixel.to_be_bytes().map(i32::from)
+    }
+
+    fn from_channels(channels: [i32; 4]) -> u32 {
+        u32::from_be_bytes(channels.map(|channel| channel.clamp(0, 255) as u8))
+    }
+
+    fn average(a: u32, b: u32) -> u32 {
+        let (a, b) = (channels(a), channels(b));
+        from_channels(std::array::from_fn(|i| (a[i] + b[i]) / 2))
+    }
+
+    fn predict(left: u32, top: u32, top_left: u32, top_right: u32, mode: u32) -> u32 {
+        let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+
+        match mode {
+            0 => 0xFF00_0000,
+            1 => left,
+            2 => top,
+            3 => top_right,
+            4 => top_left,
+            5 => average(average(left, top_right), top),
+            6 => average(left, top_left),
+            7 => average(left, top),
+            8 => average(top_left, top),
+            9 => average(top, top_right),
+            10 => average(average(left, top_left), average(top, top_right)),
+            11 => {
+                let estimate: [i32; 4] = std::array::from_fn(|i| l[i] + t[i] - tl[i]);
+                let distance = |pixel: [i32; 4]| -> i32 {
+                    (0..4).map(|i| (estimate[i] - pixel[i]).abs()).sum()
+                };
+
+                if distance(l) < distance(t) { left } else { top }
+            }
+            12 => from_channels(std::array::from_fn(|i| l[i] + t[i] - tl[i])),
+            13 => {
+                let a = channels(average(left, top));
+                from_channels(std::array::from_fn(|i| a[i] + (a[i] - tl[i]) / 2))
+            }
+            _ => panic!("Invalid predictor mode {}", mode),
+        }
+    }
+
+    fn add_pixels(a: u32, b: u32) -> u32 {
+        let (a, b) = (a.to_be_bytes(), b.to_be_bytes());
+        u32::from_be_bytes(std::array::from_fn(|i| a[i].wrapping_add(b[i])))
+    }
+
+    enum Transform {
+        Predictor { bits: u32, modes: Vec<u32> },
+        SubtractGreen,
+        ColorIndexing { palette: Vec<u32>, bits: u32 },
+    }
+
+    /// Decodes a lossless WebP following the VP8L specification.
+    fn decode_webp(data: &[u8]) -> RgbaImage {
+        assert_eq!(data[0..4], RIFF_SIGNATURE);
+        assert_eq!(
+            u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize,
+            data.len() - 8
+        );
+        assert_eq!(data[8..12], WEBP_SIGNATURE);
+        assert_eq!(data[12..16], VP8L_CHUNK);
+
+        let reader = &mut BitReader {
+            data: &data[20..],
+            bit: 0,
+        };
+        assert_eq!(reader.read(8), VP8L_SIGNATURE as u32);
+
+        let width = reader.read(14) as usize + 1;
+        let height = reader.read(14) as usize + 1;
+        reader.read(1);
+        assert_eq!(reader.read(3), 0);
+
+        let mut transforms = vec![];
+        let mut coded_width = width;
+
+        while reader.read(1) == 1 {
+            transforms.push(match reader.read(2) {
+                0 => {
+                    let bits = reader.read(3) + 2;
+                    let modes = read_image(
+                        reader,
+                        coded_width.div_ceil(1 << bits),
+                        height.div_ceil(1 << bits),
+                        false,
+                    );
+                    Transform::Predictor { bits, modes }
+                }
+                2 => Transform::SubtractGreen,
+                3 => {
+                    let size = reader.read(8) as usize + 1;
+                    let mut palette = read_image(reader, size, 1, false);
+                    for index in 1..size {
+                        palette[index] = add_pixels(palette[index], palette[index - 1]);
+                    }
+
+                    let bits = match size {
+                        0..=2 => 3,
+                        3..=4 => 2,
+                        5..=16 => 1,
+                        _ => 0,
+                    };
+                    coded_width = coded_width.div_ceil(1 << bits);
+                    Transform::ColorIndexing { palette, bits }
+                }
+                other => panic!("Unexpected transform {}", other),
+            });
+        }
+
+        let mut pixels = read_image(reader, coded_width, height, true);
+
+        for transform in transforms.iter().rev() {
+            match transform {
+                Transform::Predictor { bits, modes } => {
+                    let blocks_width = width.div_ceil(1 << bits);
+
+                    for index in 0..pixels.len() {
+                        let (x, y) = (index % width, index / width);
+                        let prediction = match (x, y) {
+                            (0, 0) => 0xFF00_0000,
+                            (_, 0) => pixels[index - 1],
+                            (0, _) => pixels[index - width],
+                            _ => {
+                                let mode =
+                                    modes[(y >> bits) * blocks_width + (x >> bits)] >> 8 & 0xF;
+                                predict(
+                                    pixels[index - 1],
+                                    pixels[index - width],
+                                    pixels[index - width - 1],
+                                    pixels[index - width + 1],
+                                    mode,
+                                )
+                            }
+                        };
+
+                        pixels[index] = add_pixels(pixels[index], prediction);
+                    }
+                }
+                Transform::SubtractGreen => {
+                    for pixel in pixels.iter_mut() {
+                        let green = *pixel >> 8 & 0xFF;
+                        *pixel = add_pixels(*pixel, green << 16 | green);
+                    }
+                }
+                Transform::ColorIndexing { palette, bits } => {
+                    let packed_width = width.div_ceil(1 << bits);
+                    let index_bits = 8 >> bits;
+
+                    pixels = (0..width * height)
+                        .map(|index| {
+                            let (x, y) = (index % width, index / width);
+                            let packed = pixels[y * packed_width + (x >> bits)] >> 8 & 0xFF;
+                            let shift = (x & ((1 << bits) - 1)) as u32 * index_bits;
+                            let color = packed >> shift & ((1 << index_bits) - 1);
+
+                            palette.get(color as usize).copied().unwrap_or(0)
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        let pixels = pixels
+            .into_iter()
+            .map(|argb| {
+                let [a, r, g, b] = argb.to_be_bytes();
+                Rgba::new(r, g, b, a)
+            })
+            .collect();
+
+        RgbaImage::from_data(width as u32, height as u32, pixels).unwrap()
+    }
+
+    fn noise(width: u32, height: u32, colors: Option<u32>) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+        let mut state = 7u32;
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let (value, blue) = match colors {
+                Some(colors) => ((state >> 16) % colors * 37, 0),
+                // Gradients with a little noise and repeated stretches
+                None => (index as u32 % 97 + (state >> 28), index / 7),
+            };
+
+            *pixel = Rgba::new(
+                value as u8,
+                (value * 3) as u8,
+                blue as u8,
+                255 - (value % 256 / 4) as u8,
+            );
+        }
+
+        image
+    }
+
+    #[test]
+    fn round_trips_at_every_effort() {
+        let image = noise(61, 47, None);
+
+        for effort in [0, 3, 5, 9] {
+            let options = WebpEncoderOptions::new(effort);
+            assert_eq!(
+                decode_webp(&encode_to_webp_with(&image, &options).unwrap()),
+                image
+            );
+        }
+
+        for color_cache_bits in [Some(0), Some(1), Some(11)] {
+            let options = WebpEncoderOptions {
+                color_cache_bits,
+                ..Default::default()
+            };
+            assert_eq!(
+                decode_webp(&encode_to_webp_with(&image, &options).unwrap()),
+                image
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_palette_images() {
+        for colors in [1, 2, 3, 4, 5, 16, 17, 200] {
+            let image = noise(29, 13, Some(colors));
+
+            assert_eq!(
+                decode_webp(&encode_to_webp(&image).unwrap()),
+                image,
+                "{} colors",
+                colors
+            );
+
+            let options = WebpEncoderOptions {
+                use_palette: false,
+                ..Default::default()
+            };
+            assert_eq!(
+                decode_webp(&encode_to_webp_with(&image, &options).unwrap()),
+                image
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_thin_images() {
+        for (width, height) in [(1, 1), (1, 40), (40, 1), (2, 3)] {
+            let image = noise(width, height, None);
+
+            assert_eq!(decode_webp(&encode_to_webp(&image).unwrap()), image);
+        }
+    }
+
+    #[test]
+    fn prefix_codes_decode_back() {
+        for value in 1..5000 {
+            let (symbol, extra_bits, extra) = prefix_encode(value);
+            let data = extra.to_le_bytes();
+            let reader = &mut BitReader {
+                data: &data,
+                bit: 0,
+            };
+
+            assert!(extra_bits < 16);
+            assert_eq!(prefix_decode(reader, symbol as usize), value as usize);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_sizes() {
+        assert!(encode_to_webp(&RgbaImage::new(0, 1)).is_err());
+        assert!(encode_to_webp(&RgbaImage::new(VP8L_MAX_DIMENSION + 1, 1)).is_err());
+    }
+}
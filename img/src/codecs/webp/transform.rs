@@ -0,0 +1,255 @@
+use std::collections::HashSet;
+
+pub const PREDICTOR_TRANSFORM: u32 = 0;
+pub const SUBTRACT_GREEN_TRANSFORM: u32 = 2;
+pub const COLOR_INDEXING_TRANSFORM: u32 = 3;
+
+const PREDICTOR_MODES: u32 = 14;
+const OPAQUE_BLACK: u32 = 0xFF00_0000;
+
+/// Stores red and blue relative to green, which removes most of the correlation in photos.
+pub fn subtract_green(pixels: &mut [u32]) {
+    for pixel in pixels {
+        let green = *pixel >> 8 & 0xFF;
+        let red = (*pixel >> 16).wrapping_sub(green) & 0xFF;
+        let blue = pixel.wrapping_sub(green) & 0xFF;
+
+        *pixel = (*pixel & 0xFF00_FF00) | red << 16 | blue;
+    }
+}
+
+/// Picks one of the 14 VP8L predictors for every `1 << tile_bits` square tile and returns
+/// the residuals together with the tile image, which holds each mode in its green channel.
+pub fn predict(pixels: &[u32], width: usize, tile_bits: u32) -> (Vec<u32>, Vec<u32>) {
+    let height = pixels.len() / width;
+    let tile_size = 1 << tile_bits;
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let mut residuals = vec![0u32; pixels.len()];
+    let mut modes: Vec<u32> = Vec::with_capacity(tiles_x * tiles_y);
+    let mut histograms = vec![0u32; 4 * 256];
+    // Residual counts of the tiles predicted so far, the first tiles get a flat prior
+    let mut accumulated = vec![1u32; 4 * 256];
+    let mut accumulated_costs = vec![0f64; 4 * 256];
+    // The entropy of a histogram is total * log2(total) - sum(count * log2(count)), which
+    // can be updated one residual at a time with this table
+    let n_log_n: Vec<f64> = (0..=tile_size * tile_size)
+        .map(|n| {
+            if n == 0 {
+                0.0
+            } else {
+                n as f64 * (n as f64).log2()
+            }
+        })
+        .collect();
+
+    for tile_y in 0..tiles_y {
+        let rows = tile_y * tile_size..((tile_y + 1) * tile_size).min(height);
+
+        for tile_x in 0..tiles_x {
+            let columns = tile_x * tile_size..((tile_x + 1) * tile_size).min(width);
+            let tile_pixels = rows.len() * columns.len();
+            let mut best_mode = 0;
+            let mut best_cost = f64::MAX;
+
+            for (channel, counts) in accumulated.chunks_exact(256).enumerate() {
+                let total: u32 = counts.iter().sum();
+                for (value, &count) in counts.iter().enumerate() {
+                    accumulated_costs[channel * 256 + value] = (total as f64 / count as f64).log2();
+                }
+            }
+
+            for mode in 0..PREDICTOR_MODES {
+                histograms.fill(0);
+                // Favor residuals that are already common in the image, so one code fits all
+                // tiles, on top of the entropy of the tile itself
+                let mut cost = 4.0 * n_log_n[tile_pixels];
+
+                for y in rows.clone() {
+                    for x in columns.clone() {
+                        let residual = sub_pixels(
+                            pixels[y * width + x],
+                            prediction(pixels, width, x, y, mode),
+                        );
+
+                        for (channel, value) in residual.to_le_bytes().into_iter().enumerate() {
+                            let bin = channel * 256 + value as usize;
+                            let count = histograms[bin] as usize;
+
+                            cost += accumulated_costs[bin] + n_log_n[count] - n_log_n[count + 1];
+                            histograms[bin] += 1;
+                        }
+                    }
+                }
+
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_mode = mode;
+                }
+            }
+
+            for y in rows.clone() {
+                for x in columns.clone() {
+                    let residual = sub_pixels(
+                        pixels[y * width + x],
+                        prediction(pixels, width, x, y, best_mode),
+                    );
+
+                    for (channel, value) in residual.to_le_bytes().into_iter().enumerate() {
+                        accumulated[channel * 256 + value as usize] += 1;
+                    }
+                    residuals[y * width + x] = residual;
+                }
+            }
+
+            modes.push(OPAQUE_BLACK | best_mode << 8);
+        }
+    }
+
+    (residuals, modes)
+}
+
+/// Prediction the decoder makes for the pixel at (x, y), the first row and column ignore
+/// the tile mode.
+fn prediction(pixels: &[u32], width: usize, x: usize, y: usize, mode: u32) -> u32 {
+    let index = y * width + x;
+
+    match (x, y) {
+        (0, 0) => return OPAQUE_BLACK,
+        (_, 0) => return pixels[index - 1],
+        (0, _) => return pixels[index - width],
+        _ => {}
+    }
+
+    let left = pixels[index - 1];
+    let top = pixels[index - width];
+    let top_left = pixels[index - width - 1];
+    // On the last column this wraps around to the first pixel of the current row, as specified
+    let top_right = pixels[index - width + 1];
+
+    match mode {
+        0 => OPAQUE_BLACK,
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => map_channels([left, top, top_left], |[l, t, tl]| l + t - tl),
+        _ => map_channels([average(left, top), top_left], |[a, b]| a + (a - b) / 2),
+    }
+}
+
+/// Per channel floor of the mean.
+fn average(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xFEFE_FEFE) >> 1) + (a & b)
+}
+
+/// Whichever of left and top is closer to the gradient estimate `left + top - top_left`.
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let distance = |a: u32, b: u32| -> i32 {
+        a.to_le_bytes()
+            .iter()
+            .zip(b.to_le_bytes())
+            .map(|(&a, b)| (a as i32 - b as i32).abs())
+            .sum()
+    };
+
+    // |estimate - left| is |top - top_left| and the other way around
+    if distance(top, top_left) < distance(left, top_left) {
+        left
+    } else {
+        top
+    }
+}
+
+/// Applies `f` to each channel of the inputs and clamps the result to a byte.
+fn map_channels<const N: usize>(pixels: [u32; N], f: impl Fn([i32; N]) -> i32) -> u32 {
+    let mut output = [0u8; 4];
+
+    for (channel, value) in output.iter_mut().enumerate() {
+        let inputs = pixels.map(|pixel| pixel.to_le_bytes()[channel] as i32);
+        *value = f(inputs).clamp(0, 255) as u8;
+    }
+
+    u32::from_le_bytes(output)
+}
+
+/// Per channel wrapping subtraction, with the borrow kept from crossing into the next byte.
+fn sub_pixels(a: u32, b: u32) -> u32 {
+    const HIGH_BITS: u32 = 0x8080_8080;
+
+    ((a | HIGH_BITS).wrapping_sub(b & !HIGH_BITS)) ^ ((a ^ !b) & HIGH_BITS)
+}
+
+/// Sorted colors of the image when there are at most 256 of them.
+pub fn find_palette(pixels: &[u32]) -> Option<Vec<u32>> {
+    let mut colors: HashSet<u32> = HashSet::new();
+
+    for &pixel in pixels {
+        if colors.insert(pixel) && colors.len() > 256 {
+            return None;
+        }
+    }
+
+    let mut palette: Vec<u32> = colors.into_iter().collect();
+    palette.sort_unstable();
+
+    Some(palette)
+}
+
+/// The palette as stored in the bitstream, every entry relative to the one before it.
+pub fn palette_deltas(palette: &[u32]) -> Vec<u32> {
+    let mut previous = 0;
+
+    palette
+        .iter()
+        .map(|&color| {
+            let delta = sub_pixels(color, previous);
+            previous = color;
+            delta
+        })
+        .collect()
+}
+
+/// How many indices share one pixel for a palette of this size, as a power of two.
+pub fn bundle_bits(palette_size: usize) -> u32 {
+    match palette_size {
+        0..=2 => 3,
+        3..=4 => 2,
+        5..=16 => 1,
+        _ => 0,
+    }
+}
+
+/// Replaces pixels with palette indices, packing several into the green channel of one
+/// pixel for small palettes. Returns the packed pixels and their row width.
+pub fn index_pixels(pixels: &[u32], width: usize, palette: &[u32]) -> (Vec<u32>, usize) {
+    let bundle_bits = bundle_bits(palette.len());
+    let index_bits = 8 >> bundle_bits;
+    let per_pixel = 1 << bundle_bits;
+    let packed_width = width.div_ceil(per_pixel);
+
+    let mut packed: Vec<u32> = Vec::with_capacity(packed_width * (pixels.len() / width));
+
+    for row in pixels.chunks_exact(width) {
+        for group in row.chunks(per_pixel) {
+            let mut bundle = 0u32;
+
+            for (slot, pixel) in group.iter().enumerate() {
+                let index = palette.binary_search(pixel).unwrap_or_default() as u32;
+                bundle |= index << (slot * index_bits);
+            }
+
+            packed.push(OPAQUE_BLACK | bundle << 8);
+        }
+    }
+
+    (packed, packed_width)
+}
@@ -0,0 +1,337 @@
+use std::sync::OnceLock;
+
+use crate::{
+    image_buffer::ImageBuffer,
+    pixel::{Pixel, RgbaF32},
+};
+
+/// Entries of the linear to sRGB table, enough that 8-bit results match the exact curve
+const LINEAR_TABLE_SIZE: usize = 4096;
+
+/// How colors are mixed when something is drawn over existing pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompositeMode {
+    /// Mixes the sRGB encoded values directly, fast but darkens anti-aliased edges and
+    /// blends between saturated colors
+    #[default]
+    FastSrgb,
+    /// Mixes in linear light, like light physically adds up
+    GammaCorrect,
+}
+
+/// How the color channels of stored pixels relate to their alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// Color channels hold the color regardless of alpha, what every codec expects
+    #[default]
+    Straight,
+    /// Color channels are already multiplied by alpha, which makes repeated blending cheaper
+    /// and filtering free of dark fringes
+    Premultiplied,
+}
+
+/// Source-over compositing of straight alpha colors onto pixels stored in `alpha` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compositor {
+    pub mode: CompositeMode,
+    pub alpha: AlphaMode,
+}
+
+impl Compositor {
+    pub const fn new(mode: CompositeMode, alpha: AlphaMode) -> Self {
+        Self { mode, alpha }
+    }
+
+    pub const fn fast() -> Self {
+        Self::new(CompositeMode::FastSrgb, AlphaMode::Straight)
+    }
+
+    pub const fn gamma_correct() -> Self {
+        Self::new(CompositeMode::GammaCorrect, AlphaMode::Straight)
+    }
+
+    /// Draws `color` over `dst`, with `coverage` scaling its alpha, e.g. for anti-aliasing.
+    pub fn blend<P: Pixel>(&self, dst: P, color: RgbaF32, coverage: f32) -> P {
+        let alpha = (color.a * coverage).clamp(0.0, 1.0);
+
+        if alpha <= 0.0 {
+            return dst;
+        }
+
+        if alpha >= 1.0 {
+            return P::from_rgba_f32(self.store(color));
+        }
+
+        let dst = self.load(dst.to_rgba_f32());
+        let src = self.decode(RgbaF32 { a: alpha, ..color }).premultiplied();
+        let inverse = 1.0 - alpha;
+
+        let out = RgbaF32::new(
+            src.r + dst.r * inverse,
+            src.g + dst.g * inverse,
+            src.b + dst.b * inverse,
+            src.a + dst.a * inverse,
+        );
+
+        P::from_rgba_f32(self.store(self.encode(out.unpremultiplied())))
+    }
+
+    /// Premultiplied color in the working space from a stored pixel.
+    fn load(&self, stored: RgbaF32) -> RgbaF32 {
+        match (self.alpha, self.mode) {
+            (AlphaMode::Premultiplied, CompositeMode::FastSrgb) => stored,
+            (AlphaMode::Straight, _) => self.decode(stored).premultiplied(),
+            (AlphaMode::Premultiplied, CompositeMode::GammaCorrect) => {
+                self.decode(stored.unpremultiplied()).premultiplied()
+            }
+        }
+    }
+
+    /// A straight sRGB color as it is stored.
+    fn store(&self, color: RgbaF32) -> RgbaF32 {
+        match self.alpha {
+            AlphaMode::Straight => color,
+            AlphaMode::Premultiplied => color.premultiplied(),
+        }
+    }
+
+    /// sRGB to the space colors are mixed in.
+    fn decode(&self, color: RgbaF32) -> RgbaF32 {
+        match self.mode {
+            CompositeMode::FastSrgb => color,
+            CompositeMode::GammaCorrect => RgbaF32 {
+                r: srgb_to_linear(color.r),
+                g: srgb_to_linear(color.g),
+                b: srgb_to_linear(color.b),
+                a: color.a,
+            },
+        }
+    }
+
+    fn encode(&self, color: RgbaF32) -> RgbaF32 {
+        match self.mode {
+            CompositeMode::FastSrgb => color,
+            CompositeMode::GammaCorrect => RgbaF32 {
+                r: linear_to_srgb(color.r),
+                g: linear_to_srgb(color.g),
+                b: linear_to_srgb(color.b),
+                a: color.a,
+            },
+        }
+    }
+}
+
+fn srgb_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+
+    TABLE.get_or_init(|| std::array::from_fn(|value| srgb_to_linear_exact(value as f32 / 255.0)))
+}
+
+fn linear_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        (0..=LINEAR_TABLE_SIZE)
+            .map(|index| linear_to_srgb_exact(index as f32 / LINEAR_TABLE_SIZE as f32))
+            .collect()
+    })
+}
+
+/// Interpolates between table entries, values outside 0 to 1 use the exact curve.
+fn lookup(table: &[f32], value: f32, exact: fn(f32) -> f32) -> f32 {
+    if !(0.0..=1.0).contains(&value) {
+        return exact(value);
+    }
+
+    let position = value * (table.len() - 1) as f32;
+    let index = (position as usize).min(table.len() - 2);
+    let fraction = position - index as f32;
+
+    table[index] + (table[index + 1] - table[index]) * fraction
+}
+
+/// Decodes an sRGB value from 0 to 1 to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    lookup(srgb_table(), value, srgb_to_linear_exact)
+}
+
+/// Encodes linear light from 0 to 1 as sRGB.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    lookup(linear_table(), value, linear_to_srgb_exact)
+}
+
+pub fn srgb8_to_linear(value: u8) -> f32 {
+    srgb_table()[value as usize]
+}
+
+pub fn linear_to_srgb8(value: f32) -> u8 {
+    (linear_to_srgb(value).clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// The piecewise sRGB transfer function, negative values are mirrored.
+pub fn srgb_to_linear_exact(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let linear = if magnitude <= 0.04045 {
+        magnitude / 12.92
+    } else {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    };
+
+    linear.copysign(value)
+}
+
+pub fn linear_to_srgb_exact(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let srgb = if magnitude <= 0.0031308 {
+        magnitude * 12.92
+    } else {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    };
+
+    srgb.copysign(value)
+}
+
+/// Converts a straight alpha image to premultiplied in place.
+pub fn premultiply<P: Pixel>(image: &mut ImageBuffer<P>) {
+    for pixel in &mut image.data {
+        *pixel = P::from_rgba_f32(pixel.to_rgba_f32().premultiplied());
+    }
+}
+
+/// Converts a premultiplied image back to straight alpha in place. Integer formats lose
+/// some precision in translucent areas, they can't get back what premultiplying rounded off.
+pub fn unpremultiply<P: Pixel>(image: &mut ImageBuffer<P>) {
+    for pixel in &mut image.data {
+        *pixel = P::from_rgba_f32(pixel.to_rgba_f32().unpremultiplied());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba::Rgba, rgba_image::RgbaImage};
+
+    fn assert_close(a: RgbaF32, b: RgbaF32) {
+        let close = a
+            .to_array()
+            .iter()
+            .zip(b.to_array())
+            .all(|(a, b)| (a - b).abs() < 1e-4);
+
+        assert!(close, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn srgb8_round_trips() {
+        for value in 0..=255u8 {
+            assert_eq!(linear_to_srgb8(srgb8_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn tables_follow_the_exact_curves() {
+        for step in 0..=1000 {
+            let value = step as f32 / 1000.0;
+
+            assert!((srgb_to_linear(value) - srgb_to_linear_exact(value)).abs() < 1e-4);
+            assert!((linear_to_srgb(value) - linear_to_srgb_exact(value)).abs() < 1e-3);
+        }
+
+        assert_eq!(srgb_to_linear(-0.5), -srgb_to_linear_exact(0.5));
+        assert_eq!(linear_to_srgb(2.0), linear_to_srgb_exact(2.0));
+    }
+
+    #[test]
+    fn coverage_limits() {
+        let dst = Rgba::new(10, 20, 30, 255);
+        let red = RgbaF32::new(1.0, 0.0, 0.0, 1.0);
+
+        for compositor in [Compositor::fast(), Compositor::gamma_correct()] {
+            assert_eq!(compositor.blend(dst, red, 0.0), dst);
+            assert_eq!(compositor.blend(dst, red, 1.0), Rgba::new(255, 0, 0, 255));
+            assert_eq!(
+                compositor.blend(dst, RgbaF32::new(1.0, 0.0, 0.0, 0.0), 1.0),
+                dst
+            );
+        }
+    }
+
+    #[test]
+    fn gamma_correct_blends_brighter() {
+        let black = Rgba::new(0, 0, 0, 255);
+        let white = RgbaF32::new(1.0, 1.0, 1.0, 1.0);
+
+        // Half coverage is half the light, which sRGB encodes well above 128
+        assert_eq!(
+            Compositor::fast().blend(black, white, 0.5),
+            Rgba::new(128, 128, 128, 255)
+        );
+        assert_eq!(
+            Compositor::gamma_correct().blend(black, white, 0.5),
+            Rgba::new(188, 188, 188, 255)
+        );
+    }
+
+    #[test]
+    fn blending_onto_transparent_keeps_the_color() {
+        let red = RgbaF32::new(1.0, 0.0, 0.0, 0.5);
+
+        for compositor in [Compositor::fast(), Compositor::gamma_correct()] {
+            assert_eq!(
+                compositor.blend(Rgba::default(), red, 1.0),
+                Rgba::new(255, 0, 0, 128)
+            );
+        }
+    }
+
+    #[test]
+    fn premultiplied_storage_matches_straight() {
+        let dst = RgbaF32::new(0.2, 0.6, 0.9, 0.4);
+        let color = RgbaF32::new(0.8, 0.3, 0.1, 0.7);
+
+        for mode in [CompositeMode::FastSrgb, CompositeMode::GammaCorrect] {
+            let straight = Compositor::new(mode, AlphaMode::Straight).blend(dst, color, 0.5);
+            let premultiplied = Compositor::new(mode, AlphaMode::Premultiplied).blend(
+                dst.premultiplied(),
+                color,
+                0.5,
+            );
+
+            assert_close(premultiplied, straight.premultiplied());
+        }
+    }
+
+    #[test]
+    fn premultiply_round_trips() {
+        let mut image = RgbaImage::from_data(
+            3,
+            1,
+            vec![
+                Rgba::new(200, 100, 50, 255),
+                Rgba::new(200, 100, 50, 0),
+                Rgba::new(255, 128, 0, 128),
+            ],
+        )
+        .unwrap();
+
+        premultiply(&mut image);
+        assert_eq!(
+            image.data,
+            [
+                Rgba::new(200, 100, 50, 255),
+                Rgba::new(0, 0, 0, 0),
+                Rgba::new(128, 64, 0, 128),
+            ]
+        );
+
+        unpremultiply(&mut image);
+        assert_eq!(
+            image.data,
+            [
+                Rgba::new(200, 100, 50, 255),
+                Rgba::new(0, 0, 0, 0),
+                Rgba::new(255, 128, 0, 128),
+            ]
+        );
+    }
+}
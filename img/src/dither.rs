@@ -0,0 +1,602 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    quantize::{IndexedImage, MAX_PALETTE_SIZE, nearest},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+/// Side of the tiled blue noise threshold map
+const BLUE_NOISE_SIZE: usize = 64;
+/// Spread of the Gaussian void-and-cluster uses to find clusters and voids
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+const MAX_BAYER_SIZE: u32 = 64;
+
+/// (dx, dy, weight) of each neighbor that receives part of the error, relative to a left
+/// to right scan
+type Kernel = &'static [(i32, i32, f32)];
+
+const FLOYD_STEINBERG: Kernel = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+/// Only passes on 6/8 of the error, which keeps contrast at the cost of detail in shadows
+const ATKINSON: Kernel = &[
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+const JARVIS_JUDICE_NINKE: Kernel = &[
+    (1, 0, 7.0 / 48.0),
+    (2, 0, 5.0 / 48.0),
+    (-2, 1, 3.0 / 48.0),
+    (-1, 1, 5.0 / 48.0),
+    (0, 1, 7.0 / 48.0),
+    (1, 1, 5.0 / 48.0),
+    (2, 1, 3.0 / 48.0),
+    (-2, 2, 1.0 / 48.0),
+    (-1, 2, 3.0 / 48.0),
+    (0, 2, 5.0 / 48.0),
+    (1, 2, 3.0 / 48.0),
+    (2, 2, 1.0 / 48.0),
+];
+const SIERRA: Kernel = &[
+    (1, 0, 5.0 / 32.0),
+    (2, 0, 3.0 / 32.0),
+    (-2, 1, 2.0 / 32.0),
+    (-1, 1, 4.0 / 32.0),
+    (0, 1, 5.0 / 32.0),
+    (1, 1, 4.0 / 32.0),
+    (2, 1, 2.0 / 32.0),
+    (-1, 2, 2.0 / 32.0),
+    (0, 2, 3.0 / 32.0),
+    (1, 2, 2.0 / 32.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DitherMethod {
+    /// Error diffusion to 4 neighbors, the usual choice
+    #[default]
+    FloydSteinberg,
+    /// Error diffusion that drops a quarter of the error, crisp but loses shadow detail
+    Atkinson,
+    /// Error diffusion over 12 neighbors, smoother but slower
+    JarvisJudiceNinke,
+    /// Error diffusion over 10 neighbors, close to Jarvis-Judice-Ninke
+    Sierra,
+    /// Ordered dithering with a `size` x `size` Bayer matrix, `size` being a power of two
+    Bayer { size: u32 },
+    /// Ordered dithering with a tiled blue noise threshold map, without the Bayer cross-hatch
+    BlueNoise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DitherOptions {
+    pub method: DitherMethod,
+    /// How much of the error is spread, or how far ordered thresholds reach. 0 maps every
+    /// pixel to its closest color, 1 is full dithering
+    pub strength: f32,
+    /// Alternate the scan direction every row, which avoids the diagonal drift of error
+    /// diffusion. Ignored by ordered methods
+    pub serpentine: bool,
+}
+
+impl Default for DitherOptions {
+    fn default() -> Self {
+        Self {
+            method: DitherMethod::FloydSteinberg,
+            strength: 1.0,
+            serpentine: true,
+        }
+    }
+}
+
+impl DitherOptions {
+    pub fn new(method: DitherMethod) -> Self {
+        Self {
+            method,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+/// Maps the image to the palette, dithering so areas between palette colors average out
+/// to the right color. Fully transparent pixels neither take nor pass on any error.
+pub fn dither(
+    image: &RgbaImage,
+    palette: &[Rgba],
+    options: &DitherOptions,
+) -> Result<IndexedImage> {
+    if palette.is_empty() || palette.len() > MAX_PALETTE_SIZE {
+        return Err(eyre!(
+            "Palette size must be between 1 and {}, got {}",
+            MAX_PALETTE_SIZE,
+            palette.len()
+        ));
+    }
+
+    if !options.strength.is_finite() || options.strength < 0.0 {
+        return Err(eyre!(
+            "Dither strength must be a non-negative number, got {}",
+            options.strength
+        ));
+    }
+
+    let entries: Vec<[u8; 4]> = palette.iter().map(|color| color.to_array()).collect();
+
+    let indices = match options.method {
+        DitherMethod::FloydSteinberg => diffuse(image, &entries, FLOYD_STEINBERG, options),
+        DitherMethod::Atkinson => diffuse(image, &entries, ATKINSON, options),
+        DitherMethod::JarvisJudiceNinke => diffuse(image, &entries, JARVIS_JUDICE_NINKE, options),
+        DitherMethod::Sierra => diffuse(image, &entries, SIERRA, options),
+        DitherMethod::Bayer { size } => {
+            if !size.is_power_of_two() || !(2..=MAX_BAYER_SIZE).contains(&size) {
+                return Err(eyre!(
+                    "Bayer matrix size must be a power of two from 2 to {}, got {}",
+                    MAX_BAYER_SIZE,
+                    size
+                ));
+            }
+
+            let matrix = bayer_matrix(size as usize);
+            ordered(image, &entries, &matrix, size as usize, options.strength)
+        }
+        DitherMethod::BlueNoise => ordered(
+            image,
+            &entries,
+            blue_noise(),
+            BLUE_NOISE_SIZE,
+            options.strength,
+        ),
+    };
+
+    Ok(IndexedImage {
+        width: image.width,
+        height: image.height,
+        palette: palette.to_vec(),
+        indices,
+    })
+}
+
+/// Nearest palette entries, remembered per color since flat areas repeat the same values.
+struct Matcher<'a> {
+    palette: &'a [[u8; 4]],
+    cache: HashMap<[u8; 4], u8>,
+    transparent: u8,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(palette: &'a [[u8; 4]]) -> Self {
+        Self {
+            palette,
+            cache: HashMap::new(),
+            transparent: nearest(palette, [0; 4]) as u8,
+        }
+    }
+
+    fn find(&mut self, color: [u8; 4]) -> u8 {
+        *self
+            .cache
+            .entry(color)
+            .or_insert_with(|| nearest(self.palette, color) as u8)
+    }
+}
+
+fn diffuse(
+    image: &RgbaImage,
+    palette: &[[u8; 4]],
+    kernel: Kernel,
+    options: &DitherOptions,
+) -> Vec<u8> {
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let rows = kernel.iter().map(|&(_, dy, _)| dy).max().unwrap_or(0) as usize + 1;
+
+    // Error still to be added to the next few rows, reused as a ring
+    let mut errors = vec![[0f32; 4]; rows * width];
+    let mut indices = vec![0u8; width * height];
+    let mut matcher = Matcher::new(palette);
+
+    for y in 0..height {
+        let reverse = options.serpentine && y % 2 == 1;
+        let row = y % rows;
+
+        for step in 0..width {
+            let x = if reverse { width - 1 - step } else { step };
+            let pixel = image.data[y * width + x];
+            let error = std::mem::take(&mut errors[row * width + x]);
+
+            if pixel.a == 0 {
+                indices[y * width + x] = matcher.transparent;
+                continue;
+            }
+
+            let mut value = [0f32; 4];
+            for (channel, (value, original)) in value.iter_mut().zip(pixel.to_array()).enumerate() {
+                *value = (original as f32 + error[channel]).clamp(0.0, 255.0);
+            }
+
+            let index = matcher.find(value.map(|value| value.round() as u8));
+            indices[y * width + x] = index;
+
+            let chosen = palette[index as usize];
+            let residual: [f32; 4] = std::array::from_fn(|channel| {
+                (value[channel] - chosen[channel] as f32) * options.strength
+            });
+
+            for &(dx, dy, weight) in kernel {
+                let dx = if reverse { -dx } else { dx };
+                let target_x = x as i32 + dx;
+                let target_y = y + dy as usize;
+
+                if target_x < 0 || target_x >= width as i32 || target_y >= height {
+                    continue;
+                }
+
+                let target = (target_y % rows) * width + target_x as usize;
+                for (error, residual) in errors[target].iter_mut().zip(residual) {
+                    *error += residual * weight;
+                }
+            }
+        }
+    }
+
+    indices
+}
+
+/// Adds a position dependent offset before picking the closest color. The offsets span the
+/// typical distance between neighboring palette colors.
+fn ordered(
+    image: &RgbaImage,
+    palette: &[[u8; 4]],
+    thresholds: &[f32],
+    size: usize,
+    strength: f32,
+) -> Vec<u8> {
+    let width = image.width as usize;
+    let spread = palette_spacing(palette) * strength;
+    let mut matcher = Matcher::new(palette);
+
+    image
+        .data
+        .iter()
+        .enumerate()
+        .map(|(index, &pixel)| {
+            if pixel.a == 0 {
+                return matcher.transparent;
+            }
+
+            let (x, y) = (index % width, index / width);
+            let offset = (thresholds[(y % size) * size + x % size] - 0.5) * spread;
+            let [r, g, b, a] = pixel.to_array();
+            let shift = |value: u8| (value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+
+            matcher.find([shift(r), shift(g), shift(b), a])
+        })
+        .collect()
+}
+
+/// Mean distance from each palette color to its closest neighbor, per channel.
+fn palette_spacing(palette: &[[u8; 4]]) -> f32 {
+    if palette.len() < 2 {
+        return 0.0;
+    }
+
+    let total: f32 = palette
+        .iter()
+        .enumerate()
+        .map(|(index, a)| {
+            palette
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != index)
+                .map(|(_, b)| {
+                    (0..3)
+                        .map(|channel| (a[channel] as f32 - b[channel] as f32).powi(2))
+                        .sum::<f32>()
+                })
+                .fold(f32::MAX, f32::min)
+                .sqrt()
+        })
+        .sum();
+
+    total / palette.len() as f32 / 3f32.sqrt()
+}
+
+/// Thresholds in (0, 1) from the recursive Bayer construction.
+fn bayer_matrix(size: usize) -> Vec<f32> {
+    let mut matrix = vec![0u32];
+    let mut current = 1;
+
+    while current < size {
+        let next = current * 2;
+        let mut expanded = vec![0u32; next * next];
+
+        for y in 0..next {
+            for x in 0..next {
+                let base = 4 * matrix[(y % current) * current + x % current];
+                expanded[y * next + x] = base
+                    + match (x >= current, y >= current) {
+                        (false, false) => 0,
+                        (true, false) => 2,
+                        (false, true) => 3,
+                        (true, true) => 1,
+                    };
+            }
+        }
+
+        matrix = expanded;
+        current = next;
+    }
+
+    let cells = (size * size) as f32;
+    matrix
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / cells)
+        .collect()
+}
+
+/// Blue noise thresholds from Ulichney's void-and-cluster method, built once on first use.
+fn blue_noise() -> &'static [f32] {
+    static BLUE_NOISE: OnceLock<Vec<f32>> = OnceLock::new();
+
+    BLUE_NOISE.get_or_init(|| {
+        let size = BLUE_NOISE_SIZE;
+        let cells = size * size;
+
+        // Gaussian weight of every wrapped offset, the map tiles seamlessly
+        let kernel: Vec<f32> = (0..cells)
+            .map(|index| {
+                let wrap = |value: usize| value.min(size - value) as f32;
+                let (dx, dy) = (wrap(index % size), wrap(index / size));
+                (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect();
+
+        let mut pattern = VoidAndCluster {
+            size,
+            kernel,
+            points: vec![false; cells],
+            energy: vec![0.0; cells],
+        };
+
+        // Deterministic initial points, about a tenth of the cells
+        let mut state = 0x2545_F491u32;
+        for index in 0..cells {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+
+            if state.is_multiple_of(10) {
+                pattern.toggle(index);
+            }
+        }
+
+        // Move points from the tightest clusters into the largest voids until stable
+        loop {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            let void = pattern.largest_void();
+
+            if void == cluster {
+                pattern.toggle(cluster);
+                break;
+            }
+
+            pattern.toggle(void);
+        }
+
+        let initial = pattern.points.clone();
+        let initial_energy = pattern.energy.clone();
+        let initial_count = initial.iter().filter(|&&point| point).count();
+        let mut ranks = vec![0usize; cells];
+
+        // Ranks below the initial pattern come from removing its tightest clusters
+        for rank in (0..initial_count).rev() {
+            let cluster = pattern.tightest_cluster();
+            pattern.toggle(cluster);
+            ranks[cluster] = rank;
+        }
+
+        // And the ones above from filling the largest voids
+        pattern.points = initial;
+        pattern.energy = initial_energy;
+        for rank in initial_count..cells {
+            let void = pattern.largest_void();
+            pattern.toggle(void);
+            ranks[void] = rank;
+        }
+
+        ranks
+            .into_iter()
+            .map(|rank| (rank as f32 + 0.5) / cells as f32)
+            .collect()
+    })
+}
+
+struct VoidAndCluster {
+    size: usize,
+    kernel: Vec<f32>,
+    points: Vec<bool>,
+    /// Sum of the kernel centered on every point, at each cell
+    energy: Vec<f32>,
+}
+
+impl VoidAndCluster {
+    fn toggle(&mut self, index: usize) {
+        let sign = if self.points[index] { -1.0 } else { 1.0 };
+        self.points[index] = !self.points[index];
+
+        let (x, y) = (index % self.size, index / self.size);
+        for (cell, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (cell % self.size + self.size - x) % self.size;
+            let dy = (cell / self.size + self.size - y) % self.size;
+            *energy += sign * self.kernel[dy * self.size + dx];
+        }
+    }
+
+    fn tightest_cluster(&self) -> usize {
+        (0..self.points.len())
+            .filter(|&index| self.points[index])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+
+    fn largest_void(&self) -> usize {
+        (0..self.points.len())
+            .filter(|&index| !self.points[index])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::quantize_to_palette;
+
+    const METHODS: [DitherMethod; 7] = [
+        DitherMethod::FloydSteinberg,
+        DitherMethod::Atkinson,
+        DitherMethod::JarvisJudiceNinke,
+        DitherMethod::Sierra,
+        DitherMethod::Bayer { size: 2 },
+        DitherMethod::Bayer { size: 8 },
+        DitherMethod::BlueNoise,
+    ];
+
+    const BLACK_AND_WHITE: [Rgba; 2] = [Rgba::new(0, 0, 0, 255), Rgba::new(255, 255, 255, 255)];
+
+    fn white_share(indexed: &IndexedImage) -> f32 {
+        let white = indexed.indices.iter().filter(|&&index| index == 1).count();
+        white as f32 / indexed.indices.len() as f32
+    }
+
+    #[test]
+    fn gray_averages_out() {
+        for (level, expected) in [(64, 0.25), (128, 0.5), (191, 0.75)] {
+            let image = RgbaImage::from_pixel(64, 64, Rgba::new(level, level, level, 255));
+
+            for method in METHODS {
+                let indexed =
+                    dither(&image, &BLACK_AND_WHITE, &DitherOptions::new(method)).unwrap();
+                let share = white_share(&indexed);
+
+                // Atkinson drops part of the error, the rest should be close
+                let tolerance = if method == DitherMethod::Atkinson {
+                    0.1
+                } else {
+                    0.03
+                };
+                assert!(
+                    (share - expected).abs() < tolerance,
+                    "{method:?} at {level}: {share}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zero_strength_picks_the_closest_color() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba::new(100, 100, 100, 255));
+        let closest = quantize_to_palette(&image, &BLACK_AND_WHITE).unwrap();
+
+        for method in METHODS {
+            let options = DitherOptions::new(method).with_strength(0.0);
+            assert_eq!(
+                dither(&image, &BLACK_AND_WHITE, &options).unwrap(),
+                closest,
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn palette_colors_are_kept() {
+        let mut image = RgbaImage::from_pixel(9, 7, BLACK_AND_WHITE[0]);
+
+        for (index, pixel) in image.pixels_mut().enumerate() {
+            if index % 3 == 0 {
+                *pixel = BLACK_AND_WHITE[1];
+            }
+        }
+
+        for method in METHODS {
+            let indexed = dither(&image, &BLACK_AND_WHITE, &DitherOptions::new(method)).unwrap();
+            assert_eq!(indexed.to_rgba_image(), image, "{method:?}");
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_take_no_error() {
+        let palette = [
+            Rgba::new(0, 0, 0, 0),
+            BLACK_AND_WHITE[0],
+            BLACK_AND_WHITE[1],
+        ];
+        let mut image = RgbaImage::from_pixel(16, 16, Rgba::new(128, 128, 128, 255));
+
+        for y in 0..16 {
+            image.set_pixel(y, y, Rgba::new(255, 255, 255, 0));
+        }
+
+        for method in METHODS {
+            let indexed = dither(&image, &palette, &DitherOptions::new(method)).unwrap();
+
+            for (index, &pixel) in image.pixels().enumerate() {
+                assert_eq!(indexed.indices[index] == 0, pixel.a == 0, "{method:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn threshold_maps_use_every_rank_once() {
+        let ranks = |thresholds: &[f32]| {
+            let mut ranks: Vec<usize> = thresholds
+                .iter()
+                .map(|threshold| (threshold * thresholds.len() as f32) as usize)
+                .collect();
+            ranks.sort_unstable();
+            ranks
+        };
+
+        for size in [2, 4, 16] {
+            let matrix = bayer_matrix(size);
+            assert_eq!(ranks(&matrix), (0..size * size).collect::<Vec<_>>());
+        }
+
+        assert_eq!(bayer_matrix(2), [0.125, 0.625, 0.875, 0.375]);
+        assert_eq!(
+            ranks(blue_noise()),
+            (0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba::new(1, 2, 3, 255));
+
+        assert!(dither(&image, &[], &DitherOptions::default()).is_err());
+        for size in [0, 1, 3, 128] {
+            let options = DitherOptions::new(DitherMethod::Bayer { size });
+            assert!(dither(&image, &BLACK_AND_WHITE, &options).is_err());
+        }
+
+        for strength in [-1.0, f32::NAN, f32::INFINITY] {
+            let options = DitherOptions::default().with_strength(strength);
+            assert!(dither(&image, &BLACK_AND_WHITE, &options).is_err());
+        }
+    }
+}
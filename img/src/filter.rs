@@ -0,0 +1,854 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    image_buffer::ImageBuffer,
+    pixel::{Pixel, RgbaF32},
+};
+
+/// Above this sigma `gaussian_blur` switches from a true Gaussian kernel to three box blurs,
+/// which cost the same at any radius and are visually indistinguishable
+const BOX_APPROXIMATION_SIGMA: f32 = 8.0;
+const BOX_PASSES: usize = 3;
+/// The Gaussian kernel is cut off where the weights become negligible
+const GAUSSIAN_SIGMAS: f32 = 3.0;
+
+/// What convolution reads past the edges of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeMode {
+    /// Repeats the edge pixels
+    #[default]
+    Clamp,
+    /// Continues from the opposite edge, for tiling images
+    Wrap,
+    /// Reflects the image around the edge pixels
+    Mirror,
+    /// Transparent black, so blurs fade out at the edges
+    Transparent,
+}
+
+impl EdgeMode {
+    /// The index to read for `index` along an axis of `size` pixels, `None` when transparent.
+    fn resolve(&self, index: i64, size: i64) -> Option<usize> {
+        if (0..size).contains(&index) {
+            return Some(index as usize);
+        }
+
+        match self {
+            Self::Clamp => Some(index.clamp(0, size - 1) as usize),
+            Self::Wrap => Some(index.rem_euclid(size) as usize),
+            Self::Mirror => {
+                if size == 1 {
+                    return Some(0);
+                }
+
+                let period = 2 * (size - 1);
+                let index = index.rem_euclid(period);
+                Some(index.min(period - index) as usize)
+            }
+            Self::Transparent => None,
+        }
+    }
+}
+
+/// A convolution matrix, centered on the pixel being filtered, with weights row by row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    pub width: u32,
+    pub height: u32,
+    pub weights: Vec<f32>,
+    /// Added to every channel after weighing, e.g. 0.5 to center signed results on gray
+    pub bias: f32,
+    /// Filter only the color and keep the alpha of every pixel, like SVG's `preserveAlpha`.
+    /// Otherwise all channels are filtered premultiplied
+    pub preserve_alpha: bool,
+}
+
+impl Kernel {
+    pub fn new(width: u32, height: u32, weights: Vec<f32>) -> Result<Self> {
+        let kernel = Self {
+            width,
+            height,
+            weights,
+            bias: 0.0,
+            preserve_alpha: false,
+        };
+
+        kernel.validate()?;
+
+        Ok(kernel)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.width.is_multiple_of(2) || self.height.is_multiple_of(2) {
+            return Err(eyre!(
+                "Kernel width and height must be odd so it has a center, got {}x{}",
+                self.width,
+                self.height
+            ));
+        }
+
+        if self.weights.len() != self.width as usize * self.height as usize {
+            return Err(eyre!(
+                "A {}x{} kernel needs {} weights, got {}",
+                self.width,
+                self.height,
+                self.width as usize * self.height as usize,
+                self.weights.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn with_bias(mut self, bias: f32) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    pub fn with_preserve_alpha(mut self, preserve_alpha: bool) -> Self {
+        self.preserve_alpha = preserve_alpha;
+        self
+    }
+
+    /// Scales the weights to add up to 1, so the filter keeps the overall brightness.
+    /// Kernels whose weights cancel out are left as they are.
+    pub fn normalized(mut self) -> Self {
+        let total: f32 = self.weights.iter().sum();
+
+        if total.abs() > f32::EPSILON {
+            self.weights.iter_mut().for_each(|weight| *weight /= total);
+        }
+
+        self
+    }
+
+    /// Adds `amount` times the difference to the 4 neighbors, 1 is the classic sharpen.
+    pub fn sharpen(amount: f32) -> Self {
+        let a = -amount;
+
+        Self {
+            width: 3,
+            height: 3,
+            weights: vec![0.0, a, 0.0, a, 1.0 + 4.0 * amount, a, 0.0, a, 0.0],
+            bias: 0.0,
+            preserve_alpha: true,
+        }
+    }
+
+    /// Relief lit from the top left that keeps the colors of the image.
+    pub fn emboss() -> Self {
+        Self {
+            width: 3,
+            height: 3,
+            weights: vec![-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0],
+            bias: 0.0,
+            preserve_alpha: true,
+        }
+    }
+
+    pub fn laplacian() -> Self {
+        Self {
+            width: 3,
+            height: 3,
+            weights: vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0],
+            bias: 0.0,
+            preserve_alpha: true,
+        }
+    }
+
+    /// Horizontal gradient, positive where the image gets brighter to the right.
+    pub fn sobel_x() -> Self {
+        Self {
+            width: 3,
+            height: 3,
+            weights: vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0],
+            bias: 0.0,
+            preserve_alpha: true,
+        }
+    }
+
+    /// Vertical gradient, positive where the image gets brighter downwards.
+    pub fn sobel_y() -> Self {
+        Self {
+            width: 3,
+            height: 3,
+            weights: vec![-1.0, -2.0, -1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 1.0],
+            bias: 0.0,
+            preserve_alpha: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EdgeDetector {
+    /// Gradient magnitude, thick edges that also show their strength
+    #[default]
+    Sobel,
+    /// Second derivative, thin edges but more sensitive to noise
+    Laplacian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnsharpMaskOptions {
+    /// Blur radius of the mask, about the size of the details to bring out
+    pub sigma: f32,
+    /// How much of the difference to the blurred image is added back
+    pub amount: f32,
+    /// Differences below this, from 0 to 1, are left alone so noise isn't sharpened
+    pub threshold: f32,
+}
+
+impl Default for UnsharpMaskOptions {
+    fn default() -> Self {
+        Self {
+            sigma: 1.0,
+            amount: 1.0,
+            threshold: 0.0,
+        }
+    }
+}
+
+/// Pixels as floating point RGBA, row by row, filtered without going back to `P` in between.
+#[derive(Clone)]
+struct Plane {
+    width: usize,
+    height: usize,
+    pixels: Vec<RgbaF32>,
+}
+
+impl Plane {
+    fn from_image<P: Pixel>(image: &ImageBuffer<P>, premultiplied: bool) -> Self {
+        Self {
+            width: image.width as usize,
+            height: image.height as usize,
+            pixels: image
+                .data
+                .iter()
+                .map(|pixel| {
+                    let pixel = pixel.to_rgba_f32();
+
+                    if premultiplied {
+                        pixel.premultiplied()
+                    } else {
+                        pixel
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn to_image<P: Pixel>(&self, premultiplied: bool) -> ImageBuffer<P> {
+        ImageBuffer {
+            width: self.width as u32,
+            height: self.height as u32,
+            data: self
+                .pixels
+                .iter()
+                .map(|&pixel| {
+                    if premultiplied {
+                        P::from_rgba_f32(clamp_premultiplied(pixel).unpremultiplied())
+                    } else {
+                        P::from_rgba_f32(pixel)
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn get(&self, x: i64, y: i64, edge: EdgeMode) -> RgbaF32 {
+        match (
+            edge.resolve(x, self.width as i64),
+            edge.resolve(y, self.height as i64),
+        ) {
+            (Some(x), Some(y)) => self.pixels[y * self.width + x],
+            _ => RgbaF32::default(),
+        }
+    }
+
+    fn convolve(&self, kernel: &Kernel, edge: EdgeMode) -> Self {
+        let (half_width, half_height) = (kernel.width as i64 / 2, kernel.height as i64 / 2);
+
+        // Zero weights are common in small kernels, skipping them saves most of the reads
+        let taps: Vec<(i64, i64, f32)> = kernel
+            .weights
+            .iter()
+            .enumerate()
+            .filter(|&(_, &weight)| weight != 0.0)
+            .map(|(index, &weight)| {
+                let dx = (index % kernel.width as usize) as i64 - half_width;
+                let dy = (index / kernel.width as usize) as i64 - half_height;
+                (dx, dy, weight)
+            })
+            .collect();
+
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let sum = taps
+                    .iter()
+                    .fold(RgbaF32::default(), |sum, &(dx, dy, weight)| {
+                        add_scaled(sum, self.get(x + dx, y + dy, edge), weight)
+                    });
+
+                pixels.push(sum);
+            }
+        }
+
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Convolves one axis with a symmetric kernel of `weights.len()` taps.
+    fn convolve_axis(&self, weights: &[f32], horizontal: bool, edge: EdgeMode) -> Self {
+        let half = weights.len() as i64 / 2;
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+
+        for y in 0..self.height as i64 {
+            for x in 0..self.width as i64 {
+                let sum =
+                    weights
+                        .iter()
+                        .enumerate()
+                        .fold(RgbaF32::default(), |sum, (index, &weight)| {
+                            let offset = index as i64 - half;
+                            let pixel = if horizontal {
+                                self.get(x + offset, y, edge)
+                            } else {
+                                self.get(x, y + offset, edge)
+                            };
+
+                            add_scaled(sum, pixel, weight)
+                        });
+
+                pixels.push(sum);
+            }
+        }
+
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Box blur along one axis with a running sum, the cost doesn't depend on `radius`.
+    fn box_axis(&self, radius: usize, horizontal: bool, edge: EdgeMode) -> Self {
+        let (length, lines) = if horizontal {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        };
+
+        let radius = radius as i64;
+        let scale = 1.0 / (2 * radius + 1) as f32;
+        let mut pixels = vec![RgbaF32::default(); self.pixels.len()];
+
+        for line in 0..lines as i64 {
+            let read = |position: i64| {
+                if horizontal {
+                    self.get(position, line, edge)
+                } else {
+                    self.get(line, position, edge)
+                }
+            };
+
+            let mut sum = (-radius..=radius).fold(RgbaF32::default(), |sum, position| {
+                add_scaled(sum, read(position), 1.0)
+            });
+
+            for position in 0..length as i64 {
+                let index = if horizontal {
+                    line as usize * self.width + position as usize
+                } else {
+                    position as usize * self.width + line as usize
+                };
+
+                pixels[index] = scaled(sum, scale);
+
+                sum = add_scaled(sum, read(position + radius + 1), 1.0);
+                sum = add_scaled(sum, read(position - radius), -1.0);
+            }
+        }
+
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    fn box_blur(&self, radius: usize, edge: EdgeMode) -> Self {
+        self.box_axis(radius, true, edge)
+            .box_axis(radius, false, edge)
+    }
+
+    fn gaussian_blur(&self, sigma: f32, edge: EdgeMode) -> Self {
+        if sigma > BOX_APPROXIMATION_SIGMA {
+            return box_radii(sigma)
+                .into_iter()
+                .fold(self.clone(), |plane, radius| plane.box_blur(radius, edge));
+        }
+
+        let weights = gaussian_weights(sigma);
+
+        self.convolve_axis(&weights, true, edge)
+            .convolve_axis(&weights, false, edge)
+    }
+}
+
+fn add_scaled(sum: RgbaF32, pixel: RgbaF32, weight: f32) -> RgbaF32 {
+    RgbaF32::new(
+        sum.r + pixel.r * weight,
+        sum.g + pixel.g * weight,
+        sum.b + pixel.b * weight,
+        sum.a + pixel.a * weight,
+    )
+}
+
+fn scaled(pixel: RgbaF32, scale: f32) -> RgbaF32 {
+    add_scaled(RgbaF32::default(), pixel, scale)
+}
+
+/// Keeps a filtered premultiplied color valid, negative weights can push it out of range.
+fn clamp_premultiplied(pixel: RgbaF32) -> RgbaF32 {
+    let a = pixel.a.clamp(0.0, 1.0);
+
+    RgbaF32::new(
+        pixel.r.clamp(0.0, a),
+        pixel.g.clamp(0.0, a),
+        pixel.b.clamp(0.0, a),
+        a,
+    )
+}
+
+fn gaussian_weights(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * GAUSSIAN_SIGMAS).ceil() as i64;
+
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let total: f32 = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+/// Radii of the box blurs that together approximate a Gaussian of `sigma`, after Kutskir's
+/// "Fastest Gaussian blur".
+fn box_radii(sigma: f32) -> Vec<usize> {
+    let passes = BOX_PASSES as f32;
+    let ideal_width = (12.0 * sigma * sigma / passes + 1.0).sqrt();
+
+    let mut lower = ideal_width.floor() as i64;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let upper = lower + 2;
+
+    let lower_f = lower as f32;
+    let lower_passes = ((12.0 * sigma * sigma
+        - passes * lower_f * lower_f
+        - 4.0 * passes * lower_f
+        - 3.0 * passes)
+        / (-4.0 * lower_f - 4.0))
+        .round() as usize;
+
+    (0..BOX_PASSES)
+        .map(|pass| {
+            let width = if pass < lower_passes { lower } else { upper };
+            (width.max(1) as usize - 1) / 2
+        })
+        .collect()
+}
+
+fn validate_sigma(sigma: f32) -> Result<()> {
+    if !sigma.is_finite() || sigma < 0.0 {
+        return Err(eyre!(
+            "Blur sigma must be a non-negative number, got {}",
+            sigma
+        ));
+    }
+
+    Ok(())
+}
+
+/// Edge modes have nothing to read from in an image without pixels, every filter returns
+/// these unchanged.
+fn is_empty<P: Pixel>(image: &ImageBuffer<P>) -> bool {
+    image.width == 0 || image.height == 0
+}
+
+/// Applies an arbitrary kernel, see `Kernel` for how alpha is treated.
+pub fn convolve<P: Pixel>(
+    image: &ImageBuffer<P>,
+    kernel: &Kernel,
+    edge: EdgeMode,
+) -> Result<ImageBuffer<P>> {
+    kernel.validate()?;
+
+    Ok(apply_kernel(image, kernel, edge))
+}
+
+/// `convolve` for a kernel that is known to be valid.
+fn apply_kernel<P: Pixel>(
+    image: &ImageBuffer<P>,
+    kernel: &Kernel,
+    edge: EdgeMode,
+) -> ImageBuffer<P> {
+    if is_empty(image) {
+        return image.clone();
+    }
+
+    let premultiplied = !kernel.preserve_alpha;
+    let source = Plane::from_image(image, premultiplied);
+    let mut filtered = source.convolve(kernel, edge);
+
+    for (pixel, original) in filtered.pixels.iter_mut().zip(&source.pixels) {
+        let a = if kernel.preserve_alpha {
+            original.a
+        } else {
+            pixel.a + kernel.bias
+        };
+
+        *pixel = RgbaF32::new(
+            pixel.r + kernel.bias,
+            pixel.g + kernel.bias,
+            pixel.b + kernel.bias,
+            a,
+        );
+    }
+
+    filtered.to_image(premultiplied)
+}
+
+/// Blurs with a Gaussian of standard deviation `sigma` pixels, filtering premultiplied so
+/// transparent areas don't darken the edges around them.
+pub fn gaussian_blur<P: Pixel>(
+    image: &ImageBuffer<P>,
+    sigma: f32,
+    edge: EdgeMode,
+) -> Result<ImageBuffer<P>> {
+    validate_sigma(sigma)?;
+
+    if sigma == 0.0 || is_empty(image) {
+        return Ok(image.clone());
+    }
+
+    Ok(Plane::from_image(image, true)
+        .gaussian_blur(sigma, edge)
+        .to_image(true))
+}
+
+/// Averages the square of `2 * radius + 1` pixels around each pixel.
+pub fn box_blur<P: Pixel>(image: &ImageBuffer<P>, radius: u32, edge: EdgeMode) -> ImageBuffer<P> {
+    if radius == 0 || is_empty(image) {
+        return image.clone();
+    }
+
+    Plane::from_image(image, true)
+        .box_blur(radius as usize, edge)
+        .to_image(true)
+}
+
+/// See `Kernel::sharpen`.
+pub fn sharpen<P: Pixel>(
+    image: &ImageBuffer<P>,
+    amount: f32,
+    edge: EdgeMode,
+) -> Result<ImageBuffer<P>> {
+    convolve(image, &Kernel::sharpen(amount), edge)
+}
+
+/// Sharpens by adding back the difference to a blurred copy, which unlike `sharpen` can
+/// target details of a given size.
+pub fn unsharp_mask<P: Pixel>(
+    image: &ImageBuffer<P>,
+    options: &UnsharpMaskOptions,
+    edge: EdgeMode,
+) -> Result<ImageBuffer<P>> {
+    validate_sigma(options.sigma)?;
+
+    // Without blur there is no difference to add back
+    if options.sigma == 0.0 || is_empty(image) {
+        return Ok(image.clone());
+    }
+
+    let source = Plane::from_image(image, true);
+    let blurred = source.gaussian_blur(options.sigma, edge);
+
+    let data = source
+        .pixels
+        .iter()
+        .zip(&blurred.pixels)
+        .map(|(&original, &blurred)| {
+            let original = original.unpremultiplied();
+            let blurred = clamp_premultiplied(blurred).unpremultiplied();
+
+            let sharpen = |value: f32, blurred: f32| {
+                let difference = value - blurred;
+
+                if difference.abs() < options.threshold {
+                    value
+                } else {
+                    value + difference * options.amount
+                }
+            };
+
+            P::from_rgba_f32(RgbaF32::new(
+                sharpen(original.r, blurred.r),
+                sharpen(original.g, blurred.g),
+                sharpen(original.b, blurred.b),
+                original.a,
+            ))
+        })
+        .collect();
+
+    Ok(ImageBuffer {
+        width: image.width,
+        height: image.height,
+        data,
+    })
+}
+
+/// Grayscale edge strength of the visible brightness, white where edges are strongest.
+/// Alpha is kept.
+pub fn detect_edges<P: Pixel>(
+    image: &ImageBuffer<P>,
+    detector: EdgeDetector,
+    edge: EdgeMode,
+) -> ImageBuffer<P> {
+    if is_empty(image) {
+        return image.clone();
+    }
+
+    // Brightness over black, so edges against transparent areas count too
+    let luma = Plane {
+        width: image.width as usize,
+        height: image.height as usize,
+        pixels: image
+            .data
+            .iter()
+            .map(|pixel| {
+                let pixel = pixel.to_rgba_f32();
+                let luma = pixel.luma() * pixel.a;
+                RgbaF32::new(luma, luma, luma, pixel.a)
+            })
+            .collect(),
+    };
+
+    let magnitudes: Vec<f32> = match detector {
+        EdgeDetector::Sobel => {
+            let gx = luma.convolve(&Kernel::sobel_x(), edge);
+            let gy = luma.convolve(&Kernel::sobel_y(), edge);
+
+            gx.pixels
+                .iter()
+                .zip(&gy.pixels)
+                .map(|(x, y)| x.r.hypot(y.r))
+                .collect()
+        }
+        EdgeDetector::Laplacian => luma
+            .convolve(&Kernel::laplacian(), edge)
+            .pixels
+            .iter()
+            .map(|pixel| pixel.r.abs())
+            .collect(),
+    };
+
+    ImageBuffer {
+        width: image.width,
+        height: image.height,
+        data: magnitudes
+            .into_iter()
+            .zip(&luma.pixels)
+            .map(|(magnitude, pixel)| {
+                let value = magnitude.min(1.0);
+                P::from_rgba_f32(RgbaF32::new(value, value, value, pixel.a))
+            })
+            .collect(),
+    }
+}
+
+/// See `Kernel::emboss`.
+pub fn emboss<P: Pixel>(image: &ImageBuffer<P>, edge: EdgeMode) -> ImageBuffer<P> {
+    apply_kernel(image, &Kernel::emboss(), edge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba::Rgba, rgba_image::RgbaImage, test_utils::numbered};
+
+    const EDGE_MODES: [EdgeMode; 4] = [
+        EdgeMode::Clamp,
+        EdgeMode::Wrap,
+        EdgeMode::Mirror,
+        EdgeMode::Transparent,
+    ];
+
+    #[test]
+    fn identity_kernel_keeps_the_image() {
+        let image = numbered(7, 5);
+        let mut weights = vec![0.0; 9];
+        weights[4] = 1.0;
+        let kernel = Kernel::new(3, 3, weights).unwrap();
+
+        for edge in EDGE_MODES {
+            assert_eq!(convolve(&image, &kernel, edge).unwrap(), image);
+            assert_eq!(
+                convolve(&image, &kernel.clone().with_preserve_alpha(true), edge).unwrap(),
+                image
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_kernels() {
+        assert!(Kernel::new(2, 3, vec![0.0; 6]).is_err());
+        assert!(Kernel::new(3, 3, vec![0.0; 8]).is_err());
+
+        let kernel = Kernel {
+            width: 3,
+            height: 1,
+            weights: vec![1.0],
+            bias: 0.0,
+            preserve_alpha: false,
+        };
+        assert!(convolve(&numbered(2, 2), &kernel, EdgeMode::Clamp).is_err());
+    }
+
+    #[test]
+    fn box_blur_matches_an_averaging_kernel() {
+        let image = numbered(9, 6);
+        let kernel = Kernel::new(5, 5, vec![1.0; 25]).unwrap().normalized();
+
+        for edge in EDGE_MODES {
+            let blurred = box_blur(&image, 2, edge);
+            let convolved = convolve(&image, &kernel, edge).unwrap();
+
+            for (a, b) in blurred.pixels().zip(convolved.pixels()) {
+                for (a, b) in a.to_array().into_iter().zip(b.to_array()) {
+                    assert!(a.abs_diff(b) <= 1, "{:?}: {} vs {}", edge, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blur_keeps_flat_images_flat() {
+        let color = Rgba::new(10, 200, 30, 255);
+        let image = RgbaImage::from_pixel(12, 9, color);
+
+        for sigma in [0.5, 2.0, BOX_APPROXIMATION_SIGMA + 4.0] {
+            let blurred = gaussian_blur(&image, sigma, EdgeMode::Clamp).unwrap();
+            assert!(blurred.pixels().all(|&pixel| pixel == color));
+        }
+    }
+
+    #[test]
+    fn blur_does_not_darken_next_to_transparency() {
+        let mut image = RgbaImage::new(20, 20);
+        let red = Rgba::new(255, 0, 0, 255);
+        image.crop_to_view_mut(5, 5, 10, 10).unwrap().fill(red);
+
+        let blurred = gaussian_blur(&image, 2.0, EdgeMode::Transparent).unwrap();
+        let halo = blurred.get_pixel(4, 10);
+
+        assert!(halo.a > 0 && halo.a < 255);
+        assert_eq!((halo.r, halo.g, halo.b), (255, 0, 0));
+    }
+
+    #[test]
+    fn large_sigma_approximates_the_gaussian() {
+        let image = numbered(40, 40);
+        let sigma = BOX_APPROXIMATION_SIGMA + 1.0;
+
+        let approximated = gaussian_blur(&image, sigma, EdgeMode::Mirror).unwrap();
+        let exact = Plane::from_image(&image, true);
+        let weights = gaussian_weights(sigma);
+        let exact: RgbaImage = exact
+            .convolve_axis(&weights, true, EdgeMode::Mirror)
+            .convolve_axis(&weights, false, EdgeMode::Mirror)
+            .to_image(true);
+
+        for (a, b) in approximated.pixels().zip(exact.pixels()) {
+            assert!(a.r.abs_diff(b.r) <= 3, "{} vs {}", a.r, b.r);
+        }
+    }
+
+    #[test]
+    fn edge_modes_read_the_expected_pixels() {
+        assert_eq!(EdgeMode::Clamp.resolve(-2, 4), Some(0));
+        assert_eq!(EdgeMode::Clamp.resolve(6, 4), Some(3));
+        assert_eq!(EdgeMode::Wrap.resolve(-1, 4), Some(3));
+        assert_eq!(EdgeMode::Wrap.resolve(5, 4), Some(1));
+        assert_eq!(EdgeMode::Mirror.resolve(-1, 4), Some(1));
+        assert_eq!(EdgeMode::Mirror.resolve(4, 4), Some(2));
+        assert_eq!(EdgeMode::Mirror.resolve(-3, 1), Some(0));
+        assert_eq!(EdgeMode::Transparent.resolve(-1, 4), None);
+    }
+
+    #[test]
+    fn edge_detection_ignores_flat_areas() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba::new(0, 0, 0, 255));
+        image
+            .crop_to_view_mut(4, 0, 4, 8)
+            .unwrap()
+            .fill(Rgba::new(255, 255, 255, 255));
+
+        for detector in [EdgeDetector::Sobel, EdgeDetector::Laplacian] {
+            let edges = detect_edges(&image, detector, EdgeMode::Clamp);
+
+            assert_eq!(edges.get_pixel(1, 4).r, 0);
+            assert_eq!(edges.get_pixel(3, 4).r, 255);
+        }
+    }
+
+    #[test]
+    fn emboss_matches_its_kernel() {
+        let mut image = numbered(6, 6);
+        image.set_pixel(2, 2, Rgba::new(40, 50, 60, 100));
+
+        assert_eq!(
+            emboss(&image, EdgeMode::Clamp),
+            convolve(&image, &Kernel::emboss(), EdgeMode::Clamp).unwrap()
+        );
+    }
+
+    #[test]
+    fn unsharp_mask_with_zero_sigma_keeps_the_image() {
+        let image = numbered(5, 5);
+        let options = UnsharpMaskOptions {
+            sigma: 0.0,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            unsharp_mask(&image, &options, EdgeMode::Clamp).unwrap(),
+            image
+        );
+        assert!(gaussian_blur(&image, -1.0, EdgeMode::Clamp).is_err());
+    }
+
+    #[test]
+    fn empty_images_stay_empty() {
+        let kernel = Kernel::laplacian();
+        let options = UnsharpMaskOptions::default();
+
+        for (width, height) in [(0, 0), (0, 3), (3, 0)] {
+            let image = RgbaImage::new(width, height);
+
+            for edge in EDGE_MODES {
+                assert_eq!(box_blur(&image, 3, edge), image);
+                assert_eq!(gaussian_blur(&image, 20.0, edge).unwrap(), image);
+                assert_eq!(convolve(&image, &kernel, edge).unwrap(), image);
+                assert_eq!(unsharp_mask(&image, &options, edge).unwrap(), image);
+                assert_eq!(detect_edges(&image, EdgeDetector::Sobel, edge), image);
+                assert_eq!(emboss(&image, edge), image);
+            }
+        }
+    }
+}
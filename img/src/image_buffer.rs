@@ -0,0 +1,214 @@
+use color_eyre::eyre::{Result, eyre};
+use std::borrow::Cow;
+
+use crate::{
+    image_view::{ImageView, ImageViewMut, region_start},
+    pixel::{Luma8, LumaA8, Pixel, Rgba16, RgbaF32},
+    rgba_image::RgbaImage,
+};
+
+pub type Rgba16Image = ImageBuffer<Rgba16>;
+pub type RgbaF32Image = ImageBuffer<RgbaF32>;
+pub type Luma8Image = ImageBuffer<Luma8>;
+pub type LumaA8Image = ImageBuffer<LumaA8>;
+
+/// Pixels stored row by row from the top left corner.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageBuffer<P: Pixel> {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<P>,
+}
+
+impl<P: Pixel> ImageBuffer<P> {
+    /// Every pixel starts out as the default of the pixel type, transparent for RGBA.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::from_pixel(width, height, P::default())
+    }
+
+    pub fn from_pixel(width: u32, height: u32, pixel: P) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![pixel; width as usize * height as usize],
+        }
+    }
+
+    pub fn from_data(width: u32, height: u32, data: Vec<P>) -> Result<Self> {
+        let expected = width as usize * height as usize;
+
+        if data.len() != expected {
+            return Err(eyre!(
+                "A {}x{} image needs {} pixels, got {}",
+                width,
+                height,
+                expected,
+                data.len()
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            data,
+        })
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, color: P) {
+        let index = self.index(x, y);
+        self.data[index] = color;
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> P {
+        self.data[self.index(x, y)]
+    }
+
+    /// Copies the image into another pixel type, see `Pixel::convert`.
+    pub fn convert<Q: Pixel>(&self) -> ImageBuffer<Q> {
+        ImageBuffer {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(|pixel| pixel.convert()).collect(),
+        }
+    }
+
+    /// The image as 8-bit RGBA, only copied when stored as something else.
+    pub fn to_rgba8(&self) -> Cow<'_, RgbaImage> {
+        match P::as_rgba8_image(self) {
+            Some(image) => Cow::Borrowed(image),
+            None => Cow::Owned(self.convert()),
+        }
+    }
+
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            data: &self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.width as usize,
+        }
+    }
+
+    pub fn as_view_mut(&mut self) -> ImageViewMut<'_, P> {
+        ImageViewMut {
+            data: &mut self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.width as usize,
+        }
+    }
+
+    /// Borrows a region without copying it.
+    pub fn crop_to_view(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageView<'_, P>> {
+        self.as_view().crop_to_view(x, y, width, height)
+    }
+
+    pub fn crop_to_view_mut(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageViewMut<'_, P>> {
+        let start = region_start(
+            (self.width, self.height),
+            self.width as usize,
+            (x, y, width, height),
+        )?;
+        let stride = self.width as usize;
+
+        if width == 0 || height == 0 {
+            return ImageViewMut::new(&mut [], width, height, stride);
+        }
+
+        ImageViewMut::new(&mut self.data[start..], width, height, stride)
+    }
+
+    /// Copies a region into a new image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Self> {
+        Ok(self.crop_to_view(x, y, width, height)?.to_image())
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> {
+        self.data.chunks_exact(self.width.max(1) as usize)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> {
+        self.data.chunks_exact_mut(self.width.max(1) as usize)
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = &P> {
+        self.data.iter()
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut P> {
+        self.data.iter_mut()
+    }
+
+    pub fn fill(&mut self, pixel: P) {
+        self.data.fill(pixel);
+    }
+
+    /// Copies `source` so its top left corner lands at (`x`, `y`), it has to fit entirely.
+    pub fn copy_from(&mut self, source: &ImageView<'_, P>, x: u32, y: u32) -> Result<()> {
+        self.as_view_mut().copy_from(source, x, y)
+    }
+
+    /// The pixels as raw bytes in memory order, without copying. Samples wider than a byte
+    /// are in native endianness.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `Pixel` implementors are `#[repr(C)]` plain data without padding, so every
+        // byte of the slice is initialized, and u8 has no alignment requirement.
+        unsafe {
+            std::slice::from_raw_parts(
+                self.data.as_ptr().cast::<u8>(),
+                std::mem::size_of_val(self.data.as_slice()),
+            )
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba::Rgba;
+
+    #[test]
+    fn to_rgba8_borrows_rgba8_images() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba::new(1, 2, 3, 4));
+
+        assert!(matches!(image.to_rgba8(), Cow::Borrowed(borrowed) if *borrowed == image));
+    }
+
+    #[test]
+    fn to_rgba8_converts_other_pixels() {
+        let image = Luma8Image::from_pixel(2, 1, Luma8::new(77));
+
+        assert!(matches!(image.to_rgba8(), Cow::Owned(_)));
+        assert_eq!(image.to_rgba8().data, [Rgba::new(77, 77, 77, 255); 2]);
+    }
+
+    #[test]
+    fn converts_between_bit_depths_losslessly() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba::new(0, 1, 128, 255));
+
+        assert_eq!(image.convert::<Rgba16>().convert::<Rgba>(), image);
+        assert_eq!(image.convert::<RgbaF32>().convert::<Rgba>(), image);
+    }
+
+    #[test]
+    fn from_data_checks_the_length() {
+        assert!(RgbaImage::from_data(2, 2, vec![Rgba::default(); 3]).is_err());
+        assert!(RgbaImage::from_data(2, 2, vec![Rgba::default(); 4]).is_ok());
+    }
+}
@@ -0,0 +1,342 @@
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{image_buffer::ImageBuffer, pixel::Pixel};
+
+/// Checks that a `width` x `height` region with rows `stride` pixels apart fits in `len`
+/// pixels, returning how many of them it spans.
+fn region_len(len: usize, width: u32, height: u32, stride: usize) -> Result<usize> {
+    let (width, height) = (width as usize, height as usize);
+
+    if height > 0 && width > stride {
+        return Err(eyre!(
+            "View width {} is larger than its stride {}",
+            width,
+            stride
+        ));
+    }
+
+    let needed = match (width, height) {
+        (0, _) | (_, 0) => 0,
+        _ => (height - 1) * stride + width,
+    };
+
+    if needed > len {
+        return Err(eyre!(
+            "A {}x{} view with stride {} needs {} pixels, got {}",
+            width,
+            height,
+            stride,
+            needed,
+            len
+        ));
+    }
+
+    Ok(needed)
+}
+
+/// Where the region at (`x`, `y`) starts in data with rows `stride` pixels apart, once it's
+/// checked to be inside `bounds_width` x `bounds_height`.
+pub(crate) fn region_start(
+    (bounds_width, bounds_height): (u32, u32),
+    stride: usize,
+    (x, y, width, height): (u32, u32, u32, u32),
+) -> Result<usize> {
+    if x as u64 + width as u64 > bounds_width as u64
+        || y as u64 + height as u64 > bounds_height as u64
+    {
+        return Err(eyre!(
+            "Region {}x{} at ({}, {}) is outside the {}x{} image",
+            width,
+            height,
+            x,
+            y,
+            bounds_width,
+            bounds_height
+        ));
+    }
+
+    Ok(y as usize * stride + x as usize)
+}
+
+/// A borrowed rectangle of pixels, rows `stride` pixels apart in the underlying data.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, P: Pixel> {
+    pub(crate) data: &'a [P],
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) stride: usize,
+}
+
+impl<'a, P: Pixel> ImageView<'a, P> {
+    /// A view over `data` starting at its first pixel.
+    pub fn new(data: &'a [P], width: u32, height: u32, stride: usize) -> Result<Self> {
+        let len = region_len(data.len(), width, height, stride)?;
+
+        Ok(Self {
+            data: &data[..len],
+            width,
+            height,
+            stride,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Pixels from the start of one row to the start of the next.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> P {
+        assert!(
+            x < self.width && y < self.height,
+            "Pixel ({}, {}) is outside the {}x{} view",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+
+        self.data[y as usize * self.stride + x as usize]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + use<'a, P> {
+        let width = self.width as usize;
+
+        self.data
+            .chunks(self.stride.max(1))
+            .take(self.height as usize)
+            .map(move |row| &row[..width])
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = &'a P> + use<'a, P> {
+        self.rows().flatten()
+    }
+
+    /// A smaller view, with `x` and `y` relative to this one.
+    pub fn crop_to_view(&self, x: u32, y: u32, width: u32, height: u32) -> Result<Self> {
+        let start = region_start(
+            (self.width, self.height),
+            self.stride,
+            (x, y, width, height),
+        )?;
+
+        // An empty region at the bottom edge can start past the last row's pixels
+        if width == 0 || height == 0 {
+            return Self::new(&[], width, height, self.stride);
+        }
+
+        Self::new(&self.data[start..], width, height, self.stride)
+    }
+
+    /// Copies the pixels into a new image.
+    pub fn to_image(&self) -> ImageBuffer<P> {
+        ImageBuffer {
+            width: self.width,
+            height: self.height,
+            data: self.pixels().copied().collect(),
+        }
+    }
+}
+
+/// Like `ImageView`, but lets the pixels be changed in place.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, P: Pixel> {
+    pub(crate) data: &'a mut [P],
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) stride: usize,
+}
+
+impl<'a, P: Pixel> ImageViewMut<'a, P> {
+    pub fn new(data: &'a mut [P], width: u32, height: u32, stride: usize) -> Result<Self> {
+        let len = region_len(data.len(), width, height, stride)?;
+
+        Ok(Self {
+            data: &mut data[..len],
+            width,
+            height,
+            stride,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Borrows the same region read-only.
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            data: self.data,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> P {
+        self.as_view().get_pixel(x, y)
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        assert!(
+            x < self.width && y < self.height,
+            "Pixel ({}, {}) is outside the {}x{} view",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+
+        self.data[y as usize * self.stride + x as usize] = pixel;
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> {
+        self.as_view().rows()
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> {
+        let width = self.width as usize;
+
+        self.data
+            .chunks_mut(self.stride.max(1))
+            .take(self.height as usize)
+            .map(move |row| &mut row[..width])
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut P> {
+        self.rows_mut().flatten()
+    }
+
+    /// A smaller mutable view, with `x` and `y` relative to this one.
+    pub fn crop_to_view_mut(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageViewMut<'_, P>> {
+        let start = region_start(
+            (self.width, self.height),
+            self.stride,
+            (x, y, width, height),
+        )?;
+
+        if width == 0 || height == 0 {
+            return ImageViewMut::new(&mut [], width, height, self.stride);
+        }
+
+        ImageViewMut::new(&mut self.data[start..], width, height, self.stride)
+    }
+
+    pub fn fill(&mut self, pixel: P) {
+        for row in self.rows_mut() {
+            row.fill(pixel);
+        }
+    }
+
+    /// Copies `source` so its top left corner lands at (`x`, `y`), it has to fit entirely.
+    pub fn copy_from(&mut self, source: &ImageView<'_, P>, x: u32, y: u32) -> Result<()> {
+        let mut target = self.crop_to_view_mut(x, y, source.width(), source.height())?;
+
+        for (target, source) in target.rows_mut().zip(source.rows()) {
+            target.copy_from_slice(source);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba::Rgba, rgba_image::RgbaImage, test_utils::numbered};
+
+    #[test]
+    fn crops_share_the_pixels() {
+        let image = numbered(4, 4);
+        let view = image.crop_to_view(1, 1, 2, 2).unwrap();
+
+        assert_eq!(view.get_pixel(0, 0), image.get_pixel(1, 1));
+        assert_eq!(view.get_pixel(1, 1), image.get_pixel(2, 2));
+        assert_eq!(view.rows().count(), 2);
+        assert_eq!(view.to_image(), image.crop(1, 1, 2, 2).unwrap());
+    }
+
+    #[test]
+    fn rejects_regions_outside_the_image() {
+        let image = numbered(4, 4);
+
+        assert!(image.crop_to_view(3, 0, 2, 1).is_err());
+        assert!(image.crop_to_view(0, 4, 1, 1).is_err());
+        assert!(image.crop(u32::MAX, 0, 2, 1).is_err());
+    }
+
+    #[test]
+    fn allows_empty_regions_at_the_edges() {
+        let image = numbered(4, 4);
+        let view = image.crop_to_view(0, 0, 4, 4).unwrap();
+        let nested = view.crop_to_view(1, 1, 3, 3).unwrap();
+
+        assert_eq!(nested.crop_to_view(0, 3, 0, 0).unwrap().pixels().count(), 0);
+        assert_eq!(nested.crop_to_view(3, 0, 0, 3).unwrap().height(), 3);
+
+        let mut image = image;
+        assert_eq!(image.crop_to_view_mut(4, 4, 0, 0).unwrap().width(), 0);
+
+        let mut view = image.crop_to_view_mut(1, 1, 3, 3).unwrap();
+        assert_eq!(view.crop_to_view_mut(0, 3, 3, 0).unwrap().height(), 0);
+    }
+
+    #[test]
+    fn rejects_rows_wider_than_the_stride() {
+        let data = [Rgba::default(); 4];
+
+        assert!(ImageView::new(&data, 5, 1, 2).is_err());
+        assert!(ImageView::new(&data, 2, 2, 2).is_ok());
+    }
+
+    #[test]
+    fn copies_into_a_region() {
+        let mut target = RgbaImage::new(4, 4);
+        let source = numbered(2, 2);
+
+        target.copy_from(&source.as_view(), 2, 1).unwrap();
+
+        assert_eq!(target.crop(2, 1, 2, 2).unwrap(), source);
+        assert_eq!(target.get_pixel(0, 0), Rgba::default());
+        assert!(target.copy_from(&source.as_view(), 3, 3).is_err());
+    }
+
+    #[test]
+    fn fills_only_the_view() {
+        let mut image = RgbaImage::new(3, 3);
+        let red = Rgba::new(255, 0, 0, 255);
+
+        image.crop_to_view_mut(1, 0, 1, 3).unwrap().fill(red);
+
+        assert_eq!(image.pixels().filter(|&&pixel| pixel == red).count(), 3);
+        assert_eq!(image.get_pixel(1, 2), red);
+    }
+
+    #[test]
+    fn exposes_pixels_as_bytes() {
+        let image = RgbaImage::from_pixel(2, 1, Rgba::new(1, 2, 3, 4));
+
+        assert_eq!(image.as_bytes(), [1, 2, 3, 4, 1, 2, 3, 4]);
+    }
+}
@@ -1,3 +1,15 @@
 pub mod codecs;
+pub mod composite;
+pub mod dither;
+pub mod filter;
+pub mod image_buffer;
+pub mod image_view;
+pub mod pixel;
+pub mod quantize;
+pub mod resize;
 pub mod rgba;
 pub mod rgba_image;
+pub mod transform;
+
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,303 @@
+use std::fmt::Debug;
+
+use crate::{image_buffer::ImageBuffer, rgba::Rgba, rgba_image::RgbaImage};
+
+/// 8 bits per channel RGBA, the pixel every codec reads and writes
+pub type Rgba8 = Rgba;
+
+/// Rec. 601 weights, the same ones the PNM encoder uses for grayscale
+const LUMA_WEIGHTS: [f32; 3] = [0.299, 0.587, 0.114];
+
+/// A pixel format `ImageBuffer` can store. Every format converts to and from straight alpha
+/// RGBA in floating point, which is how images change pixel type.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` structs of plain numbers without padding, since
+/// `ImageBuffer::as_bytes` reinterprets the pixels as bytes.
+pub unsafe trait Pixel: Copy + PartialEq + Default + Debug + Send + Sync + 'static {
+    const CHANNELS: usize;
+
+    /// sRGB encoded, straight alpha, with 0 to 1 covering the range of the format
+    fn to_rgba_f32(self) -> RgbaF32;
+
+    /// Out of range values are clamped by integer formats.
+    fn from_rgba_f32(color: RgbaF32) -> Self;
+
+    fn to_rgba8(self) -> Rgba {
+        Rgba::from_rgba_f32(self.to_rgba_f32())
+    }
+
+    fn from_rgba8(color: Rgba) -> Self {
+        Self::from_rgba_f32(color.to_rgba_f32())
+    }
+
+    /// Converting color to gray weighs the channels by how bright they look.
+    fn convert<Q: Pixel>(self) -> Q {
+        Q::from_rgba_f32(self.to_rgba_f32())
+    }
+
+    /// The image itself when its pixels already are 8-bit RGBA, so callers can skip a copy.
+    fn as_rgba8_image(_image: &ImageBuffer<Self>) -> Option<&RgbaImage> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct Rgba16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub a: u16,
+}
+
+impl Rgba16 {
+    pub const fn new(r: u16, g: u16, b: u16, a: u16) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn to_array(&self) -> [u16; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+/// Floating point RGBA, values outside 0 to 1 are kept until converted to an integer format.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct RgbaF32 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl RgbaF32 {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn to_array(&self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn premultiplied(self) -> Self {
+        Self::new(self.r * self.a, self.g * self.a, self.b * self.a, self.a)
+    }
+
+    /// Fully transparent colors come back as transparent black.
+    pub fn unpremultiplied(self) -> Self {
+        if self.a <= 0.0 {
+            return Self::default();
+        }
+
+        Self::new(self.r / self.a, self.g / self.a, self.b / self.a, self.a)
+    }
+
+    pub(crate) fn luma(&self) -> f32 {
+        self.r * LUMA_WEIGHTS[0] + self.g * LUMA_WEIGHTS[1] + self.b * LUMA_WEIGHTS[2]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct Luma8 {
+    pub l: u8,
+}
+
+impl Luma8 {
+    pub const fn new(l: u8) -> Self {
+        Self { l }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(C)]
+pub struct LumaA8 {
+    pub l: u8,
+    pub a: u8,
+}
+
+impl LumaA8 {
+    pub const fn new(l: u8, a: u8) -> Self {
+        Self { l, a }
+    }
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn to_u16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+}
+
+unsafe impl Pixel for Rgba {
+    const CHANNELS: usize = 4;
+
+    fn to_rgba_f32(self) -> RgbaF32 {
+        let [r, g, b, a] = self.to_array().map(|value| value as f32 / 255.0);
+        RgbaF32::new(r, g, b, a)
+    }
+
+    fn from_rgba_f32(color: RgbaF32) -> Self {
+        let [r, g, b, a] = color.to_array().map(to_u8);
+        Self::new(r, g, b, a)
+    }
+
+    fn to_rgba8(self) -> Rgba {
+        self
+    }
+
+    fn from_rgba8(color: Rgba) -> Self {
+        color
+    }
+
+    fn as_rgba8_image(image: &ImageBuffer<Self>) -> Option<&RgbaImage> {
+        Some(image)
+    }
+}
+
+unsafe impl Pixel for Rgba16 {
+    const CHANNELS: usize = 4;
+
+    fn to_rgba_f32(self) -> RgbaF32 {
+        let [r, g, b, a] = self.to_array().map(|value| value as f32 / 65535.0);
+        RgbaF32::new(r, g, b, a)
+    }
+
+    fn from_rgba_f32(color: RgbaF32) -> Self {
+        let [r, g, b, a] = color.to_array().map(to_u16);
+        Self::new(r, g, b, a)
+    }
+
+    fn from_rgba8(color: Rgba) -> Self {
+        // 257 maps 255 to 65535 exactly
+        let [r, g, b, a] = color.to_array().map(|value| value as u16 * 257);
+        Self::new(r, g, b, a)
+    }
+}
+
+unsafe impl Pixel for RgbaF32 {
+    const CHANNELS: usize = 4;
+
+    fn to_rgba_f32(self) -> RgbaF32 {
+        self
+    }
+
+    fn from_rgba_f32(color: RgbaF32) -> Self {
+        color
+    }
+}
+
+unsafe impl Pixel for Luma8 {
+    const CHANNELS: usize = 1;
+
+    fn to_rgba_f32(self) -> RgbaF32 {
+        let l = self.l as f32 / 255.0;
+        RgbaF32::new(l, l, l, 1.0)
+    }
+
+    fn from_rgba_f32(color: RgbaF32) -> Self {
+        Self::new(to_u8(color.luma()))
+    }
+
+    fn to_rgba8(self) -> Rgba {
+        Rgba::new(self.l, self.l, self.l, 255)
+    }
+}
+
+unsafe impl Pixel for LumaA8 {
+    const CHANNELS: usize = 2;
+
+    fn to_rgba_f32(self) -> RgbaF32 {
+        let l = self.l as f32 / 255.0;
+        RgbaF32::new(l, l, l, self.a as f32 / 255.0)
+    }
+
+    fn from_rgba_f32(color: RgbaF32) -> Self {
+        Self::new(to_u8(color.luma()), to_u8(color.a))
+    }
+
+    fn to_rgba8(self) -> Rgba {
+        Rgba::new(self.l, self.l, self.l, self.a)
+    }
+}
+
+impl From<(f32, f32, f32, f32)> for RgbaF32 {
+    fn from((r, g, b, a): (f32, f32, f32, f32)) -> Self {
+        Self::new(r, g, b, a)
+    }
+}
+
+impl From<(f32, f32, f32)> for RgbaF32 {
+    fn from((r, g, b): (f32, f32, f32)) -> Self {
+        Self::new(r, g, b, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widens_8_bit_exactly() {
+        assert_eq!(
+            Rgba16::from_rgba8(Rgba::new(0, 1, 128, 255)),
+            Rgba16::new(0, 257, 32896, 65535)
+        );
+
+        for value in 0..=255u8 {
+            let color = Rgba::new(value, value, value, value);
+            assert_eq!(Rgba16::from_rgba8(color).to_rgba8(), color);
+            assert_eq!(
+                Rgba16::from_rgba_f32(color.to_rgba_f32()),
+                Rgba16::from_rgba8(color)
+            );
+        }
+    }
+
+    #[test]
+    fn gray_weighs_channels_by_brightness() {
+        assert_eq!(Luma8::from_rgba8(Rgba::new(255, 0, 0, 255)), Luma8::new(76));
+        assert_eq!(
+            Luma8::from_rgba8(Rgba::new(0, 255, 0, 255)),
+            Luma8::new(150)
+        );
+        assert_eq!(Luma8::from_rgba8(Rgba::new(0, 0, 255, 255)), Luma8::new(29));
+        assert_eq!(
+            Luma8::from_rgba8(Rgba::new(255, 255, 255, 0)),
+            Luma8::new(255)
+        );
+        assert_eq!(
+            LumaA8::from_rgba8(Rgba::new(90, 90, 90, 40)),
+            LumaA8::new(90, 40)
+        );
+        assert_eq!(LumaA8::new(90, 40).to_rgba8(), Rgba::new(90, 90, 90, 40));
+        assert_eq!(Luma8::new(7).to_rgba8(), Rgba::new(7, 7, 7, 255));
+    }
+
+    #[test]
+    fn integer_formats_clamp() {
+        let color = RgbaF32::new(-0.5, 1.5, 0.5, 2.0);
+
+        assert_eq!(Rgba::from_rgba_f32(color), Rgba::new(0, 255, 128, 255));
+        assert_eq!(
+            Rgba16::from_rgba_f32(color),
+            Rgba16::new(0, 65535, 32768, 65535)
+        );
+        assert_eq!(RgbaF32::from_rgba_f32(color), color);
+    }
+
+    #[test]
+    fn unpremultiplies_transparent_to_black() {
+        let color = RgbaF32::new(0.8, 0.4, 0.2, 0.5);
+
+        assert_eq!(color.premultiplied(), RgbaF32::new(0.4, 0.2, 0.1, 0.5));
+        assert_eq!(color.premultiplied().unpremultiplied(), color);
+        assert_eq!(
+            RgbaF32::new(0.3, 0.3, 0.3, 0.0).unpremultiplied(),
+            RgbaF32::default()
+        );
+    }
+}
@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    dither::{DitherOptions, dither},
+    rgba::Rgba,
+    rgba_image::RgbaImage,
+};
+
+/// Indices are stored in a byte
+pub const MAX_PALETTE_SIZE: usize = 256;
+/// K-means refines a histogram of at most this many colors, larger ones are binned first
+const KMEANS_MAX_COLORS: usize = 1 << 16;
+const OCTREE_DEPTH: usize = 8;
+/// One child per combination of the red, green, blue and alpha bits at each level
+const OCTREE_CHILDREN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantizeMethod {
+    /// Splits the color space at the median of its widest channel, fast and even
+    #[default]
+    MedianCut,
+    /// Merges the least used branches of a color tree, favors common colors
+    Octree,
+    /// Refines a median cut palette by iterating on cluster means, slowest but most accurate
+    KMeans,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizeOptions {
+    pub method: QuantizeMethod,
+    /// Palette size, from 1 to 256
+    pub max_colors: usize,
+    /// Upper bound on k-means refinement passes, it stops early once the palette settles
+    pub kmeans_iterations: u32,
+    /// Map to these colors instead of building a palette
+    pub palette: Option<Vec<Rgba>>,
+    /// Dither while mapping to the palette instead of picking the closest color
+    pub dither: Option<DitherOptions>,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            method: QuantizeMethod::MedianCut,
+            max_colors: MAX_PALETTE_SIZE,
+            kmeans_iterations: 10,
+            palette: None,
+            dither: None,
+        }
+    }
+}
+
+impl QuantizeOptions {
+    pub fn new(method: QuantizeMethod, max_colors: usize) -> Self {
+        Self {
+            method,
+            max_colors,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_palette(palette: Vec<Rgba>) -> Self {
+        Self {
+            max_colors: palette.len(),
+            palette: Some(palette),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_dither(mut self, dither: DitherOptions) -> Self {
+        self.dither = Some(dither);
+        self
+    }
+}
+
+/// An image stored as one palette index per pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<Rgba>,
+    pub indices: Vec<u8>,
+}
+
+impl IndexedImage {
+    pub fn to_rgba_image(&self) -> RgbaImage {
+        RgbaImage {
+            width: self.width,
+            height: self.height,
+            data: self
+                .indices
+                .iter()
+                .map(|&index| self.palette[index as usize])
+                .collect(),
+        }
+    }
+}
+
+/// Reduces the image to at most `max_colors` colors. Images that already fit keep their
+/// exact colors. Alpha is quantized along with the color, with every fully transparent pixel
+/// sharing one entry and colors compared premultiplied, so barely visible pixels get less
+/// of the palette.
+pub fn quantize(image: &RgbaImage, options: &QuantizeOptions) -> Result<IndexedImage> {
+    if let Some(palette) = &options.palette {
+        return map_to_palette(image, palette, options);
+    }
+
+    if options.max_colors == 0 || options.max_colors > MAX_PALETTE_SIZE {
+        return Err(eyre!(
+            "Palette size must be between 1 and {}, got {}",
+            MAX_PALETTE_SIZE,
+            options.max_colors
+        ));
+    }
+
+    let mut histogram = histogram(image);
+
+    let mut palette: Vec<[u8; 4]> = if histogram.len() <= options.max_colors {
+        histogram.iter().map(|&(color, _)| color).collect()
+    } else {
+        // Fully transparent pixels keep an exact entry so they stay invisible, it sorts first
+        let transparent = options.max_colors > 1 && histogram[0].0 == [0; 4];
+        let max_colors = options.max_colors - transparent as usize;
+
+        if transparent {
+            histogram.remove(0);
+        }
+
+        let mut palette = match options.method {
+            QuantizeMethod::MedianCut => median_cut(histogram, max_colors),
+            QuantizeMethod::Octree => octree(&histogram, max_colors),
+            QuantizeMethod::KMeans => kmeans(histogram, max_colors, options.kmeans_iterations),
+        };
+
+        if transparent {
+            palette.push([0; 4]);
+        }
+
+        palette
+    };
+
+    palette.sort_unstable();
+    palette.dedup();
+
+    let palette: Vec<Rgba> = palette
+        .into_iter()
+        .map(|[r, g, b, a]| Rgba::new(r, g, b, a))
+        .collect();
+
+    map_to_palette(image, &palette, options)
+}
+
+fn map_to_palette(
+    image: &RgbaImage,
+    palette: &[Rgba],
+    options: &QuantizeOptions,
+) -> Result<IndexedImage> {
+    match &options.dither {
+        Some(dither_options) => dither(image, palette, dither_options),
+        None => quantize_to_palette(image, palette),
+    }
+}
+
+/// Maps every pixel to the closest color of a fixed palette.
+pub fn quantize_to_palette(image: &RgbaImage, palette: &[Rgba]) -> Result<IndexedImage> {
+    if palette.is_empty() || palette.len() > MAX_PALETTE_SIZE {
+        return Err(eyre!(
+            "Palette size must be between 1 and {}, got {}",
+            MAX_PALETTE_SIZE,
+            palette.len()
+        ));
+    }
+
+    let entries: Vec<[u8; 4]> = palette.iter().map(|color| color.to_array()).collect();
+    let mut lookup: HashMap<Rgba, u8> = HashMap::new();
+
+    let indices = image
+        .data
+        .iter()
+        .map(|&pixel| {
+            *lookup
+                .entry(pixel)
+                .or_insert_with(|| nearest(&entries, key(pixel)) as u8)
+        })
+        .collect();
+
+    Ok(IndexedImage {
+        width: image.width,
+        height: image.height,
+        palette: palette.to_vec(),
+        indices,
+    })
+}
+
+/// Index of the palette entry closest to the color.
+pub(crate) fn nearest(palette: &[[u8; 4]], color: [u8; 4]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| distance(color, **entry))
+        .map_or(0, |(index, _)| index)
+}
+
+/// Squared distance between premultiplied colors, plus the squared alpha difference.
+pub(crate) fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    let alpha = a[3] as i32 - b[3] as i32;
+    let mut sum = (alpha * alpha) as u32;
+
+    for channel in 0..3 {
+        let difference = (a[channel] as i32 * a[3] as i32 - b[channel] as i32 * b[3] as i32) / 255;
+        sum += (difference * difference) as u32;
+    }
+
+    sum
+}
+
+/// Fully transparent pixels all look the same, whatever their color.
+fn key(pixel: Rgba) -> [u8; 4] {
+    if pixel.a == 0 {
+        [0; 4]
+    } else {
+        pixel.to_array()
+    }
+}
+
+/// Distinct colors and how often they appear, sorted by color.
+fn histogram(image: &RgbaImage) -> Vec<([u8; 4], u32)> {
+    let mut counts: HashMap<[u8; 4], u32> = HashMap::new();
+
+    for &pixel in &image.data {
+        *counts.entry(key(pixel)).or_insert(0) += 1;
+    }
+
+    let mut histogram: Vec<([u8; 4], u32)> = counts.into_iter().collect();
+    histogram.sort_unstable();
+
+    histogram
+}
+
+/// Splits the color space into `max_colors` boxes at the weighted median of their widest
+/// channel, each box becomes its population weighted average.
+pub(crate) fn median_cut(mut colors: Vec<([u8; 4], u32)>, max_colors: usize) -> Vec<[u8; 4]> {
+    colors.sort_unstable();
+
+    let channel_range = |colors: &[([u8; 4], u32)], channel: usize| {
+        let (min, max) = colors.iter().fold((255u8, 0u8), |(min, max), (color, _)| {
+            (min.min(color[channel]), max.max(color[channel]))
+        });
+        max.saturating_sub(min)
+    };
+
+    let widest_channel = |colors: &[([u8; 4], u32)]| {
+        (0..4)
+            .map(|channel| (channel_range(colors, channel), channel))
+            .max()
+            .unwrap_or((0, 0))
+    };
+
+    let mut boxes: Vec<(usize, usize)> = vec![(0, colors.len())];
+
+    while boxes.len() < max_colors {
+        let Some((box_index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, (start, end))| end - start > 1)
+            .map(|(index, &(start, end))| {
+                let (range, channel) = widest_channel(&colors[start..end]);
+                (range, index, channel)
+            })
+            .filter(|&(range, _, _)| range > 0)
+            .max_by_key(|&(range, _, _)| range)
+            .map(|(_, index, channel)| (index, channel))
+        else {
+            break;
+        };
+
+        let (start, end) = boxes[box_index];
+        let slice = &mut colors[start..end];
+        slice.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        let total: u64 = slice.iter().map(|&(_, count)| count as u64).sum();
+        let mut running = 0u64;
+        let mut split = slice.len() / 2;
+
+        for (index, &(_, count)) in slice.iter().enumerate() {
+            running += count as u64;
+            if running * 2 >= total {
+                split = index + 1;
+                break;
+            }
+        }
+
+        let split = start + split.clamp(1, slice.len() - 1);
+
+        boxes[box_index] = (start, split);
+        boxes.push((split, end));
+    }
+
+    boxes
+        .iter()
+        .map(|&(start, end)| weighted_mean(&colors[start..end]))
+        .collect()
+}
+
+fn weighted_mean(colors: &[([u8; 4], u32)]) -> [u8; 4] {
+    let mut sums = [0u64; 4];
+    let mut total = 0u64;
+
+    for &(color, count) in colors {
+        for channel in 0..4 {
+            sums[channel] += color[channel] as u64 * count as u64;
+        }
+        total += count as u64;
+    }
+
+    sums.map(|sum| ((sum + total / 2) / total.max(1)) as u8)
+}
+
+#[derive(Debug, Clone, Default)]
+struct OctreeNode {
+    /// Index of each child in the node list, 0 for none since the root is never a child
+    children: [u32; OCTREE_CHILDREN],
+    /// Pixels below this node
+    pixels: u64,
+    /// Channel sums of the pixels merged into this node, only used by leaves
+    sums: [u64; 4],
+    leaf: bool,
+}
+
+/// A 16-way tree over the bits of all four channels, where each leaf becomes a palette entry.
+struct Octree {
+    nodes: Vec<OctreeNode>,
+    /// Nodes with children, by depth
+    reducible: Vec<Vec<u32>>,
+    leaves: usize,
+}
+
+impl Octree {
+    fn new() -> Self {
+        let mut reducible = vec![Vec::new(); OCTREE_DEPTH];
+        reducible[0].push(0);
+
+        Self {
+            nodes: vec![OctreeNode::default()],
+            reducible,
+            leaves: 0,
+        }
+    }
+
+    fn insert(&mut self, color: [u8; 4], count: u32) {
+        let mut node = 0;
+        let mut depth = 0;
+
+        // Leaves sit at the bottom level, or higher up once their children have been merged
+        while !self.nodes[node].leaf {
+            self.nodes[node].pixels += count as u64;
+
+            let shift = 7 - depth;
+            let slot = color
+                .iter()
+                .fold(0, |slot, &value| slot << 1 | (value >> shift & 1) as usize);
+
+            if self.nodes[node].children[slot] == 0 {
+                let child = self.nodes.len() as u32;
+                let leaf = depth + 1 == OCTREE_DEPTH;
+
+                self.nodes.push(OctreeNode {
+                    leaf,
+                    ..Default::default()
+                });
+                self.nodes[node].children[slot] = child;
+
+                if leaf {
+                    self.leaves += 1;
+                } else {
+                    self.reducible[depth + 1].push(child);
+                }
+            }
+
+            node = self.nodes[node].children[slot] as usize;
+            depth += 1;
+        }
+
+        let leaf = &mut self.nodes[node];
+        leaf.pixels += count as u64;
+        for (sum, &value) in leaf.sums.iter_mut().zip(&color) {
+            *sum += value as u64 * count as u64;
+        }
+    }
+
+    /// Merges the children of the least populated node on the deepest level. With a target,
+    /// only as many of its smallest children are merged as it takes to reach it, which leaves
+    /// the tree unfit for further inserts.
+    fn reduce(&mut self, target: Option<usize>) {
+        let Some(depth) = self.reducible.iter().rposition(|level| !level.is_empty()) else {
+            return;
+        };
+
+        let nodes = &self.nodes;
+        let (position, &node) = self.reducible[depth]
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &node)| nodes[node as usize].pixels)
+            .unwrap_or((0, &0));
+        let node = node as usize;
+
+        let mut children: Vec<usize> = self.nodes[node]
+            .children
+            .iter()
+            .filter(|&&child| child != 0)
+            .map(|&child| child as usize)
+            .collect();
+
+        let merge_count = match target {
+            Some(target) => (self.leaves + 1 - target).min(children.len()),
+            None => children.len(),
+        };
+
+        let (kept, merged_into) = if merge_count < children.len() {
+            children.sort_unstable_by_key(|&child| self.nodes[child].pixels);
+            let kept = children[0];
+            (Some(kept), kept)
+        } else {
+            self.reducible[depth].swap_remove(position);
+            (None, node)
+        };
+
+        let mut sums = [0u64; 4];
+        let mut pixels = 0;
+
+        for &child in &children[..merge_count] {
+            let child = &mut self.nodes[child];
+
+            for (sum, value) in sums.iter_mut().zip(child.sums) {
+                *sum += value;
+            }
+            pixels += child.pixels;
+            child.leaf = false;
+            child.pixels = 0;
+        }
+
+        self.leaves = self.leaves + 1 - merge_count;
+
+        let target = &mut self.nodes[merged_into];
+        target.sums = sums;
+        target.pixels = pixels;
+        target.leaf = true;
+
+        if kept.is_none() {
+            target.children = [0; OCTREE_CHILDREN];
+        } else {
+            let merged: Vec<usize> = children[1..merge_count].to_vec();
+            for slot in self.nodes[node].children.iter_mut() {
+                if merged.contains(&(*slot as usize)) {
+                    *slot = 0;
+                }
+            }
+        }
+    }
+
+    fn palette(&self) -> Vec<[u8; 4]> {
+        self.nodes
+            .iter()
+            .filter(|node| node.leaf && node.pixels > 0)
+            .map(|node| {
+                node.sums
+                    .map(|sum| ((sum + node.pixels / 2) / node.pixels) as u8)
+            })
+            .collect()
+    }
+}
+
+/// Builds the tree while keeping it to a bounded number of leaves, then reduces it to exactly
+/// `max_colors`.
+fn octree(histogram: &[([u8; 4], u32)], max_colors: usize) -> Vec<[u8; 4]> {
+    let mut tree = Octree::new();
+
+    for &(color, count) in histogram {
+        tree.insert(color, count);
+
+        while tree.leaves > MAX_PALETTE_SIZE.max(max_colors) {
+            tree.reduce(None);
+        }
+    }
+
+    while tree.leaves > max_colors {
+        tree.reduce(Some(max_colors));
+    }
+
+    tree.palette()
+}
+
+/// Lloyd's algorithm on the histogram, starting from the median cut palette.
+fn kmeans(histogram: Vec<([u8; 4], u32)>, max_colors: usize, iterations: u32) -> Vec<[u8; 4]> {
+    let histogram = binned_histogram(histogram, KMEANS_MAX_COLORS);
+    let mut palette = median_cut(histogram.clone(), max_colors);
+
+    for _ in 0..iterations {
+        let mut sums = vec![[0u64; 4]; palette.len()];
+        let mut counts = vec![0u64; palette.len()];
+
+        for &(color, count) in &histogram {
+            let index = nearest(&palette, color);
+
+            for (sum, &value) in sums[index].iter_mut().zip(&color) {
+                *sum += value as u64 * count as u64;
+            }
+            counts[index] += count as u64;
+        }
+
+        let mut changed = false;
+
+        for ((entry, sums), &count) in palette.iter_mut().zip(&sums).zip(&counts) {
+            // A cluster that lost all its colors keeps its old center
+            if count == 0 {
+                continue;
+            }
+
+            let mean = sums.map(|sum| ((sum + count / 2) / count) as u8);
+            changed |= mean != *entry;
+            *entry = mean;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    palette
+}
+
+/// Merges similar colors into their weighted mean, dropping low bits until at most `limit`
+/// colors are left.
+fn binned_histogram(histogram: Vec<([u8; 4], u32)>, limit: usize) -> Vec<([u8; 4], u32)> {
+    let mut histogram = histogram;
+    let mut shift = 0;
+
+    while histogram.len() > limit && shift < 8 {
+        shift += 1;
+
+        let mut bins: HashMap<[u8; 4], ([u64; 4], u32)> = HashMap::new();
+
+        for &(color, count) in &histogram {
+            let (sums, total) = bins.entry(color.map(|value| value >> shift)).or_default();
+
+            for (sum, &value) in sums.iter_mut().zip(&color) {
+                *sum += value as u64 * count as u64;
+            }
+            *total += count;
+        }
+
+        histogram = bins
+            .into_values()
+            .map(|(sums, count)| {
+                let count64 = count as u64;
+                (sums.map(|sum| ((sum + count64 / 2) / count64) as u8), count)
+            })
+            .collect();
+        histogram.sort_unstable();
+    }
+
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [QuantizeMethod; 3] = [
+        QuantizeMethod::MedianCut,
+        QuantizeMethod::Octree,
+        QuantizeMethod::KMeans,
+    ];
+
+    /// A smooth gradient with far more colors than a palette holds.
+    fn gradient(width: u32, height: u32) -> RgbaImage {
+        let mut image = RgbaImage::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = Rgba::new((x * 255 / width) as u8, (y * 255 / height) as u8, 90, 255);
+                image.set_pixel(x, y, color);
+            }
+        }
+
+        image
+    }
+
+    fn squared_error(a: &RgbaImage, b: &RgbaImage) -> u64 {
+        a.pixels()
+            .zip(b.pixels())
+            .map(|(a, b)| distance(a.to_array(), b.to_array()) as u64)
+            .sum()
+    }
+
+    #[test]
+    fn keeps_exact_colors_that_fit() {
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba::new(1, 2, 3, 255));
+        image.set_pixel(1, 2, Rgba::new(200, 100, 0, 128));
+        image.set_pixel(3, 3, Rgba::new(9, 9, 9, 9));
+
+        for method in METHODS {
+            let indexed = quantize(&image, &QuantizeOptions::new(method, 3)).unwrap();
+
+            assert_eq!(indexed.palette.len(), 3);
+            assert_eq!(indexed.to_rgba_image(), image, "{method:?}");
+        }
+    }
+
+    #[test]
+    fn limits_the_palette() {
+        let image = gradient(64, 48);
+
+        for method in METHODS {
+            for max_colors in [1, 2, 16, 256] {
+                let indexed = quantize(&image, &QuantizeOptions::new(method, max_colors)).unwrap();
+
+                assert!(
+                    indexed.palette.len() <= max_colors,
+                    "{method:?} {max_colors}"
+                );
+                assert_eq!(indexed.indices.len(), 64 * 48);
+                assert!(
+                    indexed
+                        .indices
+                        .iter()
+                        .all(|&index| (index as usize) < indexed.palette.len())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn more_colors_means_less_error() {
+        let image = gradient(64, 48);
+
+        for method in METHODS {
+            let error = |max_colors| {
+                let indexed = quantize(&image, &QuantizeOptions::new(method, max_colors)).unwrap();
+                squared_error(&image, &indexed.to_rgba_image())
+            };
+
+            assert!(error(64) < error(4), "{method:?}");
+        }
+    }
+
+    #[test]
+    fn kmeans_is_no_worse_than_median_cut() {
+        let image = gradient(64, 48);
+        let error = |method| {
+            let indexed = quantize(&image, &QuantizeOptions::new(method, 8)).unwrap();
+            squared_error(&image, &indexed.to_rgba_image())
+        };
+
+        assert!(error(QuantizeMethod::KMeans) <= error(QuantizeMethod::MedianCut));
+    }
+
+    #[test]
+    fn transparent_pixels_keep_their_own_entry() {
+        let mut image = gradient(32, 32);
+
+        for x in 0..32 {
+            image.set_pixel(x, 0, Rgba::new(x as u8, 50, 0, 0));
+        }
+
+        for method in METHODS {
+            let indexed = quantize(&image, &QuantizeOptions::new(method, 4)).unwrap();
+            let output = indexed.to_rgba_image();
+
+            assert!(
+                indexed.palette.contains(&Rgba::new(0, 0, 0, 0)),
+                "{method:?}"
+            );
+            assert!((0..32).all(|x| output.get_pixel(x, 0).a == 0));
+            assert!(output.pixels().skip(32).all(|pixel| pixel.a == 255));
+        }
+    }
+
+    #[test]
+    fn maps_to_the_nearest_palette_color() {
+        let palette = [
+            Rgba::new(0, 0, 0, 255),
+            Rgba::new(255, 255, 255, 255),
+            Rgba::new(255, 0, 0, 255),
+        ];
+        let image = RgbaImage::from_data(
+            3,
+            1,
+            vec![
+                Rgba::new(30, 20, 10, 255),
+                Rgba::new(220, 240, 230, 255),
+                Rgba::new(200, 40, 30, 255),
+            ],
+        )
+        .unwrap();
+
+        let indexed = quantize_to_palette(&image, &palette).unwrap();
+        assert_eq!(indexed.indices, [0, 1, 2]);
+        assert_eq!(indexed.palette, palette);
+
+        let options = QuantizeOptions::with_palette(palette.to_vec());
+        assert_eq!(quantize(&image, &options).unwrap(), indexed);
+    }
+
+    #[test]
+    fn binning_stays_under_the_limit() {
+        let image = gradient(256, 256);
+        let binned = binned_histogram(histogram(&image), 1000);
+
+        assert!(binned.len() <= 1000);
+        assert_eq!(
+            binned.iter().map(|&(_, count)| count).sum::<u32>(),
+            256 * 256
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_palette_sizes() {
+        let image = gradient(4, 4);
+
+        assert!(quantize(&image, &QuantizeOptions::new(QuantizeMethod::MedianCut, 0)).is_err());
+        assert!(quantize(&image, &QuantizeOptions::new(QuantizeMethod::Octree, 257)).is_err());
+        assert!(quantize_to_palette(&image, &[]).is_err());
+        assert!(quantize_to_palette(&image, &[Rgba::default(); 257]).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use std::f32::consts::PI;
+
+use color_eyre::eyre::{Result, eyre};
+
+use crate::{
+    image_buffer::ImageBuffer,
+    pixel::{Pixel, RgbaF32},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    /// Closest source pixel, keeps hard edges for pixel art
+    Nearest,
+    /// Averages the covered source pixels, good for shrinking by whole factors
+    Box,
+    /// Linear interpolation between the two closest pixels
+    Bilinear,
+    /// Sharp cubic that passes through the source samples
+    CatmullRom,
+    /// Softer cubic with less ringing than Catmull-Rom
+    Mitchell,
+    /// Windowed sinc over 3 lobes, the sharpest, can ring around hard edges
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    /// How far from the sample the kernel reaches at 1:1 scale
+    fn support(&self) -> f32 {
+        match self {
+            Self::Nearest | Self::Box => 0.5,
+            Self::Bilinear => 1.0,
+            Self::CatmullRom | Self::Mitchell => 2.0,
+            Self::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        match self {
+            Self::Nearest | Self::Box => {
+                if (-0.5..0.5).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Bilinear => (1.0 - x.abs()).max(0.0),
+            Self::CatmullRom => cubic(x, 0.0, 0.5),
+            Self::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            Self::Lanczos3 => {
+                if x.abs() < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// How the image is fitted into the requested size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    /// Stretches to exactly the requested size
+    #[default]
+    Exact,
+    /// Keeps the aspect ratio and fits inside the requested size, one side may come out
+    /// shorter
+    Contain,
+    /// Keeps the aspect ratio and fills the requested size, cropping the overflow evenly
+    /// from both sides
+    Cover,
+}
+
+/// Mitchell-Netravali cubic with parameters `b` and `c`.
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+
+    value / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+/// Source pixels and their normalized weights for one output pixel.
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// Weights for resampling the source span `offset..offset + length` onto `size` pixels.
+fn contributions(
+    filter: ResizeFilter,
+    source_size: u32,
+    offset: f64,
+    length: f64,
+    size: u32,
+) -> Vec<Contribution> {
+    let scale = length / size as f64;
+    // Shrinking widens the kernel so every source pixel still contributes
+    let stretch = scale.max(1.0);
+    let support = filter.support() as f64 * stretch;
+    let last = source_size as usize - 1;
+
+    (0..size)
+        .map(|index| {
+            let center = offset + (index as f64 + 0.5) * scale;
+
+            if filter == ResizeFilter::Nearest {
+                return Contribution {
+                    start: (center.floor().max(0.0) as usize).min(last),
+                    weights: vec![1.0],
+                };
+            }
+
+            let start = ((center - support).floor().max(0.0) as usize).min(last);
+            let end = ((center + support).ceil().max(0.0) as usize).clamp(start + 1, last + 1);
+
+            let mut weights: Vec<f32> = (start..end)
+                .map(|source| filter.weight(((source as f64 + 0.5 - center) / stretch) as f32))
+                .collect();
+
+            let total: f32 = weights.iter().sum();
+
+            if total.abs() > f32::EPSILON {
+                weights.iter_mut().for_each(|weight| *weight /= total);
+            } else {
+                // Narrow kernels can fall between samples, fall back to the closest one
+                weights.iter_mut().for_each(|weight| *weight = 0.0);
+                let closest = (center.floor().max(0.0) as usize).clamp(start, end - 1);
+                weights[closest - start] = 1.0;
+            }
+
+            Contribution { start, weights }
+        })
+        .collect()
+}
+
+fn accumulate(pixels: impl Iterator<Item = (RgbaF32, f32)>) -> RgbaF32 {
+    pixels.fold(RgbaF32::default(), |sum, (pixel, weight)| {
+        RgbaF32::new(
+            sum.r + pixel.r * weight,
+            sum.g + pixel.g * weight,
+            sum.b + pixel.b * weight,
+            sum.a + pixel.a * weight,
+        )
+    })
+}
+
+/// Resamples the source rectangle at (`x`, `y`) of `source_width` x `source_height` onto
+/// a `width` x `height` image. Colors are filtered premultiplied so transparent pixels don't
+/// bleed their color into the edges of opaque ones.
+fn resample<P: Pixel>(
+    image: &ImageBuffer<P>,
+    (x, y, source_width, source_height): (f64, f64, f64, f64),
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> ImageBuffer<P> {
+    let columns = contributions(filter, image.width, x, source_width, width);
+    let rows = contributions(filter, image.height, y, source_height, height);
+
+    // Only the source rows some output row reads from are filtered horizontally
+    let first_row = rows.iter().map(|row| row.start).min().unwrap_or(0);
+    let last_row = rows
+        .iter()
+        .map(|row| row.start + row.weights.len())
+        .max()
+        .unwrap_or(0);
+
+    let source_width = image.width as usize;
+    let width = width as usize;
+    let mut source_row = Vec::with_capacity(source_width);
+    let mut horizontal = Vec::with_capacity(width * (last_row - first_row));
+
+    for row in first_row..last_row {
+        source_row.clear();
+        source_row.extend(
+            image.data[row * source_width..(row + 1) * source_width]
+                .iter()
+                .map(|pixel| pixel.to_rgba_f32().premultiplied()),
+        );
+
+        horizontal.extend(columns.iter().map(|column| {
+            accumulate(
+                column
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, &weight)| (source_row[column.start + offset], weight)),
+            )
+        }));
+    }
+
+    let mut data = Vec::with_capacity(width * height as usize);
+
+    for row in &rows {
+        for column in 0..width {
+            let pixel = accumulate(row.weights.iter().enumerate().map(|(offset, &weight)| {
+                let source = row.start + offset - first_row;
+                (horizontal[source * width + column], weight)
+            }));
+
+            // Negative lobes can overshoot, keep the result a valid premultiplied color
+            let a = pixel.a.clamp(0.0, 1.0);
+            let pixel = RgbaF32::new(pixel.r.max(0.0), pixel.g.max(0.0), pixel.b.max(0.0), a);
+
+            data.push(P::from_rgba_f32(pixel.unpremultiplied()));
+        }
+    }
+
+    ImageBuffer {
+        width: width as u32,
+        height: rows.len() as u32,
+        data,
+    }
+}
+
+/// Output size of fitting a `source_width` x `source_height` image into `width` x `height`.
+pub fn fit_dimensions(
+    source_width: u32,
+    source_height: u32,
+    width: u32,
+    height: u32,
+    fit: FitMode,
+) -> (u32, u32) {
+    match fit {
+        FitMode::Exact | FitMode::Cover => (width, height),
+        FitMode::Contain => {
+            let scale =
+                (width as f64 / source_width as f64).min(height as f64 / source_height as f64);
+            let fitted =
+                |size: u32, target: u32| ((size as f64 * scale).round() as u32).clamp(1, target);
+
+            (fitted(source_width, width), fitted(source_height, height))
+        }
+    }
+}
+
+/// Scales the image to exactly `width` x `height`.
+pub fn resize<P: Pixel>(
+    image: &ImageBuffer<P>,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> Result<ImageBuffer<P>> {
+    resize_to_fit(image, width, height, filter, FitMode::Exact)
+}
+
+/// Scales the image into `width` x `height` as `fit` describes.
+pub fn resize_to_fit<P: Pixel>(
+    image: &ImageBuffer<P>,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+    fit: FitMode,
+) -> Result<ImageBuffer<P>> {
+    if image.width == 0 || image.height == 0 {
+        return Err(eyre!("Can't resize an empty image"));
+    }
+
+    if width == 0 || height == 0 {
+        return Err(eyre!(
+            "Resized width and height must be greater than 0, got {}x{}",
+            width,
+            height
+        ));
+    }
+
+    let (source_width, source_height) = (image.width as f64, image.height as f64);
+    let (width, height) = fit_dimensions(image.width, image.height, width, height, fit);
+
+    let source = match fit {
+        FitMode::Exact | FitMode::Contain => (0.0, 0.0, source_width, source_height),
+        FitMode::Cover => {
+            let scale = (width as f64 / source_width).max(height as f64 / source_height);
+            let (visible_width, visible_height) = (width as f64 / scale, height as f64 / scale);
+
+            (
+                (source_width - visible_width) / 2.0,
+                (source_height - visible_height) / 2.0,
+                visible_width,
+                visible_height,
+            )
+        }
+    };
+
+    Ok(resample(image, source, width, height, filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba::Rgba, rgba_image::RgbaImage, test_utils::numbered};
+
+    const FILTERS: [ResizeFilter; 6] = [
+        ResizeFilter::Nearest,
+        ResizeFilter::Box,
+        ResizeFilter::Bilinear,
+        ResizeFilter::CatmullRom,
+        ResizeFilter::Mitchell,
+        ResizeFilter::Lanczos3,
+    ];
+
+    #[test]
+    fn same_size_keeps_the_image() {
+        let image = numbered(7, 5);
+
+        // Mitchell doesn't pass through the samples, so it softens even at 1:1
+        for filter in FILTERS
+            .into_iter()
+            .filter(|&filter| filter != ResizeFilter::Mitchell)
+        {
+            assert_eq!(resize(&image, 7, 5, filter).unwrap(), image, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn solid_colors_stay_solid() {
+        let color = Rgba::new(10, 120, 250, 180);
+        let image = RgbaImage::from_pixel(9, 6, color);
+
+        for filter in FILTERS {
+            for (width, height) in [(3, 2), (20, 13), (9, 1), (1, 6)] {
+                let resized = resize(&image, width, height, filter).unwrap();
+
+                assert_eq!((resized.width, resized.height), (width, height));
+                assert!(resized.pixels().all(|&pixel| pixel == color), "{filter:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_upscale_repeats_pixels() {
+        let image = numbered(3, 2);
+        let resized = resize(&image, 6, 4, ResizeFilter::Nearest).unwrap();
+
+        for y in 0..4 {
+            for x in 0..6 {
+                assert_eq!(resized.get_pixel(x, y), image.get_pixel(x / 2, y / 2));
+            }
+        }
+    }
+
+    #[test]
+    fn box_downscale_averages_blocks() {
+        let mut image = RgbaImage::from_pixel(4, 2, Rgba::new(0, 0, 0, 255));
+        image.set_pixel(0, 0, Rgba::new(200, 0, 0, 255));
+        image.set_pixel(3, 1, Rgba::new(0, 0, 100, 255));
+
+        let resized = resize(&image, 2, 1, ResizeFilter::Box).unwrap();
+
+        assert_eq!(
+            resized.data,
+            [Rgba::new(50, 0, 0, 255), Rgba::new(0, 0, 25, 255)]
+        );
+    }
+
+    #[test]
+    fn transparent_pixels_dont_bleed() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba::new(0, 255, 0, 0));
+
+        for y in 0..8 {
+            for x in 0..4 {
+                image.set_pixel(x, y, Rgba::new(255, 0, 0, 255));
+            }
+        }
+
+        for filter in FILTERS {
+            let resized = resize(&image, 5, 3, filter).unwrap();
+
+            assert!(
+                resized
+                    .pixels()
+                    .filter(|pixel| pixel.a > 0)
+                    .all(|pixel| pixel.r == 255 && pixel.g == 0),
+                "{filter:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fit_dimensions_keep_the_aspect_ratio() {
+        assert_eq!(
+            fit_dimensions(400, 200, 100, 100, FitMode::Exact),
+            (100, 100)
+        );
+        assert_eq!(
+            fit_dimensions(400, 200, 100, 100, FitMode::Contain),
+            (100, 50)
+        );
+        assert_eq!(
+            fit_dimensions(200, 400, 100, 100, FitMode::Contain),
+            (50, 100)
+        );
+        assert_eq!(
+            fit_dimensions(400, 200, 100, 100, FitMode::Cover),
+            (100, 100)
+        );
+        // Never rounds a side down to nothing
+        assert_eq!(fit_dimensions(1000, 1, 10, 10, FitMode::Contain), (10, 1));
+    }
+
+    #[test]
+    fn cover_crops_evenly() {
+        let image = numbered(4, 2);
+        let resized = resize_to_fit(&image, 2, 2, ResizeFilter::Nearest, FitMode::Cover).unwrap();
+
+        assert_eq!(resized, image.crop(1, 0, 2, 2).unwrap());
+    }
+
+    #[test]
+    fn rejects_empty_sizes() {
+        let image = numbered(2, 2);
+
+        assert!(resize(&image, 0, 2, ResizeFilter::Bilinear).is_err());
+        assert!(resize(&image, 2, 0, ResizeFilter::Bilinear).is_err());
+        assert!(resize(&RgbaImage::new(0, 3), 2, 2, ResizeFilter::Bilinear).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+use crate::{rgba::Rgba, rgba_image::RgbaImage};
+
+/// Every pixel different, so any misplaced pixel shows.
+pub fn numbered(width: u32, height: u32) -> RgbaImage {
+    let mut image = RgbaImage::new(width, height);
+
+    for (index, pixel) in image.pixels_mut().enumerate() {
+        *pixel = Rgba::new((index * 7) as u8, (index * 13) as u8, 200, 255);
+    }
+
+    image
+}
@@ -0,0 +1,299 @@
+use crate::{
+    image_buffer::ImageBuffer,
+    pixel::{Pixel, RgbaF32},
+};
+
+/// How `rotate` samples between source pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Catmull-Rom over the closest 4x4 pixels, sharper than bilinear
+    Bicubic,
+}
+
+/// Builds a `width` x `height` image from the source pixel each output position maps to.
+fn remap<P: Pixel>(
+    image: &ImageBuffer<P>,
+    width: u32,
+    height: u32,
+    source: impl Fn(u32, u32) -> (u32, u32),
+) -> ImageBuffer<P> {
+    let mut data = Vec::with_capacity(width as usize * height as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            let (source_x, source_y) = source(x, y);
+            data.push(image.get_pixel(source_x, source_y));
+        }
+    }
+
+    ImageBuffer {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Mirrors left to right.
+pub fn flip_horizontal<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    let mut flipped = image.clone();
+
+    for row in flipped.data.chunks_exact_mut(image.width.max(1) as usize) {
+        row.reverse();
+    }
+
+    flipped
+}
+
+/// Mirrors top to bottom.
+pub fn flip_vertical<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    ImageBuffer {
+        width: image.width,
+        height: image.height,
+        data: image
+            .data
+            .chunks_exact(image.width.max(1) as usize)
+            .rev()
+            .flatten()
+            .copied()
+            .collect(),
+    }
+}
+
+/// Rotates a quarter turn clockwise.
+pub fn rotate90<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    remap(image, image.height, image.width, |x, y| {
+        (y, image.height - 1 - x)
+    })
+}
+
+/// Rotates a half turn, which is the same as flipping both ways.
+pub fn rotate180<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    let mut rotated = image.clone();
+    rotated.data.reverse();
+    rotated
+}
+
+/// Rotates a quarter turn counterclockwise.
+pub fn rotate270<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    remap(image, image.height, image.width, |x, y| {
+        (image.width - 1 - y, x)
+    })
+}
+
+/// Mirrors along the diagonal from the top left corner, swapping rows and columns.
+pub fn transpose<P: Pixel>(image: &ImageBuffer<P>) -> ImageBuffer<P> {
+    remap(image, image.height, image.width, |x, y| (y, x))
+}
+
+/// Rotates by `angle` degrees clockwise around the center. Without `expand` the image keeps
+/// its size and the corners are cut off, with it the image grows to fit the rotated bounds.
+/// Uncovered areas are transparent, or black for formats without alpha.
+pub fn rotate<P: Pixel>(
+    image: &ImageBuffer<P>,
+    angle: f32,
+    interpolation: Interpolation,
+    expand: bool,
+) -> ImageBuffer<P> {
+    if image.width == 0 || image.height == 0 {
+        return image.clone();
+    }
+
+    let angle = angle.rem_euclid(360.0);
+
+    // Quarter turns are exact, the sampling path would only add rounding
+    if expand || image.width == image.height {
+        match angle {
+            0.0 => return image.clone(),
+            90.0 => return rotate90(image),
+            180.0 => return rotate180(image),
+            270.0 => return rotate270(image),
+            _ => {}
+        }
+    }
+
+    let (sin, cos) = (angle as f64).to_radians().sin_cos();
+    let (source_width, source_height) = (image.width as f64, image.height as f64);
+
+    let (width, height) = if expand {
+        // Floating point noise shouldn't add a pixel, e.g. cos(90°) isn't exactly 0
+        let fit = |size: f64| (size - 1e-6).ceil().max(1.0) as u32;
+
+        (
+            fit(source_width * cos.abs() + source_height * sin.abs()),
+            fit(source_width * sin.abs() + source_height * cos.abs()),
+        )
+    } else {
+        (image.width, image.height)
+    };
+
+    let sampler = Sampler {
+        width: image.width as i64,
+        height: image.height as i64,
+        pixels: image
+            .data
+            .iter()
+            .map(|pixel| pixel.to_rgba_f32().premultiplied())
+            .collect(),
+    };
+
+    let mut data = Vec::with_capacity(width as usize * height as usize);
+
+    for y in 0..height {
+        for x in 0..width {
+            // Maps the output pixel center back into the source, rotating the other way
+            let dx = x as f64 + 0.5 - width as f64 / 2.0;
+            let dy = y as f64 + 0.5 - height as f64 / 2.0;
+            let source_x = dx * cos + dy * sin + source_width / 2.0 - 0.5;
+            let source_y = -dx * sin + dy * cos + source_height / 2.0 - 0.5;
+
+            let pixel = sampler.sample(source_x, source_y, interpolation);
+            data.push(P::from_rgba_f32(pixel.unpremultiplied()));
+        }
+    }
+
+    ImageBuffer {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Premultiplied source pixels, transparent outside the image so edges blend out smoothly.
+struct Sampler {
+    width: i64,
+    height: i64,
+    pixels: Vec<RgbaF32>,
+}
+
+impl Sampler {
+    fn pixel(&self, x: i64, y: i64) -> RgbaF32 {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return RgbaF32::default();
+        }
+
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// `x` and `y` are in pixel index space, 0 being the center of the first pixel.
+    fn sample(&self, x: f64, y: f64, interpolation: Interpolation) -> RgbaF32 {
+        match interpolation {
+            Interpolation::Nearest => self.pixel(x.round() as i64, y.round() as i64),
+            Interpolation::Bilinear => {
+                let (left, top) = (x.floor(), y.floor());
+                let (fx, fy) = ((x - left) as f32, (y - top) as f32);
+                let (left, top) = (left as i64, top as i64);
+
+                weighted([
+                    (self.pixel(left, top), (1.0 - fx) * (1.0 - fy)),
+                    (self.pixel(left + 1, top), fx * (1.0 - fy)),
+                    (self.pixel(left, top + 1), (1.0 - fx) * fy),
+                    (self.pixel(left + 1, top + 1), fx * fy),
+                ])
+            }
+            Interpolation::Bicubic => {
+                let (left, top) = (x.floor(), y.floor());
+                let weights_x = catmull_rom((x - left) as f32);
+                let weights_y = catmull_rom((y - top) as f32);
+                let (left, top) = (left as i64, top as i64);
+
+                let pixel = weighted((0..16).map(|index| {
+                    let (column, row) = (index % 4, index / 4);
+                    (
+                        self.pixel(left + column - 1, top + row - 1),
+                        weights_x[column as usize] * weights_y[row as usize],
+                    )
+                }));
+
+                // Negative lobes can overshoot, keep it a valid premultiplied color
+                let a = pixel.a.clamp(0.0, 1.0);
+                RgbaF32::new(pixel.r.max(0.0), pixel.g.max(0.0), pixel.b.max(0.0), a)
+            }
+        }
+    }
+}
+
+fn weighted(samples: impl IntoIterator<Item = (RgbaF32, f32)>) -> RgbaF32 {
+    samples
+        .into_iter()
+        .fold(RgbaF32::default(), |sum, (pixel, weight)| {
+            RgbaF32::new(
+                sum.r + pixel.r * weight,
+                sum.g + pixel.g * weight,
+                sum.b + pixel.b * weight,
+                sum.a + pixel.a * weight,
+            )
+        })
+}
+
+/// Weights of the 4 pixels around a point `t` past the second one.
+fn catmull_rom(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba_image::RgbaImage, test_utils::numbered};
+
+    #[test]
+    fn flips_twice_give_the_original() {
+        let image = numbered(5, 3);
+
+        assert_eq!(flip_horizontal(&flip_horizontal(&image)), image);
+        assert_eq!(flip_vertical(&flip_vertical(&image)), image);
+        assert_eq!(transpose(&transpose(&image)), image);
+    }
+
+    #[test]
+    fn quarter_turns_compose() {
+        let image = numbered(5, 3);
+
+        assert_eq!(rotate90(&rotate270(&image)), image);
+        assert_eq!(rotate90(&rotate90(&image)), rotate180(&image));
+        assert_eq!(rotate180(&image), flip_vertical(&flip_horizontal(&image)));
+    }
+
+    #[test]
+    fn rotate90_turns_clockwise() {
+        let image = numbered(2, 1);
+        let rotated = rotate90(&image);
+
+        assert_eq!((rotated.width, rotated.height), (1, 2));
+        assert_eq!(rotated.data, [image.data[0], image.data[1]]);
+    }
+
+    #[test]
+    fn rotate_takes_the_exact_path_for_quarter_turns() {
+        let image = numbered(5, 3);
+
+        assert_eq!(
+            rotate(&image, -270.0, Interpolation::Bicubic, true),
+            rotate90(&image)
+        );
+        assert_eq!(rotate(&image, 360.0, Interpolation::Bilinear, false), image);
+    }
+
+    #[test]
+    fn rotate_expands_to_the_rotated_bounds() {
+        let rotated = rotate(&numbered(10, 10), 45.0, Interpolation::Bilinear, true);
+
+        assert_eq!((rotated.width, rotated.height), (15, 15));
+    }
+
+    #[test]
+    fn rotate_keeps_empty_images_empty() {
+        let rotated = rotate(&RgbaImage::new(0, 0), 30.0, Interpolation::Bilinear, true);
+
+        assert_eq!((rotated.width, rotated.height), (0, 0));
+    }
+}